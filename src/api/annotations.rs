@@ -0,0 +1,227 @@
+//! Star and rating API endpoints.
+
+use actix_web::{delete, get, put, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::api::music::annotate_songs;
+use crate::auth::AuthenticatedUser;
+use crate::error::{AppError, AppResult};
+use crate::library::LibraryIndex;
+use crate::models::{AppState, SongMetadata};
+use crate::userdata::{Annotation, AnnotationRepository, ItemType};
+
+/// Item kind as it appears in annotation URLs.
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemKind {
+    Songs,
+    Albums,
+    Artists,
+}
+
+impl From<ItemKind> for ItemType {
+    fn from(kind: ItemKind) -> Self {
+        match kind {
+            ItemKind::Songs => ItemType::Song,
+            ItemKind::Albums => ItemType::Album,
+            ItemKind::Artists => ItemType::Artist,
+        }
+    }
+}
+
+/// Request body for rating an item.
+#[derive(Debug, Deserialize, Validate)]
+pub struct RatingRequest {
+    /// Rating from 1 to 5.
+    #[validate(range(min = 1, max = 5, message = "Rating must be between 1 and 5"))]
+    pub rating: u8,
+}
+
+/// A starred or rated album or artist.
+#[derive(Debug, Serialize)]
+pub struct AnnotatedItem {
+    pub id: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub starred: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<u8>,
+}
+
+/// The user's starred items.
+#[derive(Debug, Serialize)]
+pub struct StarredResponse {
+    pub songs: Vec<SongMetadata>,
+    pub albums: Vec<AnnotatedItem>,
+    pub artists: Vec<AnnotatedItem>,
+}
+
+/// Find the display name of a library item, or fail if it does not exist.
+fn item_name(index: &LibraryIndex, item_type: ItemType, id: &str) -> Option<String> {
+    match item_type {
+        ItemType::Song => index.song(id).map(|s| s.title.clone()),
+        ItemType::Album => index
+            .songs
+            .iter()
+            .find(|s| s.album_id == id)
+            .map(|s| s.album.clone()),
+        ItemType::Artist => index
+            .songs
+            .iter()
+            .find(|s| s.artist_id == id)
+            .map(|s| s.artist.clone()),
+    }
+}
+
+/// Load the user's annotation for an item, checking that the item exists.
+fn load_annotation(
+    data: &AppState,
+    user: &AuthenticatedUser,
+    kind: ItemKind,
+    id: &str,
+) -> AppResult<Annotation> {
+    let item_type = ItemType::from(kind);
+    let index = data.library.index()?;
+
+    if item_name(&index, item_type, id).is_none() {
+        return Err(AppError::NotFound(format!("Item not found: {}", id)));
+    }
+
+    Ok(data
+        .annotation_repo
+        .find(user.id, id)?
+        .unwrap_or_else(|| Annotation::new(user.id, id.to_string(), item_type)))
+}
+
+/// Star a song, album or artist.
+///
+/// PUT /api/music/{kind}/{id}/star
+#[put("/api/music/{kind}/{id}/star")]
+pub async fn star(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<(ItemKind, String)>,
+) -> AppResult<HttpResponse> {
+    let (kind, id) = path.into_inner();
+    let mut annotation = load_annotation(&data, &user, kind, &id)?;
+
+    if annotation.starred_at.is_none() {
+        annotation.starred_at = Some(Utc::now());
+    }
+
+    Ok(HttpResponse::Ok().json(data.annotation_repo.save(annotation)?))
+}
+
+/// Remove a star.
+///
+/// DELETE /api/music/{kind}/{id}/star
+#[delete("/api/music/{kind}/{id}/star")]
+pub async fn unstar(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<(ItemKind, String)>,
+) -> AppResult<HttpResponse> {
+    let (kind, id) = path.into_inner();
+    let mut annotation = load_annotation(&data, &user, kind, &id)?;
+
+    annotation.starred_at = None;
+    data.annotation_repo.save(annotation)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Rate a song, album or artist from 1 to 5.
+///
+/// PUT /api/music/{kind}/{id}/rating
+#[put("/api/music/{kind}/{id}/rating")]
+pub async fn set_rating(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<(ItemKind, String)>,
+    body: web::Json<RatingRequest>,
+) -> AppResult<HttpResponse> {
+    body.validate()
+        .map_err(|e| AppError::Validation(e.to_string()))?;
+
+    let (kind, id) = path.into_inner();
+    let mut annotation = load_annotation(&data, &user, kind, &id)?;
+    annotation.rating = Some(body.rating);
+
+    Ok(HttpResponse::Ok().json(data.annotation_repo.save(annotation)?))
+}
+
+/// Clear a rating.
+///
+/// DELETE /api/music/{kind}/{id}/rating
+#[delete("/api/music/{kind}/{id}/rating")]
+pub async fn clear_rating(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<(ItemKind, String)>,
+) -> AppResult<HttpResponse> {
+    let (kind, id) = path.into_inner();
+    let mut annotation = load_annotation(&data, &user, kind, &id)?;
+
+    annotation.rating = None;
+    data.annotation_repo.save(annotation)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// List the user's starred songs, albums and artists.
+///
+/// GET /api/music/starred
+#[get("/api/music/starred")]
+pub async fn list_starred(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> AppResult<HttpResponse> {
+    let index = data.library.index()?;
+    let mut starred: Vec<Annotation> = data
+        .annotation_repo
+        .list_by_user(user.id)?
+        .into_iter()
+        .filter(|a| a.starred_at.is_some())
+        .collect();
+    // Most recently starred first
+    starred.sort_by_key(|a| std::cmp::Reverse(a.starred_at));
+
+    let mut songs: Vec<SongMetadata> = starred
+        .iter()
+        .filter(|a| a.item_type == ItemType::Song)
+        .filter_map(|a| index.song(&a.item_id).cloned())
+        .collect();
+    annotate_songs(&data, user.id, &mut songs)?;
+
+    let items = |item_type: ItemType| -> Vec<AnnotatedItem> {
+        starred
+            .iter()
+            .filter(|a| a.item_type == item_type)
+            .filter_map(|a| {
+                Some(AnnotatedItem {
+                    id: a.item_id.clone(),
+                    name: item_name(&index, item_type, &a.item_id)?,
+                    starred: a.starred_at,
+                    rating: a.rating,
+                })
+            })
+            .collect()
+    };
+
+    Ok(HttpResponse::Ok().json(StarredResponse {
+        songs,
+        albums: items(ItemType::Album),
+        artists: items(ItemType::Artist),
+    }))
+}
+
+/// Configure annotation routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_starred)
+        .service(star)
+        .service(unstar)
+        .service(set_rating)
+        .service(clear_rating);
+}
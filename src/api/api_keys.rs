@@ -0,0 +1,156 @@
+//! API key endpoints.
+//!
+//! Users create keys for scripts and headless players and send them in the
+//! `X-API-Key` header instead of logging in. Keys can only be managed with
+//! a login, not with another key.
+
+use actix_web::{delete, get, post, web, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::auth::api_key_repository::{ApiKey, ApiKeyScope};
+use crate::auth::{ApiKeyRepository, AuthenticatedUser};
+use crate::error::{AppError, AppResult};
+use crate::models::AppState;
+
+/// Most keys a user can have.
+const MAX_KEYS_PER_USER: usize = 50;
+
+/// Request body for creating an API key.
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateApiKeyRequest {
+    #[validate(length(min = 1, max = 64, message = "Name must be 1-64 characters"))]
+    pub name: String,
+    /// What the key may do; every scope when omitted.
+    pub scopes: Option<Vec<ApiKeyScope>>,
+    /// Days until the key expires; never when omitted.
+    #[validate(range(min = 1, max = 3650, message = "Expiry must be 1-3650 days"))]
+    pub expires_in_days: Option<i64>,
+}
+
+/// An API key in responses, without its secret.
+#[derive(Debug, Serialize)]
+pub struct ApiKeyResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub scopes: Option<Vec<ApiKeyScope>>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+    fn from(key: ApiKey) -> Self {
+        Self {
+            id: key.id,
+            name: key.name,
+            scopes: key.scopes,
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
+
+/// A newly created API key, with the key itself.
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub info: ApiKeyResponse,
+    /// The key; it cannot be retrieved again.
+    pub key: String,
+}
+
+/// Refuse key management through an API key.
+fn require_login(user: &AuthenticatedUser) -> AppResult<()> {
+    if user.api_key_id.is_some() {
+        return Err(AppError::Forbidden(
+            "API keys cannot be managed with an API key".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// List the current user's API keys.
+///
+/// GET /api/keys
+#[get("/api/keys")]
+pub async fn list_keys(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> AppResult<HttpResponse> {
+    require_login(&user)?;
+
+    let keys: Vec<ApiKeyResponse> = data
+        .api_key_repo
+        .list_by_user(user.id)?
+        .into_iter()
+        .map(ApiKeyResponse::from)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(keys))
+}
+
+/// Create an API key.
+///
+/// POST /api/keys
+///
+/// The response is the only time the key is shown.
+#[post("/api/keys")]
+pub async fn create_key(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    body: web::Json<CreateApiKeyRequest>,
+) -> AppResult<HttpResponse> {
+    require_login(&user)?;
+    body.validate()
+        .map_err(|e| AppError::Validation(e.to_string()))?;
+
+    if data.api_key_repo.list_by_user(user.id)?.len() >= MAX_KEYS_PER_USER {
+        return Err(AppError::Validation(format!(
+            "At most {} API keys are allowed",
+            MAX_KEYS_PER_USER
+        )));
+    }
+
+    let body = body.into_inner();
+    let expires_at = body
+        .expires_in_days
+        .map(|days| Utc::now() + Duration::days(days));
+    let (key, secret) = ApiKey::new(user.id, body.name, body.scopes, expires_at);
+    let key = data.api_key_repo.create(key)?;
+
+    tracing::info!(username = %user.username, key = %key.id, name = %key.name, "API key created");
+    Ok(HttpResponse::Created().json(CreatedApiKey {
+        info: ApiKeyResponse::from(key),
+        key: secret,
+    }))
+}
+
+/// Revoke one of the current user's API keys.
+///
+/// DELETE /api/keys/{id}
+#[delete("/api/keys/{id}")]
+pub async fn delete_key(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+) -> AppResult<HttpResponse> {
+    require_login(&user)?;
+
+    if !data.api_key_repo.delete(user.id, *path)? {
+        return Err(AppError::NotFound(format!("API key not found: {}", path)));
+    }
+
+    tracing::info!(username = %user.username, key = %path, "API key revoked");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Configure API key routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_keys)
+        .service(create_key)
+        .service(delete_key);
+}
@@ -1,17 +1,29 @@
 //! Authentication API endpoints.
 
-use actix_web::{get, post, web, HttpResponse};
+use actix_web::http::header;
+use actix_web::{delete, get, post, web, FromRequest, HttpRequest, HttpResponse};
 use argon2::{
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
-use rand::rngs::OsRng;
+use base64::Engine;
 use chrono::Utc;
+use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 use validator::Validate;
 
-use crate::auth::{jwt, AuthenticatedUser, JsonUserRepository, User, UserRepository};
+use crate::api::two_factor;
+use crate::api::users::delete_user;
+use crate::auth::login_challenge::CHALLENGE_LIFETIME;
+use crate::auth::refresh_token_repository::{Client, TokenFamily};
+use crate::auth::{
+    jwt, AuthenticatedUser, InviteRepository, JsonUserRepository, RefreshTokenRepository, User,
+    UserRepository,
+};
+use crate::config::{self, RegistrationMode};
 use crate::error::{AppError, AppResult};
+use crate::models::AppState;
 
 /// Request body for user registration.
 #[derive(Debug, Deserialize, Validate)]
@@ -26,6 +38,10 @@ pub struct RegisterRequest {
     /// Password (8-128 characters).
     #[validate(length(min = 8, max = 128, message = "Password must be 8-128 characters"))]
     pub password: String,
+    /// Invite code, required with `REGISTRATION_MODE=invite-only`.
+    pub invite_code: Option<String>,
+    /// Setup token from the startup log, required for the first account.
+    pub setup_token: Option<String>,
 }
 
 lazy_static::lazy_static! {
@@ -39,6 +55,62 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+/// Request body for the second step of a two-factor login.
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorLoginRequest {
+    pub challenge_token: String,
+    /// TOTP or recovery code.
+    pub code: String,
+}
+
+/// Request body for refreshing tokens.
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// Request body for changing the current user's password.
+#[derive(Debug, Deserialize, Validate)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    /// New password (8-128 characters).
+    #[validate(length(min = 8, max = 128, message = "Password must be 8-128 characters"))]
+    pub new_password: String,
+}
+
+/// Request body for deleting the current user's account.
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountRequest {
+    pub password: String,
+}
+
+/// A login session in responses.
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    pub id: uuid::Uuid,
+    pub device: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: chrono::DateTime<Utc>,
+    pub last_used_at: chrono::DateTime<Utc>,
+    pub expires_at: chrono::DateTime<Utc>,
+    /// Whether this is the session of the requesting token.
+    pub current: bool,
+}
+
+impl SessionResponse {
+    fn new(session: TokenFamily, current: Option<uuid::Uuid>) -> Self {
+        Self {
+            current: current == Some(session.id),
+            id: session.id,
+            device: session.client.device,
+            ip: session.client.ip,
+            created_at: session.created_at,
+            last_used_at: session.refreshed_at,
+            expires_at: session.expires_at,
+        }
+    }
+}
+
 /// Response for successful authentication.
 #[derive(Debug, Serialize)]
 pub struct AuthResponse {
@@ -46,12 +118,23 @@ pub struct AuthResponse {
     pub token: jwt::TokenPair,
 }
 
+/// Response when a login needs a two-factor code.
+#[derive(Debug, Serialize)]
+pub struct TwoFactorChallenge {
+    pub two_factor_required: bool,
+    /// Token for `POST /auth/login/2fa`.
+    pub challenge_token: String,
+    /// Seconds until the challenge expires.
+    pub expires_in: i64,
+}
+
 /// Public user information in responses.
 #[derive(Debug, Serialize)]
 pub struct UserResponse {
     pub id: uuid::Uuid,
     pub username: String,
     pub is_admin: bool,
+    pub two_factor_enabled: bool,
     pub created_at: chrono::DateTime<Utc>,
 }
 
@@ -61,13 +144,14 @@ impl From<&User> for UserResponse {
             id: user.id,
             username: user.username.clone(),
             is_admin: user.is_admin,
+            two_factor_enabled: user.has_two_factor(),
             created_at: user.created_at,
         }
     }
 }
 
 /// Hash a password using Argon2.
-fn hash_password(password: &str) -> AppResult<String> {
+pub(crate) fn hash_password(password: &str) -> AppResult<String> {
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = Argon2::default();
 
@@ -81,7 +165,7 @@ fn hash_password(password: &str) -> AppResult<String> {
 }
 
 /// Verify a password against a hash.
-fn verify_password(password: &str, hash: &str) -> AppResult<bool> {
+pub(crate) fn verify_password(password: &str, hash: &str) -> AppResult<bool> {
     let parsed_hash = PasswordHash::new(hash).map_err(|e| {
         tracing::error!(error = %e, "Failed to parse password hash");
         AppError::Internal("Failed to verify password".to_string())
@@ -92,20 +176,240 @@ fn verify_password(password: &str, hash: &str) -> AppResult<bool> {
         .is_ok())
 }
 
+/// Longest `User-Agent` kept for a login session.
+const MAX_DEVICE_LEN: usize = 256;
+
+/// IP address of the client that made a request.
+///
+/// This is the peer address of the connection. Only when the peer is one
+/// of `TRUSTED_PROXIES` is `X-Forwarded-For` believed, since anyone else
+/// can put whatever they like in it.
+pub(crate) fn client_ip(req: &HttpRequest) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+    let forwarded_for: Vec<&str> = req
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|h| h.to_str().ok())
+        .collect();
+    let trusted = &config::get().trusted_proxies;
+    Some(forwarded_client(peer, &forwarded_for.join(","), trusted).to_string())
+}
+
+/// The client behind a chain of trusted proxies: walking `X-Forwarded-For`
+/// from the nearest hop, the first address that is not a trusted proxy.
+/// Entries further along were written by that client and are ignored.
+fn forwarded_client(peer: IpAddr, forwarded_for: &str, trusted: &[IpAddr]) -> IpAddr {
+    let mut client = peer;
+    for hop in forwarded_for.rsplit(',') {
+        if !trusted.contains(&client) {
+            break;
+        }
+        match hop.trim().parse() {
+            Ok(ip) => client = ip,
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+/// Log a failed login in the stable wording fail2ban-style filters match.
+pub(crate) fn log_failed_login(ip: &str, username: &str, failures: u32) {
+    tracing::warn!(
+        ip = %ip,
+        username = %username,
+        failures,
+        "Failed login for user {} from {}",
+        username,
+        ip
+    );
+}
+
+/// Device and IP address of the client that made a request.
+fn client_of(req: &HttpRequest) -> Client {
+    let device = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .filter(|ua| !ua.is_empty())
+        .map(|ua| ua.chars().take(MAX_DEVICE_LEN).collect());
+    Client {
+        device,
+        ip: client_ip(req),
+    }
+}
+
+/// Start a new login session for a user, issuing an access and refresh
+/// token.
+pub(crate) fn issue_tokens(
+    data: &AppState,
+    user: &User,
+    req: &HttpRequest,
+) -> AppResult<jwt::TokenPair> {
+    let (session_id, refresh_token) = data.refresh_token_repo.issue(user.id, client_of(req))?;
+    jwt::create_token_pair(user, session_id, refresh_token)
+}
+
+/// Authenticate with HTTP Basic credentials or a bearer token.
+///
+/// For clients that cannot obtain a token, such as WebDAV mounts and radio
+/// players. Users with two-factor authentication cannot use Basic
+/// credentials. Wrong credentials count as failed logins, and none are
+/// accepted while the client or account is backing off.
+pub(crate) async fn basic_or_bearer_user(
+    req: &HttpRequest,
+    data: &AppState,
+) -> Option<AuthenticatedUser> {
+    let basic = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Basic "));
+    let Some(encoded) = basic else {
+        return AuthenticatedUser::extract(req).await.ok();
+    };
+
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())?;
+    let (username, password) = decoded.split_once(':')?;
+
+    let ip = client_ip(req).unwrap_or_else(|| "unknown".to_string());
+    if let Err(e) = data.login_throttle.check(&ip, username) {
+        tracing::debug!(username = %username, error = %e, "HTTP Basic authentication refused");
+        return None;
+    }
+
+    let user = data
+        .user_repo
+        .find_by_username(username)
+        .ok()
+        .flatten()
+        .filter(|user| !user.disabled)
+        // The account password alone is not enough with two-factor enabled
+        .filter(|user| !user.has_two_factor())
+        .filter(|user| verify_password(password, &user.password_hash).unwrap_or(false));
+    match user {
+        Some(user) => {
+            data.login_throttle.passed(username);
+            Some(AuthenticatedUser::from_user(user))
+        }
+        None => {
+            let failures = data.login_throttle.failed(&ip, username);
+            log_failed_login(&ip, username, failures);
+            None
+        }
+    }
+}
+
+/// `401` response asking for HTTP Basic credentials.
+pub(crate) fn basic_challenge() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, "Basic realm=\"ferrum\""))
+        .finish()
+}
+
+/// How registration currently works.
+#[derive(Debug, Serialize)]
+pub struct RegistrationStatus {
+    pub mode: RegistrationMode,
+    /// Whether the next account is the first admin, which needs the setup
+    /// token.
+    pub setup_required: bool,
+}
+
+/// What let a registration through.
+enum Admission {
+    /// The first admin, with the setup token.
+    Setup(String),
+    /// With an invite code.
+    Invite(String),
+    Open,
+}
+
+impl Admission {
+    fn is_admin(&self) -> bool {
+        matches!(self, Admission::Setup(_))
+    }
+
+    /// Give back the setup token or invite code after the account could
+    /// not be created.
+    fn release(&self, data: &AppState) {
+        match self {
+            Admission::Setup(token) => data.setup_token.restore(token),
+            Admission::Invite(code) => {
+                if let Err(e) = data.invite_repo.release(code) {
+                    tracing::error!(error = %e, "Failed to give back an invite code use");
+                }
+            }
+            Admission::Open => {}
+        }
+    }
+}
+
+/// Check whether the caller may register, using up an invite code or the
+/// setup token if one is needed. Give it back with
+/// [`Admission::release`] if the account is not created.
+fn check_registration(
+    repo: &JsonUserRepository,
+    data: &AppState,
+    body: &RegisterRequest,
+) -> AppResult<Admission> {
+    if repo.count()? == 0 {
+        let token = body.setup_token.as_deref().unwrap_or_default();
+        if !data.setup_token.redeem(token) {
+            tracing::warn!(username = %body.username, "First registration without a valid setup token");
+            return Err(AppError::Forbidden(
+                "A valid setup token is required to create the first account".to_string(),
+            ));
+        }
+        return Ok(Admission::Setup(token.to_string()));
+    }
+
+    match config::get().registration_mode {
+        RegistrationMode::Open => Ok(Admission::Open),
+        RegistrationMode::InviteOnly => {
+            let code = body.invite_code.as_deref().ok_or_else(|| {
+                AppError::Forbidden("An invite code is required to register".to_string())
+            })?;
+            let invite = data
+                .invite_repo
+                .redeem(code)?
+                .ok_or_else(|| AppError::Forbidden("Invalid or expired invite code".to_string()))?;
+            tracing::info!(username = %body.username, invited_by = %invite.created_by, uses = invite.uses, "Invite code used");
+            Ok(Admission::Invite(code.to_string()))
+        }
+        RegistrationMode::Closed => Err(AppError::Forbidden("Registration is closed".to_string())),
+    }
+}
+
+/// Show how registration currently works.
+///
+/// GET /auth/registration
+#[get("/registration")]
+pub async fn registration_status(repo: web::Data<JsonUserRepository>) -> AppResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(RegistrationStatus {
+        mode: config::get().registration_mode,
+        setup_required: repo.count()? == 0,
+    }))
+}
+
 /// Register a new user.
 ///
 /// POST /auth/register
 ///
-/// The first registered user automatically becomes an admin.
+/// The first account needs the setup token printed at startup and becomes
+/// an admin. After that, `REGISTRATION_MODE` decides who may register.
 #[post("/register")]
 pub async fn register(
+    req: HttpRequest,
     repo: web::Data<JsonUserRepository>,
+    data: web::Data<AppState>,
     body: web::Json<RegisterRequest>,
 ) -> AppResult<HttpResponse> {
     // Validate input
-    body.validate().map_err(|e| {
-        AppError::Validation(e.to_string())
-    })?;
+    body.validate()
+        .map_err(|e| AppError::Validation(e.to_string()))?;
 
     // Check if username already exists
     if repo.username_exists(&body.username)? {
@@ -116,17 +420,23 @@ pub async fn register(
     }
 
     // First user becomes admin
-    let is_admin = repo.count()? == 0;
-
-    // Hash password
-    let password_hash = hash_password(&body.password)?;
+    let admission = check_registration(&repo, &data, &body)?;
 
-    // Create user
-    let user = User::new(body.username.clone(), password_hash, is_admin);
-    let user = repo.create(user)?;
+    // Hash password and create user, giving back the code on failure, e.g.
+    // when another registration took the name meanwhile
+    let user = hash_password(&body.password)
+        .map(|hash| User::new(body.username.clone(), hash, admission.is_admin()))
+        .and_then(|user| repo.create(user));
+    let user = match user {
+        Ok(user) => user,
+        Err(e) => {
+            admission.release(&data);
+            return Err(e);
+        }
+    };
 
     // Generate token
-    let token = jwt::create_token_pair(user.id, user.username.clone(), user.is_admin)?;
+    let token = issue_tokens(&data, &user, &req)?;
 
     tracing::info!(
         user_id = %user.id,
@@ -144,28 +454,54 @@ pub async fn register(
 /// Login with username and password.
 ///
 /// POST /auth/login
+///
+/// Users with two-factor authentication get a [`TwoFactorChallenge`]
+/// instead of tokens, to complete with `POST /auth/login/2fa`.
 #[post("/login")]
 pub async fn login(
+    req: HttpRequest,
     repo: web::Data<JsonUserRepository>,
+    data: web::Data<AppState>,
     body: web::Json<LoginRequest>,
 ) -> AppResult<HttpResponse> {
-    // Find user
-    let user = repo
-        .find_by_username(&body.username)?
-        .ok_or_else(|| AppError::invalid_credentials())?;
+    // Refuse while the client or account is backing off
+    let ip = client_ip(&req).unwrap_or_else(|| "unknown".to_string());
+    let failures = data.login_throttle.attempt(&ip, &body.username)?;
 
-    // Verify password
-    if !verify_password(&body.password, &user.password_hash)? {
+    // Find user and verify password. Disabled accounts are refused like
+    // wrong passwords, so a guess cannot tell that the password was right.
+    let user = match repo.find_by_username(&body.username)? {
+        Some(user) if verify_password(&body.password, &user.password_hash)? && !user.disabled => {
+            Some(user)
+        }
+        _ => None,
+    };
+    let Some(user) = user else {
+        log_failed_login(&ip, &body.username, failures);
         return Err(AppError::invalid_credentials());
+    };
+
+    // The password only starts a challenge; failures keep counting until
+    // the code is given too
+    if user.has_two_factor() {
+        tracing::info!(username = %user.username, "Password accepted, waiting for two-factor code");
+        return Ok(HttpResponse::Ok().json(TwoFactorChallenge {
+            two_factor_required: true,
+            challenge_token: data.login_challenges.issue(user.id),
+            expires_in: CHALLENGE_LIFETIME.num_seconds(),
+        }));
     }
 
+    data.login_throttle.succeeded(&ip, &body.username);
+
     // Update last login
-    let mut updated_user = user.clone();
-    updated_user.last_login = Some(Utc::now());
-    let _ = repo.update(updated_user);
+    let _ = repo.modify(user.id, |user| {
+        user.last_login = Some(Utc::now());
+        Ok(())
+    });
 
     // Generate token
-    let token = jwt::create_token_pair(user.id, user.username.clone(), user.is_admin)?;
+    let token = issue_tokens(&data, &user, &req)?;
 
     tracing::info!(user_id = %user.id, username = %user.username, "User logged in");
 
@@ -175,6 +511,103 @@ pub async fn login(
     }))
 }
 
+/// Complete a two-factor login with a TOTP or recovery code.
+///
+/// POST /auth/login/2fa
+///
+/// Each challenge allows a few wrong codes; wrong codes also count as
+/// failed logins.
+#[post("/login/2fa")]
+pub async fn login_two_factor(
+    req: HttpRequest,
+    repo: web::Data<JsonUserRepository>,
+    data: web::Data<AppState>,
+    body: web::Json<TwoFactorLoginRequest>,
+) -> AppResult<HttpResponse> {
+    let ip = client_ip(&req).unwrap_or_else(|| "unknown".to_string());
+
+    let user = data
+        .login_challenges
+        .complete(&body.challenge_token, |user_id| {
+            // The used code is saved together with the login time
+            let mut failures = 0;
+            let mut verified = false;
+            let user = repo
+                .modify(user_id, |user| {
+                    if user.disabled {
+                        return Err(AppError::account_disabled());
+                    }
+                    failures = data.login_throttle.attempt(&ip, &user.username)?;
+                    verified = two_factor::verify_code(&data, user, &body.code)?;
+                    if verified {
+                        user.last_login = Some(Utc::now());
+                    }
+                    Ok(())
+                })
+                .map_err(|e| match e {
+                    AppError::NotFound(_) => AppError::invalid_credentials(),
+                    e => e,
+                })?;
+
+            if !verified {
+                log_failed_login(&ip, &user.username, failures);
+                return Ok(None);
+            }
+            data.login_throttle.succeeded(&ip, &user.username);
+            Ok(Some(user))
+        })?;
+
+    let recovery_codes_left = user
+        .two_factor
+        .as_ref()
+        .map_or(0, |t| t.recovery_codes.len());
+
+    let token = issue_tokens(&data, &user, &req)?;
+
+    tracing::info!(
+        user_id = %user.id,
+        username = %user.username,
+        recovery_codes_left,
+        "User logged in with two-factor code"
+    );
+
+    Ok(HttpResponse::Ok().json(AuthResponse {
+        user: UserResponse::from(&user),
+        token,
+    }))
+}
+
+/// Exchange a refresh token for a new token pair.
+///
+/// POST /auth/refresh
+///
+/// The refresh token is replaced on every use. Reusing a replaced token
+/// revokes every token descending from the same login.
+#[post("/refresh")]
+pub async fn refresh(
+    req: HttpRequest,
+    repo: web::Data<JsonUserRepository>,
+    data: web::Data<AppState>,
+    body: web::Json<RefreshRequest>,
+) -> AppResult<HttpResponse> {
+    let (session, refresh_token) = data
+        .refresh_token_repo
+        .rotate(&body.refresh_token, client_ip(&req))?;
+
+    let user = repo
+        .find_by_id(session.user_id)?
+        .ok_or_else(AppError::invalid_token)?;
+    if user.disabled {
+        return Err(AppError::account_disabled());
+    }
+
+    let token = jwt::create_token_pair(&user, session.id, refresh_token)?;
+
+    tracing::debug!(user_id = %user.id, username = %user.username, "Tokens refreshed");
+
+    Ok(HttpResponse::Ok().json(token))
+}
+
 /// Get current user information.
 ///
 /// GET /auth/me
@@ -192,12 +625,259 @@ pub async fn me(
     Ok(HttpResponse::Ok().json(UserResponse::from(&user)))
 }
 
+/// Look up the current user and check their password.
+pub(crate) fn confirm_password(
+    repo: &JsonUserRepository,
+    user: &AuthenticatedUser,
+    password: &str,
+) -> AppResult<User> {
+    let account = repo
+        .find_by_id(user.id)?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    if !verify_password(password, &account.password_hash)? {
+        tracing::warn!(username = %account.username, "Password confirmation failed");
+        return Err(AppError::Forbidden(
+            "Current password is incorrect".to_string(),
+        ));
+    }
+
+    Ok(account)
+}
+
+/// Change the current user's password.
+///
+/// POST /auth/password
+///
+/// Requires authentication and the current password. All existing tokens
+/// are signed out; the response carries a fresh one.
+#[post("/password")]
+pub async fn change_password(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    repo: web::Data<JsonUserRepository>,
+    data: web::Data<AppState>,
+    body: web::Json<ChangePasswordRequest>,
+) -> AppResult<HttpResponse> {
+    body.validate()
+        .map_err(|e| AppError::Validation(e.to_string()))?;
+
+    let account = confirm_password(&repo, &user, &body.current_password)?;
+    let password_hash = hash_password(&body.new_password)?;
+    let account = repo.modify(account.id, |current| {
+        // Changed or disabled while the new password was being hashed
+        if current.password_hash != account.password_hash {
+            return Err(AppError::Conflict(
+                "Password was changed meanwhile, try again".to_string(),
+            ));
+        }
+        if current.disabled {
+            return Err(AppError::account_disabled());
+        }
+        current.set_password(password_hash);
+        Ok(())
+    })?;
+
+    data.refresh_token_repo.revoke_by_user(account.id)?;
+    let token = issue_tokens(&data, &account, &req)?;
+
+    tracing::info!(user_id = %account.id, username = %account.username, "Password changed");
+
+    Ok(HttpResponse::Ok().json(AuthResponse {
+        user: UserResponse::from(&account),
+        token,
+    }))
+}
+
+/// Delete the current user's account with their playlists, stars,
+/// ratings, history and settings.
+///
+/// DELETE /auth/account
+///
+/// Requires authentication and the current password. The last admin
+/// cannot delete their account.
+#[delete("/account")]
+pub async fn delete_account(
+    user: AuthenticatedUser,
+    repo: web::Data<JsonUserRepository>,
+    data: web::Data<AppState>,
+    body: web::Json<DeleteAccountRequest>,
+) -> AppResult<HttpResponse> {
+    let account = confirm_password(&repo, &user, &body.password)?;
+    delete_user(&data, account.id)?;
+
+    tracing::info!(user_id = %account.id, username = %account.username, "Account deleted");
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Log out, ending the login session of the access token.
+///
+/// POST /auth/logout
+///
+/// Requires authentication. The session's access and refresh tokens stop
+/// working.
+#[post("/logout")]
+pub async fn logout(user: AuthenticatedUser, data: web::Data<AppState>) -> AppResult<HttpResponse> {
+    if let Some(session_id) = user.session_id {
+        data.refresh_token_repo.revoke(session_id)?;
+    }
+
+    tracing::info!(user_id = %user.id, username = %user.username, "User logged out");
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// List the current user's login sessions.
+///
+/// GET /auth/sessions
+///
+/// Requires authentication.
+#[get("/sessions")]
+pub async fn list_sessions(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> AppResult<HttpResponse> {
+    let sessions: Vec<SessionResponse> = data
+        .refresh_token_repo
+        .list_by_user(user.id)?
+        .into_iter()
+        .map(|session| SessionResponse::new(session, user.session_id))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+/// Revoke one of the current user's login sessions.
+///
+/// DELETE /auth/sessions/{id}
+///
+/// Requires authentication.
+#[delete("/sessions/{id}")]
+pub async fn revoke_session(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<uuid::Uuid>,
+) -> AppResult<HttpResponse> {
+    let session = data
+        .refresh_token_repo
+        .find(*path)?
+        .filter(|session| session.user_id == user.id)
+        .ok_or_else(|| AppError::NotFound(format!("Session not found: {}", path)))?;
+    data.refresh_token_repo.revoke(session.id)?;
+
+    tracing::info!(user_id = %user.id, session = %session.id, "Session revoked");
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
 /// Configure auth routes.
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/auth")
+            .service(registration_status)
             .service(register)
             .service(login)
-            .service(me),
+            .service(login_two_factor)
+            .service(refresh)
+            .service(logout)
+            .service(list_sessions)
+            .service(revoke_session)
+            .service(me)
+            .service(change_password)
+            .service(delete_account)
+            .configure(two_factor::configure),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_refused_registration_gives_back_the_setup_token() {
+        let dir = tempdir().unwrap();
+        let state = AppState::for_tests(dir.path());
+        let token = state.setup_token.generate();
+        let body = RegisterRequest {
+            username: "root".to_string(),
+            password: "password123".to_string(),
+            invite_code: None,
+            setup_token: Some(token),
+        };
+
+        let admission = check_registration(&state.user_repo, &state, &body).unwrap();
+        assert!(admission.is_admin());
+        assert!(!state.setup_token.is_pending());
+
+        // e.g. another registration took the name before this one was saved
+        admission.release(&state);
+        let admission = check_registration(&state.user_repo, &state, &body).unwrap();
+        assert!(admission.is_admin());
+    }
+
+    #[test]
+    fn test_forwarded_for_is_only_believed_from_trusted_proxies() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let proxy = ip("10.0.0.1");
+        let trusted = [proxy, ip("10.0.0.2")];
+
+        // A client cannot pick its own address
+        assert_eq!(
+            forwarded_client(ip("203.0.113.7"), "198.51.100.1", &trusted),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            forwarded_client(ip("203.0.113.7"), "", &[]),
+            ip("203.0.113.7")
+        );
+
+        // Through proxies, the first untrusted hop counts; what the client
+        // sent along is ignored
+        assert_eq!(
+            forwarded_client(proxy, "198.51.100.1, 203.0.113.7", &trusted),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            forwarded_client(proxy, "198.51.100.1, 203.0.113.7, 10.0.0.2", &trusted),
+            ip("203.0.113.7")
+        );
+        assert_eq!(forwarded_client(proxy, "unknown", &trusted), proxy);
+        assert_eq!(forwarded_client(proxy, "", &trusted), proxy);
+    }
+
+    #[actix_web::test]
+    async fn test_disabled_account_login_looks_like_wrong_password() {
+        let dir = tempdir().unwrap();
+        let state = AppState::for_tests(dir.path());
+        let password_hash = hash_password("password123").unwrap();
+        let mut user = User::new("alice".to_string(), password_hash, false);
+        user.disabled = true;
+        state.user_repo.create(user).unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .app_data(web::Data::from(state.user_repo.clone()))
+                .configure(configure),
+        )
+        .await;
+
+        let mut bodies = Vec::new();
+        for password in ["password123", "guess"] {
+            let request = TestRequest::post()
+                .uri("/auth/login")
+                .set_json(serde_json::json!({ "username": "alice", "password": password }))
+                .to_request();
+            let response = call_service(&app, request).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            bodies.push(read_body(response).await);
+        }
+        assert_eq!(bodies[0], bodies[1]);
+        // The right password counted as a failure too
+        assert_eq!(state.login_throttle.failed("unknown", "alice"), 3);
+    }
+}
@@ -0,0 +1,330 @@
+//! WebSocket event channel.
+//!
+//! Pushes library, playlist and now-playing events to connected clients so
+//! they do not need to poll. See [`crate::events`] for the envelope and the
+//! resume protocol.
+//!
+//! Players that connect with a device name also register as playback
+//! sessions (see [`crate::sessions`]): they report their state with
+//! `session.state` messages and receive remote control commands as
+//! `session.command` messages.
+//!
+//! Credentials are checked again on every keep-alive ping, and the
+//! connection is closed once its session is logged out or the account is
+//! deleted, disabled or loses admin rights.
+
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{get, web, FromRequest, HttpRequest, HttpResponse};
+use actix_ws::{CloseCode, CloseReason, Closed, Message, MessageStream};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc};
+use uuid::Uuid;
+
+use crate::auth::middleware::authenticate_token;
+use crate::auth::{ApiKeyRepository, AuthenticatedUser, RefreshTokenRepository, UserRepository};
+use crate::error::{AppError, AppResult};
+use crate::events::{kind, Audience, Event};
+use crate::models::AppState;
+use crate::sessions::{Command, PlaybackState};
+
+/// Interval between keep-alive pings, which is also how often credentials
+/// are checked again.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Header identifying the client that made a request, so that its own
+/// changes are not echoed back on its event channel.
+const CLIENT_ID_HEADER: &str = "X-Client-Id";
+
+/// WebSocket subprotocol announcing that the next offered protocol is an
+/// access token, for clients that cannot set headers on WebSockets.
+const TOKEN_PROTOCOL: &str = "bearer";
+
+/// Longest accepted device name.
+const MAX_DEVICE_NAME_LEN: usize = 128;
+
+/// Client ID of a request, from the `X-Client-Id` header.
+pub(crate) fn client_id(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(CLIENT_ID_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .filter(|id| !id.is_empty())
+}
+
+/// Access token offered as a subprotocol, as in
+/// `Sec-WebSocket-Protocol: bearer, <token>`. Unlike a query parameter it
+/// does not end up in access logs.
+fn protocol_token(req: &HttpRequest) -> Option<&str> {
+    let protocols = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)?
+        .to_str()
+        .ok()?;
+    let mut protocols = protocols.split(',').map(str::trim);
+    protocols.find(|p| *p == TOKEN_PROTOCOL)?;
+    protocols.next().filter(|token| !token.is_empty())
+}
+
+/// Query parameters for connecting.
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    /// Stream ID from the last `connected` message, to resume.
+    pub stream: Option<String>,
+    /// Last sequence number received, to resume.
+    pub since: Option<u64>,
+    /// This client's ID; events it caused are not sent back to it.
+    pub client: Option<String>,
+    /// Device name; registers the connection as a playback session.
+    pub device: Option<String>,
+}
+
+/// Messages sent by clients.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", content = "data")]
+enum ClientMessage {
+    /// A player reporting its playback state.
+    #[serde(rename = "session.state")]
+    State(PlaybackState),
+}
+
+fn serialize(event: &Event) -> String {
+    serde_json::to_string(event).unwrap_or_default()
+}
+
+/// A connection registered as a playback session.
+struct Player {
+    id: Uuid,
+    commands: mpsc::UnboundedReceiver<Command>,
+}
+
+/// Wait for the next command of a player; never completes for other
+/// connections.
+async fn next_command(player: &mut Option<Player>) -> Option<Command> {
+    match player {
+        Some(player) => player.commands.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Everything a connection needs once the handshake is done.
+struct Connection {
+    data: web::Data<AppState>,
+    user_id: Uuid,
+    is_admin: bool,
+    session_id: Option<Uuid>,
+    api_key_id: Option<Uuid>,
+    client: Option<String>,
+    player: Option<Player>,
+    missed: Option<Vec<Event>>,
+    receiver: broadcast::Receiver<Event>,
+}
+
+impl Connection {
+    /// Handle a text message from the client.
+    fn receive(&self, text: &str) {
+        let Some(player) = &self.player else {
+            return;
+        };
+        match serde_json::from_str::<ClientMessage>(text) {
+            Ok(ClientMessage::State(state)) => {
+                if let Some(session) = self.data.sessions.update_state(player.id, state) {
+                    self.data.events.publish(
+                        kind::SESSION_UPDATED,
+                        Audience::User(self.user_id),
+                        self.client.as_deref(),
+                        json!({ "session": session }),
+                    );
+                }
+            }
+            Err(e) => tracing::debug!(error = %e, "Ignoring invalid client message"),
+        }
+    }
+
+    /// Why the credentials the connection was opened with no longer hold,
+    /// if they don't.
+    fn lost_access(&self) -> Option<&'static str> {
+        if let Some(session_id) = self.session_id {
+            if self.data.refresh_token_repo.is_revoked(session_id) {
+                return Some("Session ended");
+            }
+        }
+        if let Some(key_id) = self.api_key_id {
+            let keys = self.data.api_key_repo.list_by_user(self.user_id).ok()?;
+            if !keys.iter().any(|k| k.id == key_id && !k.is_expired()) {
+                return Some("API key revoked");
+            }
+        }
+
+        match self.data.user_repo.find_by_id(self.user_id).ok()? {
+            None => Some("Account deleted"),
+            Some(account) if account.disabled => Some("Account disabled"),
+            Some(account) if self.is_admin && !account.is_admin => Some("Admin rights revoked"),
+            Some(_) => None,
+        }
+    }
+
+    /// Relay events and commands until either side closes, returning the
+    /// close reason to send.
+    async fn run(
+        &mut self,
+        session: &mut actix_ws::Session,
+        messages: &mut MessageStream,
+    ) -> Result<Option<CloseReason>, Closed> {
+        let is_for = |event: &Event| event.is_for(self.user_id, self.client.as_deref());
+
+        for event in self.missed.iter().flatten().filter(|e| is_for(e)) {
+            session.text(serialize(event)).await?;
+        }
+
+        let mut ping = tokio::time::interval(PING_INTERVAL);
+        ping.tick().await;
+        loop {
+            tokio::select! {
+                event = self.receiver.recv() => match event {
+                    Ok(event) if is_for(&event) => session.text(serialize(&event)).await?,
+                    Ok(_) => {}
+                    // Too far behind to catch up live; the client reconnects
+                    // with `since` and gets the rest from history.
+                    Err(RecvError::Lagged(_)) => {
+                        return Ok(Some(CloseReason {
+                            code: CloseCode::Again,
+                            description: Some("Lagging behind, reconnect to resume".to_string()),
+                        }));
+                    }
+                    Err(RecvError::Closed) => return Ok(None),
+                },
+                Some(command) = next_command(&mut self.player) => {
+                    let message = json!({
+                        "type": "session.command",
+                        "timestamp": Utc::now(),
+                        "data": command,
+                    });
+                    session.text(message.to_string()).await?;
+                }
+                message = messages.recv() => match message {
+                    Some(Ok(Message::Text(text))) => self.receive(&text),
+                    Some(Ok(Message::Ping(bytes))) => session.pong(&bytes).await?,
+                    Some(Ok(Message::Close(reason))) => return Ok(reason),
+                    Some(Ok(_)) => {}
+                    Some(Err(_)) | None => return Ok(None),
+                },
+                _ = ping.tick() => {
+                    if let Some(reason) = self.lost_access() {
+                        return Ok(Some(CloseReason {
+                            code: CloseCode::Policy,
+                            description: Some(reason.to_string()),
+                        }));
+                    }
+                    session.ping(b"").await?
+                }
+            }
+        }
+    }
+}
+
+/// Open the event channel.
+///
+/// GET /api/events (WebSocket)
+///
+/// The first message is `connected`, carrying the stream ID and whether the
+/// requested resume succeeded, and the session ID if `device` was given.
+/// Missed events follow, then live events.
+#[get("/api/events")]
+pub async fn events(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    query: web::Query<EventsQuery>,
+    body: web::Payload,
+) -> AppResult<HttpResponse> {
+    let query = query.into_inner();
+    let token = protocol_token(&req);
+    let user = match token {
+        Some(token) => authenticate_token(&req, token)?,
+        None => AuthenticatedUser::extract(&req).await?,
+    };
+    if let Some(device) = &query.device {
+        if device.is_empty() || device.chars().count() > MAX_DEVICE_NAME_LEN {
+            return Err(AppError::Validation(format!(
+                "Device name must be 1-{} characters",
+                MAX_DEVICE_NAME_LEN
+            )));
+        }
+    }
+
+    let (mut response, mut session, mut messages) = actix_ws::handle(&req, body)
+        .map_err(|e| AppError::BadRequest(format!("WebSocket handshake failed: {}", e)))?;
+    // Browsers drop the connection unless an offered protocol is accepted
+    if token.is_some() {
+        response.headers_mut().insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(TOKEN_PROTOCOL),
+        );
+    }
+
+    let subscription = data.events.subscribe(query.stream.as_deref(), query.since);
+    let client = query.client;
+    let player = query.device.map(|device| {
+        let (player, commands) = data.sessions.register(user.id, device, client.clone());
+        data.events.publish(
+            kind::SESSION_STARTED,
+            Audience::User(user.id),
+            client.as_deref(),
+            json!({ "session": player }),
+        );
+        tracing::debug!(username = %user.username, session = %player.id, "Playback session started");
+        Player {
+            id: player.id,
+            commands,
+        }
+    });
+    let connected = json!({
+        "seq": subscription.seq,
+        "type": "connected",
+        "timestamp": Utc::now(),
+        "data": {
+            "stream": subscription.stream,
+            "resumed": subscription.missed.is_some(),
+            "session": player.as_ref().map(|p| p.id),
+        },
+    });
+
+    let mut connection = Connection {
+        data: data.clone(),
+        user_id: user.id,
+        is_admin: user.is_admin,
+        session_id: user.session_id,
+        api_key_id: user.api_key_id,
+        client,
+        player,
+        missed: subscription.missed,
+        receiver: subscription.receiver,
+    };
+    actix_web::rt::spawn(async move {
+        if session.text(connected.to_string()).await.is_ok() {
+            if let Ok(reason) = connection.run(&mut session, &mut messages).await {
+                let _ = session.close(reason).await;
+            }
+        }
+
+        if let Some(player) = connection.player {
+            data.sessions.unregister(player.id);
+            data.events.publish(
+                kind::SESSION_ENDED,
+                Audience::User(user.id),
+                None,
+                json!({ "id": player.id }),
+            );
+            tracing::debug!(username = %user.username, session = %player.id, "Playback session ended");
+        }
+    });
+
+    Ok(response)
+}
+
+/// Configure event channel routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(events);
+}
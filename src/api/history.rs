@@ -0,0 +1,231 @@
+//! Play history and scrobble API endpoints.
+
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::api::events::client_id;
+use crate::api::music::annotate_songs;
+use crate::auth::AuthenticatedUser;
+use crate::error::{AppError, AppResult};
+use crate::events::{kind, Audience};
+use crate::models::{AppState, PaginatedResponse, SongMetadata};
+use crate::userdata::{HistoryRepository, NowPlaying, PlayRecord};
+
+/// How far in the future a scrobble timestamp may be (clock skew allowance).
+const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// Request body for a scrobble.
+#[derive(Debug, Deserialize)]
+pub struct ScrobbleRequest {
+    /// Played song ID.
+    pub song_id: String,
+    /// When playback started (defaults to now).
+    pub timestamp: Option<DateTime<Utc>>,
+    /// `true` to record a finished play, `false` for a "now playing" update.
+    #[serde(default = "default_submission")]
+    pub submission: bool,
+}
+
+fn default_submission() -> bool {
+    true
+}
+
+/// Query parameters for listing history.
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    /// Page number (1-indexed).
+    #[serde(default = "default_page")]
+    pub page: usize,
+    /// Items per page (max 100).
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
+}
+
+fn default_page() -> usize {
+    1
+}
+
+fn default_per_page() -> usize {
+    50
+}
+
+/// A history entry with the song resolved.
+#[derive(Debug, Serialize)]
+pub struct HistoryEntry {
+    pub played_at: DateTime<Utc>,
+    /// `None` if the song has since been removed from the library.
+    pub song: Option<SongMetadata>,
+    pub song_id: String,
+}
+
+/// The current user's now-playing song.
+#[derive(Debug, Serialize)]
+pub struct NowPlayingResponse {
+    pub started_at: DateTime<Utc>,
+    pub song: SongMetadata,
+}
+
+/// Record a finished play and queue it for forwarding.
+pub(crate) fn record_play(
+    data: &AppState,
+    user_id: Uuid,
+    song: &SongMetadata,
+    played_at: DateTime<Utc>,
+) -> AppResult<PlayRecord> {
+    let play = data
+        .history_repo
+        .record(PlayRecord::new(user_id, song.id.clone(), played_at))?;
+
+    // The play is already recorded locally; forwarding problems must not fail it
+    if let Err(e) = data.scrobble_forwarder.enqueue(user_id, song, played_at) {
+        tracing::error!(user_id = %user_id, error = %e, "Failed to queue scrobble for forwarding");
+    }
+
+    Ok(play)
+}
+
+/// Set the user's now-playing song and forward it.
+pub(crate) fn set_now_playing(
+    data: &AppState,
+    user_id: Uuid,
+    song: &SongMetadata,
+    started_at: DateTime<Utc>,
+    client_id: Option<&str>,
+) -> NowPlaying {
+    let entry = NowPlaying {
+        song_id: song.id.clone(),
+        started_at,
+    };
+    data.history_repo.set_now_playing(user_id, entry.clone());
+    data.scrobble_forwarder.send_now_playing(user_id, song);
+    data.events.publish(
+        kind::NOW_PLAYING,
+        Audience::User(user_id),
+        client_id,
+        json!({ "song": song, "started_at": started_at, "client": client_id }),
+    );
+    entry
+}
+
+/// Record a play or a "now playing" update.
+///
+/// POST /api/music/scrobble
+#[post("/api/music/scrobble")]
+pub async fn scrobble(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    body: web::Json<ScrobbleRequest>,
+) -> AppResult<HttpResponse> {
+    let body = body.into_inner();
+    let now = Utc::now();
+    let timestamp = body.timestamp.unwrap_or(now);
+
+    if timestamp > now + Duration::seconds(MAX_CLOCK_SKEW_SECS) {
+        return Err(AppError::Validation(
+            "Timestamp cannot be in the future".to_string(),
+        ));
+    }
+
+    let index = data.library.index()?;
+    let Some(song) = index.song(&body.song_id) else {
+        return Err(AppError::NotFound(format!(
+            "Song not found: {}",
+            body.song_id
+        )));
+    };
+
+    if !body.submission {
+        let entry = set_now_playing(&data, user.id, song, timestamp, client_id(&req));
+        return Ok(HttpResponse::Ok().json(entry));
+    }
+
+    let play = record_play(&data, user.id, song, timestamp)?;
+    Ok(HttpResponse::Created().json(play))
+}
+
+/// List the current user's play history, newest first.
+///
+/// GET /api/music/history
+#[get("/api/music/history")]
+pub async fn list_history(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    query: web::Query<HistoryQuery>,
+) -> AppResult<HttpResponse> {
+    let per_page = query.per_page.clamp(1, 100);
+    let page = query.page.max(1);
+
+    let index = data.library.index()?;
+    let plays = data.history_repo.list_by_user(user.id)?;
+    let total = plays.len();
+
+    let page_plays: Vec<PlayRecord> = plays
+        .into_iter()
+        .rev()
+        .skip((page - 1) * per_page)
+        .take(per_page)
+        .collect();
+
+    let mut songs: Vec<SongMetadata> = page_plays
+        .iter()
+        .filter_map(|p| index.song(&p.song_id).cloned())
+        .collect();
+    annotate_songs(&data, user.id, &mut songs)?;
+
+    let items = page_plays
+        .into_iter()
+        .map(|play| HistoryEntry {
+            played_at: play.played_at,
+            song: songs.iter().find(|s| s.id == play.song_id).cloned(),
+            song_id: play.song_id,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::from_vec(items, page, per_page, total)))
+}
+
+/// Get the current user's now-playing song.
+///
+/// GET /api/music/now-playing
+///
+/// Returns 204 if nothing is playing or the song should have ended.
+#[get("/api/music/now-playing")]
+pub async fn get_now_playing(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> AppResult<HttpResponse> {
+    let Some(now_playing) = data.history_repo.now_playing(user.id) else {
+        return Ok(HttpResponse::NoContent().finish());
+    };
+
+    let index = data.library.index()?;
+    let Some(song) = index.song(&now_playing.song_id) else {
+        return Ok(HttpResponse::NoContent().finish());
+    };
+
+    // Allow a minute of slack for pauses before treating the entry as stale
+    let ends_at =
+        now_playing.started_at + Duration::seconds(i64::from(song.duration.unwrap_or(0)) + 60);
+    if ends_at < Utc::now() {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+
+    let mut songs = vec![song.clone()];
+    annotate_songs(&data, user.id, &mut songs)?;
+
+    Ok(HttpResponse::Ok().json(NowPlayingResponse {
+        started_at: now_playing.started_at,
+        song: songs.remove(0),
+    }))
+}
+
+/// Configure history routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(scrobble)
+        .service(list_history)
+        .service(get_now_playing);
+}
@@ -0,0 +1,86 @@
+//! Admin invite code endpoints.
+//!
+//! Invite codes let people register when `REGISTRATION_MODE` is
+//! `invite-only`. All endpoints require an admin.
+
+use actix_web::{delete, get, post, web, HttpResponse};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::auth::invite_repository::Invite;
+use crate::auth::{AuthenticatedUser, InviteRepository};
+use crate::error::{AppError, AppResult};
+use crate::models::AppState;
+
+/// Request body for creating an invite code.
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateInviteRequest {
+    /// How many accounts the code may create (default 1).
+    #[validate(range(min = 1, max = 1000, message = "Uses must be 1-1000"))]
+    pub max_uses: Option<u32>,
+    /// Hours until the code expires; never when omitted.
+    #[validate(range(min = 1, max = 8760, message = "Lifetime must be 1-8760 hours"))]
+    pub expires_in_hours: Option<i64>,
+}
+
+/// List invite codes, including used up and expired ones.
+///
+/// GET /api/admin/invites
+#[get("/api/admin/invites")]
+pub async fn list_invites(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> AppResult<HttpResponse> {
+    user.require_admin()?;
+    Ok(HttpResponse::Ok().json(data.invite_repo.list_all()?))
+}
+
+/// Create an invite code.
+///
+/// POST /api/admin/invites
+#[post("/api/admin/invites")]
+pub async fn create_invite(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    body: web::Json<CreateInviteRequest>,
+) -> AppResult<HttpResponse> {
+    user.require_admin()?;
+    body.validate()
+        .map_err(|e| AppError::Validation(e.to_string()))?;
+
+    let expires_at = body
+        .expires_in_hours
+        .map(|hours| Utc::now() + Duration::hours(hours));
+    let invite = Invite::new(user.id, body.max_uses.unwrap_or(1), expires_at);
+    let invite = data.invite_repo.create(invite)?;
+
+    tracing::info!(admin = %user.username, max_uses = invite.max_uses, "Invite code created");
+    Ok(HttpResponse::Created().json(invite))
+}
+
+/// Delete an invite code.
+///
+/// DELETE /api/admin/invites/{code}
+#[delete("/api/admin/invites/{code}")]
+pub async fn delete_invite(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    user.require_admin()?;
+
+    if !data.invite_repo.delete(&path)? {
+        return Err(AppError::NotFound(format!("Invite not found: {}", path)));
+    }
+
+    tracing::info!(admin = %user.username, "Invite code deleted");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Configure invite code routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_invites)
+        .service(create_invite)
+        .service(delete_invite);
+}
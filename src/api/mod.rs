@@ -1,5 +1,22 @@
 //! API endpoints.
 
+pub mod annotations;
+pub mod api_keys;
 pub mod auth;
+pub mod events;
 pub mod health;
+pub mod history;
+pub mod invites;
 pub mod music;
+pub mod playlists;
+pub mod queue;
+pub mod radio;
+pub mod rooms;
+pub mod scrobbling;
+pub mod sessions;
+pub mod shares;
+pub mod stats;
+pub mod subsonic;
+pub mod two_factor;
+pub mod users;
+pub mod webdav;
@@ -1,24 +1,28 @@
 //! Music API endpoints.
 
 use actix_files::NamedFile;
-use actix_web::{get, http::header, web, HttpRequest, HttpResponse};
-use lofty::file::{AudioFile, TaggedFileExt};
+use actix_web::{get, http::header, web, FromRequest, HttpRequest, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use lofty::file::TaggedFileExt;
 use lofty::picture::PictureType;
-use lofty::prelude::Accessor;
 use lofty::read_from_path;
-use std::fs;
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
 
-use crate::auth::AuthenticatedUser;
+use crate::auth::{AuthenticatedUser, Grantee, UserRepository};
+use crate::config;
 use crate::error::{AppError, AppResult};
+use crate::library::playlist_file::encode_path_segment;
 use crate::models::{
     AppState, ListSongsQuery, PaginatedResponse, SongMetadata, SortField, SortOrder,
 };
+use crate::userdata::HistoryRepository;
 
 /// Validate and sanitize a filename to prevent path traversal attacks.
 ///
 /// Returns an error if the filename contains path traversal sequences.
-fn sanitize_filename(filename: &str) -> AppResult<&str> {
+pub(crate) fn sanitize_filename(filename: &str) -> AppResult<&str> {
     // Reject empty filenames
     if filename.is_empty() {
         return Err(AppError::BadRequest("Filename cannot be empty".to_string()));
@@ -38,61 +42,80 @@ fn sanitize_filename(filename: &str) -> AppResult<&str> {
     Ok(filename)
 }
 
-/// Extract song metadata from an audio file.
-fn extract_metadata(path: &Path) -> Option<SongMetadata> {
-    let tagged_file = read_from_path(path).ok()?;
-    let tag = tagged_file.first_tag();
-    let properties = tagged_file.properties();
-
-    let filename = path.file_name()?.to_string_lossy().into_owned();
-    let extension = path
-        .extension()
-        .map(|e| e.to_string_lossy().to_lowercase())
-        .unwrap_or_else(|| "unknown".to_string());
-
-    let has_cover = tag
-        .map(|t| {
-            t.pictures()
-                .iter()
-                .any(|p| p.pic_type() == PictureType::CoverFront)
-        })
-        .unwrap_or(false);
-
-    Some(SongMetadata {
-        id: SongMetadata::generate_id(path),
-        title: tag
-            .and_then(|t| t.title())
-            .map(|s| s.to_string())
-            .unwrap_or_else(|| filename.clone()),
-        artist: tag
-            .and_then(|t| t.artist())
-            .map(|s| s.to_string())
-            .unwrap_or_else(|| "Unknown Artist".to_string()),
-        album: tag
-            .and_then(|t| t.album())
-            .map(|s| s.to_string())
-            .unwrap_or_else(|| "Unknown Album".to_string()),
-        duration: Some(properties.duration().as_secs() as u32),
-        track_number: tag.and_then(|t| t.track()),
-        year: tag.and_then(|t| t.year()).map(|y| y as i32),
-        genre: tag.and_then(|t| t.genre()).map(|s| s.to_string()),
-        format: extension,
-        file: filename,
-        has_cover,
-    })
+/// Resolve a filename to a file inside the music folder.
+///
+/// Rejects traversal attempts and anything whose canonical path escapes the
+/// music folder (e.g. through symlinks).
+pub(crate) fn resolve_music_file(music_folder: &Path, filename: &str) -> AppResult<PathBuf> {
+    let filename = sanitize_filename(filename)?;
+    let full_path = music_folder.join(filename);
+
+    // Check file exists
+    if !full_path.exists() {
+        return Err(AppError::song_not_found(filename));
+    }
+
+    // Verify the resolved path is still within music folder (extra safety)
+    let canonical = full_path
+        .canonicalize()
+        .map_err(|_| AppError::song_not_found(filename))?;
+    let music_canonical = music_folder
+        .canonicalize()
+        .map_err(|e| AppError::Internal(format!("Music folder error: {}", e)))?;
+
+    if !canonical.starts_with(&music_canonical) {
+        tracing::warn!(
+            requested = %canonical.display(),
+            music_folder = %music_canonical.display(),
+            "Path escape attempt blocked"
+        );
+        return Err(AppError::path_traversal());
+    }
+
+    Ok(full_path)
 }
 
-/// Supported audio file extensions.
-const SUPPORTED_EXTENSIONS: &[&str] = &[
-    "mp3", "flac", "ogg", "wav", "m4a", "aac", "wma", "opus", "aiff", "ape",
-];
-
-/// Check if a file has a supported audio extension.
-fn is_audio_file(path: &Path) -> bool {
-    path.extension()
-        .and_then(|e| e.to_str())
-        .map(|e| SUPPORTED_EXTENSIONS.contains(&e.to_lowercase().as_str()))
-        .unwrap_or(false)
+/// Read the embedded cover art of an audio file, returning its MIME type and bytes.
+pub(crate) fn read_cover(file_path: &Path) -> AppResult<(String, Vec<u8>)> {
+    let name = file_path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tagged_file = read_from_path(file_path).map_err(|_| AppError::song_not_found(&name))?;
+
+    let tag = tagged_file
+        .first_tag()
+        .ok_or_else(|| AppError::NotFound("No cover art available".to_string()))?;
+
+    // Find front cover or any picture
+    let picture = tag
+        .pictures()
+        .iter()
+        .find(|p| p.pic_type() == PictureType::CoverFront)
+        .or_else(|| tag.pictures().first())
+        .ok_or_else(|| AppError::NotFound("No cover art available".to_string()))?;
+
+    let mime = picture
+        .mime_type()
+        .map(|m| m.as_str())
+        .unwrap_or("image/jpeg");
+
+    Ok((mime.to_string(), picture.data().to_vec()))
+}
+
+/// Merge a user's stars, ratings and play statistics into song metadata.
+pub(crate) fn annotate_songs(
+    data: &AppState,
+    user_id: Uuid,
+    songs: &mut [SongMetadata],
+) -> AppResult<()> {
+    let annotations = data.annotation_repo.map_for_user(user_id)?;
+    let play_stats = data.history_repo.stats_for_user(user_id)?;
+    for song in songs.iter_mut() {
+        song.apply_annotation(annotations.get(&song.id));
+        song.apply_play_stats(play_stats.get(&song.id));
+    }
+    Ok(())
 }
 
 /// List all songs in the music library with filtering, sorting, and pagination.
@@ -104,13 +127,16 @@ fn is_audio_file(path: &Path) -> bool {
 /// - `artist`: Filter by artist name
 /// - `album`: Filter by album name
 /// - `genre`: Filter by genre
+/// - `starred`: Only starred (`true`) or unstarred (`false`) songs
+/// - `min_rating`: Minimum rating (1-5)
 /// - `page`: Page number (default: 1)
 /// - `per_page`: Items per page (default: 50, max: 100)
-/// - `sort`: Sort field (title, artist, album, year, duration)
+/// - `sort`: Sort field (title, artist, album, year, duration, rating, starred,
+///   play_count, last_played)
 /// - `order`: Sort order (asc, desc)
 #[get("/api/music/list")]
 pub async fn list_music(
-    _user: AuthenticatedUser,
+    user: AuthenticatedUser,
     data: web::Data<AppState>,
     query: web::Query<ListSongsQuery>,
 ) -> AppResult<HttpResponse> {
@@ -120,13 +146,8 @@ pub async fn list_music(
     let per_page = query.per_page.clamp(1, 100);
     let page = query.page.max(1);
 
-    // Scan directory for audio files
-    let mut songs: Vec<SongMetadata> = fs::read_dir(&data.music_folder)?
-        .filter_map(|entry| entry.ok())
-        .map(|entry| entry.path())
-        .filter(|path| path.is_file() && is_audio_file(path))
-        .filter_map(|path| extract_metadata(&path))
-        .collect();
+    let mut songs: Vec<SongMetadata> = data.library.index()?.songs.clone();
+    annotate_songs(&data, user.id, &mut songs)?;
 
     // Apply search filter
     if let Some(ref q) = query.q {
@@ -161,6 +182,15 @@ pub async fn list_music(
         });
     }
 
+    // Apply annotation filters
+    if let Some(starred) = query.starred {
+        songs.retain(|s| s.starred.is_some() == starred);
+    }
+
+    if let Some(min_rating) = query.min_rating {
+        songs.retain(|s| s.rating.unwrap_or(0) >= min_rating);
+    }
+
     // Sort songs
     songs.sort_by(|a, b| {
         let cmp = match query.sort {
@@ -169,6 +199,10 @@ pub async fn list_music(
             SortField::Album => a.album.to_lowercase().cmp(&b.album.to_lowercase()),
             SortField::Year => a.year.cmp(&b.year),
             SortField::Duration => a.duration.cmp(&b.duration),
+            SortField::Rating => a.rating.cmp(&b.rating),
+            SortField::Starred => a.starred.cmp(&b.starred),
+            SortField::PlayCount => a.play_count.cmp(&b.play_count),
+            SortField::LastPlayed => a.last_played.cmp(&b.last_played),
         };
 
         match query.order {
@@ -187,43 +221,157 @@ pub async fn list_music(
     Ok(HttpResponse::Ok().json(response))
 }
 
+/// Default lifetime of a signed media URL, in seconds.
+const DEFAULT_SIGNED_URL_TTL: i64 = 60 * 60;
+
+/// Longest lifetime of a signed media URL, in seconds.
+const MAX_SIGNED_URL_TTL: i64 = 24 * 60 * 60;
+
+/// Query parameters for issuing signed URLs.
+#[derive(Debug, Deserialize)]
+pub struct SignedUrlQuery {
+    /// Seconds until the URLs expire (default: 1 hour, max: 24 hours).
+    pub ttl: Option<i64>,
+}
+
+/// Signed URLs for a song.
+#[derive(Debug, Serialize)]
+pub struct SignedUrlResponse {
+    pub song_id: String,
+    pub stream_url: String,
+    /// Cover art URL, if the song has embedded cover art.
+    pub cover_url: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Signature query parameters accepted by the stream and cover endpoints.
+#[derive(Debug, Deserialize)]
+pub struct MediaSignature {
+    pub song: Option<String>,
+    pub user: Option<Uuid>,
+    pub expires: Option<i64>,
+    pub sig: Option<String>,
+}
+
+/// Authorize a media request by its URL signature, or by the usual
+/// bearer token or API key when the URL is not signed.
+///
+/// A signature is only good for the file of the song it was issued for,
+/// and only while its user exists and is enabled.
+async fn authorize_media(
+    req: &HttpRequest,
+    data: &AppState,
+    signature: &MediaSignature,
+    filename: &str,
+) -> AppResult<()> {
+    let Some(sig) = &signature.sig else {
+        AuthenticatedUser::extract(req).await?;
+        return Ok(());
+    };
+
+    let invalid = || AppError::Unauthorized("Invalid or expired signed URL".to_string());
+    let (Some(song_id), Some(user_id), Some(expires)) =
+        (&signature.song, signature.user, signature.expires)
+    else {
+        return Err(invalid());
+    };
+    if expires < Utc::now().timestamp()
+        || !data
+            .url_signer
+            .verify(song_id, Grantee::User(user_id), expires, sig)
+    {
+        return Err(invalid());
+    }
+
+    if data
+        .library
+        .index()?
+        .song(song_id)
+        .is_none_or(|song| song.file != filename)
+    {
+        return Err(invalid());
+    }
+    if data
+        .user_repo
+        .find_by_id(user_id)?
+        .is_none_or(|user| user.disabled)
+    {
+        return Err(invalid());
+    }
+
+    Ok(())
+}
+
+/// Issue signed, expiring stream and cover URLs for a song.
+///
+/// GET /api/music/signed-url/{id}
+///
+/// The URLs work without an `Authorization` header, for players that
+/// cannot send one. Query parameters:
+/// - `ttl`: Seconds until the URLs expire (default: 3600, max: 86400)
+#[get("/api/music/signed-url/{id}")]
+pub async fn sign_song_url(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<SignedUrlQuery>,
+) -> AppResult<HttpResponse> {
+    let ttl = query.ttl.unwrap_or(DEFAULT_SIGNED_URL_TTL);
+    if !(1..=MAX_SIGNED_URL_TTL).contains(&ttl) {
+        return Err(AppError::Validation(format!(
+            "TTL must be 1-{} seconds",
+            MAX_SIGNED_URL_TTL
+        )));
+    }
+
+    let index = data.library.index()?;
+    let song = index
+        .song(&path)
+        .ok_or_else(|| AppError::song_not_found(&path))?;
+
+    let expires_at = Utc::now() + Duration::seconds(ttl);
+    let expires = expires_at.timestamp();
+    let signature = data
+        .url_signer
+        .sign(&song.id, Grantee::User(user.id), expires);
+
+    let base_url = &config::get().public_url;
+    let url = |endpoint: &str| {
+        format!(
+            "{}/api/music/{}/{}?song={}&user={}&expires={}&sig={}",
+            base_url,
+            endpoint,
+            encode_path_segment(&song.file),
+            encode_path_segment(&song.id),
+            user.id,
+            expires,
+            signature
+        )
+    };
+
+    Ok(HttpResponse::Ok().json(SignedUrlResponse {
+        song_id: song.id.clone(),
+        stream_url: url("stream"),
+        cover_url: song.has_cover.then(|| url("cover")),
+        expires_at,
+    }))
+}
+
 /// Stream an audio file.
 ///
 /// GET /api/music/stream/{filename}
 ///
-/// Supports range requests for seeking.
+/// Supports range requests for seeking. Accepts a signed URL from
+/// `/api/music/signed-url/{id}` in place of authentication.
 #[get("/api/music/stream/{filename}")]
 pub async fn stream_music(
     req: HttpRequest,
-    _user: AuthenticatedUser,
     data: web::Data<AppState>,
     path: web::Path<String>,
+    signature: web::Query<MediaSignature>,
 ) -> AppResult<HttpResponse> {
-    let filename = sanitize_filename(&path)?;
-    let full_path = data.music_folder.join(filename);
-
-    // Check file exists
-    if !full_path.exists() {
-        return Err(AppError::song_not_found(filename));
-    }
-
-    // Verify the resolved path is still within music folder (extra safety)
-    let canonical = full_path
-        .canonicalize()
-        .map_err(|_| AppError::song_not_found(filename))?;
-    let music_canonical = data
-        .music_folder
-        .canonicalize()
-        .map_err(|e| AppError::Internal(format!("Music folder error: {}", e)))?;
-
-    if !canonical.starts_with(&music_canonical) {
-        tracing::warn!(
-            requested = %canonical.display(),
-            music_folder = %music_canonical.display(),
-            "Path escape attempt blocked"
-        );
-        return Err(AppError::path_traversal());
-    }
+    authorize_media(&req, &data, &signature, &path).await?;
+    let full_path = resolve_music_file(&data.music_folder, &path)?;
 
     let file = NamedFile::open(&full_path)?;
     Ok(file.into_response(&req))
@@ -234,41 +382,17 @@ pub async fn stream_music(
 /// GET /api/music/cover/{filename}
 ///
 /// Returns the embedded cover art if available, with caching headers.
+/// Accepts a signed URL in place of authentication.
 #[get("/api/music/cover/{filename}")]
 pub async fn get_cover(
-    _user: AuthenticatedUser,
+    req: HttpRequest,
     data: web::Data<AppState>,
     path: web::Path<String>,
+    signature: web::Query<MediaSignature>,
 ) -> AppResult<HttpResponse> {
-    let filename = sanitize_filename(&path)?;
-    let file_path = data.music_folder.join(filename);
-
-    // Check file exists
-    if !file_path.exists() {
-        return Err(AppError::song_not_found(filename));
-    }
-
-    let tagged_file =
-        read_from_path(&file_path).map_err(|_| AppError::song_not_found(filename))?;
-
-    let tag = tagged_file
-        .first_tag()
-        .ok_or_else(|| AppError::NotFound("No cover art available".to_string()))?;
-
-    // Find front cover or any picture
-    let picture = tag
-        .pictures()
-        .iter()
-        .find(|p| p.pic_type() == PictureType::CoverFront)
-        .or_else(|| tag.pictures().first())
-        .ok_or_else(|| AppError::NotFound("No cover art available".to_string()))?;
-
-    let mime = picture
-        .mime_type()
-        .map(|m| m.as_str())
-        .unwrap_or("image/jpeg");
-
-    let data = picture.data().to_vec();
+    authorize_media(&req, &data, &signature, &path).await?;
+    let file_path = resolve_music_file(&data.music_folder, &path)?;
+    let (mime, data) = read_cover(&file_path)?;
 
     // Cache cover art for 1 day (it rarely changes)
     Ok(HttpResponse::Ok()
@@ -285,12 +409,12 @@ pub async fn list_artists(
     _user: AuthenticatedUser,
     data: web::Data<AppState>,
 ) -> AppResult<HttpResponse> {
-    let mut artists: Vec<String> = fs::read_dir(&data.music_folder)?
-        .filter_map(|entry| entry.ok())
-        .map(|entry| entry.path())
-        .filter(|path| path.is_file() && is_audio_file(path))
-        .filter_map(|path| extract_metadata(&path))
-        .map(|song| song.artist)
+    let mut artists: Vec<String> = data
+        .library
+        .index()?
+        .songs
+        .iter()
+        .map(|song| song.artist.clone())
         .collect();
 
     artists.sort();
@@ -307,12 +431,12 @@ pub async fn list_albums(
     _user: AuthenticatedUser,
     data: web::Data<AppState>,
 ) -> AppResult<HttpResponse> {
-    let mut albums: Vec<String> = fs::read_dir(&data.music_folder)?
-        .filter_map(|entry| entry.ok())
-        .map(|entry| entry.path())
-        .filter(|path| path.is_file() && is_audio_file(path))
-        .filter_map(|path| extract_metadata(&path))
-        .map(|song| song.album)
+    let mut albums: Vec<String> = data
+        .library
+        .index()?
+        .songs
+        .iter()
+        .map(|song| song.album.clone())
         .collect();
 
     albums.sort();
@@ -324,6 +448,7 @@ pub async fn list_albums(
 /// Configure music routes.
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(list_music)
+        .service(sign_song_url)
         .service(stream_music)
         .service(get_cover)
         .service(list_artists)
@@ -333,6 +458,31 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::auth::User;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use tempfile::tempdir;
+
+    /// Write a short silent mono WAV file.
+    fn write_wav(path: &Path) {
+        let samples = 800u32;
+        let data_len = samples * 2;
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&16000u32.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        wav.resize(wav.len() + data_len as usize, 0);
+        std::fs::write(path, wav).unwrap();
+    }
 
     #[test]
     fn test_sanitize_filename_valid() {
@@ -353,12 +503,38 @@ mod tests {
         assert!(sanitize_filename("").is_err());
     }
 
-    #[test]
-    fn test_is_audio_file() {
-        assert!(is_audio_file(Path::new("song.mp3")));
-        assert!(is_audio_file(Path::new("song.FLAC")));
-        assert!(!is_audio_file(Path::new("image.jpg")));
-        assert!(!is_audio_file(Path::new("noextension")));
+    #[actix_web::test]
+    async fn test_share_signature_is_refused_for_user_urls() {
+        let dir = tempdir().unwrap();
+        write_wav(&dir.path().join("song.wav"));
+        let state = AppState::for_tests(dir.path());
+        let user = state
+            .user_repo
+            .create(User::new("alice".to_string(), "hash".to_string(), false))
+            .unwrap();
+        let song = state.library.index().unwrap().songs[0].clone();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .configure(configure),
+        )
+        .await;
+
+        let expires = (Utc::now() + Duration::hours(1)).timestamp();
+        let stream = |grantee| {
+            let sig = state.url_signer.sign(&song.id, grantee, expires);
+            TestRequest::get()
+                .uri(&format!(
+                    "/api/music/stream/song.wav?song={}&user={}&expires={}&sig={}",
+                    song.id, user.id, expires, sig
+                ))
+                .to_request()
+        };
+
+        let response = call_service(&app, stream(Grantee::User(user.id))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // Same song, ID and expiry, but signed for a share link
+        let response = call_service(&app, stream(Grantee::Share(user.id))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
     }
 }
-
@@ -0,0 +1,341 @@
+//! Playlist API endpoints.
+
+use actix_web::{delete, get, http::header, post, put, web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use validator::Validate;
+
+use crate::api::events::client_id;
+use crate::api::music::annotate_songs;
+use crate::auth::AuthenticatedUser;
+use crate::config;
+use crate::error::{AppError, AppResult};
+use crate::events::{kind, Audience};
+use crate::library::playlist_file::{self, ExportTrack};
+use crate::library::LibraryIndex;
+use crate::models::{AppState, SongMetadata};
+use crate::userdata::{Playlist, PlaylistRepository};
+
+/// Request body for creating a playlist.
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreatePlaylistRequest {
+    /// Playlist name (1-128 characters).
+    #[validate(length(min = 1, max = 128, message = "Name must be 1-128 characters"))]
+    pub name: String,
+    /// Initial song IDs.
+    #[serde(default)]
+    pub song_ids: Vec<String>,
+}
+
+/// Request body for updating a playlist.
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdatePlaylistRequest {
+    /// New playlist name.
+    #[validate(length(min = 1, max = 128, message = "Name must be 1-128 characters"))]
+    pub name: Option<String>,
+    /// Replacement song list.
+    pub song_ids: Option<Vec<String>>,
+}
+
+/// Playlist with its songs resolved.
+#[derive(Debug, Serialize)]
+pub struct PlaylistDetails {
+    #[serde(flatten)]
+    pub playlist: Playlist,
+    pub songs: Vec<SongMetadata>,
+}
+
+/// Export file format.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    M3u8,
+    Xspf,
+}
+
+/// How exported entries point at songs.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportPaths {
+    /// Absolute stream URLs on this server.
+    #[default]
+    Url,
+    /// Paths relative to the music folder.
+    Relative,
+}
+
+/// Query parameters for playlist export.
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+    #[serde(default)]
+    pub paths: ExportPaths,
+}
+
+/// Look up a playlist visible to the user, in the user store or the library.
+pub(crate) fn find_playlist(
+    data: &AppState,
+    index: &LibraryIndex,
+    user: &AuthenticatedUser,
+    id: &str,
+) -> AppResult<Playlist> {
+    let playlist = match data.playlist_repo.find_by_id(id)? {
+        Some(playlist) => Some(playlist),
+        None => index.playlist(id).cloned(),
+    };
+
+    playlist
+        .filter(|p| p.is_visible_to(user.id))
+        .ok_or_else(|| AppError::NotFound(format!("Playlist not found: {}", id)))
+}
+
+/// Look up a playlist the user may modify.
+pub(crate) fn find_owned_playlist(
+    data: &AppState,
+    user: &AuthenticatedUser,
+    id: &str,
+) -> AppResult<Playlist> {
+    let index = data.library.index()?;
+    let playlist = find_playlist(data, &index, user, id)?;
+
+    if playlist.read_only {
+        return Err(AppError::Forbidden(
+            "Imported playlists are read-only".to_string(),
+        ));
+    }
+
+    Ok(playlist)
+}
+
+/// Reject song IDs that are not in the library.
+pub(crate) fn validate_song_ids(index: &LibraryIndex, song_ids: &[String]) -> AppResult<()> {
+    match song_ids.iter().find(|id| index.song(id).is_none()) {
+        Some(id) => Err(AppError::Validation(format!("Unknown song ID: {}", id))),
+        None => Ok(()),
+    }
+}
+
+/// Resolve playlist song IDs to metadata, skipping songs no longer in the library.
+pub(crate) fn resolve_songs<'a>(
+    index: &'a LibraryIndex,
+    playlist: &Playlist,
+) -> Vec<&'a SongMetadata> {
+    playlist
+        .song_ids
+        .iter()
+        .filter_map(|id| index.song(id))
+        .collect()
+}
+
+/// Tell the owner's other sessions about a playlist change.
+pub(crate) fn publish_playlist_event(
+    data: &AppState,
+    event: &str,
+    playlist: &Playlist,
+    origin: Option<&str>,
+) {
+    let audience = playlist.owner_id.map_or(Audience::All, Audience::User);
+    let payload = if event == kind::PLAYLIST_DELETED {
+        json!({ "id": playlist.id })
+    } else {
+        json!({ "playlist": playlist })
+    };
+    data.events.publish(event, audience, origin, payload);
+}
+
+/// List playlists visible to the current user.
+///
+/// GET /api/playlists
+///
+/// Returns the user's own playlists followed by read-only playlists
+/// imported from the music folder.
+#[get("/api/playlists")]
+pub async fn list_playlists(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> AppResult<HttpResponse> {
+    let mut playlists = data.playlist_repo.list_by_owner(user.id)?;
+    playlists.extend(data.library.index()?.playlists.iter().cloned());
+
+    Ok(HttpResponse::Ok().json(playlists))
+}
+
+/// Create a playlist.
+///
+/// POST /api/playlists
+#[post("/api/playlists")]
+pub async fn create_playlist(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    body: web::Json<CreatePlaylistRequest>,
+) -> AppResult<HttpResponse> {
+    body.validate()
+        .map_err(|e| AppError::Validation(e.to_string()))?;
+
+    let body = body.into_inner();
+    let index = data.library.index()?;
+    validate_song_ids(&index, &body.song_ids)?;
+
+    let playlist = data
+        .playlist_repo
+        .create(Playlist::new(user.id, body.name, body.song_ids))?;
+    publish_playlist_event(&data, kind::PLAYLIST_CREATED, &playlist, client_id(&req));
+
+    Ok(HttpResponse::Created().json(playlist))
+}
+
+/// Get a playlist with its songs.
+///
+/// GET /api/playlists/{id}
+#[get("/api/playlists/{id}")]
+pub async fn get_playlist(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let index = data.library.index()?;
+    let playlist = find_playlist(&data, &index, &user, &path)?;
+    let mut songs: Vec<SongMetadata> = resolve_songs(&index, &playlist)
+        .into_iter()
+        .cloned()
+        .collect();
+    annotate_songs(&data, user.id, &mut songs)?;
+
+    Ok(HttpResponse::Ok().json(PlaylistDetails { playlist, songs }))
+}
+
+/// Rename a playlist or replace its songs.
+///
+/// PUT /api/playlists/{id}
+#[put("/api/playlists/{id}")]
+pub async fn update_playlist(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<UpdatePlaylistRequest>,
+) -> AppResult<HttpResponse> {
+    body.validate()
+        .map_err(|e| AppError::Validation(e.to_string()))?;
+
+    let body = body.into_inner();
+    let mut playlist = find_owned_playlist(&data, &user, &path)?;
+
+    if let Some(name) = body.name {
+        playlist.name = name;
+    }
+    if let Some(song_ids) = body.song_ids {
+        let index = data.library.index()?;
+        validate_song_ids(&index, &song_ids)?;
+        playlist.song_ids = song_ids;
+    }
+    playlist.updated_at = Utc::now();
+
+    let playlist = data.playlist_repo.update(playlist)?;
+    publish_playlist_event(&data, kind::PLAYLIST_UPDATED, &playlist, client_id(&req));
+
+    Ok(HttpResponse::Ok().json(playlist))
+}
+
+/// Delete a playlist.
+///
+/// DELETE /api/playlists/{id}
+#[delete("/api/playlists/{id}")]
+pub async fn delete_playlist(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let playlist = find_owned_playlist(&data, &user, &path)?;
+    data.playlist_repo.delete(&playlist.id)?;
+    publish_playlist_event(&data, kind::PLAYLIST_DELETED, &playlist, client_id(&req));
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Export a playlist as M3U8 or XSPF.
+///
+/// GET /api/playlists/{id}/export
+///
+/// Query parameters:
+/// - `format`: `m3u8` (default) or `xspf`
+/// - `paths`: `url` (stream URLs, default) or `relative` (paths relative to the music folder)
+#[get("/api/playlists/{id}/export")]
+pub async fn export_playlist(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ExportQuery>,
+) -> AppResult<HttpResponse> {
+    let index = data.library.index()?;
+    let playlist = find_playlist(&data, &index, &user, &path)?;
+
+    let base_url = &config::get().public_url;
+
+    let tracks: Vec<ExportTrack<'_>> = resolve_songs(&index, &playlist)
+        .into_iter()
+        .map(|song| {
+            let location = match (query.paths, query.format) {
+                (ExportPaths::Url, _) => format!(
+                    "{}/api/music/stream/{}",
+                    base_url,
+                    playlist_file::encode_path_segment(&song.file)
+                ),
+                // XSPF locations are URIs, so relative paths must be encoded too
+                (ExportPaths::Relative, ExportFormat::Xspf) => {
+                    playlist_file::encode_path_segment(&song.file)
+                }
+                (ExportPaths::Relative, ExportFormat::M3u8) => song.file.clone(),
+            };
+            ExportTrack { song, location }
+        })
+        .collect();
+
+    let (body, content_type, extension) = match query.format {
+        ExportFormat::M3u8 => (
+            playlist_file::write_m3u8(&playlist.name, &tracks),
+            "audio/x-mpegurl; charset=utf-8",
+            "m3u8",
+        ),
+        ExportFormat::Xspf => (
+            playlist_file::write_xspf(&playlist.name, &tracks),
+            "application/xspf+xml; charset=utf-8",
+            "xspf",
+        ),
+    };
+
+    let filename: String = playlist
+        .name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, content_type))
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.{}\"", filename, extension),
+        ))
+        .body(body))
+}
+
+/// Configure playlist routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_playlists)
+        .service(create_playlist)
+        .service(export_playlist)
+        .service(get_playlist)
+        .service(update_playlist)
+        .service(delete_playlist);
+}
@@ -0,0 +1,124 @@
+//! Play queue sync endpoints.
+//!
+//! Lets a user carry the queue from one device to another. Saves carry the
+//! version the device last saw, so a device working from a stale copy gets
+//! a conflict instead of overwriting newer changes.
+
+use actix_web::{get, put, web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use validator::Validate;
+
+use crate::api::events::client_id;
+use crate::api::music::annotate_songs;
+use crate::api::playlists::validate_song_ids;
+use crate::auth::AuthenticatedUser;
+use crate::error::{AppError, AppResult};
+use crate::events::{kind, Audience};
+use crate::models::{AppState, SongMetadata};
+use crate::userdata::{PlayQueue, PlayQueueRepository};
+
+/// Request body for saving the play queue.
+#[derive(Debug, Deserialize, Validate)]
+pub struct SaveQueueRequest {
+    /// Queued song IDs, in play order.
+    #[validate(length(max = 5000, message = "Queue is limited to 5000 songs"))]
+    pub song_ids: Vec<String>,
+    /// Index of the current song in `song_ids`.
+    pub current: Option<usize>,
+    /// Playback position within the current song, in milliseconds.
+    #[serde(default)]
+    pub position_ms: u64,
+    /// Name of the saving device (defaults to the `X-Client-Id` header).
+    #[validate(length(min = 1, max = 128, message = "Device must be 1-128 characters"))]
+    pub device: Option<String>,
+    /// Version the client last received (0 if it has never seen a queue).
+    pub version: u64,
+}
+
+/// Play queue with its songs resolved.
+#[derive(Debug, Serialize)]
+pub struct QueueDetails {
+    #[serde(flatten)]
+    pub queue: PlayQueue,
+    /// Songs of `song_ids` still in the library.
+    pub songs: Vec<SongMetadata>,
+}
+
+/// Resolve and annotate the songs of a queue.
+fn queue_details(data: &AppState, queue: PlayQueue) -> AppResult<QueueDetails> {
+    let index = data.library.index()?;
+    let mut songs: Vec<SongMetadata> = queue
+        .song_ids
+        .iter()
+        .filter_map(|id| index.song(id).cloned())
+        .collect();
+    annotate_songs(data, queue.user_id, &mut songs)?;
+
+    Ok(QueueDetails { queue, songs })
+}
+
+/// Get the current user's play queue.
+///
+/// GET /api/queue
+///
+/// Users who never saved a queue get an empty one at version 0.
+#[get("/api/queue")]
+pub async fn get_queue(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> AppResult<HttpResponse> {
+    let queue = data
+        .play_queue_repo
+        .find(user.id)?
+        .unwrap_or_else(|| PlayQueue::new(user.id));
+
+    Ok(HttpResponse::Ok().json(queue_details(&data, queue)?))
+}
+
+/// Save the current user's play queue.
+///
+/// PUT /api/queue
+///
+/// Fails with 409 Conflict if `version` is not the stored version; fetch
+/// the queue again and retry.
+#[put("/api/queue")]
+pub async fn save_queue(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    body: web::Json<SaveQueueRequest>,
+) -> AppResult<HttpResponse> {
+    body.validate()
+        .map_err(|e| AppError::Validation(e.to_string()))?;
+
+    let body = body.into_inner();
+    if body.current.is_some_and(|i| i >= body.song_ids.len()) {
+        return Err(AppError::Validation(
+            "Current index is outside the queue".to_string(),
+        ));
+    }
+    validate_song_ids(&*data.library.index()?, &body.song_ids)?;
+
+    let queue = PlayQueue {
+        song_ids: body.song_ids,
+        current: body.current,
+        position_ms: body.position_ms,
+        changed_by: body.device.or_else(|| client_id(&req).map(String::from)),
+        ..PlayQueue::new(user.id)
+    };
+    let queue = data.play_queue_repo.save(queue, Some(body.version))?;
+    data.events.publish(
+        kind::QUEUE_UPDATED,
+        Audience::User(user.id),
+        client_id(&req),
+        json!({ "queue": queue }),
+    );
+
+    Ok(HttpResponse::Ok().json(queue_details(&data, queue)?))
+}
+
+/// Configure play queue routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_queue).service(save_queue);
+}
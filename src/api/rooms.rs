@@ -0,0 +1,525 @@
+//! Listening room endpoints.
+//!
+//! Changes are pushed to the room's members and invitees over the event
+//! channel, so clients only need these endpoints to act and to load a
+//! room initially.
+
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::api::events::client_id;
+use crate::auth::{AuthenticatedUser, UserRepository};
+use crate::error::{AppError, AppResult};
+use crate::events::{kind, Audience};
+use crate::models::{AppState, SongMetadata};
+use crate::userdata::{Room, RoomPlayback, RoomRepository};
+
+/// Room lifetime when none is given.
+const DEFAULT_LIFETIME_HOURS: u32 = 4;
+
+/// Longest room lifetime (one week).
+const MAX_LIFETIME_HOURS: u32 = 168;
+
+/// Most members and invitees per room.
+const MAX_MEMBERS: usize = 50;
+
+/// Request body for creating a room.
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateRoomRequest {
+    /// Room name (1-128 characters).
+    #[validate(length(min = 1, max = 128, message = "Name must be 1-128 characters"))]
+    pub name: String,
+    /// Usernames to invite.
+    #[serde(default)]
+    pub invite: Vec<String>,
+    /// Hours until the room expires (default 4, at most 168).
+    #[validate(range(min = 1, max = 168, message = "Lifetime must be 1-168 hours"))]
+    pub expires_in_hours: Option<u32>,
+}
+
+/// Request body for updating a room.
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateRoomRequest {
+    /// New room name.
+    #[validate(length(min = 1, max = 128, message = "Name must be 1-128 characters"))]
+    pub name: Option<String>,
+    /// New lifetime, counted from now.
+    #[validate(range(min = 1, max = 168, message = "Lifetime must be 1-168 hours"))]
+    pub expires_in_hours: Option<u32>,
+}
+
+/// Request body for inviting users.
+#[derive(Debug, Deserialize)]
+pub struct InviteRequest {
+    pub usernames: Vec<String>,
+}
+
+/// Request body for voting for a song.
+#[derive(Debug, Deserialize)]
+pub struct VoteRequest {
+    pub song_id: String,
+}
+
+/// Request body for setting the shared playback position.
+#[derive(Debug, Deserialize)]
+pub struct PlaybackRequest {
+    /// Index of the current song in the queue.
+    pub current: Option<usize>,
+    /// Position within the current song, in milliseconds.
+    #[serde(default)]
+    pub position_ms: u64,
+    pub playing: bool,
+}
+
+/// Query parameters for listing rooms.
+#[derive(Debug, Deserialize)]
+pub struct ListRoomsQuery {
+    /// List every room (admins only).
+    #[serde(default)]
+    pub all: bool,
+}
+
+/// Room with the names and songs it refers to resolved.
+#[derive(Debug, Serialize)]
+pub struct RoomDetails {
+    #[serde(flatten)]
+    pub room: Room,
+    /// Votes a proposal needs to be queued.
+    pub votes_needed: usize,
+    /// Usernames of the host, members and invitees.
+    pub usernames: HashMap<Uuid, String>,
+    /// Songs in the queue and proposals, by ID.
+    pub songs: HashMap<String, SongMetadata>,
+}
+
+/// Resolve usernames and songs of a room.
+fn room_details(data: &AppState, room: Room) -> AppResult<RoomDetails> {
+    let mut usernames = HashMap::new();
+    for id in room.members.iter().chain(&room.invited) {
+        if let Some(user) = data.user_repo.find_by_id(*id)? {
+            usernames.insert(user.id, user.username);
+        }
+    }
+
+    let index = data.library.index()?;
+    let songs = room
+        .queue
+        .iter()
+        .chain(room.proposals.iter().map(|p| &p.song_id))
+        .filter_map(|id| index.song(id))
+        .map(|song| (song.id.clone(), song.clone()))
+        .collect();
+
+    Ok(RoomDetails {
+        votes_needed: room.votes_needed(),
+        room,
+        usernames,
+        songs,
+    })
+}
+
+/// Look up a room the user may see; admins see every room.
+fn find_room(data: &AppState, user: &AuthenticatedUser, id: &str) -> AppResult<Room> {
+    data.room_repo
+        .find_by_id(id)?
+        .filter(|r| r.is_visible_to(user.id) || user.is_admin)
+        .ok_or_else(|| AppError::NotFound(format!("Room not found: {}", id)))
+}
+
+/// Look up a room the user hosts, or moderates as an admin if
+/// `allow_admin` is set.
+fn find_hosted_room(
+    data: &AppState,
+    user: &AuthenticatedUser,
+    id: &str,
+    allow_admin: bool,
+) -> AppResult<Room> {
+    let room = find_room(data, user, id)?;
+    if room.host_id != user.id && !(allow_admin && user.is_admin) {
+        return Err(AppError::Forbidden("Only the host can do this".to_string()));
+    }
+    Ok(room)
+}
+
+/// Look up users to invite by username.
+fn find_invitees(data: &AppState, usernames: &[String]) -> AppResult<Vec<Uuid>> {
+    usernames
+        .iter()
+        .map(|name| {
+            data.user_repo
+                .find_by_username(name)?
+                .map(|u| u.id)
+                .ok_or_else(|| AppError::Validation(format!("Unknown user: {}", name)))
+        })
+        .collect()
+}
+
+/// Add invitations, skipping users already in the room.
+fn invite(room: &mut Room, user_ids: Vec<Uuid>) -> AppResult<()> {
+    for id in user_ids {
+        if !room.is_visible_to(id) {
+            room.invited.push(id);
+        }
+    }
+    if room.members.len() + room.invited.len() > MAX_MEMBERS {
+        return Err(AppError::Validation(format!(
+            "Rooms are limited to {} members",
+            MAX_MEMBERS
+        )));
+    }
+    Ok(())
+}
+
+/// Tell everyone in the room about a change.
+fn publish_room(data: &AppState, room: &Room, origin: Option<&str>) {
+    data.events.publish(
+        kind::ROOM_UPDATED,
+        Audience::Users(room.audience()),
+        origin,
+        json!({ "room": room }),
+    );
+}
+
+/// Tell users that a room is gone for them.
+fn publish_closed(data: &AppState, room_id: &str, user_ids: Vec<Uuid>) {
+    data.events.publish(
+        kind::ROOM_CLOSED,
+        Audience::Users(user_ids),
+        None,
+        json!({ "id": room_id }),
+    );
+}
+
+/// Take a deleted user out of every room, closing the rooms they host.
+pub(crate) fn remove_user_from_rooms(data: &AppState, user_id: Uuid) -> AppResult<()> {
+    for mut room in data.room_repo.list_for_user(user_id)? {
+        if room.host_id == user_id {
+            data.room_repo.delete(&room.id)?;
+            publish_closed(data, &room.id, room.audience());
+        } else if room.remove_member(user_id) {
+            let room = data.room_repo.update(room)?;
+            publish_room(data, &room, None);
+        }
+    }
+    Ok(())
+}
+
+/// List rooms the current user has joined or been invited to.
+///
+/// GET /api/rooms
+///
+/// Admins may pass `all=true` to list every room.
+#[get("/api/rooms")]
+pub async fn list_rooms(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    query: web::Query<ListRoomsQuery>,
+) -> AppResult<HttpResponse> {
+    let rooms = if query.all {
+        user.require_admin()?;
+        data.room_repo.list_all()?
+    } else {
+        data.room_repo.list_for_user(user.id)?
+    };
+
+    Ok(HttpResponse::Ok().json(rooms))
+}
+
+/// Create a room hosted by the current user.
+///
+/// POST /api/rooms
+#[post("/api/rooms")]
+pub async fn create_room(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    body: web::Json<CreateRoomRequest>,
+) -> AppResult<HttpResponse> {
+    body.validate()
+        .map_err(|e| AppError::Validation(e.to_string()))?;
+
+    let body = body.into_inner();
+    let hours = body.expires_in_hours.unwrap_or(DEFAULT_LIFETIME_HOURS);
+    let mut room = Room::new(user.id, body.name, Duration::hours(i64::from(hours)));
+    invite(&mut room, find_invitees(&data, &body.invite)?)?;
+
+    data.room_repo.delete_expired()?;
+    let room = data.room_repo.create(room)?;
+    publish_room(&data, &room, None);
+
+    Ok(HttpResponse::Created().json(room_details(&data, room)?))
+}
+
+/// Get a room.
+///
+/// GET /api/rooms/{id}
+#[get("/api/rooms/{id}")]
+pub async fn get_room(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let room = find_room(&data, &user, &path)?;
+    Ok(HttpResponse::Ok().json(room_details(&data, room)?))
+}
+
+/// Rename a room or change when it expires (host only).
+///
+/// PUT /api/rooms/{id}
+#[put("/api/rooms/{id}")]
+pub async fn update_room(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<UpdateRoomRequest>,
+) -> AppResult<HttpResponse> {
+    body.validate()
+        .map_err(|e| AppError::Validation(e.to_string()))?;
+
+    let body = body.into_inner();
+    let mut room = find_hosted_room(&data, &user, &path, false)?;
+    if let Some(name) = body.name {
+        room.name = name;
+    }
+    if let Some(hours) = body.expires_in_hours {
+        room.expires_at = Utc::now() + Duration::hours(i64::from(hours.min(MAX_LIFETIME_HOURS)));
+    }
+
+    let room = data.room_repo.update(room)?;
+    publish_room(&data, &room, client_id(&req));
+
+    Ok(HttpResponse::Ok().json(room_details(&data, room)?))
+}
+
+/// Close a room (host or admin).
+///
+/// DELETE /api/rooms/{id}
+#[delete("/api/rooms/{id}")]
+pub async fn delete_room(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let room = find_hosted_room(&data, &user, &path, true)?;
+    data.room_repo.delete(&room.id)?;
+    publish_closed(&data, &room.id, room.audience());
+
+    tracing::info!(room_id = %room.id, username = %user.username, "Listening room closed");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Invite users to a room (host only).
+///
+/// POST /api/rooms/{id}/invite
+#[post("/api/rooms/{id}/invite")]
+pub async fn invite_users(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<InviteRequest>,
+) -> AppResult<HttpResponse> {
+    let mut room = find_hosted_room(&data, &user, &path, false)?;
+    invite(&mut room, find_invitees(&data, &body.usernames)?)?;
+
+    let room = data.room_repo.update(room)?;
+    publish_room(&data, &room, client_id(&req));
+
+    Ok(HttpResponse::Ok().json(room_details(&data, room)?))
+}
+
+/// Accept an invitation.
+///
+/// POST /api/rooms/{id}/join
+#[post("/api/rooms/{id}/join")]
+pub async fn join_room(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let mut room = find_room(&data, &user, &path)?;
+    if !room.is_member(user.id) {
+        if !room.invited.contains(&user.id) {
+            return Err(AppError::Forbidden(
+                "You have not been invited to this room".to_string(),
+            ));
+        }
+        room.invited.retain(|id| *id != user.id);
+        room.members.push(user.id);
+        room = data.room_repo.update(room)?;
+        publish_room(&data, &room, client_id(&req));
+    }
+
+    Ok(HttpResponse::Ok().json(room_details(&data, room)?))
+}
+
+/// Leave a room, or decline an invitation.
+///
+/// POST /api/rooms/{id}/leave
+///
+/// The host cannot leave; closing the room ends it for everyone.
+#[post("/api/rooms/{id}/leave")]
+pub async fn leave_room(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let mut room = find_room(&data, &user, &path)?;
+    if room.host_id == user.id {
+        return Err(AppError::BadRequest(
+            "The host cannot leave the room; close it instead".to_string(),
+        ));
+    }
+    if room.remove_member(user.id) {
+        let room = data.room_repo.update(room)?;
+        publish_room(&data, &room, None);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Remove a member or invitation (host or admin).
+///
+/// DELETE /api/rooms/{id}/members/{user_id}
+#[delete("/api/rooms/{id}/members/{user_id}")]
+pub async fn remove_member(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<(String, Uuid)>,
+) -> AppResult<HttpResponse> {
+    let (id, member_id) = path.into_inner();
+    let mut room = find_hosted_room(&data, &user, &id, true)?;
+    if member_id == room.host_id {
+        return Err(AppError::BadRequest(
+            "The host cannot be removed; close the room instead".to_string(),
+        ));
+    }
+    if !room.remove_member(member_id) {
+        return Err(AppError::NotFound(format!(
+            "User is not in the room: {}",
+            member_id
+        )));
+    }
+
+    let room = data.room_repo.update(room)?;
+    publish_room(&data, &room, None);
+    publish_closed(&data, &room.id, vec![member_id]);
+
+    tracing::info!(room_id = %room.id, member_id = %member_id, username = %user.username, "Removed member from listening room");
+    Ok(HttpResponse::Ok().json(room_details(&data, room)?))
+}
+
+/// Vote for a song to be added to the queue, proposing it if needed.
+///
+/// POST /api/rooms/{id}/votes
+///
+/// A song is queued once a majority of the members voted for it. Songs
+/// proposed by the host are queued right away.
+#[post("/api/rooms/{id}/votes")]
+pub async fn vote(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<VoteRequest>,
+) -> AppResult<HttpResponse> {
+    let room = find_room(&data, &user, &path)?;
+    if data.library.index()?.song(&body.song_id).is_none() {
+        return Err(AppError::song_not_found(&body.song_id));
+    }
+
+    let room = data.room_repo.vote(&room.id, user.id, &body.song_id)?;
+    publish_room(&data, &room, client_id(&req));
+
+    Ok(HttpResponse::Ok().json(room_details(&data, room)?))
+}
+
+/// Remove a song from the queue (host or admin).
+///
+/// DELETE /api/rooms/{id}/queue/{position}
+#[delete("/api/rooms/{id}/queue/{position}")]
+pub async fn remove_from_queue(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<(String, usize)>,
+) -> AppResult<HttpResponse> {
+    let (id, position) = path.into_inner();
+    let mut room = find_hosted_room(&data, &user, &id, true)?;
+    if room.remove_from_queue(position).is_none() {
+        return Err(AppError::NotFound(format!(
+            "No song at queue position {}",
+            position
+        )));
+    }
+
+    let room = data.room_repo.update(room)?;
+    publish_room(&data, &room, client_id(&req));
+
+    Ok(HttpResponse::Ok().json(room_details(&data, room)?))
+}
+
+/// Set the shared playback position (host only).
+///
+/// PUT /api/rooms/{id}/playback
+///
+/// Members receive a `room.playback` event with the server time, so they
+/// can work out the live position as `position_ms` plus the time elapsed
+/// since `updated_at` while playing.
+#[put("/api/rooms/{id}/playback")]
+pub async fn set_playback(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<PlaybackRequest>,
+) -> AppResult<HttpResponse> {
+    let mut room = find_hosted_room(&data, &user, &path, false)?;
+    if body.current.is_some_and(|i| i >= room.queue.len()) {
+        return Err(AppError::Validation(
+            "Current index is outside the queue".to_string(),
+        ));
+    }
+
+    room.playback = RoomPlayback {
+        current: body.current,
+        position_ms: body.position_ms,
+        playing: body.playing && body.current.is_some(),
+        updated_at: Utc::now(),
+    };
+    let room = data.room_repo.update(room)?;
+    data.events.publish(
+        kind::ROOM_PLAYBACK,
+        Audience::Users(room.members.clone()),
+        client_id(&req),
+        json!({
+            "room_id": room.id,
+            "playback": room.playback,
+            "server_time": Utc::now(),
+        }),
+    );
+
+    Ok(HttpResponse::Ok().json(&room.playback))
+}
+
+/// Configure listening room routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_rooms)
+        .service(create_room)
+        .service(get_room)
+        .service(update_room)
+        .service(delete_room)
+        .service(invite_users)
+        .service(join_room)
+        .service(leave_room)
+        .service(remove_member)
+        .service(vote)
+        .service(remove_from_queue)
+        .service(set_playback);
+}
@@ -0,0 +1,200 @@
+//! Scrobble forwarding settings API endpoints.
+
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::auth::AuthenticatedUser;
+use crate::config;
+use crate::error::{AppError, AppResult};
+use crate::models::AppState;
+use crate::scrobbling::queue::ScrobbleQueue;
+use crate::userdata::{ForwardingConfig, ForwardingRepository, DEFAULT_SERVICE_URL};
+
+/// Request body for configuring ListenBrainz forwarding.
+#[derive(Debug, Deserialize, Validate)]
+pub struct ForwardingRequest {
+    /// User token on the service; may be omitted to keep the current one.
+    #[validate(length(min = 1, max = 512, message = "Token must be 1-512 characters"))]
+    pub token: Option<String>,
+    /// API root of the service (defaults to ListenBrainz).
+    #[validate(url(message = "Service URL must be a valid URL"))]
+    pub service_url: Option<String>,
+    /// Whether new plays are forwarded (defaults to true).
+    pub enabled: Option<bool>,
+}
+
+/// Forwarding settings as returned to the user (token masked).
+#[derive(Debug, Serialize)]
+pub struct ForwardingResponse {
+    pub service_url: String,
+    pub token_hint: String,
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_success_at: Option<DateTime<Utc>>,
+    /// Listens waiting for delivery.
+    pub pending: usize,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ForwardingResponse {
+    fn new(config: &ForwardingConfig, pending: usize) -> Self {
+        Self {
+            service_url: config.service_url.clone(),
+            token_hint: config.token_hint.clone(),
+            enabled: config.enabled,
+            last_error: config.last_error.clone(),
+            last_success_at: config.last_success_at,
+            pending,
+            updated_at: config.updated_at,
+        }
+    }
+}
+
+/// Load the current user's forwarding settings.
+fn find_config(data: &AppState, user: &AuthenticatedUser) -> AppResult<ForwardingConfig> {
+    data.scrobble_forwarder
+        .configs()
+        .find(user.id)?
+        .ok_or_else(|| AppError::NotFound("Scrobble forwarding is not configured".to_string()))
+}
+
+/// Check that a service URL points at ListenBrainz or a host allowed by
+/// `SCROBBLE_ALLOWED_HOSTS`, so users cannot make the server send requests
+/// to arbitrary (e.g. internal) addresses.
+fn check_service_url(url: &str) -> AppResult<()> {
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|_| AppError::Validation("Service URL must be a valid URL".to_string()))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(AppError::Validation(
+            "Service URL must use http or https".to_string(),
+        ));
+    }
+
+    let host = parsed.host_str().unwrap_or_default().to_lowercase();
+    let default_host = reqwest::Url::parse(DEFAULT_SERVICE_URL)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string));
+    if default_host.as_deref() == Some(host.as_str())
+        || config::get().scrobble_allowed_hosts.contains(&host)
+    {
+        Ok(())
+    } else {
+        Err(AppError::Forbidden(format!(
+            "Forwarding to {} is not allowed on this server",
+            host
+        )))
+    }
+}
+
+/// Get the current user's ListenBrainz forwarding settings.
+///
+/// GET /api/scrobbling/listenbrainz
+#[get("/api/scrobbling/listenbrainz")]
+pub async fn get_forwarding(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> AppResult<HttpResponse> {
+    let config = find_config(&data, &user)?;
+    let pending = data.scrobble_forwarder.queue().count_by_user(user.id);
+
+    Ok(HttpResponse::Ok().json(ForwardingResponse::new(&config, pending)))
+}
+
+/// Configure ListenBrainz forwarding for the current user.
+///
+/// PUT /api/scrobbling/listenbrainz
+#[put("/api/scrobbling/listenbrainz")]
+pub async fn set_forwarding(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    body: web::Json<ForwardingRequest>,
+) -> AppResult<HttpResponse> {
+    body.validate()
+        .map_err(|e| AppError::Validation(e.to_string()))?;
+    let body = body.into_inner();
+
+    if let Some(url) = &body.service_url {
+        check_service_url(url)?;
+    }
+
+    let secrets = data.scrobble_forwarder.secrets();
+    let existing = data.scrobble_forwarder.configs().find(user.id)?;
+    let mut config = match (existing, body.token) {
+        (Some(mut config), token) => {
+            if let Some(token) = token {
+                config.set_token(&token, secrets)?;
+            }
+            config.updated_at = Utc::now();
+            config
+        }
+        (None, Some(token)) => {
+            ForwardingConfig::new(user.id, DEFAULT_SERVICE_URL.to_string(), &token, secrets)?
+        }
+        (None, None) => {
+            return Err(AppError::Validation(
+                "A token is required to enable forwarding".to_string(),
+            ))
+        }
+    };
+
+    if let Some(url) = body.service_url {
+        config.service_url = url.trim_end_matches('/').to_string();
+    }
+    if let Some(enabled) = body.enabled {
+        config.enabled = enabled;
+    }
+    // New credentials deserve a fresh start
+    config.last_error = None;
+
+    let config = data.scrobble_forwarder.configs().save(config)?;
+    // Deliver anything held back by the old settings
+    let pending = data.scrobble_forwarder.retry_now(user.id)?;
+
+    tracing::info!(user_id = %user.id, service_url = %config.service_url, "Scrobble forwarding configured");
+    Ok(HttpResponse::Ok().json(ForwardingResponse::new(&config, pending)))
+}
+
+/// Stop forwarding and drop any undelivered listens.
+///
+/// DELETE /api/scrobbling/listenbrainz
+#[delete("/api/scrobbling/listenbrainz")]
+pub async fn delete_forwarding(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> AppResult<HttpResponse> {
+    if !data.scrobble_forwarder.configs().delete(user.id)? {
+        return Err(AppError::NotFound(
+            "Scrobble forwarding is not configured".to_string(),
+        ));
+    }
+    let dropped = data.scrobble_forwarder.queue().delete_by_user(user.id)?;
+
+    tracing::info!(user_id = %user.id, dropped, "Scrobble forwarding removed");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Retry delivery of pending listens immediately.
+///
+/// POST /api/scrobbling/listenbrainz/retry
+#[post("/api/scrobbling/listenbrainz/retry")]
+pub async fn retry_forwarding(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> AppResult<HttpResponse> {
+    let config = find_config(&data, &user)?;
+    let pending = data.scrobble_forwarder.retry_now(user.id)?;
+
+    Ok(HttpResponse::Accepted().json(ForwardingResponse::new(&config, pending)))
+}
+
+/// Configure scrobble forwarding routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_forwarding)
+        .service(set_forwarding)
+        .service(delete_forwarding)
+        .service(retry_forwarding);
+}
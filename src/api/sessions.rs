@@ -0,0 +1,62 @@
+//! Playback session endpoints for remote control.
+//!
+//! Players register by connecting to `/api/events` with a device name; these
+//! endpoints list them and send them commands.
+
+use actix_web::{get, post, web, HttpResponse};
+use uuid::Uuid;
+
+use crate::auth::AuthenticatedUser;
+use crate::error::{AppError, AppResult};
+use crate::models::AppState;
+use crate::sessions::{Command, MAX_VOLUME};
+
+/// List the current user's playback sessions.
+///
+/// GET /api/sessions
+#[get("/api/sessions")]
+pub async fn list_sessions(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> AppResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(data.sessions.list(user.id)))
+}
+
+/// Send a command to one of the current user's sessions.
+///
+/// POST /api/sessions/{id}/command
+///
+/// Returns 202 Accepted once the command is handed to the player's
+/// connection; the resulting state arrives as a `session.updated` event.
+#[post("/api/sessions/{id}/command")]
+pub async fn send_command(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    body: web::Json<Command>,
+) -> AppResult<HttpResponse> {
+    let command = body.into_inner();
+    if let Command::Volume { volume } = command {
+        if volume > MAX_VOLUME {
+            return Err(AppError::Validation(format!(
+                "Volume must be 0-{}",
+                MAX_VOLUME
+            )));
+        }
+    }
+
+    let session = data.sessions.send(user.id, *path, command.clone())?;
+    tracing::debug!(
+        username = %user.username,
+        session = %session.id,
+        command = ?command,
+        "Remote control command sent"
+    );
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Configure playback session routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_sessions).service(send_command);
+}
@@ -0,0 +1,605 @@
+//! Share link endpoints.
+//!
+//! Users share a song, album or playlist through a link that works without
+//! an account. The public endpoints under `/api/share/{id}` list and
+//! stream only the shared songs; their media URLs are signed like those of
+//! `/api/music/signed-url/{id}`, so a share password is never put in a URL.
+
+use actix_files::NamedFile;
+use actix_web::{delete, get, http::header, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::api::auth::{client_ip, hash_password, verify_password};
+use crate::api::music::{read_cover, resolve_music_file};
+use crate::api::playlists::resolve_songs;
+use crate::auth::{AuthenticatedUser, Grantee, UserRepository};
+use crate::config;
+use crate::error::{AppError, AppResult};
+use crate::library::playlist_file::encode_path_segment;
+use crate::library::LibraryIndex;
+use crate::models::{AppState, SongMetadata};
+use crate::userdata::{PlaylistRepository, Share, ShareKind, ShareRepository};
+
+/// Header carrying the password of a protected share.
+pub const SHARE_PASSWORD_HEADER: &str = "X-Share-Password";
+
+/// Most shares a user can have.
+const MAX_SHARES_PER_USER: usize = 100;
+
+/// How long media URLs handed out for a share stay valid.
+const MEDIA_URL_LIFETIME: Duration = Duration::hours(6);
+
+/// Shortest time in which further requests for a signed URL continue a
+/// play rather than start a new one.
+const MIN_PLAY_WINDOW: Duration = Duration::minutes(5);
+
+/// Request body for creating a share.
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateShareRequest {
+    pub kind: ShareKind,
+    /// Song, album or playlist ID.
+    pub item_id: String,
+    /// Hours until the share expires; never when omitted.
+    #[validate(range(min = 1, max = 8760, message = "Lifetime must be 1-8760 hours"))]
+    pub expires_in_hours: Option<i64>,
+    /// Password visitors must give; none when omitted.
+    #[validate(length(min = 4, max = 128, message = "Password must be 4-128 characters"))]
+    pub password: Option<String>,
+    /// Most plays allowed; unlimited when omitted.
+    #[validate(range(min = 1, message = "Play limit must be at least 1"))]
+    pub max_plays: Option<u32>,
+    /// Whether visitors may download the files.
+    #[serde(default)]
+    pub allow_download: bool,
+}
+
+/// Query parameters for listing shares.
+#[derive(Debug, Deserialize)]
+pub struct ListSharesQuery {
+    /// List every user's shares (admins only).
+    #[serde(default)]
+    pub all: bool,
+}
+
+/// A share as shown to its owner, without the password hash.
+#[derive(Debug, Serialize)]
+pub struct ShareResponse {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub kind: ShareKind,
+    pub item_id: String,
+    /// Public link of the share.
+    pub url: String,
+    pub has_password: bool,
+    pub max_plays: Option<u32>,
+    pub plays: u32,
+    pub allow_download: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl ShareResponse {
+    fn new(share: Share, base_url: &str) -> Self {
+        Self {
+            url: format!("{}/api/share/{}", base_url, share.id),
+            id: share.id,
+            owner_id: share.owner_id,
+            kind: share.kind,
+            item_id: share.item_id,
+            has_password: share.password_hash.is_some(),
+            max_plays: share.max_plays,
+            plays: share.plays,
+            allow_download: share.allow_download,
+            created_at: share.created_at,
+            expires_at: share.expires_at,
+        }
+    }
+}
+
+/// A shared song as shown to visitors.
+#[derive(Debug, Serialize)]
+pub struct SharedSong {
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub duration: Option<u32>,
+    pub track_number: Option<u32>,
+    pub year: Option<i32>,
+    pub stream_url: String,
+    pub cover_url: Option<String>,
+    /// Download URL, if the share allows downloads.
+    pub download_url: Option<String>,
+}
+
+/// A share as shown to visitors.
+#[derive(Debug, Serialize)]
+pub struct PublicShareResponse {
+    pub id: Uuid,
+    pub kind: ShareKind,
+    /// Song title, album or playlist name.
+    pub name: String,
+    /// Username of the owner.
+    pub shared_by: String,
+    pub allow_download: bool,
+    /// Plays left, if limited.
+    pub plays_left: Option<u32>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// When the media URLs stop working; fetch the share again for new ones.
+    pub urls_expire_at: DateTime<Utc>,
+    pub songs: Vec<SharedSong>,
+}
+
+/// Signature query parameters of share media URLs.
+#[derive(Debug, Deserialize)]
+pub struct ShareMediaQuery {
+    pub expires: i64,
+    pub sig: String,
+}
+
+/// Songs of an album, in track order.
+fn album_songs<'a>(index: &'a LibraryIndex, album_id: &str) -> Vec<&'a SongMetadata> {
+    let mut songs: Vec<&SongMetadata> = index
+        .songs
+        .iter()
+        .filter(|s| s.album_id == album_id)
+        .collect();
+    songs.sort_by(|a, b| {
+        a.track_number
+            .unwrap_or(u32::MAX)
+            .cmp(&b.track_number.unwrap_or(u32::MAX))
+            .then_with(|| a.title.cmp(&b.title))
+    });
+    songs
+}
+
+/// Resolve a shared item to its name and songs.
+///
+/// Playlists are resolved as their owner sees them, so a share stops
+/// working once the playlist is deleted.
+fn shared_songs<'a>(
+    data: &AppState,
+    index: &'a LibraryIndex,
+    kind: ShareKind,
+    item_id: &str,
+    owner_id: Uuid,
+) -> AppResult<(String, Vec<&'a SongMetadata>)> {
+    let not_found = || AppError::NotFound("Shared item no longer exists".to_string());
+    match kind {
+        ShareKind::Song => {
+            let song = index.song(item_id).ok_or_else(not_found)?;
+            Ok((song.title.clone(), vec![song]))
+        }
+        ShareKind::Album => {
+            let songs = album_songs(index, item_id);
+            let name = songs.first().ok_or_else(not_found)?.album.clone();
+            Ok((name, songs))
+        }
+        ShareKind::Playlist => {
+            let playlist = match data.playlist_repo.find_by_id(item_id)? {
+                Some(playlist) => Some(playlist),
+                None => index.playlist(item_id).cloned(),
+            }
+            .filter(|p| p.is_visible_to(owner_id))
+            .ok_or_else(not_found)?;
+            Ok((playlist.name.clone(), resolve_songs(index, &playlist)))
+        }
+    }
+}
+
+/// Look up a live share. A share stops working with its owner's account.
+fn find_share(data: &AppState, id: Uuid) -> AppResult<Share> {
+    let not_found = || AppError::NotFound(format!("Share not found: {}", id));
+    let share = data.share_repo.find_by_id(id)?.ok_or_else(not_found)?;
+    if data
+        .user_repo
+        .find_by_id(share.owner_id)?
+        .is_none_or(|owner| owner.disabled)
+    {
+        return Err(not_found());
+    }
+    Ok(share)
+}
+
+/// Look up a live share, checking its password if it has one.
+///
+/// Wrong passwords are throttled like failed logins, per client IP and
+/// per share.
+fn open_share(data: &AppState, req: &HttpRequest, id: Uuid) -> AppResult<Share> {
+    let share = find_share(data, id)?;
+
+    if let Some(hash) = &share.password_hash {
+        let password = req
+            .headers()
+            .get(SHARE_PASSWORD_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| AppError::Unauthorized("Share password required".to_string()))?;
+
+        let ip = client_ip(req).unwrap_or_else(|| "unknown".to_string());
+        // Usernames cannot contain ':', so this never counts against an account
+        let key = format!("share:{}", share.id);
+        data.login_throttle.check(&ip, &key).map_err(|e| match e {
+            AppError::TooManyRequests { retry_after, .. } => AppError::TooManyRequests {
+                message: "Too many incorrect share passwords; try again later".to_string(),
+                retry_after,
+            },
+            e => e,
+        })?;
+        if !verify_password(password, hash)? {
+            let failures = data.login_throttle.failed(&ip, &key);
+            tracing::warn!(share_id = %share.id, ip = %ip, failures, "Incorrect share password");
+            return Err(AppError::Unauthorized(
+                "Incorrect share password".to_string(),
+            ));
+        }
+        data.login_throttle.passed(&key);
+    }
+
+    Ok(share)
+}
+
+/// Look up a live share and one of its songs for a signed media URL.
+fn open_shared_song(
+    data: &AppState,
+    id: Uuid,
+    song_id: &str,
+    query: &ShareMediaQuery,
+) -> AppResult<(Share, SongMetadata)> {
+    if query.expires < Utc::now().timestamp()
+        || !data
+            .url_signer
+            .verify(song_id, Grantee::Share(id), query.expires, &query.sig)
+    {
+        return Err(AppError::Unauthorized(
+            "Invalid or expired share URL".to_string(),
+        ));
+    }
+
+    let share = find_share(data, id)?;
+    let index = data.library.index()?;
+    let (_, songs) = shared_songs(data, &index, share.kind, &share.item_id, share.owner_id)?;
+    let song = songs
+        .into_iter()
+        .find(|s| s.id == song_id)
+        .cloned()
+        .ok_or_else(|| AppError::song_not_found(song_id))?;
+
+    Ok((share, song))
+}
+
+/// Count a play unless the request continues one: any request for the
+/// same signed URL within the length of the song (at least a few minutes)
+/// counts once, whatever range it asks for.
+fn record_play(
+    data: &AppState,
+    share: &Share,
+    song: &SongMetadata,
+    query: &ShareMediaQuery,
+) -> AppResult<()> {
+    let window = Duration::seconds(i64::from(song.duration.unwrap_or(0))).max(MIN_PLAY_WINDOW);
+    data.share_repo
+        .record_play(share.id, &query.sig, window)?
+        .map(|_| ())
+        .ok_or_else(|| AppError::Forbidden("Share play limit reached".to_string()))
+}
+
+/// List the current user's shares.
+///
+/// GET /api/shares
+///
+/// Admins may pass `all=true` to list every user's shares.
+#[get("/api/shares")]
+pub async fn list_shares(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    query: web::Query<ListSharesQuery>,
+) -> AppResult<HttpResponse> {
+    let shares = if query.all {
+        user.require_admin()?;
+        data.share_repo.list_all()?
+    } else {
+        data.share_repo.list_by_owner(user.id)?
+    };
+
+    let base_url = &config::get().public_url;
+    let shares: Vec<ShareResponse> = shares
+        .into_iter()
+        .map(|share| ShareResponse::new(share, base_url))
+        .collect();
+    Ok(HttpResponse::Ok().json(shares))
+}
+
+/// Share a song, album or playlist.
+///
+/// POST /api/shares
+#[post("/api/shares")]
+pub async fn create_share(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    body: web::Json<CreateShareRequest>,
+) -> AppResult<HttpResponse> {
+    body.validate()
+        .map_err(|e| AppError::Validation(e.to_string()))?;
+
+    if data.share_repo.list_by_owner(user.id)?.len() >= MAX_SHARES_PER_USER {
+        return Err(AppError::Validation(format!(
+            "At most {} shares are allowed",
+            MAX_SHARES_PER_USER
+        )));
+    }
+
+    let body = body.into_inner();
+    let index = data.library.index()?;
+    shared_songs(&data, &index, body.kind, &body.item_id, user.id).map_err(|e| match e {
+        AppError::NotFound(_) => AppError::Validation(format!("Unknown item ID: {}", body.item_id)),
+        e => e,
+    })?;
+
+    let mut share = Share::new(user.id, body.kind, body.item_id);
+    share.password_hash = body.password.as_deref().map(hash_password).transpose()?;
+    share.max_plays = body.max_plays;
+    share.allow_download = body.allow_download;
+    share.expires_at = body
+        .expires_in_hours
+        .map(|hours| Utc::now() + Duration::hours(hours));
+
+    data.share_repo.delete_expired()?;
+    let share = data.share_repo.create(share)?;
+
+    tracing::info!(username = %user.username, share_id = %share.id, kind = ?share.kind, "Share link created");
+    Ok(HttpResponse::Created().json(ShareResponse::new(share, &config::get().public_url)))
+}
+
+/// Revoke a share (owner or admin).
+///
+/// DELETE /api/shares/{id}
+#[delete("/api/shares/{id}")]
+pub async fn delete_share(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+) -> AppResult<HttpResponse> {
+    let share = data
+        .share_repo
+        .find_by_id(*path)?
+        .filter(|s| s.owner_id == user.id || user.is_admin)
+        .ok_or_else(|| AppError::NotFound(format!("Share not found: {}", path)))?;
+    data.share_repo.delete(share.id)?;
+
+    tracing::info!(username = %user.username, share_id = %share.id, "Share link revoked");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Open a share (no auth required).
+///
+/// GET /api/share/{id}
+///
+/// Protected shares need the password in the `X-Share-Password` header.
+#[get("/api/share/{id}")]
+pub async fn get_public_share(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+) -> AppResult<HttpResponse> {
+    let share = open_share(&data, &req, *path)?;
+    let index = data.library.index()?;
+    let (name, songs) = shared_songs(&data, &index, share.kind, &share.item_id, share.owner_id)?;
+    let shared_by = data
+        .user_repo
+        .find_by_id(share.owner_id)?
+        .map(|u| u.username)
+        .unwrap_or_default();
+
+    let urls_expire_at = Utc::now() + MEDIA_URL_LIFETIME;
+    let expires = urls_expire_at.timestamp();
+    let base_url = &config::get().public_url;
+    let songs = songs
+        .into_iter()
+        .map(|song| {
+            let sig = data
+                .url_signer
+                .sign(&song.id, Grantee::Share(share.id), expires);
+            let url = |endpoint: &str| {
+                format!(
+                    "{}/api/share/{}/{}/{}?expires={}&sig={}",
+                    base_url,
+                    share.id,
+                    endpoint,
+                    encode_path_segment(&song.id),
+                    expires,
+                    sig
+                )
+            };
+            SharedSong {
+                id: song.id.clone(),
+                title: song.title.clone(),
+                artist: song.artist.clone(),
+                album: song.album.clone(),
+                duration: song.duration,
+                track_number: song.track_number,
+                year: song.year,
+                stream_url: url("stream"),
+                cover_url: song.has_cover.then(|| url("cover")),
+                download_url: share.allow_download.then(|| url("download")),
+            }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(PublicShareResponse {
+        id: share.id,
+        kind: share.kind,
+        name,
+        shared_by,
+        allow_download: share.allow_download,
+        plays_left: share.max_plays.map(|max| max.saturating_sub(share.plays)),
+        expires_at: share.expires_at,
+        urls_expire_at,
+        songs,
+    }))
+}
+
+/// Stream a shared song (signed URL from the share).
+///
+/// GET /api/share/{id}/stream/{song_id}
+///
+/// Supports range requests; seeking within a play does not count towards
+/// the play limit again.
+#[get("/api/share/{id}/stream/{song_id}")]
+pub async fn stream_shared_song(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<(Uuid, String)>,
+    query: web::Query<ShareMediaQuery>,
+) -> AppResult<HttpResponse> {
+    let (id, song_id) = path.into_inner();
+    let (share, song) = open_shared_song(&data, id, &song_id, &query)?;
+    record_play(&data, &share, &song, &query)?;
+
+    let file = NamedFile::open(resolve_music_file(&data.music_folder, &song.file)?)?;
+    Ok(file.into_response(&req))
+}
+
+/// Download a shared song, if the share allows downloads.
+///
+/// GET /api/share/{id}/download/{song_id}
+#[get("/api/share/{id}/download/{song_id}")]
+pub async fn download_shared_song(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<(Uuid, String)>,
+    query: web::Query<ShareMediaQuery>,
+) -> AppResult<HttpResponse> {
+    let (id, song_id) = path.into_inner();
+    let (share, song) = open_shared_song(&data, id, &song_id, &query)?;
+    if !share.allow_download {
+        return Err(AppError::Forbidden(
+            "This share does not allow downloads".to_string(),
+        ));
+    }
+    record_play(&data, &share, &song, &query)?;
+
+    let file = NamedFile::open(resolve_music_file(&data.music_folder, &song.file)?)?;
+    Ok(file
+        .set_content_disposition(header::ContentDisposition::attachment(song.file))
+        .into_response(&req))
+}
+
+/// Get the cover art of a shared song.
+///
+/// GET /api/share/{id}/cover/{song_id}
+#[get("/api/share/{id}/cover/{song_id}")]
+pub async fn get_shared_cover(
+    data: web::Data<AppState>,
+    path: web::Path<(Uuid, String)>,
+    query: web::Query<ShareMediaQuery>,
+) -> AppResult<HttpResponse> {
+    let (id, song_id) = path.into_inner();
+    let (_, song) = open_shared_song(&data, id, &song_id, &query)?;
+    let (mime, data) = read_cover(&resolve_music_file(&data.music_folder, &song.file)?)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, mime))
+        .insert_header((header::CACHE_CONTROL, "private, max-age=3600"))
+        .body(data))
+}
+
+/// Configure share routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_shares)
+        .service(create_share)
+        .service(delete_share)
+        .service(get_public_share)
+        .service(stream_shared_song)
+        .service(download_shared_song)
+        .service(get_shared_cover);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::User;
+    use crate::userdata::Playlist;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use tempfile::tempdir;
+
+    /// A password-protected share of an empty playlist.
+    fn protected_share(state: &AppState) -> (User, Share) {
+        let owner = state
+            .user_repo
+            .create(User::new("alice".to_string(), "hash".to_string(), false))
+            .unwrap();
+        let playlist = state
+            .playlist_repo
+            .create(Playlist::new(owner.id, "Picnic".to_string(), Vec::new()))
+            .unwrap();
+        let share = Share {
+            password_hash: Some(hash_password("picnic").unwrap()),
+            ..Share::new(owner.id, ShareKind::Playlist, playlist.id)
+        };
+        (owner, state.share_repo.create(share).unwrap())
+    }
+
+    fn open(share: &Share, ip: &str, password: &str) -> TestRequest {
+        TestRequest::get()
+            .uri(&format!("/api/share/{}", share.id))
+            .peer_addr(format!("{}:50000", ip).parse().unwrap())
+            .insert_header((SHARE_PASSWORD_HEADER, password))
+    }
+
+    #[actix_web::test]
+    async fn test_wrong_share_passwords_are_throttled() {
+        let dir = tempdir().unwrap();
+        let state = AppState::for_tests(dir.path());
+        let (_, share) = protected_share(&state);
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .configure(configure),
+        )
+        .await;
+
+        let response = call_service(&app, open(&share, "203.0.113.7", "picnic").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        for _ in 0..4 {
+            let response =
+                call_service(&app, open(&share, "203.0.113.7", "guess").to_request()).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        // Refused without checking, even with the right password
+        let response = call_service(&app, open(&share, "203.0.113.7", "picnic").to_request()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+        // The share itself is backing off, whatever the address
+        let response = call_service(&app, open(&share, "198.51.100.1", "guess").to_request()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[actix_web::test]
+    async fn test_share_stops_working_with_disabled_owner() {
+        let dir = tempdir().unwrap();
+        let state = AppState::for_tests(dir.path());
+        let (owner, share) = protected_share(&state);
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .configure(configure),
+        )
+        .await;
+
+        state
+            .user_repo
+            .modify(owner.id, |owner| {
+                owner.disabled = true;
+                Ok(())
+            })
+            .unwrap();
+        let response = call_service(&app, open(&share, "203.0.113.7", "picnic").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
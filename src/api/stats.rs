@@ -0,0 +1,147 @@
+//! Listening statistics API endpoints.
+
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Utc};
+use serde::Deserialize;
+
+use crate::auth::AuthenticatedUser;
+use crate::error::{AppError, AppResult};
+use crate::models::AppState;
+use crate::stats::{self, TopKind, Window};
+use crate::userdata::HistoryRepository;
+
+/// Query parameters selecting a statistics window.
+#[derive(Debug, Deserialize)]
+pub struct StatsQuery {
+    /// Start of the window (inclusive).
+    pub from: Option<DateTime<Utc>>,
+    /// End of the window (exclusive).
+    pub to: Option<DateTime<Utc>>,
+    /// Shorthand for a window covering the last N days.
+    pub days: Option<u32>,
+    /// Listener's UTC offset in minutes, for hour and day buckets.
+    #[serde(default)]
+    pub utc_offset: i32,
+    /// Maximum entries in top lists (max 100).
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+fn default_limit() -> usize {
+    10
+}
+
+impl StatsQuery {
+    /// Resolve the requested window.
+    fn window(&self) -> AppResult<Window> {
+        if self.days.is_some() && self.from.is_some() {
+            return Err(AppError::Validation(
+                "Use either days or from, not both".to_string(),
+            ));
+        }
+
+        let from = match self.days {
+            Some(0) => return Err(AppError::Validation("days must be at least 1".to_string())),
+            Some(days) => Some(Utc::now() - Duration::days(i64::from(days))),
+            None => self.from,
+        };
+
+        if let (Some(from), Some(to)) = (from, self.to) {
+            if from >= to {
+                return Err(AppError::Validation("from must be before to".to_string()));
+            }
+        }
+
+        Ok(Window { from, to: self.to })
+    }
+
+    /// Resolve the listener's UTC offset.
+    fn offset(&self) -> AppResult<FixedOffset> {
+        // Real-world offsets range from UTC-12:00 to UTC+14:00
+        if !(-720..=840).contains(&self.utc_offset) {
+            return Err(AppError::Validation(
+                "utc_offset must be between -720 and 840 minutes".to_string(),
+            ));
+        }
+        FixedOffset::east_opt(self.utc_offset * 60)
+            .ok_or_else(|| AppError::Validation("Invalid utc_offset".to_string()))
+    }
+
+    fn limit(&self) -> usize {
+        self.limit.clamp(1, 100)
+    }
+}
+
+/// Get aggregate listening statistics.
+///
+/// GET /api/stats
+#[get("/api/stats")]
+pub async fn get_overview(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    query: web::Query<StatsQuery>,
+) -> AppResult<HttpResponse> {
+    let window = query.window()?;
+    let offset = query.offset()?;
+
+    let index = data.library.index()?;
+    let history = data.history_repo.list_by_user(user.id)?;
+    let plays = window.filter(&history);
+
+    Ok(HttpResponse::Ok().json(stats::overview(&plays, &index, offset, Utc::now())))
+}
+
+/// Get the most played artists, albums, tracks or genres.
+///
+/// GET /api/stats/top/{kind}
+#[get("/api/stats/top/{kind}")]
+pub async fn get_top(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<TopKind>,
+    query: web::Query<StatsQuery>,
+) -> AppResult<HttpResponse> {
+    let window = query.window()?;
+
+    let index = data.library.index()?;
+    let history = data.history_repo.list_by_user(user.id)?;
+    let plays = window.filter(&history);
+
+    Ok(HttpResponse::Ok().json(stats::top(&plays, &index, path.into_inner(), query.limit())))
+}
+
+/// Get the year-in-review report.
+///
+/// GET /api/stats/year/{year}
+#[get("/api/stats/year/{year}")]
+pub async fn get_year_in_review(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<i32>,
+    query: web::Query<StatsQuery>,
+) -> AppResult<HttpResponse> {
+    let year = path.into_inner();
+    let offset = query.offset()?;
+
+    if !(1970..=Utc::now().year()).contains(&year) {
+        return Err(AppError::Validation(format!("Invalid year: {}", year)));
+    }
+
+    let index = data.library.index()?;
+    let history = data.history_repo.list_by_user(user.id)?;
+
+    Ok(HttpResponse::Ok().json(stats::year_in_review(
+        &history,
+        &index,
+        year,
+        offset,
+        query.limit(),
+    )))
+}
+
+/// Configure statistics routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_overview)
+        .service(get_top)
+        .service(get_year_in_review);
+}
@@ -0,0 +1,49 @@
+//! Subsonic media annotation endpoints.
+
+use chrono::{DateTime, Utc};
+
+use super::response::SubsonicError;
+use super::{Context, Reply};
+use crate::api::history::{record_play, set_now_playing};
+
+/// `scrobble`
+///
+/// `id` and `time` (milliseconds since the epoch) may repeat to submit
+/// several plays at once. With `submission=false` the first song becomes
+/// the user's now-playing song instead.
+pub(super) fn scrobble(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    let ids = ctx.params.all("id");
+    if ids.is_empty() {
+        return Err(SubsonicError::missing_parameter("id"));
+    }
+
+    let mut times = Vec::new();
+    for value in ctx.params.all("time") {
+        let time = value
+            .parse::<i64>()
+            .ok()
+            .and_then(DateTime::<Utc>::from_timestamp_millis)
+            .ok_or_else(|| SubsonicError::generic("Invalid value for time"))?;
+        times.push(time);
+    }
+
+    let submission: bool = ctx.params.parse_or("submission", true)?;
+    let index = ctx.data.library.index()?;
+    let now = Utc::now();
+
+    for (position, id) in ids.iter().enumerate() {
+        let song = index
+            .song(id)
+            .ok_or_else(|| SubsonicError::not_found("Song not found"))?;
+        let time = times.get(position).copied().unwrap_or(now);
+
+        if submission {
+            record_play(ctx.data, ctx.user.id, song, time)?;
+        } else {
+            set_now_playing(ctx.data, ctx.user.id, song, time, ctx.params.get("c"));
+            break;
+        }
+    }
+
+    Ok(Reply::Data(None))
+}
@@ -0,0 +1,403 @@
+//! Subsonic browsing and searching endpoints, and the element builders
+//! shared with the other endpoint groups.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::collections::{BTreeMap, HashMap};
+
+use super::response::{Element, SubsonicError};
+use super::{Context, Reply, MUSIC_FOLDER_ID};
+use crate::api::music::annotate_songs;
+use crate::library::LibraryIndex;
+use crate::models::SongMetadata;
+use crate::userdata::Annotation;
+
+/// Articles ignored when indexing and sorting artists.
+const IGNORED_ARTICLES: &[&str] = &["The", "El", "La", "Los", "Las", "Le", "Les"];
+
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// A user's view of the library: annotated songs plus album and artist annotations.
+pub(super) struct UserLibrary {
+    pub songs: Vec<SongMetadata>,
+    annotations: HashMap<String, Annotation>,
+    created: HashMap<String, DateTime<Utc>>,
+    sizes: HashMap<String, u64>,
+}
+
+impl UserLibrary {
+    /// Load the library as seen by the requesting user.
+    pub fn load(ctx: &Context<'_>, index: &LibraryIndex) -> Result<Self, SubsonicError> {
+        let mut songs = index.songs.clone();
+        annotate_songs(ctx.data, ctx.user.id, &mut songs)?;
+        let annotations = ctx.data.annotation_repo.map_for_user(ctx.user.id)?;
+
+        let mut created = HashMap::new();
+        let mut sizes = HashMap::new();
+        for song in &songs {
+            if let Ok(meta) = std::fs::metadata(ctx.data.music_folder.join(&song.file)) {
+                sizes.insert(song.id.clone(), meta.len());
+                if let Ok(modified) = meta.modified() {
+                    created.insert(song.id.clone(), DateTime::<Utc>::from(modified));
+                }
+            }
+        }
+
+        Ok(Self {
+            songs,
+            annotations,
+            created,
+            sizes,
+        })
+    }
+
+    /// Find a song by ID.
+    pub fn song(&self, id: &str) -> Option<&SongMetadata> {
+        self.songs.iter().find(|s| s.id == id)
+    }
+
+    /// Songs of an album, in track order.
+    pub fn album_songs(&self, album_id: &str) -> Vec<&SongMetadata> {
+        let mut songs: Vec<&SongMetadata> = self
+            .songs
+            .iter()
+            .filter(|s| s.album_id == album_id)
+            .collect();
+        songs.sort_by(|a, b| {
+            a.track_number
+                .unwrap_or(u32::MAX)
+                .cmp(&b.track_number.unwrap_or(u32::MAX))
+                .then_with(|| a.title.cmp(&b.title))
+        });
+        songs
+    }
+
+    /// Album IDs of an artist, sorted by year then name.
+    fn artist_album_ids(&self, artist_id: &str) -> Vec<String> {
+        let mut albums: Vec<(&SongMetadata, String)> = Vec::new();
+        for song in self.songs.iter().filter(|s| s.artist_id == artist_id) {
+            if !albums.iter().any(|(_, id)| *id == song.album_id) {
+                albums.push((song, song.album_id.clone()));
+            }
+        }
+        albums.sort_by(|(a, _), (b, _)| a.year.cmp(&b.year).then_with(|| a.album.cmp(&b.album)));
+        albums.into_iter().map(|(_, id)| id).collect()
+    }
+
+    /// All album IDs, sorted by name.
+    pub fn album_ids(&self) -> Vec<String> {
+        let mut albums: BTreeMap<(String, String), ()> = BTreeMap::new();
+        for song in &self.songs {
+            albums.insert((song.album.to_lowercase(), song.album_id.clone()), ());
+        }
+        albums.into_keys().map(|(_, id)| id).collect()
+    }
+
+    /// All artists as (ID, name), sorted ignoring leading articles.
+    pub fn artists(&self) -> Vec<(String, String)> {
+        let mut artists: Vec<(String, String)> = Vec::new();
+        for song in &self.songs {
+            if !artists.iter().any(|(id, _)| *id == song.artist_id) {
+                artists.push((song.artist_id.clone(), song.artist.clone()));
+            }
+        }
+        artists.sort_by_key(|(_, name)| sort_name(name).to_lowercase());
+        artists
+    }
+
+    /// Build a song (`child`) element.
+    pub fn song_element(&self, name: &'static str, song: &SongMetadata) -> Element {
+        Element::new(name)
+            .attr("id", &song.id)
+            .attr("parent", &song.album_id)
+            .attr("isDir", false)
+            .attr("title", &song.title)
+            .attr("album", &song.album)
+            .attr("artist", &song.artist)
+            .attr_opt("track", song.track_number)
+            .attr_opt("year", song.year)
+            .attr_opt("genre", song.genre.as_ref())
+            .attr_opt("coverArt", song.has_cover.then(|| song.id.clone()))
+            .attr_opt("size", self.sizes.get(&song.id).copied())
+            .attr(
+                "contentType",
+                actix_files::file_extension_to_mime(&song.format).to_string(),
+            )
+            .attr("suffix", &song.format)
+            .attr_opt("duration", song.duration)
+            .attr("path", &song.file)
+            .attr("playCount", song.play_count)
+            .attr_opt("played", song.last_played.map(timestamp))
+            .attr_opt(
+                "created",
+                self.created.get(&song.id).copied().map(timestamp),
+            )
+            .attr("albumId", &song.album_id)
+            .attr("artistId", &song.artist_id)
+            .attr("type", "music")
+            .attr("mediaType", "song")
+            .attr_opt("starred", song.starred.map(timestamp))
+            .attr_opt("userRating", song.rating)
+    }
+
+    /// Build an album element, optionally with its songs.
+    pub fn album_element(&self, album_id: &str, with_songs: bool) -> Option<Element> {
+        let songs = self.album_songs(album_id);
+        let first = songs.first()?;
+        let annotation = self.annotations.get(album_id);
+
+        let element = Element::new("album")
+            .attr("id", album_id)
+            .attr("name", &first.album)
+            .attr("artist", &first.artist)
+            .attr("artistId", &first.artist_id)
+            .attr_opt(
+                "coverArt",
+                songs
+                    .iter()
+                    .any(|s| s.has_cover)
+                    .then(|| album_id.to_string()),
+            )
+            .attr("songCount", songs.len())
+            .attr(
+                "duration",
+                songs
+                    .iter()
+                    .map(|s| u64::from(s.duration.unwrap_or(0)))
+                    .sum::<u64>(),
+            )
+            .attr(
+                "playCount",
+                songs.iter().map(|s| u64::from(s.play_count)).sum::<u64>(),
+            )
+            .attr_opt(
+                "created",
+                songs
+                    .iter()
+                    .filter_map(|s| self.created.get(&s.id))
+                    .min()
+                    .copied()
+                    .map(timestamp),
+            )
+            .attr_opt("year", songs.iter().find_map(|s| s.year))
+            .attr_opt("genre", songs.iter().find_map(|s| s.genre.clone()))
+            .attr_opt(
+                "starred",
+                annotation.and_then(|a| a.starred_at).map(timestamp),
+            )
+            .attr_opt("userRating", annotation.and_then(|a| a.rating));
+
+        if !with_songs {
+            return Some(element);
+        }
+        let songs = songs.iter().map(|s| self.song_element("song", s)).collect();
+        Some(element.list("song", songs))
+    }
+
+    /// Build an artist element, optionally with its albums.
+    pub fn artist_element(&self, artist_id: &str, with_albums: bool) -> Option<Element> {
+        let name = &self.songs.iter().find(|s| s.artist_id == artist_id)?.artist;
+        let album_ids = self.artist_album_ids(artist_id);
+        let annotation = self.annotations.get(artist_id);
+        let cover = album_ids
+            .iter()
+            .find(|id| self.songs.iter().any(|s| s.album_id == **id && s.has_cover));
+
+        let element = Element::new("artist")
+            .attr("id", artist_id)
+            .attr("name", name)
+            .attr("albumCount", album_ids.len())
+            .attr_opt("coverArt", cover)
+            .attr_opt(
+                "starred",
+                annotation.and_then(|a| a.starred_at).map(timestamp),
+            )
+            .attr_opt("userRating", annotation.and_then(|a| a.rating));
+
+        if !with_albums {
+            return Some(element);
+        }
+        let albums = album_ids
+            .iter()
+            .filter_map(|id| self.album_element(id, false))
+            .collect();
+        Some(element.list("album", albums))
+    }
+}
+
+/// Artist name without a leading ignored article.
+fn sort_name(name: &str) -> &str {
+    for article in IGNORED_ARTICLES {
+        if let Some(rest) = name.strip_prefix(article) {
+            if let Some(rest) = rest.strip_prefix(' ') {
+                return rest.trim_start();
+            }
+        }
+    }
+    name
+}
+
+/// Index letter of an artist name.
+fn index_letter(name: &str) -> String {
+    match sort_name(name).chars().next() {
+        Some(c) if c.is_alphabetic() => c.to_uppercase().collect(),
+        _ => "#".to_string(),
+    }
+}
+
+/// `getMusicFolders`
+pub(super) fn get_music_folders(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    let name = ctx
+        .data
+        .music_folder
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "Music".to_string());
+
+    Ok(Reply::Data(Some(Element::new("musicFolders").list(
+        "musicFolder",
+        vec![Element::new("musicFolder")
+            .attr("id", MUSIC_FOLDER_ID)
+            .attr("name", name)],
+    ))))
+}
+
+/// `getArtists`
+pub(super) fn get_artists(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    let index = ctx.data.library.index()?;
+    let library = UserLibrary::load(ctx, &index)?;
+
+    let mut groups: Vec<(String, Vec<Element>)> = Vec::new();
+    for (id, name) in library.artists() {
+        let letter = index_letter(&name);
+        let Some(element) = library.artist_element(&id, false) else {
+            continue;
+        };
+        match groups.iter_mut().find(|(l, _)| *l == letter) {
+            Some((_, artists)) => artists.push(element),
+            None => groups.push((letter, vec![element])),
+        }
+    }
+    // Keep "#" last, as most clients expect
+    groups.sort_by(|(a, _), (b, _)| (a == "#").cmp(&(b == "#")).then_with(|| a.cmp(b)));
+
+    let indexes = groups
+        .into_iter()
+        .map(|(letter, artists)| {
+            Element::new("index")
+                .attr("name", letter)
+                .list("artist", artists)
+        })
+        .collect();
+
+    Ok(Reply::Data(Some(
+        Element::new("artists")
+            .attr("ignoredArticles", IGNORED_ARTICLES.join(" "))
+            .list("index", indexes),
+    )))
+}
+
+/// `getArtist`
+pub(super) fn get_artist(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    let id = ctx.params.required("id")?;
+    let index = ctx.data.library.index()?;
+    let library = UserLibrary::load(ctx, &index)?;
+
+    library
+        .artist_element(id, true)
+        .map(|artist| Reply::Data(Some(artist)))
+        .ok_or_else(|| SubsonicError::not_found("Artist not found"))
+}
+
+/// `getAlbum`
+pub(super) fn get_album(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    let id = ctx.params.required("id")?;
+    let index = ctx.data.library.index()?;
+    let library = UserLibrary::load(ctx, &index)?;
+
+    library
+        .album_element(id, true)
+        .map(|album| Reply::Data(Some(album)))
+        .ok_or_else(|| SubsonicError::not_found("Album not found"))
+}
+
+/// Page of `items` selected by `{prefix}Count` and `{prefix}Offset` parameters.
+fn page<T>(ctx: &Context<'_>, prefix: &str, items: Vec<T>) -> Result<Vec<T>, SubsonicError> {
+    let count: usize = ctx.params.parse_or(&format!("{}Count", prefix), 20)?;
+    let offset: usize = ctx.params.parse_or(&format!("{}Offset", prefix), 0)?;
+    Ok(items
+        .into_iter()
+        .skip(offset)
+        .take(count.min(500))
+        .collect())
+}
+
+/// `search3`
+///
+/// An empty query matches everything, which clients use to sync the library.
+pub(super) fn search3(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    let query = ctx.params.get("query").unwrap_or("");
+    let terms: Vec<String> = query
+        .trim_matches('"')
+        .split_whitespace()
+        .map(|t| t.trim_end_matches('*').to_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect();
+    let matches = |fields: &[&str]| {
+        let haystack = fields.join(" ").to_lowercase();
+        terms.iter().all(|t| haystack.contains(t.as_str()))
+    };
+
+    let index = ctx.data.library.index()?;
+    let library = UserLibrary::load(ctx, &index)?;
+
+    let artists: Vec<Element> = library
+        .artists()
+        .into_iter()
+        .filter(|(_, name)| matches(&[name]))
+        .filter_map(|(id, _)| library.artist_element(&id, false))
+        .collect();
+
+    let albums: Vec<Element> = library
+        .album_ids()
+        .into_iter()
+        .filter(|id| {
+            library
+                .songs
+                .iter()
+                .find(|s| s.album_id == *id)
+                .map(|s| matches(&[&s.album, &s.artist]))
+                .unwrap_or(false)
+        })
+        .filter_map(|id| library.album_element(&id, false))
+        .collect();
+
+    let songs: Vec<Element> = library
+        .songs
+        .iter()
+        .filter(|s| matches(&[&s.title, &s.artist, &s.album]))
+        .map(|s| library.song_element("song", s))
+        .collect();
+
+    Ok(Reply::Data(Some(
+        Element::new("searchResult3")
+            .list("artist", page(ctx, "artist", artists)?)
+            .list("album", page(ctx, "album", albums)?)
+            .list("song", page(ctx, "song", songs)?),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_artist_index_ignores_articles() {
+        assert_eq!(sort_name("The Beatles"), "Beatles");
+        assert_eq!(sort_name("Theatre of Tragedy"), "Theatre of Tragedy");
+        assert_eq!(index_letter("The Beatles"), "B");
+        assert_eq!(index_letter("étoile"), "É");
+        assert_eq!(index_letter("2Pac"), "#");
+    }
+}
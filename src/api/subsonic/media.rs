@@ -0,0 +1,68 @@
+//! Subsonic media retrieval endpoints.
+//!
+//! Files are served as stored; transcoding parameters such as `maxBitRate`
+//! and `format` are accepted and ignored.
+
+use actix_files::NamedFile;
+use actix_web::{http::header, HttpResponse};
+
+use super::response::SubsonicError;
+use super::{Context, Reply};
+use crate::api::music::{read_cover, resolve_music_file};
+use crate::error::AppError;
+
+/// Open the file of a song for streaming.
+fn song_file(ctx: &Context<'_>) -> Result<NamedFile, SubsonicError> {
+    let id = ctx.params.required("id")?;
+    let index = ctx.data.library.index()?;
+    let song = index
+        .song(id)
+        .ok_or_else(|| SubsonicError::not_found("Song not found"))?;
+
+    let path = resolve_music_file(&ctx.data.music_folder, &song.file)?;
+    NamedFile::open(&path).map_err(|e| AppError::from(e).into())
+}
+
+/// `stream`
+pub(super) fn stream(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    let file = song_file(ctx)?;
+    Ok(Reply::File(file.into_response(ctx.req)))
+}
+
+/// `download`
+pub(super) fn download(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    let file = song_file(ctx)?.set_content_disposition(header::ContentDisposition {
+        disposition: header::DispositionType::Attachment,
+        parameters: Vec::new(),
+    });
+    Ok(Reply::File(file.into_response(ctx.req)))
+}
+
+/// `getCoverArt`
+///
+/// Accepts song, album and artist IDs; albums and artists use the cover of
+/// their first song that has one. The `size` parameter is ignored.
+pub(super) fn get_cover_art(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    let id = ctx.params.required("id")?;
+    let index = ctx.data.library.index()?;
+    let song = index
+        .song(id)
+        .filter(|s| s.has_cover)
+        .or_else(|| {
+            index
+                .songs
+                .iter()
+                .find(|s| s.has_cover && (s.album_id == id || s.artist_id == id))
+        })
+        .ok_or_else(|| SubsonicError::not_found("Cover art not found"))?;
+
+    let path = resolve_music_file(&ctx.data.music_folder, &song.file)?;
+    let (mime, data) = read_cover(&path)?;
+
+    Ok(Reply::File(
+        HttpResponse::Ok()
+            .insert_header((header::CONTENT_TYPE, mime))
+            .insert_header((header::CACHE_CONTROL, "public, max-age=86400"))
+            .body(data),
+    ))
+}
@@ -0,0 +1,241 @@
+//! Subsonic/OpenSubsonic API compatibility layer.
+//!
+//! Serves the subset of the Subsonic API needed by common clients
+//! (DSub, Symfonium, play:Sub, Feishin, ...) under `/rest/{method}`, with
+//! or without the `.view` suffix, via GET or form-encoded POST.
+//!
+//! Clients authenticate per request with `u` plus either a token (`t`,
+//! `s`) or a password (`p`). Token authentication needs a password the
+//! server can recover, so each user sets a separate Subsonic password with
+//! `PUT /api/subsonic/password`; it is stored encrypted. Plain `p` also
+//! accepts the account password, unless two-factor authentication is on.
+
+mod annotation;
+mod browsing;
+mod media;
+mod playlists;
+mod queue;
+pub mod request;
+pub mod response;
+
+use actix_web::{delete, http::header, put, web, HttpRequest, HttpResponse};
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::api::auth::client_ip;
+use crate::auth::{AuthenticatedUser, UserRepository};
+use crate::error::{AppError, AppResult};
+use crate::models::AppState;
+use request::{authenticate, Params};
+use response::{Element, SubsonicError};
+
+/// Implemented Subsonic API version.
+pub const API_VERSION: &str = "1.16.1";
+
+/// Server type reported to OpenSubsonic clients.
+pub const SERVER_TYPE: &str = "ferrum";
+
+/// ID of the single music folder.
+const MUSIC_FOLDER_ID: u32 = 1;
+
+/// Length of generated Subsonic passwords.
+const GENERATED_PASSWORD_LEN: usize = 24;
+
+/// State shared by the handlers of one request.
+pub(crate) struct Context<'a> {
+    pub req: &'a HttpRequest,
+    pub data: &'a AppState,
+    pub params: &'a Params,
+    pub user: AuthenticatedUser,
+}
+
+/// Result of a method: a response payload, or a raw HTTP response for
+/// binary data.
+pub(crate) enum Reply {
+    Data(Option<Element>),
+    File(HttpResponse),
+}
+
+/// Dispatch a request to its method handler.
+fn dispatch(
+    req: &HttpRequest,
+    data: &AppState,
+    method: &str,
+    params: &Params,
+) -> Result<Reply, SubsonicError> {
+    // Allowed without credentials, per the OpenSubsonic specification
+    if method == "getOpenSubsonicExtensions" {
+        return Ok(Reply::Data(Some(
+            Element::new("openSubsonicExtensions").list("openSubsonicExtensions", Vec::new()),
+        )));
+    }
+
+    let ip = client_ip(req).unwrap_or_else(|| "unknown".to_string());
+    let user = authenticate(
+        params,
+        &data.user_repo,
+        &data.secret_box,
+        &data.login_throttle,
+        &ip,
+    )?;
+    let ctx = Context {
+        req,
+        data,
+        params,
+        user: AuthenticatedUser::from_user(user),
+    };
+
+    match method {
+        "ping" => Ok(Reply::Data(None)),
+        "getLicense" => Ok(Reply::Data(Some(
+            Element::new("license").attr("valid", true),
+        ))),
+        "getMusicFolders" => browsing::get_music_folders(&ctx),
+        "getArtists" => browsing::get_artists(&ctx),
+        "getArtist" => browsing::get_artist(&ctx),
+        "getAlbum" => browsing::get_album(&ctx),
+        "search3" => browsing::search3(&ctx),
+        "stream" => media::stream(&ctx),
+        "download" => media::download(&ctx),
+        "getCoverArt" => media::get_cover_art(&ctx),
+        "getPlaylists" => playlists::get_playlists(&ctx),
+        "getPlaylist" => playlists::get_playlist(&ctx),
+        "createPlaylist" => playlists::create_playlist(&ctx),
+        "updatePlaylist" => playlists::update_playlist(&ctx),
+        "deletePlaylist" => playlists::delete_playlist(&ctx),
+        "getPlayQueue" => queue::get_play_queue(&ctx),
+        "savePlayQueue" => queue::save_play_queue(&ctx),
+        "scrobble" => annotation::scrobble(&ctx),
+        _ => Err(SubsonicError::not_found(format!(
+            "Method not implemented: {}",
+            method
+        ))),
+    }
+}
+
+/// Handle a Subsonic API request.
+///
+/// GET/POST /rest/{method}
+async fn handle(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Bytes,
+) -> HttpResponse {
+    let is_form = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("application/x-www-form-urlencoded"))
+        .unwrap_or(false);
+    let form = if is_form {
+        std::str::from_utf8(&body).ok()
+    } else {
+        None
+    };
+
+    let params = Params::parse(req.query_string(), form);
+    let format = params.format();
+    let method = path.trim_end_matches(".view");
+
+    match dispatch(&req, &data, method, &params) {
+        Ok(Reply::Data(payload)) => response::ok(&format, payload),
+        Ok(Reply::File(response)) => response,
+        Err(e) => response::error(&format, &e),
+    }
+}
+
+/// Request body for setting a Subsonic password.
+#[derive(Debug, Default, Deserialize, Validate)]
+pub struct SubsonicPasswordRequest {
+    /// New password; a random one is generated if omitted.
+    #[validate(length(min = 8, max = 128, message = "Password must be 8-128 characters"))]
+    pub password: Option<String>,
+}
+
+/// Subsonic credentials as returned to the user.
+#[derive(Debug, Serialize)]
+pub struct SubsonicPasswordResponse {
+    pub username: String,
+    pub password: String,
+}
+
+/// Refuse Subsonic password changes through an API key, which would let a
+/// leaked key mint a password that works without it.
+fn require_login(user: &AuthenticatedUser) -> AppResult<()> {
+    if user.api_key_id.is_some() {
+        return Err(AppError::Forbidden(
+            "Subsonic passwords cannot be managed with an API key".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Set the current user's Subsonic password.
+///
+/// PUT /api/subsonic/password
+///
+/// Returns the password so a generated one can be copied into the client.
+#[put("/api/subsonic/password")]
+pub async fn set_subsonic_password(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    body: Option<web::Json<SubsonicPasswordRequest>>,
+) -> AppResult<HttpResponse> {
+    require_login(&user)?;
+    let body = body.map(|b| b.into_inner()).unwrap_or_default();
+    body.validate()
+        .map_err(|e| AppError::Validation(e.to_string()))?;
+
+    let password = body.password.unwrap_or_else(|| {
+        OsRng
+            .sample_iter(&Alphanumeric)
+            .take(GENERATED_PASSWORD_LEN)
+            .map(char::from)
+            .collect()
+    });
+
+    let sealed = data.secret_box.seal(&password)?;
+    data.user_repo.modify(user.id, |account| {
+        account.subsonic_password = Some(sealed);
+        Ok(())
+    })?;
+
+    tracing::info!(username = %user.username, "Subsonic password set");
+
+    Ok(HttpResponse::Ok().json(SubsonicPasswordResponse {
+        username: user.username,
+        password,
+    }))
+}
+
+/// Remove the current user's Subsonic password, disabling token authentication.
+///
+/// DELETE /api/subsonic/password
+#[delete("/api/subsonic/password")]
+pub async fn delete_subsonic_password(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+) -> AppResult<HttpResponse> {
+    require_login(&user)?;
+    data.user_repo.modify(user.id, |account| {
+        account.subsonic_password = None;
+        Ok(())
+    })?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Configure Subsonic routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/rest/{method}")
+            .route(web::get().to(handle))
+            .route(web::post().to(handle)),
+    )
+    .service(set_subsonic_password)
+    .service(delete_subsonic_password);
+}
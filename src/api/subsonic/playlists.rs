@@ -0,0 +1,213 @@
+//! Subsonic playlist endpoints, backed by the regular playlist repository.
+//!
+//! Playlists are private to their owner, so `public` is always false.
+//! Playlists imported from the music folder are listed as read-only.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use super::browsing::UserLibrary;
+use super::response::{Element, SubsonicError};
+use super::{Context, Reply};
+use crate::api::playlists::{
+    find_owned_playlist, find_playlist, publish_playlist_event, validate_song_ids,
+};
+use crate::events::kind;
+use crate::userdata::{Playlist, PlaylistRepository};
+
+/// Owner shown for playlists imported from the music folder.
+const LIBRARY_OWNER: &str = "(library)";
+
+pub(super) fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Build a playlist element, optionally with its entries.
+fn playlist_element(
+    ctx: &Context<'_>,
+    library: &UserLibrary,
+    playlist: &Playlist,
+    with_entries: bool,
+) -> Element {
+    let songs: Vec<_> = playlist
+        .song_ids
+        .iter()
+        .filter_map(|id| library.song(id))
+        .collect();
+    let owner = match playlist.owner_id {
+        Some(_) => ctx.user.username.as_str(),
+        None => LIBRARY_OWNER,
+    };
+
+    let element = Element::new("playlist")
+        .attr("id", &playlist.id)
+        .attr("name", &playlist.name)
+        .attr("owner", owner)
+        .attr("public", false)
+        .attr("readonly", playlist.read_only)
+        .attr("songCount", songs.len())
+        .attr(
+            "duration",
+            songs
+                .iter()
+                .map(|s| u64::from(s.duration.unwrap_or(0)))
+                .sum::<u64>(),
+        )
+        .attr("created", timestamp(playlist.created_at))
+        .attr("changed", timestamp(playlist.updated_at))
+        .attr_opt(
+            "coverArt",
+            songs.iter().find(|s| s.has_cover).map(|s| s.id.clone()),
+        );
+
+    if !with_entries {
+        return element;
+    }
+    let entries = songs
+        .iter()
+        .map(|s| library.song_element("entry", s))
+        .collect();
+    element.list("entry", entries)
+}
+
+fn validate_name(name: &str) -> Result<(), SubsonicError> {
+    match name.chars().count() {
+        1..=128 => Ok(()),
+        _ => Err(SubsonicError::generic("Name must be 1-128 characters")),
+    }
+}
+
+/// `getPlaylists`
+pub(super) fn get_playlists(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    if let Some(username) = ctx.params.get("username") {
+        if username != ctx.user.username {
+            return Err(SubsonicError::not_authorized(
+                "Playlists of other users are private",
+            ));
+        }
+    }
+
+    let index = ctx.data.library.index()?;
+    let library = UserLibrary::load(ctx, &index)?;
+    let mut playlists = ctx.data.playlist_repo.list_by_owner(ctx.user.id)?;
+    playlists.extend(index.playlists.iter().cloned());
+
+    let elements = playlists
+        .iter()
+        .map(|p| playlist_element(ctx, &library, p, false))
+        .collect();
+
+    Ok(Reply::Data(Some(
+        Element::new("playlists").list("playlist", elements),
+    )))
+}
+
+/// `getPlaylist`
+pub(super) fn get_playlist(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    let id = ctx.params.required("id")?;
+    let index = ctx.data.library.index()?;
+    let playlist = find_playlist(ctx.data, &index, &ctx.user, id)?;
+    let library = UserLibrary::load(ctx, &index)?;
+
+    Ok(Reply::Data(Some(playlist_element(
+        ctx, &library, &playlist, true,
+    ))))
+}
+
+/// `createPlaylist`
+///
+/// Creates a playlist from `name`, or replaces the songs of `playlistId`.
+pub(super) fn create_playlist(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    let song_ids: Vec<String> = ctx
+        .params
+        .all("songId")
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let index = ctx.data.library.index()?;
+    validate_song_ids(&index, &song_ids)?;
+
+    let playlist = match (ctx.params.get("playlistId"), ctx.params.get("name")) {
+        (Some(id), name) => {
+            let mut playlist = find_owned_playlist(ctx.data, &ctx.user, id)?;
+            if let Some(name) = name {
+                validate_name(name)?;
+                playlist.name = name.to_string();
+            }
+            playlist.song_ids = song_ids;
+            playlist.updated_at = Utc::now();
+            let playlist = ctx.data.playlist_repo.update(playlist)?;
+            publish_playlist_event(ctx.data, kind::PLAYLIST_UPDATED, &playlist, None);
+            playlist
+        }
+        (None, Some(name)) => {
+            validate_name(name)?;
+            let playlist = ctx.data.playlist_repo.create(Playlist::new(
+                ctx.user.id,
+                name.to_string(),
+                song_ids,
+            ))?;
+            publish_playlist_event(ctx.data, kind::PLAYLIST_CREATED, &playlist, None);
+            playlist
+        }
+        (None, None) => return Err(SubsonicError::missing_parameter("name or playlistId")),
+    };
+
+    let library = UserLibrary::load(ctx, &index)?;
+    Ok(Reply::Data(Some(playlist_element(
+        ctx, &library, &playlist, true,
+    ))))
+}
+
+/// `updatePlaylist`
+///
+/// Removals (`songIndexToRemove`) refer to positions before the update and
+/// are applied before additions (`songIdToAdd`).
+pub(super) fn update_playlist(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    let id = ctx.params.required("playlistId")?;
+    let mut playlist = find_owned_playlist(ctx.data, &ctx.user, id)?;
+
+    if let Some(name) = ctx.params.get("name") {
+        validate_name(name)?;
+        playlist.name = name.to_string();
+    }
+
+    let mut remove = Vec::new();
+    for value in ctx.params.all("songIndexToRemove") {
+        let position: usize = value
+            .parse()
+            .map_err(|_| SubsonicError::generic("Invalid value for songIndexToRemove"))?;
+        remove.push(position);
+    }
+    playlist.song_ids = playlist
+        .song_ids
+        .into_iter()
+        .enumerate()
+        .filter(|(position, _)| !remove.contains(position))
+        .map(|(_, id)| id)
+        .collect();
+
+    let add: Vec<String> = ctx
+        .params
+        .all("songIdToAdd")
+        .into_iter()
+        .map(String::from)
+        .collect();
+    validate_song_ids(&*ctx.data.library.index()?, &add)?;
+    playlist.song_ids.extend(add);
+
+    playlist.updated_at = Utc::now();
+    let playlist = ctx.data.playlist_repo.update(playlist)?;
+    publish_playlist_event(ctx.data, kind::PLAYLIST_UPDATED, &playlist, None);
+
+    Ok(Reply::Data(None))
+}
+
+/// `deletePlaylist`
+pub(super) fn delete_playlist(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    let id = ctx.params.required("id")?;
+    let playlist = find_owned_playlist(ctx.data, &ctx.user, id)?;
+    ctx.data.playlist_repo.delete(&playlist.id)?;
+    publish_playlist_event(ctx.data, kind::PLAYLIST_DELETED, &playlist, None);
+
+    Ok(Reply::Data(None))
+}
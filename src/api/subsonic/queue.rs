@@ -0,0 +1,77 @@
+//! Subsonic play queue endpoints, backed by the regular play queue
+//! repository so the queue follows the user between Subsonic and native
+//! clients.
+//!
+//! Subsonic has no version numbers, so `savePlayQueue` always overwrites.
+
+use serde_json::json;
+
+use super::browsing::UserLibrary;
+use super::playlists::timestamp;
+use super::response::{Element, SubsonicError};
+use super::{Context, Reply};
+use crate::api::playlists::validate_song_ids;
+use crate::events::{kind, Audience};
+use crate::userdata::{PlayQueue, PlayQueueRepository};
+
+/// `getPlayQueue`
+pub(super) fn get_play_queue(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    let Some(queue) = ctx.data.play_queue_repo.find(ctx.user.id)? else {
+        return Ok(Reply::Data(None));
+    };
+
+    let index = ctx.data.library.index()?;
+    let library = UserLibrary::load(ctx, &index)?;
+    let entries = queue
+        .song_ids
+        .iter()
+        .filter_map(|id| library.song(id))
+        .map(|song| library.song_element("entry", song))
+        .collect();
+
+    Ok(Reply::Data(Some(
+        Element::new("playQueue")
+            .attr_opt("current", queue.current.map(|i| queue.song_ids[i].clone()))
+            .attr("position", queue.position_ms)
+            .attr("username", &ctx.user.username)
+            .attr("changed", timestamp(queue.updated_at))
+            .attr_opt("changedBy", queue.changed_by)
+            .list("entry", entries),
+    )))
+}
+
+/// `savePlayQueue`
+///
+/// `current` is a song ID; if it is queued more than once, the first
+/// occurrence is taken.
+pub(super) fn save_play_queue(ctx: &Context<'_>) -> Result<Reply, SubsonicError> {
+    let song_ids: Vec<String> = ctx.params.all("id").into_iter().map(String::from).collect();
+    validate_song_ids(&*ctx.data.library.index()?, &song_ids)?;
+
+    let current = match ctx.params.get("current") {
+        Some(id) => Some(
+            song_ids
+                .iter()
+                .position(|s| s == id)
+                .ok_or_else(|| SubsonicError::generic("Current song is not in the queue"))?,
+        ),
+        None => None,
+    };
+
+    let queue = PlayQueue {
+        song_ids,
+        current,
+        position_ms: ctx.params.parse_or("position", 0)?,
+        changed_by: ctx.params.get("c").map(String::from),
+        ..PlayQueue::new(ctx.user.id)
+    };
+    let queue = ctx.data.play_queue_repo.save(queue, None)?;
+    ctx.data.events.publish(
+        kind::QUEUE_UPDATED,
+        Audience::User(ctx.user.id),
+        None,
+        json!({ "queue": queue }),
+    );
+
+    Ok(Reply::Data(None))
+}
@@ -0,0 +1,215 @@
+//! Subsonic request parameters and authentication.
+
+use actix_web::web;
+use md5::{Digest, Md5};
+use std::str::FromStr;
+
+use super::response::{Format, SubsonicError};
+use crate::api::auth::{log_failed_login, verify_password};
+use crate::auth::{JsonUserRepository, LoginThrottle, SecretBox, User, UserRepository};
+
+/// Request parameters, from the query string and any form-encoded body.
+///
+/// Parameters may repeat (e.g. `songId` in `createPlaylist`), so they are
+/// kept as an ordered list rather than a map.
+#[derive(Debug, Clone, Default)]
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+    /// Parse parameters from a query string and an optional form body.
+    pub fn parse(query: &str, form: Option<&str>) -> Self {
+        let mut params = Vec::new();
+        for source in std::iter::once(query).chain(form) {
+            if let Ok(parsed) = web::Query::<Vec<(String, String)>>::from_query(source) {
+                params.extend(parsed.into_inner());
+            }
+        }
+        Self(params)
+    }
+
+    /// First value of a parameter.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All values of a repeated parameter.
+    pub fn all(&self, name: &str) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    /// First value of a required parameter.
+    pub fn required(&self, name: &str) -> Result<&str, SubsonicError> {
+        self.get(name)
+            .ok_or_else(|| SubsonicError::missing_parameter(name))
+    }
+
+    /// Parse an optional parameter.
+    pub fn parse_opt<T: FromStr>(&self, name: &str) -> Result<Option<T>, SubsonicError> {
+        self.get(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| SubsonicError::generic(format!("Invalid value for {}", name)))
+            })
+            .transpose()
+    }
+
+    /// Parse a parameter, falling back to a default.
+    pub fn parse_or<T: FromStr>(&self, name: &str, default: T) -> Result<T, SubsonicError> {
+        Ok(self.parse_opt(name)?.unwrap_or(default))
+    }
+
+    /// Requested response format.
+    pub fn format(&self) -> Format {
+        match self.get("f") {
+            Some("json") => Format::Json,
+            Some("jsonp") => match self.get("callback") {
+                Some(callback) if is_valid_callback(callback) => {
+                    Format::Jsonp(callback.to_string())
+                }
+                _ => Format::Json,
+            },
+            _ => Format::Xml,
+        }
+    }
+}
+
+/// Only allow plain JavaScript identifiers as JSONP callbacks.
+fn is_valid_callback(callback: &str) -> bool {
+    !callback.is_empty()
+        && callback
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == '.')
+}
+
+/// Decode a `p` parameter, which may be hex-encoded with an `enc:` prefix.
+fn decode_password(password: &str) -> Result<String, SubsonicError> {
+    match password.strip_prefix("enc:") {
+        Some(encoded) => hex::decode(encoded)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(SubsonicError::wrong_credentials),
+        None => Ok(password.to_string()),
+    }
+}
+
+/// Compute the Subsonic token `md5(password + salt)` as lowercase hex.
+pub fn token(password: &str, salt: &str) -> String {
+    let mut hasher = Md5::new();
+    hasher.update(password.as_bytes());
+    hasher.update(salt.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Compare two strings without short-circuiting on the first difference.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Authenticate a request.
+///
+/// Supports token authentication (`t` + `s`) against the user's Subsonic
+/// password, and plain authentication (`p`) against either the Subsonic
+/// password or the account password. Wrong credentials from `ip` count as
+/// failed logins in `throttle`.
+pub fn authenticate(
+    params: &Params,
+    users: &JsonUserRepository,
+    secrets: &SecretBox,
+    throttle: &LoginThrottle,
+    ip: &str,
+) -> Result<User, SubsonicError> {
+    if params.get("apiKey").is_some() {
+        return Err(SubsonicError::auth_mechanism_unsupported());
+    }
+
+    let username = params.required("u")?;
+    throttle.check(ip, username)?;
+    let wrong_credentials = || {
+        let failures = throttle.failed(ip, username);
+        log_failed_login(ip, username, failures);
+        SubsonicError::wrong_credentials()
+    };
+
+    let Some(user) = users.find_by_username(username)? else {
+        return Err(wrong_credentials());
+    };
+
+    let subsonic_password = user
+        .subsonic_password
+        .as_deref()
+        .map(|sealed| secrets.open(sealed))
+        .transpose()
+        .map_err(SubsonicError::from)?;
+
+    let authenticated = match (params.get("t"), params.get("s"), params.get("p")) {
+        (Some(token_param), Some(salt), _) => {
+            let password = subsonic_password.ok_or_else(SubsonicError::token_auth_unavailable)?;
+            constant_time_eq(&token(&password, salt), &token_param.to_lowercase())
+        }
+        (_, _, Some(password)) => {
+            let password = decode_password(password)?;
+            subsonic_password
+                .map(|expected| constant_time_eq(&expected, &password))
+                .unwrap_or(false)
+                || (!user.has_two_factor()
+                    && verify_password(&password, &user.password_hash).unwrap_or(false))
+        }
+        _ => return Err(SubsonicError::missing_parameter("p or t and s")),
+    };
+
+    // Refuse disabled accounts like wrong credentials, so a guess cannot
+    // tell that the password was right
+    if !authenticated || user.disabled {
+        return Err(wrong_credentials());
+    }
+    throttle.passed(username);
+
+    Ok(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_params_keep_repeated_values() {
+        let params = Params::parse("u=alice&songId=a&songId=b", Some("songId=c&f=json"));
+
+        assert_eq!(params.get("u"), Some("alice"));
+        assert_eq!(params.all("songId"), vec!["a", "b", "c"]);
+        assert_eq!(params.format(), Format::Json);
+        assert_eq!(params.parse_or("count", 20u32).unwrap(), 20);
+    }
+
+    #[test]
+    fn test_token_and_encoded_password() {
+        // Example from the Subsonic API documentation
+        assert_eq!(
+            token("sesame", "c19b2d"),
+            "26719a1196d2a940705a59634eb18eab"
+        );
+        assert_eq!(decode_password("enc:736573616d65").unwrap(), "sesame");
+        assert_eq!(decode_password("sesame").unwrap(), "sesame");
+    }
+
+    #[test]
+    fn test_jsonp_callback_is_validated() {
+        let params = Params::parse("f=jsonp&callback=alert(1)", None);
+        assert_eq!(params.format(), Format::Json);
+
+        let params = Params::parse("f=jsonp&callback=cb_1", None);
+        assert_eq!(params.format(), Format::Jsonp("cb_1".to_string()));
+    }
+}
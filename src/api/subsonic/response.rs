@@ -0,0 +1,362 @@
+//! Subsonic response model and its XML/JSON rendering.
+//!
+//! Responses are built once as a tree of [`Element`]s and rendered in the
+//! format the client asked for. Attributes become XML attributes or JSON
+//! fields; children declared as lists always render as JSON arrays, as
+//! Subsonic clients expect even for single-item results.
+
+use actix_web::{http::header, HttpResponse};
+use quick_xml::escape::escape;
+use serde_json::{Map, Value as Json};
+
+use super::{API_VERSION, SERVER_TYPE};
+use crate::error::AppError;
+
+/// Response format requested with the `f` parameter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Format {
+    Xml,
+    Json,
+    /// JSON wrapped in a call to the given callback.
+    Jsonp(String),
+}
+
+/// An attribute value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Str(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Str(value)
+    }
+}
+
+impl From<&String> for Value {
+    fn from(value: &String) -> Self {
+        Value::Str(value.clone())
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+macro_rules! int_value {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Value {
+            fn from(value: $t) -> Self {
+                Value::Int(value as i64)
+            }
+        })*
+    };
+}
+
+int_value!(u8, u32, u64, i32, i64, usize);
+
+impl Value {
+    fn to_json(&self) -> Json {
+        match self {
+            Value::Str(s) => Json::String(s.clone()),
+            Value::Int(i) => Json::from(*i),
+            Value::Bool(b) => Json::Bool(*b),
+        }
+    }
+
+    fn to_xml(&self) -> String {
+        match self {
+            Value::Str(s) => escape(s.as_str()).into_owned(),
+            Value::Int(i) => i.to_string(),
+            Value::Bool(b) => b.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Child {
+    Single(Element),
+    List(&'static str, Vec<Element>),
+}
+
+/// A response element.
+#[derive(Debug, Clone)]
+pub struct Element {
+    name: &'static str,
+    attrs: Vec<(&'static str, Value)>,
+    children: Vec<Child>,
+}
+
+impl Element {
+    /// Create an empty element.
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            attrs: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Add an attribute.
+    pub fn attr(mut self, key: &'static str, value: impl Into<Value>) -> Self {
+        self.attrs.push((key, value.into()));
+        self
+    }
+
+    /// Add an attribute if the value is present.
+    pub fn attr_opt<V: Into<Value>>(self, key: &'static str, value: Option<V>) -> Self {
+        match value {
+            Some(value) => self.attr(key, value),
+            None => self,
+        }
+    }
+
+    /// Add a single child element.
+    pub fn child(mut self, child: Element) -> Self {
+        self.children.push(Child::Single(child));
+        self
+    }
+
+    /// Add a list of child elements named `name`.
+    pub fn list(mut self, name: &'static str, items: Vec<Element>) -> Self {
+        self.children.push(Child::List(name, items));
+        self
+    }
+
+    fn to_json(&self) -> Json {
+        let mut object = Map::new();
+        for (key, value) in &self.attrs {
+            object.insert((*key).to_string(), value.to_json());
+        }
+        for child in &self.children {
+            match child {
+                Child::Single(element) => {
+                    object.insert(element.name.to_string(), element.to_json());
+                }
+                Child::List(name, items) => {
+                    let items = items.iter().map(Element::to_json).collect();
+                    object.insert((*name).to_string(), Json::Array(items));
+                }
+            }
+        }
+        Json::Object(object)
+    }
+
+    fn write_xml(&self, out: &mut String) {
+        out.push('<');
+        out.push_str(self.name);
+        for (key, value) in &self.attrs {
+            out.push_str(&format!(" {}=\"{}\"", key, value.to_xml()));
+        }
+
+        let children: Vec<&Element> = self
+            .children
+            .iter()
+            .flat_map(|child| match child {
+                Child::Single(element) => std::slice::from_ref(element).iter(),
+                Child::List(_, items) => items.iter(),
+            })
+            .collect();
+
+        if children.is_empty() {
+            out.push_str("/>");
+            return;
+        }
+
+        out.push('>');
+        for child in children {
+            child.write_xml(out);
+        }
+        out.push_str(&format!("</{}>", self.name));
+    }
+}
+
+/// A Subsonic API error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubsonicError {
+    pub code: u32,
+    pub message: String,
+}
+
+impl SubsonicError {
+    /// Generic error (code 0).
+    pub fn generic(message: impl Into<String>) -> Self {
+        Self {
+            code: 0,
+            message: message.into(),
+        }
+    }
+
+    /// A required parameter is missing (code 10).
+    pub fn missing_parameter(name: &str) -> Self {
+        Self {
+            code: 10,
+            message: format!("Required parameter is missing: {}", name),
+        }
+    }
+
+    /// Wrong username or password (code 40).
+    pub fn wrong_credentials() -> Self {
+        Self {
+            code: 40,
+            message: "Wrong username or password".to_string(),
+        }
+    }
+
+    /// Token authentication is not available for this user (code 41).
+    pub fn token_auth_unavailable() -> Self {
+        Self {
+            code: 41,
+            message: "Token authentication requires a Subsonic password; set one with PUT /api/subsonic/password".to_string(),
+        }
+    }
+
+    /// The authentication mechanism is not supported (code 42).
+    pub fn auth_mechanism_unsupported() -> Self {
+        Self {
+            code: 42,
+            message: "Provided authentication mechanism not supported".to_string(),
+        }
+    }
+
+    /// The user may not perform the operation (code 50).
+    pub fn not_authorized(message: impl Into<String>) -> Self {
+        Self {
+            code: 50,
+            message: message.into(),
+        }
+    }
+
+    /// The requested data was not found (code 70).
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self {
+            code: 70,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<AppError> for SubsonicError {
+    fn from(error: AppError) -> Self {
+        match error {
+            AppError::NotFound(message) => Self::not_found(message),
+            AppError::Unauthorized(_) => Self::wrong_credentials(),
+            AppError::Forbidden(message) => Self::not_authorized(message),
+            AppError::Validation(message)
+            | AppError::BadRequest(message)
+            | AppError::TooManyRequests { message, .. } => Self::generic(message),
+            other => {
+                tracing::error!(error = %other, "Subsonic request failed");
+                Self::generic("Internal server error")
+            }
+        }
+    }
+}
+
+/// Build the `subsonic-response` envelope.
+fn envelope(status: &str, body: Option<Element>) -> Element {
+    let root = Element::new("subsonic-response")
+        .attr("status", status)
+        .attr("version", API_VERSION)
+        .attr("type", SERVER_TYPE)
+        .attr("serverVersion", env!("CARGO_PKG_VERSION"))
+        .attr("openSubsonic", true);
+    match body {
+        Some(body) => root.child(body),
+        None => root,
+    }
+}
+
+/// Render an envelope in the requested format.
+fn render(format: &Format, root: Element) -> HttpResponse {
+    match format {
+        Format::Xml => {
+            let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+            // The namespace is XML-only, so it is spliced in rather than stored as an attribute
+            let mut body = String::new();
+            root.write_xml(&mut body);
+            out.push_str(&body.replacen(
+                "<subsonic-response",
+                "<subsonic-response xmlns=\"http://subsonic.org/restapi\"",
+                1,
+            ));
+            HttpResponse::Ok()
+                .insert_header((header::CONTENT_TYPE, "application/xml; charset=utf-8"))
+                .body(out)
+        }
+        Format::Json => HttpResponse::Ok().json(json_document(&root)),
+        Format::Jsonp(callback) => HttpResponse::Ok()
+            .insert_header((
+                header::CONTENT_TYPE,
+                "application/javascript; charset=utf-8",
+            ))
+            .body(format!("{}({});", callback, json_document(&root))),
+    }
+}
+
+fn json_document(root: &Element) -> Json {
+    let mut document = Map::new();
+    document.insert(root.name.to_string(), root.to_json());
+    Json::Object(document)
+}
+
+/// Render a successful response with an optional payload.
+pub fn ok(format: &Format, body: Option<Element>) -> HttpResponse {
+    render(format, envelope("ok", body))
+}
+
+/// Render an error response. Subsonic errors use HTTP 200.
+pub fn error(format: &Format, error: &SubsonicError) -> HttpResponse {
+    let body = Element::new("error")
+        .attr("code", error.code)
+        .attr("message", error.message.as_str());
+    render(format, envelope("failed", Some(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folders() -> Element {
+        Element::new("musicFolders").list(
+            "musicFolder",
+            vec![Element::new("musicFolder")
+                .attr("id", 1u32)
+                .attr("name", "Rock & Roll")],
+        )
+    }
+
+    #[test]
+    fn test_json_lists_are_arrays() {
+        let json = json_document(&envelope("ok", Some(folders())));
+        let response = &json["subsonic-response"];
+
+        assert_eq!(response["status"], "ok");
+        assert_eq!(response["openSubsonic"], true);
+        assert_eq!(response["musicFolders"]["musicFolder"][0]["id"], 1);
+        assert_eq!(
+            response["musicFolders"]["musicFolder"][0]["name"],
+            "Rock & Roll"
+        );
+    }
+
+    #[test]
+    fn test_xml_rendering_escapes_attributes() {
+        let mut out = String::new();
+        folders().write_xml(&mut out);
+        assert_eq!(
+            out,
+            "<musicFolders><musicFolder id=\"1\" name=\"Rock &amp; Roll\"/></musicFolders>"
+        );
+    }
+}
@@ -0,0 +1,335 @@
+//! Two-factor authentication endpoints.
+//!
+//! Users enroll by getting a TOTP secret, adding it to an authenticator
+//! app and confirming with a code, which hands out recovery codes. From
+//! then on, logins need a code after the password (see
+//! `POST /auth/login/2fa`). None of this can be done with an API key.
+
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::api::auth::{client_ip, confirm_password, log_failed_login};
+use crate::auth::totp::{self, TwoFactor};
+use crate::auth::{AuthenticatedUser, JsonUserRepository, User, UserRepository};
+use crate::config;
+use crate::error::{AppError, AppResult};
+use crate::models::AppState;
+
+/// Request body for starting enrollment.
+#[derive(Debug, Deserialize)]
+pub struct EnrollRequest {
+    pub password: String,
+}
+
+/// Request body carrying a TOTP code.
+#[derive(Debug, Deserialize)]
+pub struct CodeRequest {
+    pub code: String,
+}
+
+/// Request body for turning two-factor authentication off.
+#[derive(Debug, Deserialize)]
+pub struct DisableRequest {
+    pub password: String,
+    /// TOTP or recovery code; not needed to cancel an unconfirmed
+    /// enrollment.
+    pub code: Option<String>,
+}
+
+/// Two-factor state of the current user.
+#[derive(Debug, Serialize)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    /// Whether an enrollment is waiting for confirmation.
+    pub pending: bool,
+    pub recovery_codes_left: usize,
+    /// Whether the account must use two-factor authentication.
+    pub required: bool,
+}
+
+/// A new secret to add to an authenticator app.
+#[derive(Debug, Serialize)]
+pub struct Enrollment {
+    /// Base32 secret, for typing in by hand.
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+/// Recovery codes; they cannot be retrieved again.
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+/// Whether a user must use two-factor authentication.
+fn is_required(user: &User) -> bool {
+    user.is_admin && config::get().require_admin_2fa
+}
+
+/// Refuse two-factor management through an API key.
+fn require_login(user: &AuthenticatedUser) -> AppResult<()> {
+    if user.api_key_id.is_some() {
+        return Err(AppError::Forbidden(
+            "Two-factor authentication cannot be managed with an API key".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn find_account(repo: &JsonUserRepository, user: &AuthenticatedUser) -> AppResult<User> {
+    repo.find_by_id(user.id)?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))
+}
+
+/// Check a TOTP or recovery code of a user, using it up if it matches.
+///
+/// Call within [`UserRepository::modify`], so that a used code is saved
+/// before it can be tried again.
+pub(crate) fn verify_code(data: &AppState, user: &mut User, code: &str) -> AppResult<bool> {
+    let Some(two_factor) = user.two_factor.as_mut() else {
+        return Ok(false);
+    };
+    let secret = data.secret_box.open(&two_factor.secret)?;
+    Ok(two_factor.verify(&secret, code))
+}
+
+/// Check the code of a user with two-factor authentication enabled.
+///
+/// Wrong codes from `ip` count as failed logins, like at
+/// `POST /auth/login/2fa`.
+fn confirm_code(data: &AppState, user: &mut User, code: &str, ip: &str) -> AppResult<()> {
+    if !user.has_two_factor() {
+        return Err(AppError::Conflict(
+            "Two-factor authentication is not enabled".to_string(),
+        ));
+    }
+    data.login_throttle.check(ip, &user.username)?;
+    if !verify_code(data, user, code)? {
+        let failures = data.login_throttle.failed(ip, &user.username);
+        log_failed_login(ip, &user.username, failures);
+        return Err(AppError::Forbidden("Invalid two-factor code".to_string()));
+    }
+    data.login_throttle.passed(&user.username);
+    Ok(())
+}
+
+/// Show the current user's two-factor state.
+///
+/// GET /auth/2fa
+#[get("/2fa")]
+pub async fn status(
+    user: AuthenticatedUser,
+    repo: web::Data<JsonUserRepository>,
+) -> AppResult<HttpResponse> {
+    let account = find_account(&repo, &user)?;
+    let two_factor = account.two_factor.as_ref();
+
+    Ok(HttpResponse::Ok().json(TwoFactorStatus {
+        enabled: account.has_two_factor(),
+        pending: two_factor.is_some_and(|t| !t.enabled),
+        recovery_codes_left: two_factor.map_or(0, |t| t.recovery_codes.len()),
+        required: is_required(&account),
+    }))
+}
+
+/// Start enrolling, replacing any unconfirmed enrollment.
+///
+/// POST /auth/2fa/enroll
+///
+/// Requires the current password. Two-factor authentication is not used
+/// until confirmed with `POST /auth/2fa/confirm`.
+#[post("/2fa/enroll")]
+pub async fn enroll(
+    user: AuthenticatedUser,
+    repo: web::Data<JsonUserRepository>,
+    data: web::Data<AppState>,
+    body: web::Json<EnrollRequest>,
+) -> AppResult<HttpResponse> {
+    require_login(&user)?;
+    confirm_password(&repo, &user, &body.password)?;
+
+    let secret = totp::generate_secret();
+    let sealed = data.secret_box.seal(&secret)?;
+    let account = repo.modify(user.id, |account| {
+        if account.has_two_factor() {
+            return Err(AppError::Conflict(
+                "Two-factor authentication is already enabled".to_string(),
+            ));
+        }
+        account.two_factor = Some(TwoFactor::pending(sealed));
+        Ok(())
+    })?;
+
+    tracing::info!(username = %account.username, "Two-factor enrollment started");
+    Ok(HttpResponse::Ok().json(Enrollment {
+        otpauth_uri: totp::otpauth_uri(&account.username, &secret),
+        secret,
+    }))
+}
+
+/// Confirm enrollment with a code from the authenticator app, turning
+/// two-factor authentication on.
+///
+/// POST /auth/2fa/confirm
+///
+/// The response holds the recovery codes.
+#[post("/2fa/confirm")]
+pub async fn confirm(
+    user: AuthenticatedUser,
+    repo: web::Data<JsonUserRepository>,
+    data: web::Data<AppState>,
+    body: web::Json<CodeRequest>,
+) -> AppResult<HttpResponse> {
+    require_login(&user)?;
+    let mut recovery_codes = Vec::new();
+    let account = repo.modify(user.id, |account| {
+        match &account.two_factor {
+            None => {
+                return Err(AppError::Conflict(
+                    "No two-factor enrollment to confirm".to_string(),
+                ))
+            }
+            Some(two_factor) if two_factor.enabled => {
+                return Err(AppError::Conflict(
+                    "Two-factor authentication is already enabled".to_string(),
+                ))
+            }
+            Some(_) => {}
+        }
+        if !verify_code(&data, account, &body.code)? {
+            return Err(AppError::Validation("Invalid two-factor code".to_string()));
+        }
+
+        let two_factor = account.two_factor.as_mut().expect("checked above");
+        two_factor.enabled = true;
+        recovery_codes = two_factor.reset_recovery_codes();
+        Ok(())
+    })?;
+
+    tracing::info!(username = %account.username, "Two-factor authentication enabled");
+    Ok(HttpResponse::Ok().json(RecoveryCodes { recovery_codes }))
+}
+
+/// Replace the recovery codes.
+///
+/// POST /auth/2fa/recovery-codes
+///
+/// Requires a current code.
+#[post("/2fa/recovery-codes")]
+pub async fn regenerate_recovery_codes(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    repo: web::Data<JsonUserRepository>,
+    data: web::Data<AppState>,
+    body: web::Json<CodeRequest>,
+) -> AppResult<HttpResponse> {
+    require_login(&user)?;
+    let ip = client_ip(&req).unwrap_or_else(|| "unknown".to_string());
+    let mut recovery_codes = Vec::new();
+    let account = repo.modify(user.id, |account| {
+        confirm_code(&data, account, &body.code, &ip)?;
+        recovery_codes = account
+            .two_factor
+            .as_mut()
+            .expect("checked above")
+            .reset_recovery_codes();
+        Ok(())
+    })?;
+
+    tracing::info!(username = %account.username, "Recovery codes replaced");
+    Ok(HttpResponse::Ok().json(RecoveryCodes { recovery_codes }))
+}
+
+/// Turn two-factor authentication off, or cancel an enrollment.
+///
+/// DELETE /auth/2fa
+///
+/// Requires the current password and, once enabled, a code. Admins cannot
+/// turn it off while `REQUIRE_ADMIN_2FA` is set.
+#[delete("/2fa")]
+pub async fn disable(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    repo: web::Data<JsonUserRepository>,
+    data: web::Data<AppState>,
+    body: web::Json<DisableRequest>,
+) -> AppResult<HttpResponse> {
+    require_login(&user)?;
+    confirm_password(&repo, &user, &body.password)?;
+    let ip = client_ip(&req).unwrap_or_else(|| "unknown".to_string());
+    let account = repo.modify(user.id, |account| {
+        if account.has_two_factor() {
+            if is_required(account) {
+                return Err(AppError::Forbidden(
+                    "Two-factor authentication is required for admin accounts".to_string(),
+                ));
+            }
+            let code = body.code.as_deref().unwrap_or_default();
+            confirm_code(&data, account, code, &ip)?;
+        }
+        account.two_factor = None;
+        Ok(())
+    })?;
+
+    tracing::info!(username = %account.username, "Two-factor authentication disabled");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Configure two-factor routes, within the `/auth` scope.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(status)
+        .service(enroll)
+        .service(confirm)
+        .service(regenerate_recovery_codes)
+        .service(disable);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::jwt;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use tempfile::tempdir;
+    use uuid::Uuid;
+
+    #[actix_web::test]
+    async fn test_wrong_codes_are_throttled() {
+        let dir = tempdir().unwrap();
+        let state = AppState::for_tests(dir.path());
+        let sealed = state.secret_box.seal(&totp::generate_secret()).unwrap();
+        let mut user = User::new("alice".to_string(), "hash".to_string(), false);
+        user.two_factor = Some(TwoFactor {
+            enabled: true,
+            ..TwoFactor::pending(sealed)
+        });
+        let user = state.user_repo.create(user).unwrap();
+        let token = jwt::create_token_pair(&user, Uuid::new_v4(), String::new())
+            .unwrap()
+            .access_token;
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .app_data(web::Data::from(state.user_repo.clone()))
+                .app_data(web::Data::from(state.refresh_token_repo.clone()))
+                .configure(configure),
+        )
+        .await;
+        let guess = || {
+            TestRequest::post()
+                .uri("/2fa/recovery-codes")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .set_json(serde_json::json!({ "code": "guess" }))
+                .to_request()
+        };
+
+        for _ in 0..4 {
+            let response = call_service(&app, guess()).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+        let response = call_service(&app, guess()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+    }
+}
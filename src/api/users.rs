@@ -0,0 +1,478 @@
+//! Admin user management endpoints.
+//!
+//! All endpoints require an admin. The user repository refuses changes
+//! that would leave no active admin, so the last admin cannot be demoted,
+//! disabled or deleted.
+
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::api::auth::hash_password;
+use crate::api::rooms::remove_user_from_rooms;
+use crate::auth::{
+    ApiKeyRepository, AuthenticatedUser, RefreshTokenRepository, User, UserRepository,
+};
+use crate::error::{AppError, AppResult};
+use crate::models::{AppState, PaginatedResponse};
+use crate::scrobbling::queue::ScrobbleQueue;
+use crate::userdata::{
+    AnnotationRepository, ForwardingRepository, HistoryRepository, PlayQueueRepository,
+    PlaylistRepository, ShareRepository,
+};
+
+/// Query parameters for listing users.
+#[derive(Debug, Deserialize)]
+pub struct ListUsersQuery {
+    /// Only users whose name contains this text.
+    pub q: Option<String>,
+    /// Page number (1-indexed).
+    #[serde(default = "default_page")]
+    pub page: usize,
+    /// Items per page (max 100).
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
+}
+
+fn default_page() -> usize {
+    1
+}
+
+fn default_per_page() -> usize {
+    50
+}
+
+/// Request body for changing a user's role or status.
+#[derive(Debug, Deserialize)]
+pub struct UpdateUserRequest {
+    pub is_admin: Option<bool>,
+    pub disabled: Option<bool>,
+}
+
+/// Request body for resetting a user's password.
+#[derive(Debug, Deserialize, Validate)]
+pub struct ResetPasswordRequest {
+    #[validate(length(min = 8, max = 128, message = "Password must be 8-128 characters"))]
+    pub password: String,
+}
+
+/// A user as shown to admins.
+#[derive(Debug, Serialize)]
+pub struct AdminUserResponse {
+    pub id: Uuid,
+    pub username: String,
+    pub is_admin: bool,
+    pub disabled: bool,
+    pub created_at: DateTime<Utc>,
+    pub last_login: Option<DateTime<Utc>>,
+    /// Whether a Subsonic password is set.
+    pub subsonic_enabled: bool,
+    pub two_factor_enabled: bool,
+}
+
+impl From<&User> for AdminUserResponse {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username.clone(),
+            is_admin: user.is_admin,
+            disabled: user.disabled,
+            created_at: user.created_at,
+            last_login: user.last_login,
+            subsonic_enabled: user.subsonic_password.is_some(),
+            two_factor_enabled: user.has_two_factor(),
+        }
+    }
+}
+
+/// A user with a summary of their data.
+#[derive(Debug, Serialize)]
+pub struct UserDetails {
+    #[serde(flatten)]
+    pub user: AdminUserResponse,
+    pub playlists: usize,
+    pub plays: usize,
+}
+
+fn find_user(data: &AppState, id: Uuid) -> AppResult<User> {
+    data.user_repo
+        .find_by_id(id)?
+        .ok_or_else(|| AppError::NotFound(format!("User not found: {}", id)))
+}
+
+/// Apply an admin's changes to a user's role or status.
+fn apply_update(account: &mut User, body: &UpdateUserRequest, admin_id: Uuid) -> AppResult<()> {
+    if let Some(is_admin) = body.is_admin {
+        account.is_admin = is_admin;
+    }
+    if let Some(disabled) = body.disabled {
+        if disabled && account.id == admin_id {
+            return Err(AppError::BadRequest(
+                "You cannot disable your own account".to_string(),
+            ));
+        }
+        account.disabled = disabled;
+    }
+    Ok(())
+}
+
+/// Delete a user and everything stored for them.
+///
+/// The account goes first, so a refused deletion (the last admin) leaves
+/// the user's data alone.
+pub(crate) fn delete_user(data: &AppState, user_id: Uuid) -> AppResult<()> {
+    if !data.user_repo.delete(user_id)? {
+        return Err(AppError::NotFound(format!("User not found: {}", user_id)));
+    }
+
+    for playlist in data.playlist_repo.list_by_owner(user_id)? {
+        data.playlist_repo.delete(&playlist.id)?;
+    }
+    data.refresh_token_repo.revoke_by_user(user_id)?;
+    data.api_key_repo.delete_by_user(user_id)?;
+    data.share_repo.delete_by_owner(user_id)?;
+    data.annotation_repo.delete_by_user(user_id)?;
+    data.history_repo.delete_by_user(user_id)?;
+    data.play_queue_repo.delete(user_id)?;
+    data.scrobble_forwarder.configs().delete(user_id)?;
+    data.scrobble_forwarder.queue().delete_by_user(user_id)?;
+    remove_user_from_rooms(data, user_id)?;
+
+    Ok(())
+}
+
+/// List users.
+///
+/// GET /api/admin/users
+///
+/// Query parameters:
+/// - `q`: Only users whose name contains this text
+/// - `page`: Page number (default: 1)
+/// - `per_page`: Items per page (default: 50, max: 100)
+#[get("/api/admin/users")]
+pub async fn list_users(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    query: web::Query<ListUsersQuery>,
+) -> AppResult<HttpResponse> {
+    user.require_admin()?;
+
+    let per_page = query.per_page.clamp(1, 100);
+    let page = query.page.max(1);
+
+    let mut users = data.user_repo.list_all()?;
+    if let Some(q) = &query.q {
+        let q_lower = q.to_lowercase();
+        users.retain(|u| u.username.to_lowercase().contains(&q_lower));
+    }
+    users.sort_by(|a, b| {
+        a.created_at
+            .cmp(&b.created_at)
+            .then_with(|| a.username.cmp(&b.username))
+    });
+
+    let total = users.len();
+    let items: Vec<AdminUserResponse> = users
+        .iter()
+        .skip((page - 1) * per_page)
+        .take(per_page)
+        .map(AdminUserResponse::from)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse::from_vec(items, page, per_page, total)))
+}
+
+/// Get a user's details.
+///
+/// GET /api/admin/users/{id}
+#[get("/api/admin/users/{id}")]
+pub async fn get_user(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+) -> AppResult<HttpResponse> {
+    user.require_admin()?;
+
+    let account = find_user(&data, *path)?;
+    Ok(HttpResponse::Ok().json(UserDetails {
+        user: AdminUserResponse::from(&account),
+        playlists: data.playlist_repo.list_by_owner(account.id)?.len(),
+        plays: data.history_repo.list_by_user(account.id)?.len(),
+    }))
+}
+
+/// Promote, demote, disable or re-enable a user.
+///
+/// PUT /api/admin/users/{id}
+#[put("/api/admin/users/{id}")]
+pub async fn update_user(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    body: web::Json<UpdateUserRequest>,
+) -> AppResult<HttpResponse> {
+    user.require_admin()?;
+
+    let account = data
+        .user_repo
+        .modify(*path, |account| apply_update(account, &body, user.id))
+        .map_err(|e| match e {
+            AppError::NotFound(_) => AppError::NotFound(format!("User not found: {}", path)),
+            e => e,
+        })?;
+
+    tracing::info!(
+        admin = %user.username,
+        username = %account.username,
+        is_admin = account.is_admin,
+        disabled = account.disabled,
+        "User updated by admin"
+    );
+    Ok(HttpResponse::Ok().json(AdminUserResponse::from(&account)))
+}
+
+/// Set a new password for a user, signing them out everywhere.
+///
+/// POST /api/admin/users/{id}/password
+#[post("/api/admin/users/{id}/password")]
+pub async fn reset_password(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    body: web::Json<ResetPasswordRequest>,
+) -> AppResult<HttpResponse> {
+    user.require_admin()?;
+    body.validate()
+        .map_err(|e| AppError::Validation(e.to_string()))?;
+
+    let account = find_user(&data, *path)?;
+    let password_hash = hash_password(&body.password)?;
+    data.user_repo.modify(account.id, |account| {
+        account.set_password(password_hash);
+        Ok(())
+    })?;
+    data.refresh_token_repo.revoke_by_user(account.id)?;
+
+    tracing::info!(admin = %user.username, username = %account.username, "Password reset by admin");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Turn off a user's two-factor authentication, e.g. after they lost
+/// their device and recovery codes.
+///
+/// DELETE /api/admin/users/{id}/2fa
+#[delete("/api/admin/users/{id}/2fa")]
+pub async fn reset_two_factor(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+) -> AppResult<HttpResponse> {
+    user.require_admin()?;
+
+    let account = find_user(&data, *path)?;
+    data.user_repo
+        .modify(account.id, |account| match account.two_factor.take() {
+            Some(_) => Ok(()),
+            None => Err(AppError::NotFound(
+                "Two-factor authentication is not set up".to_string(),
+            )),
+        })?;
+
+    tracing::info!(admin = %user.username, username = %account.username, "Two-factor authentication reset by admin");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Delete a user with their playlists, stars, ratings, history and settings.
+///
+/// DELETE /api/admin/users/{id}
+#[delete("/api/admin/users/{id}")]
+pub async fn delete_user_account(
+    user: AuthenticatedUser,
+    data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+) -> AppResult<HttpResponse> {
+    user.require_admin()?;
+
+    let account = find_user(&data, *path)?;
+    delete_user(&data, account.id)?;
+
+    tracing::info!(admin = %user.username, username = %account.username, "User deleted by admin");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Configure admin user management routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_users)
+        .service(get_user)
+        .service(update_user)
+        .service(reset_password)
+        .service(reset_two_factor)
+        .service(delete_user_account);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{jwt, JsonUserRepository};
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+    use tempfile::tempdir;
+
+    const HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA";
+
+    fn token(user: &User) -> String {
+        jwt::create_token_pair(user, Uuid::new_v4(), String::new())
+            .unwrap()
+            .access_token
+    }
+
+    /// Send a request as `user`, returning the status and body.
+    async fn send(state: &AppState, user: &User, request: TestRequest) -> (StatusCode, String) {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .app_data(web::Data::from(state.user_repo.clone()))
+                .app_data(web::Data::from(state.refresh_token_repo.clone()))
+                .configure(configure),
+        )
+        .await;
+        let request = request
+            .insert_header(("Authorization", format!("Bearer {}", token(user))))
+            .to_request();
+        let response = call_service(&app, request).await;
+        let status = response.status();
+        let body = read_body(response).await;
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn update(id: Uuid, body: serde_json::Value) -> TestRequest {
+        TestRequest::put()
+            .uri(&format!("/api/admin/users/{}", id))
+            .set_json(body)
+    }
+
+    #[test]
+    fn test_stale_update_after_disable_keeps_it_disabled() {
+        let dir = tempdir().unwrap();
+        let repo = JsonUserRepository::new(dir.path().join("users.json")).unwrap();
+        let admin = repo
+            .create(User::new("admin".to_string(), "hash".to_string(), true))
+            .unwrap();
+        let account = repo
+            .create(User::new("user".to_string(), "hash".to_string(), false))
+            .unwrap();
+
+        // Another admin's promotion lands after the disable; it applies to
+        // the stored account rather than the one read before
+        let promote = UpdateUserRequest {
+            is_admin: Some(true),
+            disabled: None,
+        };
+        let disable = UpdateUserRequest {
+            is_admin: None,
+            disabled: Some(true),
+        };
+        repo.modify(account.id, |a| apply_update(a, &disable, admin.id))
+            .unwrap();
+        let promoted = repo
+            .modify(account.id, |a| apply_update(a, &promote, admin.id))
+            .unwrap();
+        assert!(promoted.is_admin);
+        assert!(promoted.disabled);
+
+        assert!(matches!(
+            repo.modify(admin.id, |a| apply_update(a, &disable, admin.id)),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn test_last_admin_cannot_be_removed() {
+        let dir = tempdir().unwrap();
+        let state = AppState::for_tests(dir.path());
+        let root = state
+            .user_repo
+            .create(User::new("root".to_string(), HASH.to_string(), true))
+            .unwrap();
+        let alice = state
+            .user_repo
+            .create(User::new("alice".to_string(), HASH.to_string(), true))
+            .unwrap();
+
+        let demote = serde_json::json!({ "is_admin": false });
+        let (status, _) = send(&state, &root, update(alice.id, demote.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // root is the last active admin now
+        let (status, _) = send(&state, &root, update(root.id, demote)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let delete = TestRequest::delete().uri(&format!("/api/admin/users/{}", root.id));
+        let (status, _) = send(&state, &root, delete).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let disable = serde_json::json!({ "disabled": true });
+        let (status, _) = send(&state, &root, update(root.id, disable)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let root = state.user_repo.find_by_id(root.id).unwrap().unwrap();
+        assert!(root.is_active_admin());
+    }
+
+    #[actix_web::test]
+    async fn test_disabled_user_token_is_rejected() {
+        let dir = tempdir().unwrap();
+        let state = AppState::for_tests(dir.path());
+        let root = state
+            .user_repo
+            .create(User::new("root".to_string(), HASH.to_string(), true))
+            .unwrap();
+        let alice = state
+            .user_repo
+            .create(User::new("alice".to_string(), HASH.to_string(), true))
+            .unwrap();
+        let list = || TestRequest::get().uri("/api/admin/users");
+
+        let (status, _) = send(&state, &alice, list()).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let disable = serde_json::json!({ "disabled": true });
+        let (status, _) = send(&state, &root, update(alice.id, disable)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // The token issued before is refused from the next request on
+        let (status, body) = send(&state, &alice, list()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(body.contains("Account is disabled"));
+    }
+
+    #[actix_web::test]
+    async fn test_responses_never_include_password_hashes() {
+        let dir = tempdir().unwrap();
+        let state = AppState::for_tests(dir.path());
+        let root = state
+            .user_repo
+            .create(User::new("root".to_string(), HASH.to_string(), true))
+            .unwrap();
+        let alice = state
+            .user_repo
+            .create(User::new("alice".to_string(), HASH.to_string(), false))
+            .unwrap();
+
+        let requests = [
+            TestRequest::get().uri("/api/admin/users"),
+            TestRequest::get().uri("/api/admin/users?q=ali"),
+            TestRequest::get().uri(&format!("/api/admin/users/{}", alice.id)),
+            update(alice.id, serde_json::json!({ "is_admin": true })),
+            update(alice.id, serde_json::json!({ "disabled": true })),
+        ];
+        for request in requests {
+            let (status, body) = send(&state, &root, request).await;
+            assert_eq!(status, StatusCode::OK);
+            assert!(body.contains("alice"));
+            assert!(!body.contains("password_hash"), "{}", body);
+            assert!(!body.contains(HASH), "{}", body);
+        }
+    }
+}
@@ -0,0 +1,455 @@
+//! Read-only WebDAV view of the library.
+//!
+//! `/dav/` exposes the files in the music folder. With `WEBDAV_ARTIST_TREE`
+//! enabled, `/dav-artists/` offers the same files as a virtual
+//! Artist/Album tree built from tags. Clients log in with HTTP Basic using
+//! their ferrum credentials (or a bearer token); only OPTIONS, PROPFIND,
+//! GET and HEAD are supported.
+
+use actix_files::NamedFile;
+use actix_web::http::header::{self, HttpDate};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse};
+use percent_encoding::percent_decode_str;
+use quick_xml::escape::escape;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::api::auth::{basic_challenge, basic_or_bearer_user};
+use crate::api::music::resolve_music_file;
+use crate::config;
+use crate::error::{AppError, AppResult};
+use crate::library::{playlist_file::encode_path_segment, LibraryIndex};
+use crate::models::{AppState, SongMetadata};
+
+/// Mount point of the music folder.
+const FILES_ROOT: &str = "/dav";
+/// Mount point of the virtual Artist/Album tree.
+const ARTISTS_ROOT: &str = "/dav-artists";
+/// Methods supported on every resource.
+const ALLOW: &str = "OPTIONS, PROPFIND, GET, HEAD";
+
+/// A file in a listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileInfo {
+    path: PathBuf,
+    len: u64,
+    modified: Option<std::time::SystemTime>,
+}
+
+/// A resource as reported by PROPFIND.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    href: String,
+    name: String,
+    /// `None` for collections.
+    file: Option<FileInfo>,
+}
+
+impl Entry {
+    fn collection(href: String, name: impl Into<String>) -> Self {
+        Self {
+            href,
+            name: name.into(),
+            file: None,
+        }
+    }
+
+    /// A file entry, or `None` if the file cannot be read.
+    fn file(href: String, name: &str, path: PathBuf) -> Option<Self> {
+        let metadata = std::fs::metadata(&path).ok().filter(|m| m.is_file())?;
+        Some(Self {
+            href,
+            name: name.to_string(),
+            file: Some(FileInfo {
+                path,
+                len: metadata.len(),
+                modified: metadata.modified().ok(),
+            }),
+        })
+    }
+}
+
+/// A requested resource and its members.
+#[derive(Debug)]
+struct Listing {
+    entry: Entry,
+    children: Vec<Entry>,
+}
+
+/// Name of a virtual directory: tags may contain path separators or be
+/// empty, which would not survive as a path segment.
+fn dir_name(tag: &str) -> String {
+    match tag.trim() {
+        "" | "." | ".." => "_".to_string(),
+        name => name.replace(['/', '\\'], "_"),
+    }
+}
+
+/// Split a request path below `root` into decoded segments.
+fn segments(path: &str, root: &str) -> Option<Vec<String>> {
+    path.strip_prefix(root)?
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            percent_decode_str(s)
+                .decode_utf8()
+                .ok()
+                .map(|s| s.into_owned())
+        })
+        .collect()
+}
+
+/// Resolve a path below `/dav/`.
+///
+/// The library is flat, so only the root and the files directly inside the
+/// music folder exist. Files go through `resolve_music_file`, which rejects
+/// traversal and symlinks escaping the music folder.
+fn files_listing(music_folder: &Path, segments: &[String]) -> AppResult<Listing> {
+    let href = |name: &str| format!("{}/{}", FILES_ROOT, encode_path_segment(name));
+
+    match segments {
+        [] => {
+            let mut names: Vec<String> = std::fs::read_dir(music_folder)?
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|name| !name.starts_with('.'))
+                .collect();
+            names.sort();
+
+            let children = names
+                .iter()
+                .filter_map(|name| {
+                    let path = resolve_music_file(music_folder, name).ok()?;
+                    Entry::file(href(name), name, path)
+                })
+                .collect();
+            Ok(Listing {
+                entry: Entry::collection(format!("{}/", FILES_ROOT), "Music"),
+                children,
+            })
+        }
+        [name] => {
+            let path = resolve_music_file(music_folder, name)?;
+            let entry = Entry::file(href(name), name, path)
+                .ok_or_else(|| AppError::song_not_found(name))?;
+            Ok(Listing {
+                entry,
+                children: Vec::new(),
+            })
+        }
+        _ => Err(AppError::NotFound("Resource not found".to_string())),
+    }
+}
+
+/// Resolve a path below `/dav-artists/`.
+fn artists_listing(
+    index: &LibraryIndex,
+    music_folder: &Path,
+    segments: &[String],
+) -> AppResult<Listing> {
+    let not_found = || AppError::NotFound("Resource not found".to_string());
+
+    let mut tree: BTreeMap<String, BTreeMap<String, Vec<&SongMetadata>>> = BTreeMap::new();
+    for song in &index.songs {
+        tree.entry(dir_name(&song.artist))
+            .or_default()
+            .entry(dir_name(&song.album))
+            .or_default()
+            .push(song);
+    }
+
+    let collection_href = |parts: &[&str]| {
+        let mut href = format!("{}/", ARTISTS_ROOT);
+        for part in parts {
+            href.push_str(&encode_path_segment(part));
+            href.push('/');
+        }
+        href
+    };
+    let song_entry = |artist: &str, album: &str, song: &SongMetadata| {
+        let href = format!(
+            "{}{}",
+            collection_href(&[artist, album]),
+            encode_path_segment(&song.file)
+        );
+        let path = resolve_music_file(music_folder, &song.file).ok()?;
+        Entry::file(href, &song.file, path)
+    };
+
+    match segments {
+        [] => Ok(Listing {
+            entry: Entry::collection(collection_href(&[]), "Artists"),
+            children: tree
+                .keys()
+                .map(|artist| Entry::collection(collection_href(&[artist]), artist))
+                .collect(),
+        }),
+        [artist] => {
+            let albums = tree.get(artist).ok_or_else(not_found)?;
+            Ok(Listing {
+                entry: Entry::collection(collection_href(&[artist]), artist),
+                children: albums
+                    .keys()
+                    .map(|album| Entry::collection(collection_href(&[artist, album]), album))
+                    .collect(),
+            })
+        }
+        [artist, album] => {
+            let songs = tree
+                .get(artist)
+                .and_then(|albums| albums.get(album))
+                .ok_or_else(not_found)?;
+            let mut children: Vec<Entry> = songs
+                .iter()
+                .filter_map(|song| song_entry(artist, album, song))
+                .collect();
+            children.sort_by(|a, b| a.name.cmp(&b.name));
+            Ok(Listing {
+                entry: Entry::collection(collection_href(&[artist, album]), album.as_str()),
+                children,
+            })
+        }
+        [artist, album, file] => {
+            let song = tree
+                .get(artist)
+                .and_then(|albums| albums.get(album))
+                .and_then(|songs| songs.iter().find(|s| s.file == *file))
+                .ok_or_else(not_found)?;
+            Ok(Listing {
+                entry: song_entry(artist, album, song).ok_or_else(not_found)?,
+                children: Vec::new(),
+            })
+        }
+        _ => Err(not_found()),
+    }
+}
+
+/// Render a PROPFIND `207 Multi-Status` body.
+fn multistatus<'a>(entries: impl IntoIterator<Item = &'a Entry>) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    for entry in entries {
+        xml.push_str("<D:response><D:href>");
+        xml.push_str(&escape(entry.href.as_str()));
+        xml.push_str("</D:href><D:propstat><D:prop><D:displayname>");
+        xml.push_str(&escape(entry.name.as_str()));
+        xml.push_str("</D:displayname>");
+        match &entry.file {
+            None => xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>"),
+            Some(file) => {
+                let mime = Path::new(&entry.name)
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| actix_files::file_extension_to_mime(ext).to_string())
+                    .unwrap_or_else(|| "application/octet-stream".to_string());
+                let mtime = file
+                    .modified
+                    .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+
+                xml.push_str("<D:resourcetype/>");
+                xml.push_str(&format!(
+                    "<D:getcontentlength>{}</D:getcontentlength>",
+                    file.len
+                ));
+                xml.push_str(&format!(
+                    "<D:getcontenttype>{}</D:getcontenttype>",
+                    escape(mime.as_str())
+                ));
+                if let Some(modified) = file.modified {
+                    xml.push_str(&format!(
+                        "<D:getlastmodified>{}</D:getlastmodified>",
+                        HttpDate::from(modified)
+                    ));
+                }
+                xml.push_str(&format!(
+                    "<D:getetag>\"{:x}-{:x}\"</D:getetag>",
+                    file.len, mtime
+                ));
+            }
+        }
+        xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+    }
+    xml.push_str("</D:multistatus>\n");
+    xml
+}
+
+/// Handle any request below the WebDAV mount points.
+///
+/// OPTIONS|PROPFIND|GET|HEAD /dav/{file}
+/// OPTIONS|PROPFIND|GET|HEAD /dav-artists/{artist}/{album}/{file}
+pub async fn handle(req: HttpRequest, data: web::Data<AppState>) -> AppResult<HttpResponse> {
+    if req.method() == Method::OPTIONS {
+        return Ok(HttpResponse::Ok()
+            .insert_header(("DAV", "1"))
+            .insert_header((header::ALLOW, ALLOW))
+            .finish());
+    }
+
+    if basic_or_bearer_user(&req, &data).await.is_none() {
+        return Ok(basic_challenge());
+    }
+
+    // The view is read-only
+    if !matches!(req.method().as_str(), "PROPFIND" | "GET" | "HEAD") {
+        return Ok(HttpResponse::MethodNotAllowed()
+            .insert_header((header::ALLOW, ALLOW))
+            .finish());
+    }
+
+    let path = req.path();
+    let listing = if let Some(segments) = segments(path, ARTISTS_ROOT) {
+        if !config::get().webdav_artist_tree {
+            return Err(AppError::NotFound("Resource not found".to_string()));
+        }
+        let index = data.library.index()?;
+        artists_listing(&index, &data.music_folder, &segments)?
+    } else {
+        let segments = segments(path, FILES_ROOT)
+            .ok_or_else(|| AppError::NotFound("Resource not found".to_string()))?;
+        files_listing(&data.music_folder, &segments)?
+    };
+
+    match req.method().as_str() {
+        "PROPFIND" => {
+            // Depth: infinity is treated as 1; the trees are at most
+            // three levels deep and clients walk them anyway.
+            let depth_zero = req
+                .headers()
+                .get("Depth")
+                .and_then(|h| h.to_str().ok())
+                .is_some_and(|depth| depth.trim() == "0");
+            let body = if depth_zero {
+                multistatus([&listing.entry])
+            } else {
+                multistatus(std::iter::once(&listing.entry).chain(&listing.children))
+            };
+            Ok(HttpResponse::build(StatusCode::MULTI_STATUS)
+                .insert_header((header::CONTENT_TYPE, "application/xml; charset=utf-8"))
+                .body(body))
+        }
+        _ => match listing.entry.file {
+            // NamedFile handles ranges, conditional requests and HEAD
+            Some(file) => Ok(NamedFile::open(&file.path)?.into_response(&req)),
+            None => Ok(HttpResponse::MethodNotAllowed()
+                .insert_header((header::ALLOW, "OPTIONS, PROPFIND"))
+                .finish()),
+        },
+    }
+}
+
+/// Configure WebDAV routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource([FILES_ROOT, "/dav/{tail:.*}"]).to(handle))
+        .service(web::resource([ARTISTS_ROOT, "/dav-artists/{tail:.*}"]).to(handle));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn song(file: &str, artist: &str, album: &str) -> SongMetadata {
+        SongMetadata {
+            id: SongMetadata::generate_id(Path::new(file)),
+            title: file.to_string(),
+            artist: artist.to_string(),
+            artist_id: SongMetadata::generate_name_id("artist", artist),
+            album: album.to_string(),
+            album_id: SongMetadata::generate_name_id("album", album),
+            duration: None,
+            track_number: None,
+            year: None,
+            genre: None,
+            format: "mp3".to_string(),
+            file: file.to_string(),
+            has_cover: false,
+            starred: None,
+            rating: None,
+            play_count: 0,
+            last_played: None,
+        }
+    }
+
+    fn segs(path: &str, root: &str) -> Vec<String> {
+        segments(path, root).unwrap()
+    }
+
+    #[test]
+    fn test_files_listing_and_traversal() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("One More Time.mp3"), b"abc").unwrap();
+        std::fs::write(dir.path().join(".hidden"), b"x").unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+
+        let root = files_listing(dir.path(), &segs("/dav/", FILES_ROOT)).unwrap();
+        assert_eq!(root.entry.href, "/dav/");
+        assert_eq!(root.children.len(), 1);
+        assert_eq!(root.children[0].href, "/dav/One%20More%20Time.mp3");
+        assert_eq!(root.children[0].file.as_ref().unwrap().len, 3);
+
+        let file =
+            files_listing(dir.path(), &segs("/dav/One%20More%20Time.mp3", FILES_ROOT)).unwrap();
+        assert_eq!(file.entry.name, "One More Time.mp3");
+
+        assert!(files_listing(dir.path(), &segs("/dav/..%2Fetc%2Fpasswd", FILES_ROOT)).is_err());
+        assert!(files_listing(dir.path(), &segs("/dav/sub", FILES_ROOT)).is_err());
+        assert!(files_listing(dir.path(), &segs("/dav/sub/x.mp3", FILES_ROOT)).is_err());
+    }
+
+    #[test]
+    fn test_artist_tree() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("one.mp3"), b"abc").unwrap();
+        std::fs::write(dir.path().join("tear.mp3"), b"abcd").unwrap();
+        let mut index = LibraryIndex::default();
+        index.songs = vec![
+            song("one.mp3", "Daft Punk", "Discovery"),
+            song("tear.mp3", "AC/DC", ""),
+        ];
+
+        let root = artists_listing(&index, dir.path(), &[]).unwrap();
+        let names: Vec<&str> = root.children.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["AC_DC", "Daft Punk"]);
+
+        let album = artists_listing(
+            &index,
+            dir.path(),
+            &segs("/dav-artists/AC_DC/_/", ARTISTS_ROOT),
+        )
+        .unwrap();
+        assert_eq!(album.children[0].href, "/dav-artists/AC_DC/_/tear.mp3");
+
+        let file = artists_listing(
+            &index,
+            dir.path(),
+            &segs("/dav-artists/Daft%20Punk/Discovery/one.mp3", ARTISTS_ROOT),
+        )
+        .unwrap();
+        assert_eq!(file.entry.file.unwrap().len, 3);
+        assert!(artists_listing(
+            &index,
+            dir.path(),
+            &segs("/dav-artists/Daft%20Punk/Discovery/tear.mp3", ARTISTS_ROOT)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_multistatus() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("a&b.flac"), b"abc").unwrap();
+        let listing = files_listing(dir.path(), &[]).unwrap();
+
+        let xml = multistatus(std::iter::once(&listing.entry).chain(&listing.children));
+        assert!(xml.contains("<D:href>/dav/</D:href>"));
+        assert!(xml.contains("<D:resourcetype><D:collection/></D:resourcetype>"));
+        assert!(xml.contains("<D:href>/dav/a&amp;b.flac</D:href>"));
+        assert!(xml.contains("<D:displayname>a&amp;b.flac</D:displayname>"));
+        assert!(xml.contains("<D:getcontentlength>3</D:getcontentlength>"));
+        assert!(xml.contains("<D:getcontenttype>audio/flac</D:getcontenttype>"));
+    }
+}
@@ -0,0 +1,297 @@
+//! API keys and their repository.
+//!
+//! Long-lived credentials for scripts and headless players, sent in the
+//! `X-API-Key` header. A key is shown once when created; only a hash of
+//! its secret is stored (see [`super::opaque_token`]).
+
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use super::opaque_token::{format_token, generate_secret, hash_secret, parse_token};
+use crate::error::{AppError, AppResult};
+use crate::storage;
+
+/// How stale `last_used_at` may get before a use is written to disk.
+const LAST_USED_PRECISION: Duration = Duration::minutes(5);
+
+/// What an API key may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiKeyScope {
+    /// Read-only requests (`GET`, `HEAD`).
+    Read,
+    /// Requests that change data.
+    Write,
+    /// Admin endpoints, if the owner is an admin.
+    Admin,
+}
+
+impl ApiKeyScope {
+    /// Name of the scope as used in requests.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+/// A stored API key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    /// Key ID, the first part of the key.
+    pub id: Uuid,
+    /// Owning user.
+    pub user_id: Uuid,
+    /// Name given by the owner.
+    pub name: String,
+    /// Hash of the key's secret.
+    secret_hash: String,
+    /// What the key may do; every scope when absent.
+    pub scopes: Option<Vec<ApiKeyScope>>,
+    /// When the key was created.
+    pub created_at: DateTime<Utc>,
+    /// When the key expires, if ever.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// When the key was last used, to within a few minutes.
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    /// Create a key, returning it with the secret key string.
+    pub fn new(
+        user_id: Uuid,
+        name: String,
+        scopes: Option<Vec<ApiKeyScope>>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> (Self, String) {
+        let (secret, secret_hash) = generate_secret();
+        let key = Self {
+            id: Uuid::new_v4(),
+            user_id,
+            name,
+            secret_hash,
+            scopes,
+            created_at: Utc::now(),
+            expires_at,
+            last_used_at: None,
+        };
+        let token = format_token(key.id, &secret);
+        (key, token)
+    }
+
+    /// Whether the key has expired.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| Utc::now() > at)
+    }
+
+    /// Whether the key has a scope.
+    pub fn allows(&self, scope: ApiKeyScope) -> bool {
+        self.scopes.as_ref().is_none_or(|s| s.contains(&scope))
+    }
+}
+
+/// API key storage format for JSON file.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct ApiKeyStore {
+    keys: Vec<ApiKey>,
+}
+
+/// Trait for API key repository operations.
+pub trait ApiKeyRepository: Send + Sync {
+    /// Store a new key.
+    fn create(&self, key: ApiKey) -> AppResult<ApiKey>;
+
+    /// Find the key matching a key string, recording its use.
+    ///
+    /// Fails with an invalid token error for unknown or expired keys.
+    fn authenticate(&self, token: &str) -> AppResult<ApiKey>;
+
+    /// List a user's keys, oldest first.
+    fn list_by_user(&self, user_id: Uuid) -> AppResult<Vec<ApiKey>>;
+
+    /// Delete one of a user's keys, returning whether it existed.
+    fn delete(&self, user_id: Uuid, id: Uuid) -> AppResult<bool>;
+
+    /// Delete all of a user's keys, returning how many were removed.
+    fn delete_by_user(&self, user_id: Uuid) -> AppResult<usize>;
+}
+
+/// JSON file-based API key repository.
+#[derive(Debug)]
+pub struct JsonApiKeyRepository {
+    file_path: PathBuf,
+    /// In-memory cache keyed by key ID.
+    cache: RwLock<HashMap<Uuid, ApiKey>>,
+}
+
+impl JsonApiKeyRepository {
+    /// Create a new JSON API key repository.
+    pub fn new(file_path: impl AsRef<Path>) -> AppResult<Self> {
+        let file_path = file_path.as_ref().to_path_buf();
+        let store: ApiKeyStore = storage::load_json(&file_path)?;
+
+        let cache = store
+            .keys
+            .into_iter()
+            .map(|k| (k.id, k))
+            .collect::<HashMap<_, _>>();
+
+        tracing::info!(count = cache.len(), "Loaded API keys from file");
+
+        Ok(Self {
+            file_path,
+            cache: RwLock::new(cache),
+        })
+    }
+
+    /// Write keys from cache to file.
+    fn persist(&self) -> AppResult<()> {
+        let cache = self.cache.read();
+        let store = ApiKeyStore {
+            keys: cache.values().cloned().collect(),
+        };
+        storage::save_json(&self.file_path, &store)
+    }
+}
+
+impl ApiKeyRepository for JsonApiKeyRepository {
+    fn create(&self, key: ApiKey) -> AppResult<ApiKey> {
+        self.cache.write().insert(key.id, key.clone());
+        self.persist()?;
+
+        tracing::debug!(user_id = %key.user_id, key = %key.id, "Created API key");
+        Ok(key)
+    }
+
+    fn authenticate(&self, token: &str) -> AppResult<ApiKey> {
+        let (id, secret) = parse_token(token).ok_or_else(AppError::invalid_token)?;
+
+        let (key, stale) = {
+            let mut cache = self.cache.write();
+            let key = cache
+                .get_mut(&id)
+                .filter(|k| k.secret_hash == hash_secret(secret) && !k.is_expired())
+                .ok_or_else(AppError::invalid_token)?;
+
+            let now = Utc::now();
+            let stale = key
+                .last_used_at
+                .is_none_or(|at| now - at > LAST_USED_PRECISION);
+            if stale {
+                key.last_used_at = Some(now);
+            }
+            (key.clone(), stale)
+        };
+        if stale {
+            self.persist()?;
+        }
+
+        Ok(key)
+    }
+
+    fn list_by_user(&self, user_id: Uuid) -> AppResult<Vec<ApiKey>> {
+        let mut keys: Vec<ApiKey> = self
+            .cache
+            .read()
+            .values()
+            .filter(|k| k.user_id == user_id)
+            .cloned()
+            .collect();
+        keys.sort_by_key(|k| k.created_at);
+        Ok(keys)
+    }
+
+    fn delete(&self, user_id: Uuid, id: Uuid) -> AppResult<bool> {
+        let removed = {
+            let mut cache = self.cache.write();
+            match cache.get(&id) {
+                Some(key) if key.user_id == user_id => cache.remove(&id).is_some(),
+                _ => false,
+            }
+        };
+        if removed {
+            self.persist()?;
+        }
+        Ok(removed)
+    }
+
+    fn delete_by_user(&self, user_id: Uuid) -> AppResult<usize> {
+        let removed = {
+            let mut cache = self.cache.write();
+            let before = cache.len();
+            cache.retain(|_, k| k.user_id != user_id);
+            before - cache.len()
+        };
+        if removed > 0 {
+            self.persist()?;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_key_authenticates_after_reload() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("api_keys.json");
+        let user = Uuid::new_v4();
+
+        let repo = JsonApiKeyRepository::new(&path).unwrap();
+        let (key, token) = ApiKey::new(user, "backup".to_string(), None, None);
+        repo.create(key).unwrap();
+
+        let repo = JsonApiKeyRepository::new(&path).unwrap();
+        let key = repo.authenticate(&token).unwrap();
+        assert_eq!(key.user_id, user);
+        assert!(key.last_used_at.is_some());
+        assert!(key.allows(ApiKeyScope::Admin));
+
+        assert!(!std::fs::read_to_string(&path)
+            .unwrap()
+            .contains(token.split_once('.').unwrap().1));
+        let (id, _) = token.split_once('.').unwrap();
+        assert!(repo.authenticate(&format!("{}.wrong", id)).is_err());
+    }
+
+    #[test]
+    fn test_expired_and_deleted_keys_are_rejected() {
+        let dir = tempdir().unwrap();
+        let repo = JsonApiKeyRepository::new(dir.path().join("api_keys.json")).unwrap();
+        let user = Uuid::new_v4();
+
+        let (expired, expired_token) = ApiKey::new(
+            user,
+            "old".to_string(),
+            None,
+            Some(Utc::now() - Duration::minutes(1)),
+        );
+        repo.create(expired).unwrap();
+        assert!(repo.authenticate(&expired_token).is_err());
+
+        let (key, token) = ApiKey::new(
+            user,
+            "player".to_string(),
+            Some(vec![ApiKeyScope::Read]),
+            None,
+        );
+        let key = repo.create(key).unwrap();
+        assert!(key.allows(ApiKeyScope::Read));
+        assert!(!key.allows(ApiKeyScope::Write));
+
+        // Only the owner can delete a key
+        assert!(!repo.delete(Uuid::new_v4(), key.id).unwrap());
+        assert!(repo.delete(user, key.id).unwrap());
+        assert!(repo.authenticate(&token).is_err());
+        assert_eq!(repo.list_by_user(user).unwrap().len(), 1);
+    }
+}
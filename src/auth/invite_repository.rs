@@ -0,0 +1,212 @@
+//! Invite codes and their repository.
+//!
+//! With `REGISTRATION_MODE=invite-only`, registering needs a code created
+//! by an admin. A code can be used a limited number of times and may
+//! expire.
+
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::error::AppResult;
+use crate::storage;
+
+/// An invite code.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Invite {
+    /// The code itself.
+    pub code: String,
+    /// Admin who created the code.
+    pub created_by: Uuid,
+    /// How many accounts the code may create.
+    pub max_uses: u32,
+    /// How many accounts it created so far.
+    pub uses: u32,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Invite {
+    /// Create an invite with a new random code.
+    pub fn new(created_by: Uuid, max_uses: u32, expires_at: Option<DateTime<Utc>>) -> Self {
+        let mut bytes = [0u8; 12];
+        OsRng.fill_bytes(&mut bytes);
+        Self {
+            code: base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes),
+            created_by,
+            max_uses,
+            uses: 0,
+            created_at: Utc::now(),
+            expires_at,
+        }
+    }
+
+    /// Whether the code can no longer be used.
+    pub fn is_spent(&self) -> bool {
+        self.uses >= self.max_uses || self.expires_at.is_some_and(|at| at <= Utc::now())
+    }
+}
+
+/// Invite storage format for JSON file.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct InviteStore {
+    invites: Vec<Invite>,
+}
+
+/// Trait for invite repository operations.
+pub trait InviteRepository: Send + Sync {
+    /// Store a new invite.
+    fn create(&self, invite: Invite) -> AppResult<Invite>;
+
+    /// List all invites, including spent ones, oldest first.
+    fn list_all(&self) -> AppResult<Vec<Invite>>;
+
+    /// Use a code once, returning the updated invite, or `None` if the
+    /// code is unknown, used up or expired.
+    fn redeem(&self, code: &str) -> AppResult<Option<Invite>>;
+
+    /// Give back a use of a redeemed code whose account could not be
+    /// created.
+    fn release(&self, code: &str) -> AppResult<()>;
+
+    /// Delete an invite, returning whether it existed.
+    fn delete(&self, code: &str) -> AppResult<bool>;
+}
+
+/// JSON file-based invite repository.
+#[derive(Debug)]
+pub struct JsonInviteRepository {
+    file_path: PathBuf,
+    /// In-memory cache keyed by code.
+    cache: RwLock<HashMap<String, Invite>>,
+}
+
+impl JsonInviteRepository {
+    /// Create a new JSON invite repository.
+    pub fn new(file_path: impl AsRef<Path>) -> AppResult<Self> {
+        let file_path = file_path.as_ref().to_path_buf();
+        let store: InviteStore = storage::load_json(&file_path)?;
+
+        let cache = store
+            .invites
+            .into_iter()
+            .map(|i| (i.code.clone(), i))
+            .collect::<HashMap<_, _>>();
+
+        tracing::info!(count = cache.len(), "Loaded invite codes from file");
+
+        Ok(Self {
+            file_path,
+            cache: RwLock::new(cache),
+        })
+    }
+
+    /// Write invites from cache to file.
+    fn persist(&self) -> AppResult<()> {
+        let cache = self.cache.read();
+        let store = InviteStore {
+            invites: cache.values().cloned().collect(),
+        };
+        storage::save_json(&self.file_path, &store)
+    }
+}
+
+impl InviteRepository for JsonInviteRepository {
+    fn create(&self, invite: Invite) -> AppResult<Invite> {
+        self.cache
+            .write()
+            .insert(invite.code.clone(), invite.clone());
+        self.persist()?;
+        Ok(invite)
+    }
+
+    fn list_all(&self) -> AppResult<Vec<Invite>> {
+        let mut invites: Vec<Invite> = self.cache.read().values().cloned().collect();
+        invites.sort_by_key(|i| i.created_at);
+        Ok(invites)
+    }
+
+    fn redeem(&self, code: &str) -> AppResult<Option<Invite>> {
+        let invite = {
+            let mut cache = self.cache.write();
+            match cache.get_mut(code) {
+                Some(invite) if !invite.is_spent() => {
+                    invite.uses += 1;
+                    invite.clone()
+                }
+                _ => return Ok(None),
+            }
+        };
+        self.persist()?;
+        Ok(Some(invite))
+    }
+
+    fn release(&self, code: &str) -> AppResult<()> {
+        match self.cache.write().get_mut(code) {
+            Some(invite) if invite.uses > 0 => invite.uses -= 1,
+            _ => return Ok(()),
+        }
+        self.persist()
+    }
+
+    fn delete(&self, code: &str) -> AppResult<bool> {
+        let removed = self.cache.write().remove(code).is_some();
+        if removed {
+            self.persist()?;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_invite_is_spent_after_max_uses() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("invites.json");
+        let repo = JsonInviteRepository::new(&path).unwrap();
+        let invite = repo.create(Invite::new(Uuid::new_v4(), 2, None)).unwrap();
+
+        assert_eq!(repo.redeem(&invite.code).unwrap().unwrap().uses, 1);
+        let repo = JsonInviteRepository::new(&path).unwrap();
+        assert!(repo.redeem(&invite.code).unwrap().is_some());
+        assert!(repo.redeem(&invite.code).unwrap().is_none());
+        assert!(repo.redeem("unknown").unwrap().is_none());
+
+        // A use given back can be used again
+        repo.release(&invite.code).unwrap();
+        assert!(repo.redeem(&invite.code).unwrap().is_some());
+        assert!(repo.redeem(&invite.code).unwrap().is_none());
+
+        // Spent invites are still listed, until deleted
+        assert_eq!(repo.list_all().unwrap().len(), 1);
+        assert!(repo.delete(&invite.code).unwrap());
+        assert!(repo.list_all().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_expired_invite_cannot_be_redeemed() {
+        let dir = tempdir().unwrap();
+        let repo = JsonInviteRepository::new(dir.path().join("invites.json")).unwrap();
+        let invite = repo
+            .create(Invite::new(
+                Uuid::new_v4(),
+                5,
+                Some(Utc::now() - Duration::minutes(1)),
+            ))
+            .unwrap();
+
+        assert!(invite.is_spent());
+        assert!(repo.redeem(&invite.code).unwrap().is_none());
+    }
+}
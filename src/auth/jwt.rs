@@ -5,6 +5,7 @@ use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation}
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::auth::User;
 use crate::config;
 use crate::error::{AppError, AppResult};
 
@@ -21,13 +22,29 @@ pub struct Claims {
     pub exp: i64,
     /// Issued at time (Unix timestamp).
     pub iat: i64,
+    /// The user's token version when issued (see [`User::token_version`]).
+    #[serde(default)]
+    pub ver: u32,
+    /// Login session (refresh token family) the token was issued for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
 }
 
 impl Claims {
     /// Create new claims for a user.
     pub fn new(user_id: Uuid, username: String, is_admin: bool, expiry_days: i64) -> Self {
+        Self::with_lifetime(user_id, username, is_admin, Duration::days(expiry_days))
+    }
+
+    /// Create new claims for a user, valid for `lifetime`.
+    pub fn with_lifetime(
+        user_id: Uuid,
+        username: String,
+        is_admin: bool,
+        lifetime: Duration,
+    ) -> Self {
         let now = Utc::now();
-        let exp = now + Duration::days(expiry_days);
+        let exp = now + lifetime;
 
         Self {
             sub: user_id,
@@ -35,6 +52,8 @@ impl Claims {
             is_admin,
             exp: exp.timestamp(),
             iat: now.timestamp(),
+            ver: 0,
+            sid: None,
         }
     }
 
@@ -44,15 +63,19 @@ impl Claims {
     }
 }
 
-/// Token pair (for future refresh token support).
+/// Token pair issued on login and refresh.
 #[derive(Debug, Clone, Serialize)]
 pub struct TokenPair {
     /// Access token.
     pub access_token: String,
     /// Token type (always "Bearer").
     pub token_type: String,
-    /// Expiration time in seconds.
+    /// Access token expiration time in seconds.
     pub expires_in: i64,
+    /// Opaque token for `/auth/refresh`, replaced on every use.
+    pub refresh_token: String,
+    /// Refresh token expiration time in seconds.
+    pub refresh_expires_in: i64,
 }
 
 /// Encode a JWT token.
@@ -80,22 +103,29 @@ pub fn decode_token(token: &str) -> AppResult<Claims> {
         })
 }
 
-/// Create a new token pair for a user.
+/// Create a new token pair for a user around the refresh token of a login
+/// session.
 pub fn create_token_pair(
-    user_id: Uuid,
-    username: String,
-    is_admin: bool,
+    user: &User,
+    session_id: Uuid,
+    refresh_token: String,
 ) -> AppResult<TokenPair> {
     let config = config::get();
-    let expiry_days = config.jwt_expiry_days;
+    let lifetime = Duration::minutes(config.access_token_minutes);
 
-    let claims = Claims::new(user_id, username, is_admin, expiry_days);
+    let claims = Claims {
+        ver: user.token_version,
+        sid: Some(session_id),
+        ..Claims::with_lifetime(user.id, user.username.clone(), user.is_admin, lifetime)
+    };
     let access_token = encode_token(&claims)?;
 
     Ok(TokenPair {
         access_token,
         token_type: "Bearer".to_string(),
-        expires_in: expiry_days * 24 * 60 * 60, // Convert days to seconds
+        expires_in: lifetime.num_seconds(),
+        refresh_token,
+        refresh_expires_in: Duration::days(config.jwt_expiry_days).num_seconds(),
     })
 }
 
@@ -0,0 +1,142 @@
+//! Pending second steps of two-factor logins.
+//!
+//! When a user with two-factor authentication gives the right password,
+//! they get a short-lived challenge token instead of a session. The token
+//! is exchanged for a session together with a code. A challenge allows a
+//! few wrong codes before it has to be started over with the password.
+//! Challenges are kept in memory and lost on restart.
+
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use super::opaque_token::{format_token, generate_secret, hash_secret, parse_token};
+use crate::error::{AppError, AppResult};
+
+/// How long a challenge can be completed.
+pub const CHALLENGE_LIFETIME: Duration = Duration::minutes(5);
+
+/// Wrong codes allowed per challenge.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// A started two-factor login.
+#[derive(Debug)]
+struct Challenge {
+    user_id: Uuid,
+    secret_hash: String,
+    attempts: u32,
+    expires_at: DateTime<Utc>,
+}
+
+/// Issues and completes login challenges.
+#[derive(Debug, Default)]
+pub struct LoginChallenges {
+    challenges: Mutex<HashMap<Uuid, Challenge>>,
+}
+
+impl LoginChallenges {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a challenge for a user whose password was correct.
+    pub fn issue(&self, user_id: Uuid) -> String {
+        let id = Uuid::new_v4();
+        let (secret, secret_hash) = generate_secret();
+        let now = Utc::now();
+
+        let mut challenges = self.challenges.lock();
+        challenges.retain(|_, c| c.expires_at > now);
+        challenges.insert(
+            id,
+            Challenge {
+                user_id,
+                secret_hash,
+                attempts: 0,
+                expires_at: now + CHALLENGE_LIFETIME,
+            },
+        );
+        format_token(id, &secret)
+    }
+
+    /// Complete a challenge with what `check` returns for its user, if it
+    /// accepts the code. The challenge is used up on success or after too
+    /// many wrong codes.
+    ///
+    /// The challenge is taken out while `check` runs, so guesses on one
+    /// challenge cannot race, and is put back if the code was wrong.
+    pub fn complete<T>(
+        &self,
+        token: &str,
+        check: impl FnOnce(Uuid) -> AppResult<Option<T>>,
+    ) -> AppResult<T> {
+        let invalid = || AppError::Unauthorized("Invalid or expired login challenge".to_string());
+        let (id, secret) = parse_token(token).ok_or_else(invalid)?;
+
+        let mut challenge = {
+            let mut challenges = self.challenges.lock();
+            match challenges.get(&id) {
+                Some(c) if c.secret_hash == hash_secret(secret) && c.expires_at > Utc::now() => {
+                    challenges.remove(&id).expect("found above")
+                }
+                _ => return Err(invalid()),
+            }
+        };
+
+        match check(challenge.user_id) {
+            Ok(Some(value)) => return Ok(value),
+            Ok(None) => challenge.attempts += 1,
+            Err(e) => {
+                self.challenges.lock().insert(id, challenge);
+                return Err(e);
+            }
+        }
+        if challenge.attempts < MAX_ATTEMPTS {
+            self.challenges.lock().insert(id, challenge);
+        }
+        Err(AppError::Unauthorized(
+            "Invalid two-factor code".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenge_allows_limited_attempts() {
+        let challenges = LoginChallenges::new();
+        let user_id = Uuid::new_v4();
+
+        let token = challenges.issue(user_id);
+        assert!(challenges.complete("garbage", |id| Ok(Some(id))).is_err());
+        assert!(challenges.complete(&token, |_| Ok(None::<Uuid>)).is_err());
+        assert_eq!(
+            challenges.complete(&token, |id| Ok(Some(id))).unwrap(),
+            user_id
+        );
+        // Used up
+        assert!(challenges.complete(&token, |id| Ok(Some(id))).is_err());
+
+        let token = challenges.issue(user_id);
+        for _ in 0..MAX_ATTEMPTS {
+            assert!(challenges.complete(&token, |_| Ok(None::<Uuid>)).is_err());
+        }
+        assert!(challenges.complete(&token, |id| Ok(Some(id))).is_err());
+
+        // Checked without the lock; refusals keep the challenge as it was
+        let token = challenges.issue(user_id);
+        let result = challenges.complete(&token, |_| {
+            assert!(challenges.complete(&token, |id| Ok(Some(id))).is_err());
+            Err::<Option<Uuid>, _>(AppError::Forbidden("Account is disabled".to_string()))
+        });
+        assert!(matches!(result, Err(AppError::Forbidden(_))));
+        assert_eq!(
+            challenges.complete(&token, |id| Ok(Some(id))).unwrap(),
+            user_id
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//! Brute-force protection for password logins.
+//!
+//! Failed attempts are counted per client IP and per username. After a few
+//! free attempts, each further failure blocks the next attempt for twice
+//! as long as the last, up to a cap. A username that keeps failing is
+//! locked for a fixed time. A login attempt counts as a failure until it
+//! succeeds, so parallel guesses cannot slip through before the first one
+//! is recorded. Credentials sent with every request are only counted once
+//! found wrong. A successful login clears the username's count, while an
+//! IP's count is only forgotten with time, so an attacker cannot reset it
+//! by logging into an account of their own. Counts are kept in memory and
+//! reset on restart.
+
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+
+use crate::error::{AppError, AppResult};
+
+/// Failures allowed before backing off.
+const FREE_FAILURES: u32 = 3;
+
+/// Longest backoff between attempts.
+const MAX_BACKOFF: Duration = Duration::minutes(15);
+
+/// How long after the last failure a count is forgotten.
+const FORGET_AFTER: Duration = Duration::hours(1);
+
+/// Most IPs or usernames tracked before old entries are pruned.
+const MAX_TRACKED: usize = 10_000;
+
+/// Recent failures of one IP or username.
+#[derive(Debug, Clone)]
+struct Failures {
+    count: u32,
+    last_at: DateTime<Utc>,
+    blocked_until: DateTime<Utc>,
+}
+
+/// Backoff after `count` consecutive failures.
+fn backoff(count: u32) -> Duration {
+    if count <= FREE_FAILURES {
+        return Duration::zero();
+    }
+    let exponent = (count - FREE_FAILURES - 1).min(20);
+    Duration::seconds(1 << exponent).min(MAX_BACKOFF)
+}
+
+/// Seconds until `until`, rounded up.
+fn seconds_until(until: DateTime<Utc>, now: DateTime<Utc>) -> u64 {
+    let millis = (until - now).num_milliseconds().max(0) as u64;
+    millis.div_ceil(1000).max(1)
+}
+
+/// Counts failed logins and refuses attempts while backing off.
+#[derive(Debug)]
+pub struct LoginThrottle {
+    by_ip: Mutex<HashMap<String, Failures>>,
+    by_username: Mutex<HashMap<String, Failures>>,
+    /// Failures after which a username is locked; `0` disables lockout.
+    lockout_threshold: u32,
+    lockout: Duration,
+}
+
+impl LoginThrottle {
+    /// Create a throttle that locks a username for `lockout` after
+    /// `lockout_threshold` consecutive failures.
+    pub fn new(lockout_threshold: u32, lockout: Duration) -> Self {
+        Self {
+            by_ip: Mutex::new(HashMap::new()),
+            by_username: Mutex::new(HashMap::new()),
+            lockout_threshold,
+            lockout,
+        }
+    }
+
+    /// Start a login attempt, counting it as a failure until
+    /// [`succeeded`](Self::succeeded) is called.
+    ///
+    /// Returns the username's consecutive failures including this one, or
+    /// a [`AppError::TooManyRequests`] if the IP or username must wait.
+    pub fn attempt(&self, ip: &str, username: &str) -> AppResult<u32> {
+        self.attempt_at(ip, username, Utc::now())
+    }
+
+    /// Refuse a login while the IP or username must wait, without counting
+    /// it.
+    ///
+    /// For credentials sent with every request (HTTP Basic, Subsonic, MPD),
+    /// where a client's parallel requests would trip the backoff if each
+    /// counted until it succeeded. Report wrong ones with
+    /// [`failed`](Self::failed).
+    pub fn check(&self, ip: &str, username: &str) -> AppResult<()> {
+        let now = Utc::now();
+        self.check_blocked(
+            &self.by_ip.lock(),
+            &self.by_username.lock(),
+            ip,
+            &username.to_lowercase(),
+            now,
+        )
+    }
+
+    /// Count a failed login checked with [`check`](Self::check), returning
+    /// the username's consecutive failures.
+    pub fn failed(&self, ip: &str, username: &str) -> u32 {
+        self.failed_at(ip, username, Utc::now())
+    }
+
+    /// End an [`attempt`](Self::attempt) that succeeded, clearing the
+    /// username's failures and taking the attempt back from the IP.
+    ///
+    /// The IP's earlier failures stay until they are forgotten, so logging
+    /// into one account does not reset guesses at others.
+    pub fn succeeded(&self, ip: &str, username: &str) {
+        if let Some(failures) = self.by_ip.lock().get_mut(ip) {
+            failures.count = failures.count.saturating_sub(1);
+            failures.blocked_until = failures
+                .blocked_until
+                .min(failures.last_at + backoff(failures.count));
+        }
+        self.passed(username);
+    }
+
+    /// Clear a username's failures after credentials checked with
+    /// [`check`](Self::check) were right. The IP's failures stay until
+    /// they are forgotten.
+    pub fn passed(&self, username: &str) {
+        self.by_username.lock().remove(&username.to_lowercase());
+    }
+
+    fn is_locked(&self, count: u32) -> bool {
+        self.lockout_threshold > 0 && count >= self.lockout_threshold
+    }
+
+    fn attempt_at(&self, ip: &str, username: &str, now: DateTime<Utc>) -> AppResult<u32> {
+        let username = username.to_lowercase();
+        let mut by_ip = self.by_ip.lock();
+        let mut by_username = self.by_username.lock();
+
+        self.check_blocked(&by_ip, &by_username, ip, &username, now)?;
+        Ok(self.record_failure(&mut by_ip, &mut by_username, ip, &username, now))
+    }
+
+    fn failed_at(&self, ip: &str, username: &str, now: DateTime<Utc>) -> u32 {
+        let username = username.to_lowercase();
+        let mut by_ip = self.by_ip.lock();
+        let mut by_username = self.by_username.lock();
+
+        self.record_failure(&mut by_ip, &mut by_username, ip, &username, now)
+    }
+
+    fn check_blocked(
+        &self,
+        by_ip: &HashMap<String, Failures>,
+        by_username: &HashMap<String, Failures>,
+        ip: &str,
+        username: &str,
+        now: DateTime<Utc>,
+    ) -> AppResult<()> {
+        if let Some(failures) = by_username.get(username) {
+            if failures.blocked_until > now {
+                let message = if self.is_locked(failures.count) {
+                    "Too many failed logins; the account is temporarily locked"
+                } else {
+                    "Too many failed logins; try again later"
+                };
+                return Err(AppError::TooManyRequests {
+                    message: message.to_string(),
+                    retry_after: seconds_until(failures.blocked_until, now),
+                });
+            }
+        }
+        if let Some(failures) = by_ip.get(ip) {
+            if failures.blocked_until > now {
+                return Err(AppError::TooManyRequests {
+                    message: "Too many failed logins from this address; try again later"
+                        .to_string(),
+                    retry_after: seconds_until(failures.blocked_until, now),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn record_failure(
+        &self,
+        by_ip: &mut HashMap<String, Failures>,
+        by_username: &mut HashMap<String, Failures>,
+        ip: &str,
+        username: &str,
+        now: DateTime<Utc>,
+    ) -> u32 {
+        record(by_ip, ip, now, backoff);
+        record(by_username, username, now, |count| {
+            if self.is_locked(count) {
+                self.lockout
+            } else {
+                backoff(count)
+            }
+        })
+    }
+}
+
+/// Count a failure of `key`, blocking it for `delay(count)`.
+fn record(
+    failures: &mut HashMap<String, Failures>,
+    key: &str,
+    now: DateTime<Utc>,
+    delay: impl Fn(u32) -> Duration,
+) -> u32 {
+    if failures.len() >= MAX_TRACKED {
+        failures.retain(|_, f| now - f.last_at < FORGET_AFTER || f.blocked_until > now);
+    }
+
+    let entry = failures.entry(key.to_string()).or_insert(Failures {
+        count: 0,
+        last_at: now,
+        blocked_until: now,
+    });
+    if now - entry.last_at >= FORGET_AFTER && entry.blocked_until <= now {
+        entry.count = 0;
+    }
+    entry.count += 1;
+    entry.last_at = now;
+    entry.blocked_until = now + delay(entry.count);
+    entry.count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retry_after(result: AppResult<u32>) -> u64 {
+        match result {
+            Err(AppError::TooManyRequests { retry_after, .. }) => retry_after,
+            other => panic!("expected TooManyRequests, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_after_free_attempts() {
+        let throttle = LoginThrottle::new(0, Duration::minutes(15));
+        let mut now = Utc::now();
+
+        for expected in 1..=FREE_FAILURES {
+            assert_eq!(
+                throttle.attempt_at("1.2.3.4", "alice", now).unwrap(),
+                expected
+            );
+        }
+        // 4th failure: 1s, 5th: 2s, 6th: 4s
+        for delay in [1, 2, 4] {
+            throttle.attempt_at("1.2.3.4", "alice", now).unwrap();
+            assert_eq!(
+                retry_after(throttle.attempt_at("1.2.3.4", "alice", now)),
+                delay
+            );
+            // Another address is held back by the username
+            assert!(throttle.attempt_at("5.6.7.8", "Alice", now).is_err());
+            now += Duration::seconds(delay as i64);
+        }
+
+        // Logging into another account clears only that username
+        throttle.attempt_at("1.2.3.4", "bob", now).unwrap();
+        throttle.succeeded("1.2.3.4", "bob");
+        assert_eq!(retry_after(throttle.attempt_at("1.2.3.4", "alice", now)), 4);
+        assert!(throttle.attempt_at("5.6.7.8", "alice", now).is_ok());
+
+        throttle.succeeded("5.6.7.8", "ALICE");
+        assert_eq!(throttle.attempt_at("5.6.7.8", "alice", now).unwrap(), 1);
+        assert_eq!(backoff(100), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_username_is_locked_after_threshold() {
+        let throttle = LoginThrottle::new(5, Duration::minutes(15));
+        let mut now = Utc::now();
+
+        // Spread over addresses, so only the username is counted
+        for i in 0..5 {
+            throttle
+                .attempt_at(&format!("10.0.0.{}", i), "bob", now)
+                .unwrap();
+            now += MAX_BACKOFF;
+        }
+        let result = throttle.attempt_at("10.0.1.1", "bob", now - MAX_BACKOFF);
+        assert_eq!(retry_after(result), 15 * 60);
+        assert!(throttle.attempt_at("10.0.1.1", "bob", now).is_ok());
+    }
+
+    #[test]
+    fn test_checked_logins_count_only_failures() {
+        let throttle = LoginThrottle::new(0, Duration::minutes(15));
+        let now = Utc::now();
+
+        // Checking alone never blocks, however often a client asks
+        for _ in 0..10 {
+            throttle.check("1.2.3.4", "carol").unwrap();
+        }
+
+        for _ in 0..=FREE_FAILURES {
+            throttle.failed_at("1.2.3.4", "Carol", now);
+        }
+        assert!(throttle.check("1.2.3.4", "carol").is_err());
+        assert_eq!(retry_after(throttle.attempt_at("1.2.3.4", "carol", now)), 1);
+
+        // A right password for another account leaves the address blocked
+        throttle.passed("dave");
+        assert!(throttle.check("1.2.3.4", "dave").is_err());
+        assert!(throttle.check("5.6.7.8", "carol").is_err());
+
+        throttle.passed("Carol");
+        assert!(throttle.check("5.6.7.8", "carol").is_ok());
+    }
+}
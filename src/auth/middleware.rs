@@ -1,10 +1,16 @@
 //! Authentication middleware and extractors.
 
-use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
 use std::future::{ready, Ready};
 use uuid::Uuid;
 
+use super::api_key_repository::ApiKeyScope;
 use super::jwt::{decode_token, Claims};
+use super::{
+    ApiKeyRepository, JsonApiKeyRepository, JsonRefreshTokenRepository, JsonUserRepository,
+    RefreshTokenRepository, User, UserRepository,
+};
+use crate::config;
 use crate::error::AppError;
 
 /// Authenticated user extractor.
@@ -25,6 +31,13 @@ pub struct AuthenticatedUser {
     pub username: String,
     /// Whether the user is an admin.
     pub is_admin: bool,
+    /// Whether admin privileges are withheld until the user enables
+    /// two-factor authentication (`REQUIRE_ADMIN_2FA`).
+    pub needs_two_factor: bool,
+    /// Login session of the access token, if authenticated with one.
+    pub session_id: Option<Uuid>,
+    /// API key used to authenticate, if any.
+    pub api_key_id: Option<Uuid>,
 }
 
 impl AuthenticatedUser {
@@ -34,17 +47,36 @@ impl AuthenticatedUser {
             id: claims.sub,
             username: claims.username,
             is_admin: claims.is_admin,
+            needs_two_factor: false,
+            session_id: claims.sid,
+            api_key_id: None,
+        }
+    }
+
+    /// Create from a stored user, outside of any login session.
+    pub fn from_user(user: User) -> Self {
+        let needs_two_factor =
+            user.is_admin && !user.has_two_factor() && config::get().require_admin_2fa;
+        Self {
+            id: user.id,
+            username: user.username,
+            is_admin: user.is_admin && !needs_two_factor,
+            needs_two_factor,
+            session_id: None,
+            api_key_id: None,
         }
     }
 
     /// Check if the user has admin privileges.
     pub fn require_admin(&self) -> Result<(), AppError> {
-        if self.is_admin {
-            Ok(())
-        } else {
+        if self.needs_two_factor {
             Err(AppError::Forbidden(
-                "Admin privileges required".to_string(),
+                "Admin accounts must enable two-factor authentication".to_string(),
             ))
+        } else if self.is_admin {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden("Admin privileges required".to_string()))
         }
     }
 }
@@ -58,25 +90,44 @@ impl FromRequest for AuthenticatedUser {
     }
 }
 
+/// Header carrying an API key, as an alternative to a bearer token.
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
 /// Extract the authenticated user from request headers.
 fn extract_user(req: &HttpRequest) -> Result<AuthenticatedUser, AppError> {
+    if let Some(key) = req.headers().get(API_KEY_HEADER) {
+        let key = key.to_str().map_err(|_| AppError::invalid_token())?;
+        return authenticate_api_key(req, key);
+    }
+
     // Get Authorization header
     let auth_header = req
         .headers()
         .get("Authorization")
         .and_then(|h| h.to_str().ok())
-        .ok_or_else(|| {
-            AppError::Unauthorized("Missing Authorization header".to_string())
-        })?;
+        .ok_or_else(|| AppError::Unauthorized("Missing Authorization header".to_string()))?;
 
     // Parse Bearer token
     let token = auth_header
         .strip_prefix("Bearer ")
         .or_else(|| auth_header.strip_prefix("bearer "))
         .ok_or_else(|| {
-            AppError::Unauthorized("Invalid Authorization header format. Expected: Bearer <token>".to_string())
+            AppError::Unauthorized(
+                "Invalid Authorization header format. Expected: Bearer <token>".to_string(),
+            )
         })?;
 
+    authenticate_token(req, token)
+}
+
+/// Authenticate an access token.
+///
+/// Used directly where a token cannot be sent in a header, such as
+/// WebSocket connections from browsers.
+///
+/// The username and admin flag are taken from the user store rather than
+/// the token, so a demoted admin loses access straight away.
+pub fn authenticate_token(req: &HttpRequest, token: &str) -> Result<AuthenticatedUser, AppError> {
     // Decode and validate token
     let claims = decode_token(token)?;
 
@@ -85,7 +136,73 @@ fn extract_user(req: &HttpRequest) -> Result<AuthenticatedUser, AppError> {
         return Err(AppError::invalid_token());
     }
 
-    Ok(AuthenticatedUser::from_claims(claims))
+    // Tokens of sessions that were logged out or revoked
+    if let (Some(sid), Some(sessions)) = (
+        claims.sid,
+        req.app_data::<web::Data<JsonRefreshTokenRepository>>(),
+    ) {
+        if sessions.is_revoked(sid) {
+            return Err(AppError::invalid_token());
+        }
+    }
+
+    let Some(users) = req.app_data::<web::Data<JsonUserRepository>>() else {
+        return Ok(AuthenticatedUser::from_claims(claims));
+    };
+
+    // Tokens issued before a password change or for deleted users are void
+    let user = users
+        .find_by_id(claims.sub)?
+        .filter(|user| user.token_version == claims.ver)
+        .ok_or_else(AppError::invalid_token)?;
+    if user.disabled {
+        return Err(AppError::account_disabled());
+    }
+
+    Ok(AuthenticatedUser {
+        session_id: claims.sid,
+        ..AuthenticatedUser::from_user(user)
+    })
+}
+
+/// Authenticate an API key.
+///
+/// Keys without the `read` or `write` scope are refused for safe or
+/// unsafe methods respectively; admin privileges need the `admin` scope.
+fn authenticate_api_key(req: &HttpRequest, key: &str) -> Result<AuthenticatedUser, AppError> {
+    let (Some(keys), Some(users)) = (
+        req.app_data::<web::Data<JsonApiKeyRepository>>(),
+        req.app_data::<web::Data<JsonUserRepository>>(),
+    ) else {
+        return Err(AppError::invalid_token());
+    };
+
+    let key = keys.authenticate(key)?;
+    let user = users
+        .find_by_id(key.user_id)?
+        .ok_or_else(AppError::invalid_token)?;
+    if user.disabled {
+        return Err(AppError::account_disabled());
+    }
+
+    let scope = if req.method().is_safe() {
+        ApiKeyScope::Read
+    } else {
+        ApiKeyScope::Write
+    };
+    if !key.allows(scope) {
+        return Err(AppError::Forbidden(format!(
+            "API key lacks the '{}' scope",
+            scope.as_str()
+        )));
+    }
+
+    let user = AuthenticatedUser::from_user(user);
+    Ok(AuthenticatedUser {
+        is_admin: user.is_admin && key.allows(ApiKeyScope::Admin),
+        api_key_id: Some(key.id),
+        ..user
+    })
 }
 
 /// Optional authenticated user extractor.
@@ -106,7 +223,10 @@ impl FromRequest for OptionalUser {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::auth::{jwt, User};
+    use crate::config;
     use actix_web::test::TestRequest;
+    use tempfile::tempdir;
 
     #[test]
     fn test_missing_auth_header() {
@@ -125,4 +245,116 @@ mod tests {
 
         assert!(matches!(result, Err(AppError::Unauthorized(_))));
     }
+
+    #[test]
+    fn test_password_change_revokes_tokens() {
+        std::env::set_var("JWT_SECRET", "test-secret-key-for-testing-purposes-only");
+        std::env::set_var("MUSIC_FOLDER", ".");
+        let _ = config::init();
+
+        let dir = tempdir().unwrap();
+        let repo = JsonUserRepository::new(dir.path().join("users.json")).unwrap();
+        let mut user = repo
+            .create(User::new("alice".to_string(), "hash".to_string(), false))
+            .unwrap();
+        let old_token = jwt::create_token_pair(&user, Uuid::new_v4(), String::new())
+            .unwrap()
+            .access_token;
+
+        user.set_password("new-hash".to_string());
+        let user = repo.update(user).unwrap();
+        let new_token = jwt::create_token_pair(&user, Uuid::new_v4(), String::new())
+            .unwrap()
+            .access_token;
+
+        let req = TestRequest::default()
+            .app_data(web::Data::new(repo))
+            .to_http_request();
+        assert!(matches!(
+            authenticate_token(&req, &old_token),
+            Err(AppError::Unauthorized(_))
+        ));
+        assert_eq!(authenticate_token(&req, &new_token).unwrap().id, user.id);
+    }
+
+    #[test]
+    fn test_claims_are_checked_against_user_store() {
+        std::env::set_var("JWT_SECRET", "test-secret-key-for-testing-purposes-only");
+        std::env::set_var("MUSIC_FOLDER", ".");
+        let _ = config::init();
+
+        let dir = tempdir().unwrap();
+        let repo = web::Data::new(JsonUserRepository::new(dir.path().join("users.json")).unwrap());
+        repo.create(User::new("root".to_string(), "hash".to_string(), true))
+            .unwrap();
+        let mut user = repo
+            .create(User::new("alice".to_string(), "hash".to_string(), true))
+            .unwrap();
+        let token = jwt::create_token_pair(&user, Uuid::new_v4(), String::new())
+            .unwrap()
+            .access_token;
+        let req = TestRequest::default()
+            .app_data(repo.clone())
+            .to_http_request();
+
+        // Demoted after the token was issued
+        user.is_admin = false;
+        let mut user = repo.update(user).unwrap();
+        let authenticated = authenticate_token(&req, &token).unwrap();
+        assert!(!authenticated.is_admin);
+        assert!(authenticated.session_id.is_some());
+
+        user.disabled = true;
+        repo.update(user.clone()).unwrap();
+        assert!(matches!(
+            authenticate_token(&req, &token),
+            Err(AppError::Forbidden(_))
+        ));
+
+        repo.delete(user.id).unwrap();
+        assert!(matches!(
+            authenticate_token(&req, &token),
+            Err(AppError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_api_key_scopes() {
+        use crate::auth::api_key_repository::ApiKey;
+
+        let dir = tempdir().unwrap();
+        let users = web::Data::new(JsonUserRepository::new(dir.path().join("users.json")).unwrap());
+        let keys =
+            web::Data::new(JsonApiKeyRepository::new(dir.path().join("api_keys.json")).unwrap());
+        let user = users
+            .create(User::new("alice".to_string(), "hash".to_string(), true))
+            .unwrap();
+        let (key, token) = ApiKey::new(
+            user.id,
+            "player".to_string(),
+            Some(vec![ApiKeyScope::Read]),
+            None,
+        );
+        keys.create(key).unwrap();
+
+        let request = |method| {
+            TestRequest::default()
+                .method(method)
+                .insert_header((API_KEY_HEADER, token.as_str()))
+                .app_data(users.clone())
+                .app_data(keys.clone())
+                .to_http_request()
+        };
+
+        let authenticated = extract_user(&request(actix_web::http::Method::GET)).unwrap();
+        assert_eq!(authenticated.id, user.id);
+        assert!(authenticated.api_key_id.is_some());
+        // Admin privileges need the admin scope
+        assert!(!authenticated.is_admin);
+
+        assert!(matches!(
+            extract_user(&request(actix_web::http::Method::POST)),
+            Err(AppError::Forbidden(_))
+        ));
+    }
 }
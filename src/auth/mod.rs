@@ -1,8 +1,26 @@
 //! Authentication and authorization module.
 
+pub mod api_key_repository;
+pub mod invite_repository;
 pub mod jwt;
+pub mod login_challenge;
+pub mod login_throttle;
 pub mod middleware;
+mod opaque_token;
+pub mod refresh_token_repository;
+pub mod secret_box;
+pub mod setup_token;
+pub mod totp;
+pub mod url_signer;
 pub mod user_repository;
 
+pub use api_key_repository::{ApiKeyRepository, JsonApiKeyRepository};
+pub use invite_repository::{InviteRepository, JsonInviteRepository};
+pub use login_challenge::LoginChallenges;
+pub use login_throttle::LoginThrottle;
 pub use middleware::AuthenticatedUser;
+pub use refresh_token_repository::{JsonRefreshTokenRepository, RefreshTokenRepository};
+pub use secret_box::SecretBox;
+pub use setup_token::SetupToken;
+pub use url_signer::{Grantee, UrlSigner};
 pub use user_repository::{JsonUserRepository, User, UserRepository};
@@ -0,0 +1,403 @@
+//! Refresh tokens, login sessions and token revocation.
+//!
+//! A refresh token is `<family>.<secret>`. Each login starts a family, and
+//! every refresh replaces the family's secret, so only the latest token of
+//! a family works. Presenting an older one means the token was copied:
+//! the whole family is revoked, signing out both the thief and the owner.
+//!
+//! Families are the login sessions users see and revoke. Access tokens
+//! name their family in the `sid` claim; revoking a family puts its ID on
+//! a revocation list until every access token issued for it has expired.
+//!
+//! Only a hash of the secret is stored (see [`super::opaque_token`]).
+
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use super::opaque_token::{format_token, generate_secret, hash_secret, parse_token};
+use crate::error::{AppError, AppResult};
+use crate::storage;
+
+/// Where a login was made from.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Client {
+    /// The client's `User-Agent`.
+    pub device: Option<String>,
+    /// The client's IP address.
+    pub ip: Option<String>,
+}
+
+/// A chain of refresh tokens descending from one login.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenFamily {
+    /// Family ID, the first part of its tokens.
+    pub id: Uuid,
+    /// User the tokens belong to.
+    pub user_id: Uuid,
+    /// Hash of the current token's secret.
+    secret_hash: String,
+    /// Client that logged in; the IP is updated on every refresh.
+    #[serde(flatten)]
+    pub client: Client,
+    /// When the family was started by a login.
+    pub created_at: DateTime<Utc>,
+    /// When the current token was issued.
+    pub refreshed_at: DateTime<Utc>,
+    /// When the current token expires.
+    pub expires_at: DateTime<Utc>,
+}
+
+impl TokenFamily {
+    /// Whether the current token has expired.
+    pub fn is_expired(&self) -> bool {
+        Utc::now() > self.expires_at
+    }
+}
+
+/// A revoked family.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Revocation {
+    id: Uuid,
+    /// When the last access token issued for it expires.
+    until: DateTime<Utc>,
+}
+
+/// Refresh token storage format for JSON file.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct RefreshTokenStore {
+    families: Vec<TokenFamily>,
+    #[serde(default)]
+    revoked: Vec<Revocation>,
+}
+
+/// Trait for refresh token repository operations.
+pub trait RefreshTokenRepository: Send + Sync {
+    /// Start a new family for a user, returning its ID and first token.
+    fn issue(&self, user_id: Uuid, client: Client) -> AppResult<(Uuid, String)>;
+
+    /// Exchange a token for the next one of its family, returning the
+    /// family and the new token.
+    ///
+    /// Fails with an invalid token error for unknown or expired tokens,
+    /// and revokes the family when a replaced token is reused.
+    fn rotate(&self, token: &str, ip: Option<String>) -> AppResult<(TokenFamily, String)>;
+
+    /// Find a family by ID.
+    fn find(&self, family_id: Uuid) -> AppResult<Option<TokenFamily>>;
+
+    /// List a user's families, oldest first.
+    fn list_by_user(&self, user_id: Uuid) -> AppResult<Vec<TokenFamily>>;
+
+    /// Revoke a family and its access tokens, returning whether it existed.
+    fn revoke(&self, family_id: Uuid) -> AppResult<bool>;
+
+    /// Revoke all of a user's families, returning how many were removed.
+    fn revoke_by_user(&self, user_id: Uuid) -> AppResult<usize>;
+
+    /// Whether a family was revoked while its access tokens may still be
+    /// valid.
+    fn is_revoked(&self, family_id: Uuid) -> bool;
+}
+
+/// Families and revocations, kept under one lock.
+#[derive(Debug, Default)]
+struct Cache {
+    /// Families keyed by ID.
+    families: HashMap<Uuid, TokenFamily>,
+    /// Revoked family IDs and when they can be forgotten.
+    revoked: HashMap<Uuid, DateTime<Utc>>,
+}
+
+/// JSON file-based refresh token repository.
+#[derive(Debug)]
+pub struct JsonRefreshTokenRepository {
+    file_path: PathBuf,
+    /// How long a token stays valid unless replaced.
+    lifetime: Duration,
+    /// How long access tokens stay valid, to know how long a revoked
+    /// family must be remembered.
+    access_lifetime: Duration,
+    cache: RwLock<Cache>,
+}
+
+impl JsonRefreshTokenRepository {
+    /// Create a new JSON refresh token repository.
+    pub fn new(
+        file_path: impl AsRef<Path>,
+        lifetime: Duration,
+        access_lifetime: Duration,
+    ) -> AppResult<Self> {
+        let file_path = file_path.as_ref().to_path_buf();
+        let store: RefreshTokenStore = storage::load_json(&file_path)?;
+
+        let now = Utc::now();
+        let cache = Cache {
+            families: store
+                .families
+                .into_iter()
+                .filter(|f| !f.is_expired())
+                .map(|f| (f.id, f))
+                .collect(),
+            revoked: store
+                .revoked
+                .into_iter()
+                .filter(|r| r.until > now)
+                .map(|r| (r.id, r.until))
+                .collect(),
+        };
+
+        tracing::info!(
+            count = cache.families.len(),
+            revoked = cache.revoked.len(),
+            "Loaded refresh tokens from file"
+        );
+
+        Ok(Self {
+            file_path,
+            lifetime,
+            access_lifetime,
+            cache: RwLock::new(cache),
+        })
+    }
+
+    /// Remove a family, remembering its ID until its access tokens expire.
+    fn remove_family(&self, cache: &mut Cache, family_id: Uuid) -> bool {
+        let removed = cache.families.remove(&family_id).is_some();
+        if removed {
+            cache
+                .revoked
+                .insert(family_id, Utc::now() + self.access_lifetime);
+        }
+        removed
+    }
+
+    /// Write the cache to file, dropping expired entries.
+    ///
+    /// The lock is held until the file is written, so that an older
+    /// snapshot never replaces a newer one.
+    fn persist(&self) -> AppResult<()> {
+        let mut cache = self.cache.write();
+        let now = Utc::now();
+        cache.families.retain(|_, f| !f.is_expired());
+        cache.revoked.retain(|_, until| *until > now);
+        let store = RefreshTokenStore {
+            families: cache.families.values().cloned().collect(),
+            revoked: cache
+                .revoked
+                .iter()
+                .map(|(id, until)| Revocation {
+                    id: *id,
+                    until: *until,
+                })
+                .collect(),
+        };
+        storage::save_json(&self.file_path, &store)
+    }
+}
+
+impl RefreshTokenRepository for JsonRefreshTokenRepository {
+    fn issue(&self, user_id: Uuid, client: Client) -> AppResult<(Uuid, String)> {
+        let (secret, secret_hash) = generate_secret();
+        let now = Utc::now();
+        let family = TokenFamily {
+            id: Uuid::new_v4(),
+            user_id,
+            secret_hash,
+            client,
+            created_at: now,
+            refreshed_at: now,
+            expires_at: now + self.lifetime,
+        };
+        let family_id = family.id;
+
+        self.cache.write().families.insert(family_id, family);
+        self.persist()?;
+
+        Ok((family_id, format_token(family_id, &secret)))
+    }
+
+    fn rotate(&self, token: &str, ip: Option<String>) -> AppResult<(TokenFamily, String)> {
+        let (family_id, presented) = parse_token(token).ok_or_else(AppError::invalid_token)?;
+        let (secret, secret_hash) = generate_secret();
+
+        let rotated = {
+            let mut cache = self.cache.write();
+            let family = cache
+                .families
+                .get_mut(&family_id)
+                .filter(|f| !f.is_expired())
+                .ok_or_else(AppError::invalid_token)?;
+
+            if family.secret_hash == hash_secret(presented) {
+                let now = Utc::now();
+                family.secret_hash = secret_hash;
+                family.client.ip = ip.or(family.client.ip.take());
+                family.refreshed_at = now;
+                family.expires_at = now + self.lifetime;
+                Some(family.clone())
+            } else {
+                tracing::warn!(
+                    user_id = %family.user_id,
+                    family = %family_id,
+                    "Refresh token reused, revoking its family"
+                );
+                self.remove_family(&mut cache, family_id);
+                None
+            }
+        };
+        self.persist()?;
+
+        let family = rotated.ok_or_else(AppError::invalid_token)?;
+        Ok((family, format_token(family_id, &secret)))
+    }
+
+    fn find(&self, family_id: Uuid) -> AppResult<Option<TokenFamily>> {
+        Ok(self
+            .cache
+            .read()
+            .families
+            .get(&family_id)
+            .filter(|f| !f.is_expired())
+            .cloned())
+    }
+
+    fn list_by_user(&self, user_id: Uuid) -> AppResult<Vec<TokenFamily>> {
+        let mut families: Vec<TokenFamily> = self
+            .cache
+            .read()
+            .families
+            .values()
+            .filter(|f| f.user_id == user_id && !f.is_expired())
+            .cloned()
+            .collect();
+        families.sort_by_key(|f| f.created_at);
+        Ok(families)
+    }
+
+    fn revoke(&self, family_id: Uuid) -> AppResult<bool> {
+        let removed = self.remove_family(&mut self.cache.write(), family_id);
+        if removed {
+            self.persist()?;
+        }
+        Ok(removed)
+    }
+
+    fn revoke_by_user(&self, user_id: Uuid) -> AppResult<usize> {
+        let removed = {
+            let mut cache = self.cache.write();
+            let ids: Vec<Uuid> = cache
+                .families
+                .values()
+                .filter(|f| f.user_id == user_id)
+                .map(|f| f.id)
+                .collect();
+            for id in &ids {
+                self.remove_family(&mut cache, *id);
+            }
+            ids.len()
+        };
+        if removed > 0 {
+            self.persist()?;
+        }
+        Ok(removed)
+    }
+
+    fn is_revoked(&self, family_id: Uuid) -> bool {
+        self.cache
+            .read()
+            .revoked
+            .get(&family_id)
+            .is_some_and(|until| *until > Utc::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn open(path: &Path, lifetime: Duration) -> JsonRefreshTokenRepository {
+        JsonRefreshTokenRepository::new(path, lifetime, Duration::minutes(15)).unwrap()
+    }
+
+    #[test]
+    fn test_rotation_persists_and_replaces_token() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("refresh_tokens.json");
+        let user = Uuid::new_v4();
+
+        let (family_id, first) = open(&path, Duration::days(30))
+            .issue(user, Client::default())
+            .unwrap();
+
+        let repo = open(&path, Duration::days(30));
+        let (family, second) = repo.rotate(&first, Some("192.0.2.1".to_string())).unwrap();
+        assert_eq!(family.id, family_id);
+        assert_eq!(family.user_id, user);
+        assert_eq!(family.client.ip.as_deref(), Some("192.0.2.1"));
+        assert_ne!(first, second);
+        assert_eq!(repo.rotate(&second, None).unwrap().0.user_id, user);
+
+        let stored = std::fs::read_to_string(&path).unwrap();
+        assert!(!stored.contains(first.split_once('.').unwrap().1));
+    }
+
+    #[test]
+    fn test_reuse_revokes_family() {
+        let dir = tempdir().unwrap();
+        let repo = open(&dir.path().join("refresh_tokens.json"), Duration::days(30));
+        let user = Uuid::new_v4();
+        let (_, other_login) = repo.issue(user, Client::default()).unwrap();
+
+        let (family_id, stolen) = repo.issue(user, Client::default()).unwrap();
+        let (_, current) = repo.rotate(&stolen, None).unwrap();
+
+        assert!(matches!(
+            repo.rotate(&stolen, None),
+            Err(AppError::Unauthorized(_))
+        ));
+        assert!(matches!(
+            repo.rotate(&current, None),
+            Err(AppError::Unauthorized(_))
+        ));
+        assert!(repo.is_revoked(family_id));
+
+        // Other logins are unaffected
+        assert!(repo.rotate(&other_login, None).is_ok());
+        assert_eq!(repo.list_by_user(user).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_expired_token_is_rejected() {
+        let dir = tempdir().unwrap();
+        let repo = open(&dir.path().join("refresh_tokens.json"), Duration::zero());
+        let (_, token) = repo.issue(Uuid::new_v4(), Client::default()).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+
+        assert!(repo.rotate(&token, None).is_err());
+        assert!(repo.rotate("not-a-token", None).is_err());
+    }
+
+    #[test]
+    fn test_revocations_survive_reload() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("refresh_tokens.json");
+        let user = Uuid::new_v4();
+
+        let repo = open(&path, Duration::days(30));
+        let (family_id, _) = repo.issue(user, Client::default()).unwrap();
+        let (other_id, _) = repo.issue(user, Client::default()).unwrap();
+        assert!(repo.revoke(family_id).unwrap());
+
+        let repo = open(&path, Duration::days(30));
+        assert!(repo.is_revoked(family_id));
+        assert!(!repo.is_revoked(other_id));
+        assert!(repo.find(family_id).unwrap().is_none());
+        assert_eq!(repo.revoke_by_user(user).unwrap(), 1);
+        assert!(repo.is_revoked(other_id));
+    }
+}
@@ -0,0 +1,121 @@
+//! Encryption of secrets that must be stored recoverably.
+//!
+//! Passwords are normally stored as one-way hashes, but some protocols
+//! (e.g. Subsonic token authentication) need the server to know the
+//! secret. Those are sealed with AES-256-GCM under a key kept in its own
+//! file, so a leaked data store alone does not reveal them.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use std::path::Path;
+
+use crate::error::{AppError, AppResult};
+
+/// Length of the AES-GCM nonce in bytes.
+const NONCE_LEN: usize = 12;
+
+/// Seals and opens secrets with a server-side key.
+pub struct SecretBox {
+    cipher: Aes256Gcm,
+}
+
+impl SecretBox {
+    /// Create from a raw 256-bit key.
+    pub fn from_key(key: &[u8; 32]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+        }
+    }
+
+    /// Load the key from `path`, generating and saving a new one if missing.
+    pub fn load_or_create(path: &Path) -> AppResult<Self> {
+        let key = load_or_create_key(path)?;
+        Ok(Self::from_key(&key))
+    }
+
+    /// Encrypt a secret, returning hex-encoded nonce and ciphertext.
+    pub fn seal(&self, plaintext: &str) -> AppResult<String> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| AppError::Internal("Failed to encrypt secret".to_string()))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(hex::encode(sealed))
+    }
+
+    /// Decrypt a secret produced by [`SecretBox::seal`].
+    pub fn open(&self, sealed: &str) -> AppResult<String> {
+        let bytes = hex::decode(sealed)
+            .map_err(|_| AppError::Internal("Corrupt sealed secret".to_string()))?;
+        if bytes.len() < NONCE_LEN {
+            return Err(AppError::Internal("Corrupt sealed secret".to_string()));
+        }
+
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| AppError::Internal("Failed to decrypt secret".to_string()))?;
+
+        String::from_utf8(plaintext)
+            .map_err(|_| AppError::Internal("Corrupt sealed secret".to_string()))
+    }
+}
+
+/// Load a hex-encoded 256-bit key from a file, creating it if missing.
+///
+/// New key files are written with owner-only permissions on Unix.
+pub fn load_or_create_key(path: &Path) -> AppResult<[u8; 32]> {
+    if path.exists() {
+        let content = std::fs::read_to_string(path)?;
+        let bytes = hex::decode(content.trim())
+            .map_err(|_| AppError::Internal(format!("Invalid key file: {}", path.display())))?;
+        return bytes
+            .try_into()
+            .map_err(|_| AppError::Internal(format!("Invalid key file: {}", path.display())));
+    }
+
+    let key: [u8; 32] = Aes256Gcm::generate_key(&mut OsRng).into();
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, hex::encode(key))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+
+    tracing::info!(path = %path.display(), "Generated new key file");
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_seal_and_open_roundtrip() {
+        let secret_box = SecretBox::from_key(&[7u8; 32]);
+        let sealed = secret_box.seal("hunter22").unwrap();
+
+        assert_ne!(sealed, secret_box.seal("hunter22").unwrap());
+        assert_eq!(secret_box.open(&sealed).unwrap(), "hunter22");
+        assert!(SecretBox::from_key(&[8u8; 32]).open(&sealed).is_err());
+    }
+
+    #[test]
+    fn test_key_file_is_reused() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("secret.key");
+
+        let first = load_or_create_key(&path).unwrap();
+        let second = load_or_create_key(&path).unwrap();
+        assert_eq!(first, second);
+    }
+}
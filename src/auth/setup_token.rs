@@ -0,0 +1,78 @@
+//! One-time token for creating the first admin.
+//!
+//! While there are no users, registering needs a token that is printed to
+//! the log at startup, so whoever reaches a fresh server first cannot make
+//! themselves admin. The token is kept in memory only and is discarded
+//! once the first account exists.
+
+use parking_lot::Mutex;
+
+use super::opaque_token::{generate_secret, hash_secret};
+
+/// Holds the setup token while the server has no users.
+#[derive(Debug, Default)]
+pub struct SetupToken {
+    /// Hash of the current token, if any.
+    hash: Mutex<Option<String>>,
+}
+
+impl SetupToken {
+    /// Create without a token.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Generate a new token, replacing any previous one.
+    pub fn generate(&self) -> String {
+        let (token, hash) = generate_secret();
+        *self.hash.lock() = Some(hash);
+        token
+    }
+
+    /// Whether a setup token is waiting to be used.
+    pub fn is_pending(&self) -> bool {
+        self.hash.lock().is_some()
+    }
+
+    /// Use the token, returning whether it matched. A matching token
+    /// cannot be used again.
+    pub fn redeem(&self, token: &str) -> bool {
+        let mut hash = self.hash.lock();
+        if hash.as_deref() == Some(hash_secret(token).as_str()) {
+            *hash = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Put back a redeemed token whose account could not be created. Does
+    /// nothing if a new token was generated meanwhile.
+    pub fn restore(&self, token: &str) {
+        let mut hash = self.hash.lock();
+        if hash.is_none() {
+            *hash = Some(hash_secret(token));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_works_once() {
+        let setup = SetupToken::new();
+        assert!(!setup.redeem(""));
+
+        let token = setup.generate();
+        assert!(setup.is_pending());
+        assert!(!setup.redeem("wrong"));
+        assert!(setup.redeem(&token));
+        assert!(!setup.redeem(&token));
+        assert!(!setup.is_pending());
+
+        setup.restore(&token);
+        assert!(setup.redeem(&token));
+    }
+}
@@ -8,24 +8,38 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use uuid::Uuid;
 
+use super::totp::TwoFactor;
 use crate::error::{AppError, AppResult};
+use crate::storage;
 
 /// User model.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct User {
     /// Unique user ID.
     pub id: Uuid,
     /// Username (unique).
     pub username: String,
     /// Argon2 password hash.
-    #[serde(skip_serializing)]
     pub password_hash: String,
     /// Whether the user has admin privileges.
     pub is_admin: bool,
+    /// Whether the account has been disabled by an admin.
+    #[serde(default)]
+    pub disabled: bool,
+    /// Bumped to sign out every token issued before; tokens carry the
+    /// version they were issued at.
+    #[serde(default)]
+    pub token_version: u32,
     /// Account creation timestamp.
     pub created_at: DateTime<Utc>,
     /// Last login timestamp.
     pub last_login: Option<DateTime<Utc>>,
+    /// Sealed password for Subsonic clients (see [`crate::auth::SecretBox`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subsonic_password: Option<String>,
+    /// TOTP settings, once enrollment has started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub two_factor: Option<TwoFactor>,
 }
 
 impl User {
@@ -36,11 +50,31 @@ impl User {
             username,
             password_hash,
             is_admin,
+            disabled: false,
+            token_version: 0,
             created_at: Utc::now(),
             last_login: None,
+            subsonic_password: None,
+            two_factor: None,
         }
     }
 
+    /// Whether the user is an admin who can still log in.
+    pub fn is_active_admin(&self) -> bool {
+        self.is_admin && !self.disabled
+    }
+
+    /// Whether logins need a TOTP code.
+    pub fn has_two_factor(&self) -> bool {
+        self.two_factor.as_ref().is_some_and(|t| t.enabled)
+    }
+
+    /// Replace the password hash, signing out all existing tokens.
+    pub fn set_password(&mut self, password_hash: String) {
+        self.password_hash = password_hash;
+        self.token_version = self.token_version.wrapping_add(1);
+    }
+
     /// Convert to a public representation (without sensitive data).
     pub fn to_public(&self) -> PublicUser {
         PublicUser {
@@ -79,9 +113,23 @@ pub trait UserRepository: Send + Sync {
     fn create(&self, user: User) -> AppResult<User>;
 
     /// Update a user.
+    ///
+    /// Fails with a conflict if it would leave no active admin.
     fn update(&self, user: User) -> AppResult<User>;
 
+    /// Change a user in place under the write lock, so that concurrent
+    /// changes to other fields are not lost. Nothing is saved if `change`
+    /// fails or leaves the user as it was.
+    ///
+    /// Fails with a conflict if it would leave no active admin.
+    fn modify<F>(&self, id: Uuid, change: F) -> AppResult<User>
+    where
+        Self: Sized,
+        F: FnOnce(&mut User) -> AppResult<()>;
+
     /// Delete a user by ID.
+    ///
+    /// Fails with a conflict if it would leave no active admin.
     fn delete(&self, id: Uuid) -> AppResult<bool>;
 
     /// Get all users.
@@ -146,23 +194,35 @@ impl JsonUserRepository {
             users: cache.values().cloned().collect(),
         };
 
-        let content = serde_json::to_string_pretty(&store)?;
-
-        // Ensure parent directory exists
-        if let Some(parent) = self.file_path.parent() {
-            std::fs::create_dir_all(parent)?;
-        }
-
-        // Write atomically using temp file
-        let temp_path = self.file_path.with_extension("json.tmp");
-        std::fs::write(&temp_path, &content)?;
-        std::fs::rename(&temp_path, &self.file_path)?;
+        storage::save_json(&self.file_path, &store)?;
 
         tracing::debug!(path = %self.file_path.display(), count = cache.len(), "Saved users to file");
         Ok(())
     }
 }
 
+/// Reject a change that takes away the last active admin.
+fn ensure_admin_remains(
+    cache: &HashMap<Uuid, User>,
+    id: Uuid,
+    replacement: Option<&User>,
+) -> AppResult<()> {
+    let was_admin = cache.get(&id).is_some_and(User::is_active_admin);
+    let stays_admin = replacement.is_some_and(User::is_active_admin);
+    if was_admin && !stays_admin {
+        let others = cache
+            .values()
+            .filter(|u| u.id != id && u.is_active_admin())
+            .count();
+        if others == 0 {
+            return Err(AppError::Conflict(
+                "Cannot remove the last admin".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
 impl UserRepository for JsonUserRepository {
     fn find_by_id(&self, id: Uuid) -> AppResult<Option<User>> {
         let cache = self.cache.read();
@@ -179,16 +239,20 @@ impl UserRepository for JsonUserRepository {
     }
 
     fn create(&self, user: User) -> AppResult<User> {
-        // Check for duplicate username
-        if self.username_exists(&user.username)? {
-            return Err(AppError::Conflict(format!(
-                "Username '{}' already exists",
-                user.username
-            )));
-        }
-
         {
+            // Checked under the lock, so concurrent registrations cannot
+            // both take a name
             let mut cache = self.cache.write();
+            let username_lower = user.username.to_lowercase();
+            if cache
+                .values()
+                .any(|u| u.username.to_lowercase() == username_lower)
+            {
+                return Err(AppError::Conflict(format!(
+                    "Username '{}' already exists",
+                    user.username
+                )));
+            }
             cache.insert(user.id, user.clone());
         }
 
@@ -203,6 +267,7 @@ impl UserRepository for JsonUserRepository {
             if !cache.contains_key(&user.id) {
                 return Err(AppError::NotFound(format!("User {} not found", user.id)));
             }
+            ensure_admin_remains(&cache, user.id, Some(&user))?;
             cache.insert(user.id, user.clone());
         }
 
@@ -211,9 +276,34 @@ impl UserRepository for JsonUserRepository {
         Ok(user)
     }
 
+    fn modify<F>(&self, id: Uuid, change: F) -> AppResult<User>
+    where
+        F: FnOnce(&mut User) -> AppResult<()>,
+    {
+        let user = {
+            let mut cache = self.cache.write();
+            let current = cache
+                .get(&id)
+                .ok_or_else(|| AppError::NotFound(format!("User {} not found", id)))?;
+            let mut user = current.clone();
+            change(&mut user)?;
+            if user == *current {
+                return Ok(user);
+            }
+            ensure_admin_remains(&cache, id, Some(&user))?;
+            cache.insert(id, user.clone());
+            user
+        };
+
+        self.save()?;
+        tracing::debug!(user_id = %id, "Updated user");
+        Ok(user)
+    }
+
     fn delete(&self, id: Uuid) -> AppResult<bool> {
         let removed = {
             let mut cache = self.cache.write();
+            ensure_admin_remains(&cache, id, None)?;
             cache.remove(&id).is_some()
         };
 
@@ -284,4 +374,101 @@ mod tests {
         assert!(repo.find_by_username("testuser").unwrap().is_some());
         assert!(repo.find_by_username("TESTUSER").unwrap().is_some());
     }
+
+    #[test]
+    fn test_password_hash_survives_reload() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("users.json");
+        let repo = JsonUserRepository::new(&path).unwrap();
+        let user = repo
+            .create(User::new("alice".to_string(), "hash".to_string(), true))
+            .unwrap();
+
+        let repo = JsonUserRepository::new(&path).unwrap();
+        let loaded = repo.find_by_id(user.id).unwrap().unwrap();
+        assert_eq!(loaded.password_hash, "hash");
+        assert!(!loaded.disabled);
+    }
+
+    #[test]
+    fn test_last_admin_cannot_be_removed() {
+        let repo = create_test_repo();
+        let admin = repo
+            .create(User::new("admin".to_string(), "hash".to_string(), true))
+            .unwrap();
+        repo.create(User::new("user".to_string(), "hash".to_string(), false))
+            .unwrap();
+
+        let mut demoted = admin.clone();
+        demoted.is_admin = false;
+        assert!(matches!(repo.update(demoted), Err(AppError::Conflict(_))));
+        let mut disabled = admin.clone();
+        disabled.disabled = true;
+        assert!(matches!(repo.update(disabled), Err(AppError::Conflict(_))));
+        assert!(matches!(repo.delete(admin.id), Err(AppError::Conflict(_))));
+
+        // Fine once there is another admin
+        repo.create(User::new("second".to_string(), "hash".to_string(), true))
+            .unwrap();
+        assert!(repo.delete(admin.id).unwrap());
+    }
+
+    #[test]
+    fn test_modify_keeps_concurrent_changes() {
+        let repo = create_test_repo();
+        let user = repo
+            .create(User::new("user".to_string(), "hash".to_string(), false))
+            .unwrap();
+
+        // Disabled after `user` was read; changing the password keeps it so
+        let mut disabled = user.clone();
+        disabled.disabled = true;
+        repo.update(disabled).unwrap();
+        let changed = repo
+            .modify(user.id, |u| {
+                u.set_password("new".to_string());
+                Ok(())
+            })
+            .unwrap();
+        assert!(changed.disabled);
+        assert_eq!(changed.password_hash, "new");
+
+        // A failed change saves nothing
+        let result = repo.modify(user.id, |u| {
+            u.password_hash = "lost".to_string();
+            Err(AppError::Forbidden("no".to_string()))
+        });
+        assert!(result.is_err());
+        assert_eq!(repo.find_by_id(user.id).unwrap().unwrap(), changed);
+        assert!(matches!(
+            repo.modify(Uuid::new_v4(), |_| Ok(())),
+            Err(AppError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_concurrent_creates_take_a_name_once() {
+        let dir = tempdir().unwrap();
+        let repo = JsonUserRepository::new(dir.path().join("users.json")).unwrap();
+
+        let created = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|i| {
+                    let repo = &repo;
+                    let name = if i % 2 == 0 { "alice" } else { "ALICE" };
+                    scope.spawn(move || {
+                        repo.create(User::new(name.to_string(), "hash".to_string(), false))
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .filter(Result::is_ok)
+                .count()
+        });
+
+        assert_eq!(created, 1);
+        assert_eq!(repo.count().unwrap(), 1);
+    }
 }
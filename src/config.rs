@@ -2,6 +2,7 @@
 //!
 //! Loads configuration from environment variables with sensible defaults.
 
+use std::net::IpAddr;
 use std::path::PathBuf;
 use std::sync::OnceLock;
 
@@ -19,16 +20,70 @@ pub struct Config {
     pub music_folder: PathBuf,
     /// Path to the users JSON file.
     pub users_file: PathBuf,
+    /// Directory for the other data stores (playlists, annotations, ...).
+    pub data_dir: PathBuf,
     /// JWT secret key for signing tokens.
     pub jwt_secret: String,
-    /// JWT token expiry in days.
+    /// Lifetime of a login in days: how long a refresh token stays valid
+    /// unless used.
     pub jwt_expiry_days: i64,
+    /// Access token lifetime in minutes.
+    pub access_token_minutes: i64,
+    /// Who may register once the first admin exists.
+    pub registration_mode: RegistrationMode,
+    /// Failed logins after which an account is locked.
+    pub login_lockout_threshold: u32,
+    /// How long a locked account stays locked, in minutes.
+    pub login_lockout_minutes: i64,
+    /// Whether admin privileges need two-factor authentication.
+    pub require_admin_2fa: bool,
+    /// Hosts besides ListenBrainz that scrobbles may be forwarded to.
+    pub scrobble_allowed_hosts: Vec<String>,
+    /// Reverse proxies whose `X-Forwarded-For` headers are believed.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Base URL of absolute links in exports and signed or shared URLs;
+    /// empty for relative links.
+    pub public_url: String,
     /// Log level (trace, debug, info, warn, error).
     pub log_level: String,
     /// Log format (json or pretty).
     pub log_format: LogFormat,
     /// Allowed CORS origins (comma-separated, or * for all).
     pub cors_origins: Vec<String>,
+    /// Whether the UPnP/DLNA media server is enabled.
+    pub dlna_enabled: bool,
+    /// Network interfaces (names or IPv4 addresses) the media server is offered on.
+    pub dlna_interfaces: Vec<String>,
+    /// Port of the media server's HTTP listener.
+    pub dlna_port: u16,
+    /// Name shown by UPnP devices.
+    pub dlna_name: String,
+    /// Whether the MPD protocol listener is enabled.
+    pub mpd_enabled: bool,
+    /// Address of the MPD protocol listener.
+    pub mpd_bind: String,
+    /// Base URL used to build stream URIs reported to MPD clients.
+    pub mpd_base_url: String,
+    /// Whether MPD clients may browse without logging in.
+    pub mpd_anonymous: bool,
+    /// Whether WebDAV also offers a virtual Artist/Album tree.
+    pub webdav_artist_tree: bool,
+    /// Path of the ffmpeg binary used to transcode radio streams.
+    pub radio_ffmpeg: String,
+    /// Bitrate of radio streams in kbit/s.
+    pub radio_bitrate: u32,
+}
+
+/// Who may create an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RegistrationMode {
+    /// Anyone who can reach the server.
+    Open,
+    /// Only holders of an invite code.
+    InviteOnly,
+    /// Nobody.
+    Closed,
 }
 
 /// Log output format.
@@ -61,6 +116,9 @@ impl Config {
             std::env::var("USERS_FILE").unwrap_or_else(|_| "./data/users.json".to_string()),
         );
 
+        let data_dir =
+            PathBuf::from(std::env::var("DATA_DIR").unwrap_or_else(|_| "./data".to_string()));
+
         let jwt_secret = std::env::var("JWT_SECRET").unwrap_or_else(|_| {
             tracing::warn!(
                 "JWT_SECRET not set, using random secret. Tokens will be invalidated on restart!"
@@ -73,6 +131,58 @@ impl Config {
             .parse::<i64>()
             .expect("JWT_EXPIRY_DAYS must be a valid integer");
 
+        let access_token_minutes = std::env::var("ACCESS_TOKEN_MINUTES")
+            .unwrap_or_else(|_| "15".to_string())
+            .parse::<i64>()
+            .expect("ACCESS_TOKEN_MINUTES must be a valid integer");
+
+        let registration_mode = match std::env::var("REGISTRATION_MODE")
+            .unwrap_or_else(|_| "open".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "open" => RegistrationMode::Open,
+            "invite-only" | "invite" => RegistrationMode::InviteOnly,
+            "closed" => RegistrationMode::Closed,
+            _ => panic!("REGISTRATION_MODE must be open, invite-only or closed"),
+        };
+
+        let login_lockout_threshold = std::env::var("LOGIN_LOCKOUT_THRESHOLD")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse::<u32>()
+            .expect("LOGIN_LOCKOUT_THRESHOLD must be a valid integer");
+
+        let login_lockout_minutes = std::env::var("LOGIN_LOCKOUT_MINUTES")
+            .unwrap_or_else(|_| "15".to_string())
+            .parse::<i64>()
+            .expect("LOGIN_LOCKOUT_MINUTES must be a valid integer");
+
+        let require_admin_2fa = env_flag("REQUIRE_ADMIN_2FA");
+
+        let scrobble_allowed_hosts = std::env::var("SCROBBLE_ALLOWED_HOSTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        let trusted_proxies = std::env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                s.parse::<IpAddr>()
+                    .expect("TRUSTED_PROXIES must be comma-separated IP addresses")
+            })
+            .collect();
+
+        let public_url = std::env::var("PUBLIC_URL")
+            .unwrap_or_default()
+            .trim()
+            .trim_end_matches('/')
+            .to_string();
+
         let log_level = std::env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string());
 
         let log_format = match std::env::var("LOG_FORMAT")
@@ -91,16 +201,72 @@ impl Config {
             .filter(|s| !s.is_empty())
             .collect();
 
+        let dlna_enabled = env_flag("DLNA_ENABLED");
+
+        let dlna_interfaces = std::env::var("DLNA_INTERFACES")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        let dlna_port = std::env::var("DLNA_PORT")
+            .unwrap_or_else(|_| "8200".to_string())
+            .parse::<u16>()
+            .expect("DLNA_PORT must be a valid u16");
+
+        let dlna_name = std::env::var("DLNA_NAME").unwrap_or_else(|_| "Ferrum".to_string());
+
+        let mpd_enabled = env_flag("MPD_ENABLED");
+
+        let mpd_bind = std::env::var("MPD_BIND").unwrap_or_else(|_| "127.0.0.1:6600".to_string());
+
+        let mpd_base_url = std::env::var("MPD_BASE_URL")
+            .unwrap_or_else(|_| format!("http://localhost:{}", port))
+            .trim_end_matches('/')
+            .to_string();
+
+        let mpd_anonymous = env_flag("MPD_ANONYMOUS");
+
+        let webdav_artist_tree = env_flag("WEBDAV_ARTIST_TREE");
+
+        let radio_ffmpeg = std::env::var("RADIO_FFMPEG").unwrap_or_else(|_| "ffmpeg".to_string());
+
+        let radio_bitrate = std::env::var("RADIO_BITRATE")
+            .unwrap_or_else(|_| "128".to_string())
+            .parse::<u32>()
+            .expect("RADIO_BITRATE must be a valid integer");
+
         Self {
             host,
             port,
             music_folder,
             users_file,
+            data_dir,
             jwt_secret,
             jwt_expiry_days,
+            access_token_minutes,
+            registration_mode,
+            login_lockout_threshold,
+            login_lockout_minutes,
+            require_admin_2fa,
+            scrobble_allowed_hosts,
+            trusted_proxies,
+            public_url,
             log_level,
             log_format,
             cors_origins,
+            dlna_enabled,
+            dlna_interfaces,
+            dlna_port,
+            dlna_name,
+            mpd_enabled,
+            mpd_bind,
+            mpd_base_url,
+            mpd_anonymous,
+            webdav_artist_tree,
+            radio_ffmpeg,
+            radio_bitrate,
         }
     }
 
@@ -127,6 +293,16 @@ impl Config {
             );
         }
 
+        if self.dlna_enabled && self.dlna_interfaces.is_empty() {
+            return Err(ConfigError::DlnaInterfacesMissing);
+        }
+
+        if !self.data_dir.exists() {
+            std::fs::create_dir_all(&self.data_dir).map_err(|e| {
+                ConfigError::DataDirectoryCreationFailed(self.data_dir.display().to_string(), e)
+            })?;
+        }
+
         // Ensure users file parent directory exists
         if let Some(parent) = self.users_file.parent() {
             if !parent.exists() {
@@ -145,6 +321,17 @@ impl Config {
     }
 }
 
+/// Read a boolean flag from the environment, defaulting to false.
+fn env_flag(name: &str) -> bool {
+    matches!(
+        std::env::var(name)
+            .unwrap_or_default()
+            .to_lowercase()
+            .as_str(),
+        "true" | "1" | "yes"
+    )
+}
+
 /// Configuration errors.
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigError {
@@ -156,6 +343,9 @@ pub enum ConfigError {
 
     #[error("Failed to create data directory '{0}': {1}")]
     DataDirectoryCreationFailed(String, std::io::Error),
+
+    #[error("DLNA_INTERFACES must list at least one interface when DLNA is enabled")]
+    DlnaInterfacesMissing,
 }
 
 /// Initialize the global configuration.
@@ -185,6 +375,7 @@ mod tests {
         std::env::remove_var("HOST");
         std::env::remove_var("PORT");
         std::env::remove_var("LOG_LEVEL");
+        std::env::remove_var("REGISTRATION_MODE");
 
         let config = Config::from_env();
 
@@ -192,6 +383,8 @@ mod tests {
         assert_eq!(config.port, 8080);
         assert_eq!(config.log_level, "info");
         assert_eq!(config.jwt_expiry_days, 7);
+        assert_eq!(config.access_token_minutes, 15);
+        assert_eq!(config.registration_mode, RegistrationMode::Open);
     }
 
     #[test]
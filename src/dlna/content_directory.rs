@@ -0,0 +1,541 @@
+//! ContentDirectory object tree and DIDL-Lite rendering.
+//!
+//! Object IDs are paths, so the parent of any object is its ID without the
+//! last segment:
+//!
+//! ```text
+//! 0                                    root
+//! artists/{artist_id}/{album_id}/{id}  by artist, then album
+//! albums/{album_id}/{id}               by album
+//! genres/{genre_id}/{id}               by genre
+//! folders/{id}                         files in the music folder
+//! ```
+
+use quick_xml::escape::escape;
+
+use super::soap::UpnpError;
+use crate::library::LibraryIndex;
+use crate::models::SongMetadata;
+
+/// ID of the root container.
+pub const ROOT_ID: &str = "0";
+
+/// Top-level containers as (ID, title).
+const TOP_CONTAINERS: &[(&str, &str)] = &[
+    ("artists", "Artists"),
+    ("albums", "Albums"),
+    ("genres", "Genres"),
+    ("folders", "Folders"),
+];
+
+const CLASS_FOLDER: &str = "object.container.storageFolder";
+const CLASS_ARTIST: &str = "object.container.person.musicArtist";
+const CLASS_ALBUM: &str = "object.container.album.musicAlbum";
+const CLASS_GENRE: &str = "object.container.genre.musicGenre";
+const CLASS_TRACK: &str = "object.item.audioItem.musicTrack";
+
+/// The `BrowseFlag` argument of a Browse action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrowseFlag {
+    /// Describe the object itself.
+    Metadata,
+    /// List the object's children.
+    DirectChildren,
+}
+
+impl BrowseFlag {
+    /// Parse a `BrowseFlag` argument.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "BrowseMetadata" => Some(Self::Metadata),
+            "BrowseDirectChildren" => Some(Self::DirectChildren),
+            _ => None,
+        }
+    }
+}
+
+/// Result of a Browse action.
+#[derive(Debug, Clone)]
+pub struct BrowseResult {
+    /// DIDL-Lite document describing the returned objects.
+    pub didl: String,
+    /// Number of objects returned.
+    pub returned: usize,
+    /// Number of objects matching before paging.
+    pub total: usize,
+}
+
+/// A ContentDirectory object.
+#[derive(Debug)]
+enum Entry<'a> {
+    Container {
+        id: String,
+        parent: String,
+        title: String,
+        class: &'static str,
+        child_count: usize,
+        artist: Option<&'a str>,
+        cover: Option<&'a SongMetadata>,
+    },
+    Item {
+        id: String,
+        parent: String,
+        song: &'a SongMetadata,
+    },
+}
+
+impl Entry<'_> {
+    fn id(&self) -> &str {
+        match self {
+            Entry::Container { id, .. } | Entry::Item { id, .. } => id,
+        }
+    }
+}
+
+/// Browse an object or its children.
+///
+/// `count` of 0 returns all objects from `start`. Media URLs are built
+/// from `base_url` (e.g. `http://192.168.1.10:8200`).
+pub fn browse(
+    index: &LibraryIndex,
+    object_id: &str,
+    flag: BrowseFlag,
+    start: usize,
+    count: usize,
+    base_url: &str,
+) -> Result<BrowseResult, UpnpError> {
+    let entries = match flag {
+        BrowseFlag::Metadata => vec![lookup(index, object_id).ok_or(UpnpError::NO_SUCH_OBJECT)?],
+        BrowseFlag::DirectChildren => {
+            children(index, object_id).ok_or(UpnpError::NO_SUCH_OBJECT)?
+        }
+    };
+
+    let total = entries.len();
+    let count = if count == 0 { usize::MAX } else { count };
+    let page: Vec<&Entry<'_>> = entries.iter().skip(start).take(count).collect();
+
+    let mut didl = String::from(concat!(
+        "<DIDL-Lite xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\"",
+        " xmlns:dc=\"http://purl.org/dc/elements/1.1/\"",
+        " xmlns:upnp=\"urn:schemas-upnp-org:metadata-1-0/upnp/\"",
+        " xmlns:dlna=\"urn:schemas-dlna-org:metadata-1-0/\">"
+    ));
+    for entry in &page {
+        write_entry(&mut didl, entry, base_url);
+    }
+    didl.push_str("</DIDL-Lite>");
+
+    Ok(BrowseResult {
+        didl,
+        returned: page.len(),
+        total,
+    })
+}
+
+/// Value for `SystemUpdateID`, which changes when the library does.
+pub fn system_update_id(index: &LibraryIndex) -> u32 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    for song in &index.songs {
+        song.id.hash(&mut hasher);
+    }
+    hasher.finish() as u32
+}
+
+/// Genre ID of a genre name.
+fn genre_id(name: &str) -> String {
+    SongMetadata::generate_name_id("genre", name)
+}
+
+/// Songs matching a predicate, in album track order.
+fn tracks(index: &LibraryIndex, predicate: impl Fn(&SongMetadata) -> bool) -> Vec<&SongMetadata> {
+    let mut songs: Vec<&SongMetadata> = index.songs.iter().filter(|s| predicate(s)).collect();
+    songs.sort_by(|a, b| {
+        a.album
+            .to_lowercase()
+            .cmp(&b.album.to_lowercase())
+            .then_with(|| {
+                a.track_number
+                    .unwrap_or(u32::MAX)
+                    .cmp(&b.track_number.unwrap_or(u32::MAX))
+            })
+            .then_with(|| a.title.cmp(&b.title))
+    });
+    songs
+}
+
+fn items<'a>(parent: &str, songs: Vec<&'a SongMetadata>) -> Vec<Entry<'a>> {
+    songs
+        .into_iter()
+        .map(|song| Entry::Item {
+            id: format!("{}/{}", parent, song.id),
+            parent: parent.to_string(),
+            song,
+        })
+        .collect()
+}
+
+/// Album containers under `parent`, in the order albums first appear in `songs`.
+fn albums<'a>(parent: &str, songs: &[&'a SongMetadata]) -> Vec<Entry<'a>> {
+    let mut album_ids: Vec<&str> = Vec::new();
+    for song in songs {
+        if !album_ids.contains(&song.album_id.as_str()) {
+            album_ids.push(&song.album_id);
+        }
+    }
+
+    album_ids
+        .into_iter()
+        .map(|album_id| {
+            let album_songs: Vec<&&SongMetadata> =
+                songs.iter().filter(|s| s.album_id == album_id).collect();
+            let first = album_songs[0];
+            Entry::Container {
+                id: format!("{}/{}", parent, album_id),
+                parent: parent.to_string(),
+                title: first.album.clone(),
+                class: CLASS_ALBUM,
+                child_count: album_songs.len(),
+                artist: Some(&first.artist),
+                cover: album_songs.iter().find(|s| s.has_cover).map(|s| **s),
+            }
+        })
+        .collect()
+}
+
+fn non_empty<T>(entries: Vec<T>) -> Option<Vec<T>> {
+    (!entries.is_empty()).then_some(entries)
+}
+
+/// Children of a container, or `None` if there is no such container.
+fn children<'a>(index: &'a LibraryIndex, id: &str) -> Option<Vec<Entry<'a>>> {
+    let segments: Vec<&str> = id.split('/').collect();
+    match segments.as_slice() {
+        [ROOT_ID] => Some(
+            TOP_CONTAINERS
+                .iter()
+                .map(|(top, title)| Entry::Container {
+                    id: top.to_string(),
+                    parent: ROOT_ID.to_string(),
+                    title: title.to_string(),
+                    class: CLASS_FOLDER,
+                    child_count: children(index, top).map(|c| c.len()).unwrap_or(0),
+                    artist: None,
+                    cover: None,
+                })
+                .collect(),
+        ),
+        ["artists"] => {
+            let mut artists: Vec<&SongMetadata> = Vec::new();
+            for song in &index.songs {
+                if !artists.iter().any(|a| a.artist_id == song.artist_id) {
+                    artists.push(song);
+                }
+            }
+            artists.sort_by_key(|s| s.artist.to_lowercase());
+            Some(
+                artists
+                    .into_iter()
+                    .map(|artist| {
+                        let songs = tracks(index, |s| s.artist_id == artist.artist_id);
+                        let id = format!("artists/{}", artist.artist_id);
+                        Entry::Container {
+                            child_count: albums(&id, &songs).len(),
+                            id,
+                            parent: "artists".to_string(),
+                            title: artist.artist.clone(),
+                            class: CLASS_ARTIST,
+                            artist: None,
+                            cover: songs.iter().find(|s| s.has_cover).copied(),
+                        }
+                    })
+                    .collect(),
+            )
+        }
+        ["artists", artist_id] => {
+            let mut songs = tracks(index, |s| s.artist_id == *artist_id);
+            songs.sort_by_key(|s| s.year);
+            non_empty(albums(id, &songs))
+        }
+        ["artists", artist_id, album_id] => non_empty(items(
+            id,
+            tracks(index, |s| {
+                s.artist_id == *artist_id && s.album_id == *album_id
+            }),
+        )),
+        ["albums"] => Some(albums(id, &tracks(index, |_| true))),
+        ["albums", album_id] => non_empty(items(id, tracks(index, |s| s.album_id == *album_id))),
+        ["genres"] => {
+            let mut genres: Vec<&str> = index
+                .songs
+                .iter()
+                .filter_map(|s| s.genre.as_deref())
+                .collect();
+            genres.sort_by_key(|g| g.to_lowercase());
+            genres.dedup_by_key(|g| g.to_lowercase());
+            Some(
+                genres
+                    .into_iter()
+                    .map(|genre| {
+                        let id = format!("genres/{}", genre_id(genre));
+                        Entry::Container {
+                            child_count: children(index, &id).map(|c| c.len()).unwrap_or(0),
+                            id,
+                            parent: "genres".to_string(),
+                            title: genre.to_string(),
+                            class: CLASS_GENRE,
+                            artist: None,
+                            cover: None,
+                        }
+                    })
+                    .collect(),
+            )
+        }
+        ["genres", id_of_genre] => non_empty(items(
+            id,
+            tracks(index, |s| {
+                s.genre.as_deref().map(genre_id).as_deref() == Some(*id_of_genre)
+            }),
+        )),
+        ["folders"] => {
+            let mut songs: Vec<&SongMetadata> = index.songs.iter().collect();
+            songs.sort_by(|a, b| a.file.cmp(&b.file));
+            Some(items(id, songs))
+        }
+        _ => None,
+    }
+}
+
+/// Look up a single object by ID.
+fn lookup<'a>(index: &'a LibraryIndex, id: &str) -> Option<Entry<'a>> {
+    if id == ROOT_ID {
+        return Some(Entry::Container {
+            id: ROOT_ID.to_string(),
+            parent: "-1".to_string(),
+            title: "Music".to_string(),
+            class: CLASS_FOLDER,
+            child_count: TOP_CONTAINERS.len(),
+            artist: None,
+            cover: None,
+        });
+    }
+
+    let parent = id
+        .rsplit_once('/')
+        .map(|(parent, _)| parent)
+        .unwrap_or(ROOT_ID);
+    children(index, parent)?.into_iter().find(|e| e.id() == id)
+}
+
+/// Format a duration in seconds as `H:MM:SS.000`.
+fn format_duration(seconds: u32) -> String {
+    format!(
+        "{}:{:02}:{:02}.000",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+fn write_element(out: &mut String, name: &str, value: &str) {
+    out.push_str(&format!("<{}>{}</{}>", name, escape(value), name));
+}
+
+fn write_cover(out: &mut String, song: Option<&SongMetadata>, base_url: &str) {
+    if let Some(song) = song.filter(|s| s.has_cover) {
+        out.push_str(&format!(
+            "<upnp:albumArtURI>{}/dlna/cover/{}</upnp:albumArtURI>",
+            escape(base_url),
+            song.id
+        ));
+    }
+}
+
+fn write_entry(out: &mut String, entry: &Entry<'_>, base_url: &str) {
+    match entry {
+        Entry::Container {
+            id,
+            parent,
+            title,
+            class,
+            child_count,
+            artist,
+            cover,
+        } => {
+            out.push_str(&format!(
+                "<container id=\"{}\" parentID=\"{}\" restricted=\"1\" searchable=\"0\" childCount=\"{}\">",
+                escape(id.as_str()),
+                escape(parent.as_str()),
+                child_count
+            ));
+            write_element(out, "dc:title", title);
+            if let Some(artist) = artist {
+                write_element(out, "upnp:artist", artist);
+            }
+            write_cover(out, *cover, base_url);
+            write_element(out, "upnp:class", class);
+            out.push_str("</container>");
+        }
+        Entry::Item { id, parent, song } => {
+            out.push_str(&format!(
+                "<item id=\"{}\" parentID=\"{}\" restricted=\"1\">",
+                escape(id.as_str()),
+                escape(parent.as_str())
+            ));
+            write_element(out, "dc:title", &song.title);
+            write_element(out, "dc:creator", &song.artist);
+            write_element(out, "upnp:artist", &song.artist);
+            write_element(out, "upnp:album", &song.album);
+            if let Some(genre) = &song.genre {
+                write_element(out, "upnp:genre", genre);
+            }
+            if let Some(track) = song.track_number {
+                write_element(out, "upnp:originalTrackNumber", &track.to_string());
+            }
+            if let Some(year) = song.year {
+                write_element(out, "dc:date", &format!("{:04}-01-01", year));
+            }
+            write_cover(out, Some(song), base_url);
+            write_element(out, "upnp:class", CLASS_TRACK);
+
+            let mime = actix_files::file_extension_to_mime(&song.format);
+            out.push_str(&format!("<res protocolInfo=\"http-get:*:{}:*\"", mime));
+            if let Some(duration) = song.duration {
+                out.push_str(&format!(" duration=\"{}\"", format_duration(duration)));
+            }
+            out.push_str(&format!(
+                ">{}/dlna/media/{}</res></item>",
+                escape(base_url),
+                song.id
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(title: &str, artist: &str, album: &str, genre: &str, track: u32) -> SongMetadata {
+        let file = format!("{}.mp3", title);
+        SongMetadata {
+            id: SongMetadata::generate_id(std::path::Path::new(&file)),
+            title: title.to_string(),
+            artist: artist.to_string(),
+            artist_id: SongMetadata::generate_name_id("artist", artist),
+            album: album.to_string(),
+            album_id: SongMetadata::generate_name_id("album", album),
+            duration: Some(225),
+            track_number: Some(track),
+            year: Some(2001),
+            genre: Some(genre.to_string()),
+            format: "mp3".to_string(),
+            file,
+            has_cover: false,
+            starred: None,
+            rating: None,
+            play_count: 0,
+            last_played: None,
+        }
+    }
+
+    fn index() -> LibraryIndex {
+        let mut index = LibraryIndex::default();
+        index.songs = vec![
+            song("Digital Love", "Daft Punk", "Discovery", "House", 3),
+            song("One More Time", "Daft Punk", "Discovery", "House", 1),
+            song("Teardrop", "Massive Attack", "Mezzanine", "Trip Hop", 1),
+        ];
+        index
+    }
+
+    #[test]
+    fn test_browse_root_and_artists() {
+        let index = index();
+
+        let root = browse(
+            &index,
+            ROOT_ID,
+            BrowseFlag::DirectChildren,
+            0,
+            0,
+            "http://h",
+        )
+        .unwrap();
+        assert_eq!(root.total, 4);
+        assert!(root.didl.contains("<container id=\"artists\" parentID=\"0\" restricted=\"1\" searchable=\"0\" childCount=\"2\">"));
+
+        let artists = browse(
+            &index,
+            "artists",
+            BrowseFlag::DirectChildren,
+            1,
+            1,
+            "http://h",
+        )
+        .unwrap();
+        assert_eq!((artists.returned, artists.total), (1, 2));
+        assert!(artists.didl.contains("<dc:title>Massive Attack</dc:title>"));
+    }
+
+    #[test]
+    fn test_browse_album_tracks_in_order() {
+        let index = index();
+        let album_id = SongMetadata::generate_name_id("album", "Discovery");
+        let container = format!("albums/{}", album_id);
+
+        let album = browse(
+            &index,
+            &container,
+            BrowseFlag::DirectChildren,
+            0,
+            0,
+            "http://h:8200",
+        )
+        .unwrap();
+        assert_eq!(album.total, 2);
+        let first = album.didl.find("One More Time").unwrap();
+        let second = album.didl.find("Digital Love").unwrap();
+        assert!(first < second);
+        assert!(album.didl.contains("duration=\"0:03:45.000\""));
+        assert!(album.didl.contains(&format!(
+            ">http://h:8200/dlna/media/{}</res>",
+            index.songs[1].id
+        )));
+    }
+
+    #[test]
+    fn test_browse_metadata_and_unknown_objects() {
+        let index = index();
+        let genre = format!("genres/{}", genre_id("Trip Hop"));
+        let item = format!("{}/{}", genre, index.songs[2].id);
+
+        let result = browse(&index, &item, BrowseFlag::Metadata, 0, 0, "http://h").unwrap();
+        assert_eq!(result.total, 1);
+        assert!(result.didl.contains(&format!("parentID=\"{}\"", genre)));
+        assert!(result.didl.contains(CLASS_TRACK));
+
+        let error = browse(
+            &index,
+            "albums/nope",
+            BrowseFlag::DirectChildren,
+            0,
+            0,
+            "http://h",
+        )
+        .unwrap_err();
+        assert_eq!(error, UpnpError::NO_SUCH_OBJECT);
+        assert!(browse(
+            &index,
+            "albums/nope/x",
+            BrowseFlag::Metadata,
+            0,
+            0,
+            "http://h"
+        )
+        .is_err());
+    }
+}
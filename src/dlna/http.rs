@@ -0,0 +1,195 @@
+//! HTTP endpoints of the media server: descriptions, control and media.
+//!
+//! These are served by a separate listener bound only to the configured
+//! interfaces, and are unauthenticated since UPnP devices cannot log in.
+
+use actix_files::NamedFile;
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+
+use super::content_directory::{self, BrowseFlag};
+use super::description::{
+    self, CONNECTION_MANAGER, CONNECTION_MANAGER_SCPD, CONTENT_DIRECTORY, CONTENT_DIRECTORY_SCPD,
+    SOURCE_PROTOCOL_INFO,
+};
+use super::soap::{self, Action, UpnpError};
+use super::Device;
+use crate::api::music::{read_cover, resolve_music_file};
+use crate::error::{AppError, AppResult};
+use crate::models::AppState;
+
+/// DLNA flags: streaming transfer, byte seeking, DLNA 1.5.
+const CONTENT_FEATURES: &str =
+    "DLNA.ORG_OP=01;DLNA.ORG_CI=0;DLNA.ORG_FLAGS=01700000000000000000000000000000";
+
+fn xml(body: impl Into<String>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "text/xml; charset=\"utf-8\""))
+        .body(body.into())
+}
+
+fn soap_reply(result: Result<String, UpnpError>) -> HttpResponse {
+    match result {
+        Ok(body) => xml(body),
+        Err(error) => HttpResponse::InternalServerError()
+            .insert_header((header::CONTENT_TYPE, "text/xml; charset=\"utf-8\""))
+            .body(soap::fault(error)),
+    }
+}
+
+fn parse_action(body: &[u8]) -> Result<Action, UpnpError> {
+    std::str::from_utf8(body)
+        .ok()
+        .and_then(soap::parse_action)
+        .ok_or(UpnpError::INVALID_ACTION)
+}
+
+/// Device description.
+///
+/// GET /dlna/description.xml
+#[get("/dlna/description.xml")]
+pub async fn device_description(device: web::Data<Device>) -> HttpResponse {
+    xml(description::device_description(&device.udn, &device.name))
+}
+
+/// ContentDirectory service description.
+///
+/// GET /dlna/ContentDirectory.xml
+#[get("/dlna/ContentDirectory.xml")]
+pub async fn content_directory_scpd() -> HttpResponse {
+    xml(CONTENT_DIRECTORY_SCPD)
+}
+
+/// ConnectionManager service description.
+///
+/// GET /dlna/ConnectionManager.xml
+#[get("/dlna/ConnectionManager.xml")]
+pub async fn connection_manager_scpd() -> HttpResponse {
+    xml(CONNECTION_MANAGER_SCPD)
+}
+
+/// ContentDirectory control.
+///
+/// POST /dlna/control/ContentDirectory
+#[post("/dlna/control/ContentDirectory")]
+pub async fn content_directory_control(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    body: web::Bytes,
+) -> AppResult<HttpResponse> {
+    let index = data.library.index()?;
+    let base_url = format!("http://{}", req.app_config().local_addr());
+
+    let result = parse_action(&body).and_then(|action| {
+        let args = match action.name.as_str() {
+            "Browse" => {
+                let flag = BrowseFlag::parse(action.required("BrowseFlag")?)
+                    .ok_or(UpnpError::INVALID_ARGS)?;
+                let result = content_directory::browse(
+                    &index,
+                    action.required("ObjectID")?,
+                    flag,
+                    action.number("StartingIndex")?,
+                    action.number("RequestedCount")?,
+                    &base_url,
+                )?;
+                vec![
+                    ("Result", result.didl),
+                    ("NumberReturned", result.returned.to_string()),
+                    ("TotalMatches", result.total.to_string()),
+                    (
+                        "UpdateID",
+                        content_directory::system_update_id(&index).to_string(),
+                    ),
+                ]
+            }
+            "GetSearchCapabilities" => vec![("SearchCaps", String::new())],
+            "GetSortCapabilities" => vec![("SortCaps", String::new())],
+            "GetSystemUpdateID" => vec![(
+                "Id",
+                content_directory::system_update_id(&index).to_string(),
+            )],
+            _ => return Err(UpnpError::INVALID_ACTION),
+        };
+        Ok(soap::response(CONTENT_DIRECTORY, &action.name, &args))
+    });
+
+    Ok(soap_reply(result))
+}
+
+/// ConnectionManager control.
+///
+/// POST /dlna/control/ConnectionManager
+#[post("/dlna/control/ConnectionManager")]
+pub async fn connection_manager_control(body: web::Bytes) -> HttpResponse {
+    let result = parse_action(&body).and_then(|action| {
+        let args = match action.name.as_str() {
+            "GetProtocolInfo" => vec![
+                ("Source", SOURCE_PROTOCOL_INFO.to_string()),
+                ("Sink", String::new()),
+            ],
+            "GetCurrentConnectionIDs" => vec![("ConnectionIDs", "0".to_string())],
+            _ => return Err(UpnpError::INVALID_ACTION),
+        };
+        Ok(soap::response(CONNECTION_MANAGER, &action.name, &args))
+    });
+
+    soap_reply(result)
+}
+
+/// Stream a song, with range support.
+///
+/// GET /dlna/media/{id}
+#[get("/dlna/media/{id}")]
+pub async fn stream_media(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let index = data.library.index()?;
+    let song = index
+        .song(&path)
+        .ok_or_else(|| AppError::song_not_found(&path))?;
+    let full_path = resolve_music_file(&data.music_folder, &song.file)?;
+
+    let mut response = NamedFile::open(&full_path)?.into_response(&req);
+    let headers = response.headers_mut();
+    headers.insert(
+        HeaderName::from_static("transfermode.dlna.org"),
+        HeaderValue::from_static("Streaming"),
+    );
+    headers.insert(
+        HeaderName::from_static("contentfeatures.dlna.org"),
+        HeaderValue::from_static(CONTENT_FEATURES),
+    );
+    Ok(response)
+}
+
+/// Cover art of a song.
+///
+/// GET /dlna/cover/{id}
+#[get("/dlna/cover/{id}")]
+pub async fn cover(data: web::Data<AppState>, path: web::Path<String>) -> AppResult<HttpResponse> {
+    let index = data.library.index()?;
+    let song = index
+        .song(&path)
+        .ok_or_else(|| AppError::song_not_found(&path))?;
+    let file_path = resolve_music_file(&data.music_folder, &song.file)?;
+    let (mime, image) = read_cover(&file_path)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, mime))
+        .insert_header((header::CACHE_CONTROL, "public, max-age=86400"))
+        .body(image))
+}
+
+/// Configure media server routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(device_description)
+        .service(content_directory_scpd)
+        .service(connection_manager_scpd)
+        .service(content_directory_control)
+        .service(connection_manager_control)
+        .service(stream_media)
+        .service(cover);
+}
@@ -0,0 +1,192 @@
+//! UPnP/DLNA media server for TVs, receivers and other LAN devices.
+//!
+//! Consists of an SSDP announcer and responder plus a separate HTTP
+//! listener serving the device description, the ContentDirectory and
+//! ConnectionManager services, and media files. Everything is bound only
+//! to the configured interfaces, since UPnP clients are unauthenticated.
+
+pub mod content_directory;
+pub mod description;
+mod http;
+pub mod soap;
+pub mod ssdp;
+
+use actix_web::{middleware::Logger, web, App, HttpServer};
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::error::AppResult;
+use crate::models::AppState;
+use crate::storage;
+use ssdp::Ssdp;
+
+/// A network interface the media server is offered on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    pub name: String,
+    pub addr: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+}
+
+impl Interface {
+    /// Whether an address is on this interface's subnet.
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        let mask = u32::from(self.netmask);
+        u32::from(addr) & mask == u32::from(self.addr) & mask
+    }
+}
+
+/// Resolve configured interface names or IPv4 addresses.
+///
+/// A name matches all IPv4 addresses of that interface.
+pub fn resolve_interfaces(configured: &[String]) -> std::io::Result<Vec<Interface>> {
+    let available: Vec<Interface> = if_addrs::get_if_addrs()?
+        .into_iter()
+        .filter_map(|iface| match iface.addr {
+            if_addrs::IfAddr::V4(v4) => Some(Interface {
+                name: iface.name,
+                addr: v4.ip,
+                netmask: v4.netmask,
+            }),
+            if_addrs::IfAddr::V6(_) => None,
+        })
+        .collect();
+
+    let mut interfaces = Vec::new();
+    for entry in configured {
+        let matches: Vec<&Interface> = available
+            .iter()
+            .filter(|i| i.name == *entry || i.addr.to_string() == *entry)
+            .collect();
+        if matches.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "No IPv4 interface matches DLNA_INTERFACES entry '{}'",
+                    entry
+                ),
+            ));
+        }
+        for interface in matches {
+            if !interfaces.contains(interface) {
+                interfaces.push(interface.clone());
+            }
+        }
+    }
+    Ok(interfaces)
+}
+
+/// Identity of the media server.
+#[derive(Debug, Clone)]
+pub struct Device {
+    /// Unique device name (a UUID), stable across restarts.
+    pub udn: String,
+    /// Name shown by UPnP devices.
+    pub name: String,
+}
+
+/// Persisted media server identity.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DeviceStore {
+    udn: Option<String>,
+}
+
+/// Load the device UUID, generating one on first start.
+///
+/// Devices remember servers by UUID, so it must not change between runs.
+fn load_or_create_udn(path: &Path) -> AppResult<String> {
+    let mut store: DeviceStore = storage::load_json(path)?;
+    if let Some(udn) = store.udn {
+        return Ok(udn);
+    }
+
+    let udn = Uuid::new_v4().to_string();
+    store.udn = Some(udn.clone());
+    storage::save_json(path, &store)?;
+    Ok(udn)
+}
+
+/// A running media server.
+pub struct MediaServer {
+    ssdp: Arc<Ssdp>,
+}
+
+impl MediaServer {
+    /// Start the HTTP listener and SSDP on the configured interfaces.
+    pub fn start(config: &Config, state: AppState) -> std::io::Result<Self> {
+        let interfaces = resolve_interfaces(&config.dlna_interfaces)?;
+        let udn = load_or_create_udn(&config.data_dir.join("dlna.json"))
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        let device = Device {
+            udn: udn.clone(),
+            name: config.dlna_name.clone(),
+        };
+
+        let mut server = HttpServer::new(move || {
+            App::new()
+                .wrap(Logger::default())
+                .app_data(web::Data::new(state.clone()))
+                .app_data(web::Data::new(device.clone()))
+                .configure(http::configure)
+        });
+        for interface in &interfaces {
+            server = server.bind((interface.addr, config.dlna_port))?;
+        }
+        actix_web::rt::spawn(server.run());
+
+        let ssdp = Arc::new(Ssdp::bind(
+            udn,
+            &interfaces,
+            ssdp::SSDP_PORT,
+            config.dlna_port,
+        )?);
+        ssdp.clone().spawn();
+
+        tracing::info!(
+            interfaces = ?interfaces.iter().map(|i| i.addr.to_string()).collect::<Vec<_>>(),
+            port = config.dlna_port,
+            "Started DLNA media server"
+        );
+
+        Ok(Self { ssdp })
+    }
+
+    /// Tell devices the server is going away.
+    pub async fn shutdown(&self) {
+        self.ssdp.announce(false).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_interface_subnet_and_resolution() {
+        let lan = Interface {
+            name: "eth0".to_string(),
+            addr: Ipv4Addr::new(192, 168, 1, 10),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+        };
+        assert!(lan.contains(Ipv4Addr::new(192, 168, 1, 77)));
+        assert!(!lan.contains(Ipv4Addr::new(192, 168, 2, 77)));
+
+        let resolved = resolve_interfaces(&["127.0.0.1".to_string()]).unwrap();
+        assert_eq!(resolved[0].addr, Ipv4Addr::LOCALHOST);
+        assert!(resolve_interfaces(&["no-such-interface0".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_udn_is_stable() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("dlna.json");
+
+        let first = load_or_create_udn(&path).unwrap();
+        assert_eq!(load_or_create_udn(&path).unwrap(), first);
+    }
+}
@@ -0,0 +1,194 @@
+//! Minimal SOAP handling for UPnP control requests.
+
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+/// A UPnP error returned as a SOAP fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpnpError {
+    pub code: u16,
+    pub description: &'static str,
+}
+
+impl UpnpError {
+    pub const INVALID_ACTION: Self = Self {
+        code: 401,
+        description: "Invalid Action",
+    };
+    pub const INVALID_ARGS: Self = Self {
+        code: 402,
+        description: "Invalid Args",
+    };
+    pub const NO_SUCH_OBJECT: Self = Self {
+        code: 701,
+        description: "No such object",
+    };
+}
+
+/// A parsed control request: the action name and its arguments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Action {
+    pub name: String,
+    pub args: Vec<(String, String)>,
+}
+
+impl Action {
+    /// Value of an argument.
+    pub fn arg(&self, name: &str) -> Option<&str> {
+        self.args
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Value of a required argument.
+    pub fn required(&self, name: &str) -> Result<&str, UpnpError> {
+        self.arg(name).ok_or(UpnpError::INVALID_ARGS)
+    }
+
+    /// Parse a numeric argument, treating a missing one as 0.
+    pub fn number(&self, name: &str) -> Result<usize, UpnpError> {
+        match self.arg(name) {
+            None | Some("") => Ok(0),
+            Some(value) => value.parse().map_err(|_| UpnpError::INVALID_ARGS),
+        }
+    }
+}
+
+/// Parse the action out of a SOAP envelope.
+///
+/// The action is the first element inside `Body`; its child elements are
+/// the arguments.
+pub fn parse_action(body: &str) -> Option<Action> {
+    let mut reader = Reader::from_str(body);
+    reader.config_mut().trim_text(true);
+
+    let mut action: Option<Action> = None;
+    let mut in_body = false;
+    let mut current_arg: Option<String> = None;
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                match &mut action {
+                    None if in_body => {
+                        action = Some(Action {
+                            name,
+                            args: Vec::new(),
+                        })
+                    }
+                    None => in_body = name == "Body",
+                    Some(action) => {
+                        action.args.push((name.clone(), String::new()));
+                        current_arg = Some(name);
+                    }
+                }
+            }
+            Ok(Event::Empty(e)) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                match &mut action {
+                    None if in_body => {
+                        return Some(Action {
+                            name,
+                            args: Vec::new(),
+                        })
+                    }
+                    Some(action) => action.args.push((name, String::new())),
+                    None => {}
+                }
+            }
+            Ok(Event::Text(t)) => {
+                if let (Some(action), Some(_)) = (&mut action, &current_arg) {
+                    let text = t.unescape().ok()?.into_owned();
+                    if let Some((_, value)) = action.args.last_mut() {
+                        value.push_str(&text);
+                    }
+                }
+            }
+            Ok(Event::End(e)) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                if current_arg.as_deref() == Some(name.as_str()) {
+                    current_arg = None;
+                } else if action.as_ref().is_some_and(|a| a.name == name) {
+                    return action;
+                }
+            }
+            Ok(Event::Eof) | Err(_) => return None,
+            _ => {}
+        }
+    }
+}
+
+const ENVELOPE_START: &str = concat!(
+    "<?xml version=\"1.0\" encoding=\"utf-8\"?>",
+    "<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"",
+    " s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body>"
+);
+
+const ENVELOPE_END: &str = "</s:Body></s:Envelope>";
+
+/// Render a successful action response.
+pub fn response(service_type: &str, action: &str, args: &[(&str, String)]) -> String {
+    let mut out = String::from(ENVELOPE_START);
+    out.push_str(&format!(
+        "<u:{}Response xmlns:u=\"{}\">",
+        action, service_type
+    ));
+    for (name, value) in args {
+        out.push_str(&format!("<{}>{}</{}>", name, escape(value.as_str()), name));
+    }
+    out.push_str(&format!("</u:{}Response>", action));
+    out.push_str(ENVELOPE_END);
+    out
+}
+
+/// Render a SOAP fault carrying a UPnP error.
+pub fn fault(error: UpnpError) -> String {
+    format!(
+        concat!(
+            "{}<s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring>",
+            "<detail><UPnPError xmlns=\"urn:schemas-upnp-org:control-1-0\">",
+            "<errorCode>{}</errorCode><errorDescription>{}</errorDescription>",
+            "</UPnPError></detail></s:Fault>{}"
+        ),
+        ENVELOPE_START, error.code, error.description, ENVELOPE_END
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_browse_action() {
+        let body = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+  <s:Body>
+    <u:Browse xmlns:u="urn:schemas-upnp-org:service:ContentDirectory:1">
+      <ObjectID>albums/1</ObjectID>
+      <BrowseFlag>BrowseDirectChildren</BrowseFlag>
+      <Filter>*</Filter>
+      <StartingIndex>0</StartingIndex>
+      <RequestedCount>10</RequestedCount>
+      <SortCriteria></SortCriteria>
+    </u:Browse>
+  </s:Body>
+</s:Envelope>"#;
+
+        let action = parse_action(body).unwrap();
+        assert_eq!(action.name, "Browse");
+        assert_eq!(action.arg("ObjectID"), Some("albums/1"));
+        assert_eq!(action.number("RequestedCount").unwrap(), 10);
+        assert_eq!(action.arg("SortCriteria"), Some(""));
+        assert!(action.required("Missing").is_err());
+    }
+
+    #[test]
+    fn test_response_escapes_values() {
+        let xml = response("urn:x", "Browse", &[("Result", "<DIDL-Lite/>".to_string())]);
+        assert!(xml.contains("<u:BrowseResponse xmlns:u=\"urn:x\"><Result>&lt;DIDL-Lite/&gt;</Result></u:BrowseResponse>"));
+        assert!(fault(UpnpError::NO_SUCH_OBJECT).contains("<errorCode>701</errorCode>"));
+    }
+}
@@ -0,0 +1,336 @@
+//! SSDP discovery: answers M-SEARCH requests and announces the media
+//! server with periodic NOTIFY messages.
+//!
+//! A single socket receives multicast searches from all configured
+//! interfaces. Each interface has its own sending socket bound to its
+//! address, so replies and announcements leave through that interface and
+//! carry a description URL reachable from it. Searches from outside the
+//! configured interfaces' subnets are ignored.
+
+use rand::Rng;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+use super::description::{CONNECTION_MANAGER, CONTENT_DIRECTORY, DEVICE_TYPE};
+use super::Interface;
+
+/// SSDP multicast group.
+pub const MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+
+/// SSDP port.
+pub const SSDP_PORT: u16 = 1900;
+
+/// How long announcements stay valid, in seconds.
+const MAX_AGE: u64 = 1800;
+
+/// Interval between announcements, well within [`MAX_AGE`].
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(MAX_AGE / 3);
+
+/// Upper bound on the random delay before answering a multicast search.
+const MAX_RESPONSE_DELAY: u64 = 5;
+
+/// `SERVER` header value.
+fn server_header() -> String {
+    format!(
+        "{}/{} UPnP/1.0 Ferrum/{}",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// All (notification type, unique service name) pairs of the device.
+fn targets(udn: &str) -> Vec<(String, String)> {
+    let uuid = format!("uuid:{}", udn);
+    let mut targets = vec![
+        (
+            "upnp:rootdevice".to_string(),
+            format!("{}::upnp:rootdevice", uuid),
+        ),
+        (uuid.clone(), uuid.clone()),
+    ];
+    for kind in [DEVICE_TYPE, CONTENT_DIRECTORY, CONNECTION_MANAGER] {
+        targets.push((kind.to_string(), format!("{}::{}", uuid, kind)));
+    }
+    targets
+}
+
+/// A parsed M-SEARCH request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Search {
+    /// Search target.
+    st: String,
+    /// Maximum response delay in seconds; absent for unicast searches.
+    mx: Option<u64>,
+}
+
+/// Parse an M-SEARCH request, ignoring anything else (e.g. other devices' NOTIFYs).
+fn parse_search(packet: &str) -> Option<Search> {
+    let mut lines = packet.lines();
+    if !lines.next()?.trim().starts_with("M-SEARCH * HTTP/1.1") {
+        return None;
+    }
+
+    let mut st = None;
+    let mut mx = None;
+    let mut discover = false;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_uppercase().as_str() {
+            "ST" => st = Some(value.to_string()),
+            "MX" => mx = value.parse().ok(),
+            "MAN" => discover = value.trim_matches('"') == "ssdp:discover",
+            _ => {}
+        }
+    }
+
+    discover.then_some(Search { st: st?, mx })
+}
+
+/// Targets answering a search target.
+fn matching_targets(udn: &str, st: &str) -> Vec<(String, String)> {
+    targets(udn)
+        .into_iter()
+        .filter(|(nt, _)| st == "ssdp:all" || st == nt)
+        .collect()
+}
+
+fn search_response(st: &str, usn: &str, location: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age={}\r\nDATE: {}\r\nEXT:\r\nLOCATION: {}\r\nSERVER: {}\r\nST: {}\r\nUSN: {}\r\nContent-Length: 0\r\n\r\n",
+        MAX_AGE,
+        chrono::Utc::now().format("%a, %d %b %Y %H:%M:%S GMT"),
+        location,
+        server_header(),
+        st,
+        usn
+    )
+}
+
+fn notify(nt: &str, usn: &str, location: &str, alive: bool) -> String {
+    if alive {
+        format!(
+            "NOTIFY * HTTP/1.1\r\nHOST: {}:{}\r\nCACHE-CONTROL: max-age={}\r\nLOCATION: {}\r\nNT: {}\r\nNTS: ssdp:alive\r\nSERVER: {}\r\nUSN: {}\r\n\r\n",
+            MULTICAST_ADDR, SSDP_PORT, MAX_AGE, location, nt, server_header(), usn
+        )
+    } else {
+        format!(
+            "NOTIFY * HTTP/1.1\r\nHOST: {}:{}\r\nNT: {}\r\nNTS: ssdp:byebye\r\nUSN: {}\r\n\r\n",
+            MULTICAST_ADDR, SSDP_PORT, nt, usn
+        )
+    }
+}
+
+/// Per-interface sending socket.
+struct Sender {
+    interface: Interface,
+    socket: UdpSocket,
+    location: String,
+}
+
+/// The SSDP responder and announcer.
+pub struct Ssdp {
+    udn: String,
+    listener: UdpSocket,
+    senders: Vec<Sender>,
+}
+
+impl Ssdp {
+    /// Bind the SSDP sockets.
+    ///
+    /// `port` is the SSDP port to listen on ([`SSDP_PORT`] outside tests);
+    /// `http_port` is the port of the description and media listener.
+    pub fn bind(
+        udn: String,
+        interfaces: &[Interface],
+        port: u16,
+        http_port: u16,
+    ) -> std::io::Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port).into())?;
+        for interface in interfaces {
+            // Loopback usually has no multicast; unicast searches still work there
+            if let Err(e) = socket.join_multicast_v4(&MULTICAST_ADDR, &interface.addr) {
+                tracing::warn!(interface = %interface.name, error = %e, "Failed to join SSDP multicast group");
+            }
+        }
+        let listener = UdpSocket::from_std(socket.into())?;
+
+        let mut senders = Vec::new();
+        for interface in interfaces {
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.set_nonblocking(true)?;
+            socket.bind(&SocketAddrV4::new(interface.addr, 0).into())?;
+            socket.set_multicast_if_v4(&interface.addr)?;
+            socket.set_multicast_ttl_v4(4)?;
+            senders.push(Sender {
+                interface: interface.clone(),
+                socket: UdpSocket::from_std(socket.into())?,
+                location: format!(
+                    "http://{}:{}/dlna/description.xml",
+                    interface.addr, http_port
+                ),
+            });
+        }
+
+        Ok(Self {
+            udn,
+            listener,
+            senders,
+        })
+    }
+
+    /// Port the responder listens on.
+    pub fn local_port(&self) -> std::io::Result<u16> {
+        Ok(self.listener.local_addr()?.port())
+    }
+
+    /// Send alive or byebye notifications on every interface.
+    pub async fn announce(&self, alive: bool) {
+        let group = SocketAddrV4::new(MULTICAST_ADDR, SSDP_PORT);
+        for sender in &self.senders {
+            for (nt, usn) in targets(&self.udn) {
+                let message = notify(&nt, &usn, &sender.location, alive);
+                if let Err(e) = sender.socket.send_to(message.as_bytes(), group).await {
+                    tracing::debug!(interface = %sender.interface.name, error = %e, "Failed to send SSDP notification");
+                }
+            }
+        }
+    }
+
+    /// Answer searches and announce periodically in background tasks.
+    pub fn spawn(self: Arc<Self>) {
+        let announcer = self.clone();
+        tokio::spawn(async move {
+            loop {
+                announcer.announce(true).await;
+                tokio::time::sleep(ANNOUNCE_INTERVAL).await;
+            }
+        });
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 2048];
+            loop {
+                let (len, from) = match self.listener.recv_from(&mut buf).await {
+                    Ok(received) => received,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "SSDP receive failed");
+                        continue;
+                    }
+                };
+                let Some(search) = std::str::from_utf8(&buf[..len]).ok().and_then(parse_search)
+                else {
+                    continue;
+                };
+                self.clone().answer(search, from);
+            }
+        });
+    }
+
+    /// Answer a search from the interface facing the requester.
+    fn answer(self: Arc<Self>, search: Search, from: SocketAddr) {
+        let SocketAddr::V4(from_v4) = from else {
+            return;
+        };
+        let Some(index) = self
+            .senders
+            .iter()
+            .position(|s| s.interface.contains(*from_v4.ip()))
+        else {
+            tracing::debug!(from = %from, "Ignoring SSDP search from outside the configured interfaces");
+            return;
+        };
+        let responses = matching_targets(&self.udn, &search.st);
+        if responses.is_empty() {
+            return;
+        }
+
+        let delay = match search.mx {
+            Some(mx) if mx > 0 => {
+                let max = mx.min(MAX_RESPONSE_DELAY) * 1000;
+                Duration::from_millis(rand::thread_rng().gen_range(0..max))
+            }
+            _ => Duration::ZERO,
+        };
+
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let sender = &self.senders[index];
+            for (st, usn) in responses {
+                let response = search_response(&st, &usn, &sender.location);
+                if let Err(e) = sender.socket.send_to(response.as_bytes(), from).await {
+                    tracing::debug!(to = %from, error = %e, "Failed to answer SSDP search");
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UDN: &str = "4d696e69-444c-164e-9d41-0123456789ab";
+
+    #[test]
+    fn test_parse_search() {
+        let packet = "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: upnp:rootdevice\r\n\r\n";
+        assert_eq!(
+            parse_search(packet),
+            Some(Search {
+                st: "upnp:rootdevice".to_string(),
+                mx: Some(2)
+            })
+        );
+
+        let notify = notify("upnp:rootdevice", "uuid:x", "http://h/", true);
+        assert_eq!(parse_search(&notify), None);
+
+        assert_eq!(matching_targets(UDN, "ssdp:all").len(), 5);
+        assert_eq!(matching_targets(UDN, DEVICE_TYPE).len(), 1);
+        assert!(matching_targets(UDN, "urn:schemas-upnp-org:device:MediaRenderer:1").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unicast_search_on_loopback() {
+        let loopback = Interface {
+            name: "lo".to_string(),
+            addr: Ipv4Addr::LOCALHOST,
+            netmask: Ipv4Addr::new(255, 0, 0, 0),
+        };
+        let ssdp = Arc::new(Ssdp::bind(UDN.to_string(), &[loopback], 0, 8200).unwrap());
+        let port = ssdp.local_port().unwrap();
+        ssdp.spawn();
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let search = format!(
+            "M-SEARCH * HTTP/1.1\r\nHOST: 127.0.0.1:{}\r\nMAN: \"ssdp:discover\"\r\nST: {}\r\n\r\n",
+            port, CONTENT_DIRECTORY
+        );
+        client
+            .send_to(search.as_bytes(), ("127.0.0.1", port))
+            .await
+            .unwrap();
+
+        let mut buf = [0u8; 2048];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(5), client.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let response = std::str::from_utf8(&buf[..len]).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("LOCATION: http://127.0.0.1:8200/dlna/description.xml"));
+        assert!(response.contains(&format!("USN: uuid:{}::{}", UDN, CONTENT_DIRECTORY)));
+    }
+}
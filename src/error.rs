@@ -2,7 +2,10 @@
 //!
 //! Provides structured error responses for the API.
 
-use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use actix_web::{
+    http::{header, StatusCode},
+    HttpResponse, ResponseError,
+};
 use serde::Serialize;
 
 /// API error response body.
@@ -61,6 +64,11 @@ pub enum AppError {
     #[error("Bad request: {0}")]
     BadRequest(String),
 
+    /// Too many attempts; the client should retry after the given number
+    /// of seconds.
+    #[error("Too many requests: {message}")]
+    TooManyRequests { message: String, retry_after: u64 },
+
     /// Internal server error.
     #[error("Internal error: {0}")]
     Internal(String),
@@ -84,6 +92,7 @@ impl AppError {
             Self::Validation(_) => "VALIDATION_ERROR",
             Self::Conflict(_) => "CONFLICT",
             Self::BadRequest(_) => "BAD_REQUEST",
+            Self::TooManyRequests { .. } => "TOO_MANY_REQUESTS",
             Self::Internal(_) => "INTERNAL_ERROR",
             Self::Io(_) => "IO_ERROR",
             Self::Json(_) => "JSON_ERROR",
@@ -95,6 +104,11 @@ impl AppError {
         Self::Unauthorized("Invalid username or password".to_string())
     }
 
+    /// Create a forbidden error for a disabled account.
+    pub fn account_disabled() -> Self {
+        Self::Forbidden("Account is disabled".to_string())
+    }
+
     /// Create an unauthorized error for invalid token.
     pub fn invalid_token() -> Self {
         Self::Unauthorized("Invalid or expired token".to_string())
@@ -120,13 +134,14 @@ impl ResponseError for AppError {
             Self::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::Conflict(_) => StatusCode::CONFLICT,
             Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
             Self::Internal(_) | Self::Io(_) | Self::Json(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
     fn error_response(&self) -> HttpResponse {
         let status = self.status_code();
-        let error_response = ErrorResponse::new(self.error_code(), self.to_string());
+        let mut error_response = ErrorResponse::new(self.error_code(), self.to_string());
 
         tracing::error!(
             error_code = %self.error_code(),
@@ -135,7 +150,13 @@ impl ResponseError for AppError {
             "API error"
         );
 
-        HttpResponse::build(status).json(error_response)
+        let mut response = HttpResponse::build(status);
+        if let Self::TooManyRequests { retry_after, .. } = self {
+            response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+            error_response =
+                error_response.with_details(serde_json::json!({ "retry_after": retry_after }));
+        }
+        response.json(error_response)
     }
 }
 
@@ -184,6 +205,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_too_many_requests_sets_retry_after() {
+        let response = AppError::TooManyRequests {
+            message: "test".into(),
+            retry_after: 30,
+        }
+        .error_response();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "30");
+    }
+
     #[test]
     fn test_error_response_serialization() {
         let response = ErrorResponse::new("TEST_ERROR", "Test message");
@@ -0,0 +1,262 @@
+//! In-process event bus behind the WebSocket event channel.
+//!
+//! Every event gets a sequence number. Recent events are kept in memory so
+//! a client that reconnects with the last sequence number it saw can be
+//! sent what it missed. Sequence numbers belong to a stream identified by a
+//! random ID chosen at startup; after a restart (or when too many events
+//! were missed) clients are told to reload their state instead.
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Events kept for resuming clients.
+const HISTORY_LEN: usize = 1000;
+
+/// Live events buffered per subscriber before it lags.
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Event types.
+pub mod kind {
+    pub const SCAN_STARTED: &str = "scan.started";
+    pub const SCAN_PROGRESS: &str = "scan.progress";
+    pub const SCAN_FINISHED: &str = "scan.finished";
+    pub const SONGS_ADDED: &str = "songs.added";
+    pub const SONGS_REMOVED: &str = "songs.removed";
+    pub const SONGS_UPDATED: &str = "songs.updated";
+    pub const PLAYLIST_CREATED: &str = "playlist.created";
+    pub const PLAYLIST_UPDATED: &str = "playlist.updated";
+    pub const PLAYLIST_DELETED: &str = "playlist.deleted";
+    pub const NOW_PLAYING: &str = "now_playing";
+    pub const QUEUE_UPDATED: &str = "queue.updated";
+    pub const SESSION_STARTED: &str = "session.started";
+    pub const SESSION_UPDATED: &str = "session.updated";
+    pub const SESSION_ENDED: &str = "session.ended";
+    pub const ROOM_UPDATED: &str = "room.updated";
+    pub const ROOM_PLAYBACK: &str = "room.playback";
+    pub const ROOM_CLOSED: &str = "room.closed";
+}
+
+/// Who receives an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Audience {
+    /// Every authenticated user (library changes).
+    All,
+    /// Only the sessions of one user.
+    User(Uuid),
+    /// The sessions of several users (members of a listening room).
+    Users(Vec<Uuid>),
+}
+
+/// An event, serialized as the envelope sent to clients:
+///
+/// ```json
+/// {"seq": 42, "type": "songs.added", "timestamp": "...", "data": {...}}
+/// ```
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub seq: u64,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub timestamp: DateTime<Utc>,
+    pub data: serde_json::Value,
+    #[serde(skip)]
+    pub audience: Audience,
+    /// Client ID of the session that caused the event, if known. That
+    /// session does not receive it back.
+    #[serde(skip)]
+    pub origin: Option<String>,
+}
+
+impl Event {
+    /// Whether a session should receive this event.
+    pub fn is_for(&self, user_id: Uuid, client_id: Option<&str>) -> bool {
+        let audience = match &self.audience {
+            Audience::All => true,
+            Audience::User(id) => *id == user_id,
+            Audience::Users(ids) => ids.contains(&user_id),
+        };
+        audience && (client_id.is_none() || self.origin.as_deref() != client_id)
+    }
+}
+
+struct History {
+    next_seq: u64,
+    events: VecDeque<Event>,
+}
+
+/// A new subscription.
+pub struct Subscription {
+    /// Current stream ID.
+    pub stream: String,
+    /// Last sequence number published so far.
+    pub seq: u64,
+    /// Missed events to replay, or `None` if the client must reload its
+    /// state because they are no longer available.
+    pub missed: Option<Vec<Event>>,
+    pub receiver: broadcast::Receiver<Event>,
+}
+
+/// Publishes events to connected clients.
+pub struct EventBus {
+    stream: String,
+    history: Mutex<History>,
+    sender: broadcast::Sender<Event>,
+}
+
+impl std::fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventBus")
+            .field("stream", &self.stream)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            stream: Uuid::new_v4().simple().to_string(),
+            history: Mutex::new(History {
+                next_seq: 1,
+                events: VecDeque::new(),
+            }),
+            sender,
+        }
+    }
+
+    /// Publish an event.
+    pub fn publish(
+        &self,
+        kind: &str,
+        audience: Audience,
+        origin: Option<&str>,
+        data: impl Serialize,
+    ) {
+        let data = match serde_json::to_value(data) {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::error!(kind = %kind, error = %e, "Failed to serialize event");
+                return;
+            }
+        };
+
+        let mut history = self.history.lock();
+        let event = Event {
+            seq: history.next_seq,
+            kind: kind.to_string(),
+            timestamp: Utc::now(),
+            data,
+            audience,
+            origin: origin.map(String::from),
+        };
+        history.next_seq += 1;
+        if history.events.len() == HISTORY_LEN {
+            history.events.pop_front();
+        }
+        history.events.push_back(event.clone());
+
+        // No subscribers is not an error
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribe, resuming after `since` in `stream` if given.
+    pub fn subscribe(&self, stream: Option<&str>, since: Option<u64>) -> Subscription {
+        let history = self.history.lock();
+        let seq = history.next_seq - 1;
+
+        let missed = match (stream, since) {
+            (Some(stream), Some(since)) if stream == self.stream && since <= seq => {
+                let oldest = history.events.front().map_or(seq + 1, |e| e.seq);
+                (since + 1 >= oldest).then(|| {
+                    history
+                        .events
+                        .iter()
+                        .filter(|e| e.seq > since)
+                        .cloned()
+                        .collect()
+                })
+            }
+            _ => None,
+        };
+
+        Subscription {
+            stream: self.stream.clone(),
+            seq,
+            missed,
+            receiver: self.sender.subscribe(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resume_after_reconnect() {
+        let bus = EventBus::new();
+        bus.publish(kind::SONGS_REMOVED, Audience::All, None, ["a"]);
+        let first = bus.subscribe(None, None);
+        assert_eq!(first.seq, 1);
+        assert!(first.missed.is_none());
+
+        bus.publish(kind::SONGS_REMOVED, Audience::All, None, ["b"]);
+        bus.publish(kind::SONGS_REMOVED, Audience::All, None, ["c"]);
+
+        let resumed = bus.subscribe(Some(&first.stream), Some(first.seq));
+        let missed = resumed.missed.unwrap();
+        assert_eq!(missed.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(missed[1].data, serde_json::json!(["c"]));
+
+        // Unknown stream (server restarted) or a future sequence: reload
+        assert!(bus.subscribe(Some("other"), Some(1)).missed.is_none());
+        assert!(bus.subscribe(Some(&first.stream), Some(9)).missed.is_none());
+        let current = bus.subscribe(Some(&first.stream), Some(3));
+        assert_eq!(current.missed.map(|m| m.len()), Some(0));
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let bus = EventBus::new();
+        let stream = bus.subscribe(None, None).stream;
+        for i in 0..HISTORY_LEN + 5 {
+            bus.publish(kind::SCAN_PROGRESS, Audience::All, None, i);
+        }
+        assert!(bus.subscribe(Some(&stream), Some(2)).missed.is_none());
+        let missed = bus.subscribe(Some(&stream), Some(5)).missed.unwrap();
+        assert_eq!(missed.len(), HISTORY_LEN);
+    }
+
+    #[test]
+    fn test_audience_and_origin() {
+        let alice = Uuid::new_v4();
+        let bob = Uuid::new_v4();
+        let bus = EventBus::new();
+        bus.publish(
+            kind::NOW_PLAYING,
+            Audience::User(alice),
+            Some("phone"),
+            "song",
+        );
+        let event = bus
+            .subscribe(Some(&bus.stream), Some(0))
+            .missed
+            .unwrap()
+            .remove(0);
+
+        assert!(event.is_for(alice, None));
+        assert!(event.is_for(alice, Some("laptop")));
+        assert!(!event.is_for(alice, Some("phone")));
+        assert!(!event.is_for(bob, None));
+    }
+}
@@ -0,0 +1,20 @@
+//! Ferrum - A lightweight, self-hosted music streaming server.
+//!
+//! Ferrum provides a REST API for streaming local music files,
+//! with JWT-based authentication and multi-user support.
+
+pub mod api;
+pub mod auth;
+pub mod config;
+pub mod dlna;
+pub mod error;
+pub mod events;
+pub mod library;
+pub mod models;
+pub mod mpd;
+pub mod radio;
+pub mod scrobbling;
+pub mod sessions;
+pub mod stats;
+pub mod storage;
+pub mod userdata;
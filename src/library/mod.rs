@@ -0,0 +1,426 @@
+//! Music library index.
+//!
+//! Scans the music folder for audio and playlist files and caches the
+//! result. The index is rebuilt whenever the folder's modification time
+//! changes (files added, removed or renamed), or on an explicit rescan.
+//! Scan progress and song changes are published as events.
+
+pub mod playlist_file;
+
+use chrono::{DateTime, Utc};
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::picture::PictureType;
+use lofty::prelude::Accessor;
+use lofty::read_from_path;
+use parking_lot::{Mutex, RwLock};
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::error::AppResult;
+use crate::events::{kind, Audience, EventBus};
+use crate::models::SongMetadata;
+use crate::userdata::Playlist;
+use playlist_file::PlaylistFormat;
+
+/// Files between `scan.progress` events.
+const PROGRESS_INTERVAL: usize = 50;
+
+/// Supported audio file extensions.
+const SUPPORTED_EXTENSIONS: &[&str] = &[
+    "mp3", "flac", "ogg", "wav", "m4a", "aac", "wma", "opus", "aiff", "ape",
+];
+
+/// Check if a file has a supported audio extension.
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| SUPPORTED_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Extract song metadata from an audio file.
+pub fn extract_metadata(path: &Path) -> Option<SongMetadata> {
+    let tagged_file = read_from_path(path).ok()?;
+    let tag = tagged_file.first_tag();
+    let properties = tagged_file.properties();
+
+    let filename = path.file_name()?.to_string_lossy().into_owned();
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let has_cover = tag
+        .map(|t| {
+            t.pictures()
+                .iter()
+                .any(|p| p.pic_type() == PictureType::CoverFront)
+        })
+        .unwrap_or(false);
+
+    let artist = tag
+        .and_then(|t| t.artist())
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| "Unknown Artist".to_string());
+    let album = tag
+        .and_then(|t| t.album())
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| "Unknown Album".to_string());
+
+    Some(SongMetadata {
+        id: SongMetadata::generate_id(path),
+        title: tag
+            .and_then(|t| t.title())
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| filename.clone()),
+        artist_id: SongMetadata::generate_name_id("artist", &artist),
+        artist,
+        album_id: SongMetadata::generate_name_id("album", &album),
+        album,
+        duration: Some(properties.duration().as_secs() as u32),
+        track_number: tag.and_then(|t| t.track()),
+        year: tag.and_then(|t| t.year()).map(|y| y as i32),
+        genre: tag.and_then(|t| t.genre()).map(|s| s.to_string()),
+        format: extension,
+        file: filename,
+        has_cover,
+        starred: None,
+        rating: None,
+        play_count: 0,
+        last_played: None,
+    })
+}
+
+/// A snapshot of the scanned library.
+#[derive(Debug, Clone, Default)]
+pub struct LibraryIndex {
+    /// All songs in the music folder.
+    pub songs: Vec<SongMetadata>,
+    /// Read-only playlists imported from playlist files.
+    pub playlists: Vec<Playlist>,
+    /// Folder modification time at scan.
+    modified: Option<SystemTime>,
+}
+
+impl LibraryIndex {
+    /// Find a song by ID.
+    pub fn song(&self, id: &str) -> Option<&SongMetadata> {
+        self.songs.iter().find(|s| s.id == id)
+    }
+
+    /// Find an imported playlist by ID.
+    pub fn playlist(&self, id: &str) -> Option<&Playlist> {
+        self.playlists.iter().find(|p| p.id == id)
+    }
+}
+
+/// Cached index of the music folder.
+#[derive(Debug)]
+pub struct Library {
+    music_folder: PathBuf,
+    index: RwLock<Option<Arc<LibraryIndex>>>,
+    /// Serializes scans, so concurrent requests scan only once.
+    scan_lock: Mutex<()>,
+    events: Option<Arc<EventBus>>,
+}
+
+impl Library {
+    /// Create a library for a music folder. Nothing is scanned until first use.
+    pub fn new(music_folder: impl Into<PathBuf>) -> Self {
+        Self {
+            music_folder: music_folder.into(),
+            index: RwLock::new(None),
+            scan_lock: Mutex::new(()),
+            events: None,
+        }
+    }
+
+    /// Publish scan progress and song changes to an event bus.
+    pub fn with_events(mut self, events: Arc<EventBus>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    fn publish(&self, kind: &str, data: serde_json::Value) {
+        if let Some(events) = &self.events {
+            events.publish(kind, Audience::All, None, data);
+        }
+    }
+
+    /// The cached index, if it is still current.
+    fn current(&self, modified: Option<SystemTime>) -> Option<Arc<LibraryIndex>> {
+        self.index
+            .read()
+            .as_ref()
+            .filter(|index| modified.is_some() && index.modified == modified)
+            .cloned()
+    }
+
+    /// Get the current index, rescanning if the music folder changed.
+    pub fn index(&self) -> AppResult<Arc<LibraryIndex>> {
+        let modified = fs::metadata(&self.music_folder)?.modified().ok();
+        if let Some(index) = self.current(modified) {
+            return Ok(index);
+        }
+
+        // Another request may have finished scanning while we waited
+        let _scan = self.scan_lock.lock();
+        if let Some(index) = self.current(modified) {
+            return Ok(index);
+        }
+        self.scan()
+    }
+
+    /// Rescan the music folder and replace the cached index.
+    pub fn rescan(&self) -> AppResult<Arc<LibraryIndex>> {
+        let _scan = self.scan_lock.lock();
+        self.scan()
+    }
+
+    fn scan(&self) -> AppResult<Arc<LibraryIndex>> {
+        let modified = fs::metadata(&self.music_folder)?.modified().ok();
+
+        let paths: Vec<PathBuf> = fs::read_dir(&self.music_folder)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect();
+
+        let audio: Vec<&PathBuf> = paths.iter().filter(|path| is_audio_file(path)).collect();
+        let total = audio.len();
+        self.publish(kind::SCAN_STARTED, json!({ "total": total }));
+
+        let mut songs = Vec::with_capacity(total);
+        for (scanned, path) in audio.into_iter().enumerate() {
+            songs.extend(extract_metadata(path));
+            if (scanned + 1) % PROGRESS_INTERVAL == 0 {
+                self.publish(
+                    kind::SCAN_PROGRESS,
+                    json!({ "scanned": scanned + 1, "total": total }),
+                );
+            }
+        }
+
+        let playlists: Vec<Playlist> = paths
+            .iter()
+            .filter_map(|path| Some((path, PlaylistFormat::from_path(path)?)))
+            .filter_map(|(path, format)| self.import_playlist(path, format, &songs))
+            .collect();
+
+        tracing::info!(
+            songs = songs.len(),
+            playlists = playlists.len(),
+            "Scanned music library"
+        );
+
+        let index = Arc::new(LibraryIndex {
+            songs,
+            playlists,
+            modified,
+        });
+        let previous = self.index.write().replace(index.clone());
+        self.publish_changes(previous.as_deref(), &index);
+
+        Ok(index)
+    }
+
+    /// Publish the songs added, removed and updated by a scan. The first
+    /// scan has nothing to compare against, so it only reports totals.
+    fn publish_changes(&self, previous: Option<&LibraryIndex>, index: &LibraryIndex) {
+        let (added, removed, updated) = match previous {
+            Some(previous) => diff_songs(previous, index),
+            None => Default::default(),
+        };
+
+        self.publish(
+            kind::SCAN_FINISHED,
+            json!({
+                "songs": index.songs.len(),
+                "playlists": index.playlists.len(),
+                "added": added.len(),
+                "removed": removed.len(),
+                "updated": updated.len(),
+            }),
+        );
+        if !added.is_empty() {
+            self.publish(kind::SONGS_ADDED, json!({ "songs": added }));
+        }
+        if !removed.is_empty() {
+            self.publish(kind::SONGS_REMOVED, json!({ "ids": removed }));
+        }
+        if !updated.is_empty() {
+            self.publish(kind::SONGS_UPDATED, json!({ "songs": updated }));
+        }
+    }
+
+    /// Parse a playlist file and resolve its entries against the scanned songs.
+    fn import_playlist(
+        &self,
+        path: &Path,
+        format: PlaylistFormat,
+        songs: &[SongMetadata],
+    ) -> Option<Playlist> {
+        let bytes = fs::read(path)
+            .map_err(
+                |e| tracing::warn!(path = %path.display(), error = %e, "Failed to read playlist"),
+            )
+            .ok()?;
+        let parsed = playlist_file::parse(format, &playlist_file::decode_bytes(&bytes))
+            .map_err(
+                |e| tracing::warn!(path = %path.display(), error = %e, "Failed to parse playlist"),
+            )
+            .ok()?;
+
+        let base_dir = path.parent().unwrap_or(&self.music_folder);
+        let song_ids: Vec<String> = parsed
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                playlist_file::resolve_entry(entry, base_dir, &self.music_folder, songs)
+            })
+            .map(|song| song.id.clone())
+            .collect();
+        let unresolved = parsed.entries.len() - song_ids.len();
+
+        if unresolved > 0 {
+            tracing::debug!(
+                path = %path.display(),
+                unresolved,
+                "Some playlist entries could not be matched"
+            );
+        }
+
+        let modified: DateTime<Utc> = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map(DateTime::from)
+            .unwrap_or_else(|_| Utc::now());
+
+        let source = path.file_name()?.to_string_lossy().into_owned();
+        let name = parsed.title.unwrap_or_else(|| {
+            path.file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| source.clone())
+        });
+
+        Some(Playlist {
+            id: SongMetadata::generate_id(path),
+            name,
+            owner_id: None,
+            song_ids,
+            read_only: true,
+            source: Some(source),
+            unresolved,
+            created_at: modified,
+            updated_at: modified,
+        })
+    }
+}
+
+/// Songs added, IDs removed and songs updated between two indexes.
+fn diff_songs(
+    previous: &LibraryIndex,
+    index: &LibraryIndex,
+) -> (Vec<SongMetadata>, Vec<String>, Vec<SongMetadata>) {
+    let mut added = Vec::new();
+    let mut updated = Vec::new();
+    for song in &index.songs {
+        match previous.song(&song.id) {
+            None => added.push(song.clone()),
+            Some(old) if old != song => updated.push(song.clone()),
+            Some(_) => {}
+        }
+    }
+    let removed = previous
+        .songs
+        .iter()
+        .filter(|song| index.song(&song.id).is_none())
+        .map(|song| song.id.clone())
+        .collect();
+
+    (added, removed, updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_is_audio_file() {
+        assert!(is_audio_file(Path::new("song.mp3")));
+        assert!(is_audio_file(Path::new("song.FLAC")));
+        assert!(!is_audio_file(Path::new("image.jpg")));
+        assert!(!is_audio_file(Path::new("noextension")));
+    }
+
+    #[test]
+    fn test_scan_discovers_playlist_files() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("mix.m3u8"), "#EXTM3U\nmissing.mp3\n").unwrap();
+        fs::write(dir.path().join("notes.txt"), "not a playlist").unwrap();
+
+        let library = Library::new(dir.path());
+        let index = library.index().unwrap();
+
+        assert!(index.songs.is_empty());
+        assert_eq!(index.playlists.len(), 1);
+        assert_eq!(index.playlists[0].name, "mix");
+        assert_eq!(index.playlists[0].unresolved, 1);
+        assert!(index.playlists[0].read_only);
+    }
+
+    #[test]
+    fn test_diff_songs() {
+        let song = |file: &str, title: &str| SongMetadata {
+            id: SongMetadata::generate_id(Path::new(file)),
+            title: title.to_string(),
+            artist: "Unknown Artist".to_string(),
+            artist_id: SongMetadata::generate_name_id("artist", "Unknown Artist"),
+            album: "Unknown Album".to_string(),
+            album_id: SongMetadata::generate_name_id("album", "Unknown Album"),
+            duration: None,
+            track_number: None,
+            year: None,
+            genre: None,
+            format: "mp3".to_string(),
+            file: file.to_string(),
+            has_cover: false,
+            starred: None,
+            rating: None,
+            play_count: 0,
+            last_played: None,
+        };
+        let previous = LibraryIndex {
+            songs: vec![song("a.mp3", "A"), song("b.mp3", "B")],
+            ..Default::default()
+        };
+        let index = LibraryIndex {
+            songs: vec![song("b.mp3", "B (Remastered)"), song("c.mp3", "C")],
+            ..Default::default()
+        };
+
+        let (added, removed, updated) = diff_songs(&previous, &index);
+        assert_eq!(added[0].file, "c.mp3");
+        assert_eq!(removed, vec![previous.songs[0].id.clone()]);
+        assert_eq!(updated[0].title, "B (Remastered)");
+    }
+
+    #[test]
+    fn test_scan_publishes_events() {
+        let dir = tempdir().unwrap();
+        let events = Arc::new(EventBus::new());
+        let subscription = events.subscribe(None, None);
+        let library = Library::new(dir.path()).with_events(events.clone());
+        library.rescan().unwrap();
+
+        let missed = events
+            .subscribe(Some(&subscription.stream), Some(0))
+            .missed
+            .unwrap();
+        let kinds: Vec<&str> = missed.iter().map(|e| e.kind.as_str()).collect();
+        assert_eq!(kinds, vec![kind::SCAN_STARTED, kind::SCAN_FINISHED]);
+    }
+}
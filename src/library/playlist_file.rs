@@ -0,0 +1,597 @@
+//! Playlist file formats (M3U/M3U8, PLS, XSPF).
+//!
+//! Parses playlist files found in the music folder, resolves their entries
+//! to library songs and writes playlists back out for export.
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::path::{Path, PathBuf};
+
+use crate::error::{AppError, AppResult};
+use crate::models::SongMetadata;
+
+/// Characters escaped when a filename is embedded in a URL path segment.
+pub const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Supported playlist file formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    /// M3U / extended M3U (`.m3u`, `.m3u8`).
+    M3u,
+    /// Winamp PLS (`.pls`).
+    Pls,
+    /// XML Shareable Playlist Format (`.xspf`).
+    Xspf,
+}
+
+impl PlaylistFormat {
+    /// Detect the playlist format from a file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "m3u" | "m3u8" => Some(Self::M3u),
+            "pls" => Some(Self::Pls),
+            "xspf" => Some(Self::Xspf),
+            _ => None,
+        }
+    }
+}
+
+/// A single entry read from a playlist file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlaylistEntry {
+    /// Path or URI as written in the file.
+    pub location: String,
+    /// Track title, if the file carries one.
+    pub title: Option<String>,
+    /// Artist name, if the file carries one.
+    pub artist: Option<String>,
+    /// Duration in seconds, if known.
+    pub duration: Option<u32>,
+}
+
+/// A parsed playlist file.
+#[derive(Debug, Clone, Default)]
+pub struct ParsedPlaylist {
+    /// Playlist title embedded in the file.
+    pub title: Option<String>,
+    /// Entries in playlist order.
+    pub entries: Vec<PlaylistEntry>,
+}
+
+/// Decode playlist file bytes.
+///
+/// `.m3u8`, PLS and XSPF are UTF-8; legacy `.m3u` files are often Latin-1,
+/// so invalid UTF-8 falls back to a byte-per-char decoding.
+pub fn decode_bytes(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Parse playlist file content.
+pub fn parse(format: PlaylistFormat, content: &str) -> AppResult<ParsedPlaylist> {
+    match format {
+        PlaylistFormat::M3u => Ok(parse_m3u(content)),
+        PlaylistFormat::Pls => Ok(parse_pls(content)),
+        PlaylistFormat::Xspf => parse_xspf(content),
+    }
+}
+
+/// Split an `Artist - Title` display string.
+fn split_display_title(display: &str) -> (Option<String>, Option<String>) {
+    let display = display.trim();
+    if display.is_empty() {
+        return (None, None);
+    }
+
+    match display.split_once(" - ") {
+        Some((artist, title)) => (
+            Some(artist.trim().to_string()),
+            Some(title.trim().to_string()),
+        ),
+        None => (None, Some(display.to_string())),
+    }
+}
+
+fn parse_m3u(content: &str) -> ParsedPlaylist {
+    let mut playlist = ParsedPlaylist::default();
+    let mut pending = PlaylistEntry::default();
+
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // #EXTINF:<seconds>[ attrs],<Artist - Title>
+            let (head, display) = info.split_once(',').unwrap_or((info, ""));
+            let seconds = head.split_whitespace().next().unwrap_or("");
+            pending.duration = seconds
+                .parse::<i64>()
+                .ok()
+                .filter(|s| *s > 0)
+                .map(|s| s as u32);
+            let (artist, title) = split_display_title(display);
+            pending.artist = artist;
+            pending.title = title;
+        } else if let Some(name) = line.strip_prefix("#PLAYLIST:") {
+            playlist.title = Some(name.trim().to_string());
+        } else if !line.starts_with('#') {
+            pending.location = line.to_string();
+            playlist.entries.push(std::mem::take(&mut pending));
+        }
+    }
+
+    playlist
+}
+
+fn parse_pls(content: &str) -> ParsedPlaylist {
+    use std::collections::BTreeMap;
+
+    let mut entries: BTreeMap<u32, PlaylistEntry> = BTreeMap::new();
+
+    for line in content.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim();
+
+        let (field, index) = if let Some(n) = key.strip_prefix("file") {
+            ("file", n)
+        } else if let Some(n) = key.strip_prefix("title") {
+            ("title", n)
+        } else if let Some(n) = key.strip_prefix("length") {
+            ("length", n)
+        } else {
+            continue;
+        };
+
+        let Ok(index) = index.parse::<u32>() else {
+            continue;
+        };
+        let entry = entries.entry(index).or_default();
+
+        match field {
+            "file" => entry.location = value.to_string(),
+            "title" => {
+                let (artist, title) = split_display_title(value);
+                entry.artist = artist;
+                entry.title = title;
+            }
+            _ => {
+                entry.duration = value
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .map(|s| s as u32)
+            }
+        }
+    }
+
+    ParsedPlaylist {
+        title: None,
+        entries: entries
+            .into_values()
+            .filter(|e| !e.location.is_empty())
+            .collect(),
+    }
+}
+
+fn parse_xspf(content: &str) -> AppResult<ParsedPlaylist> {
+    let mut reader = Reader::from_str(content);
+    reader.config_mut().trim_text(true);
+
+    let mut playlist = ParsedPlaylist::default();
+    let mut current: Option<PlaylistEntry> = None;
+    let mut path: Vec<String> = Vec::new();
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                if name == "track" {
+                    current = Some(PlaylistEntry::default());
+                }
+                path.push(name);
+            }
+            Ok(Event::End(e)) => {
+                path.pop();
+                if e.local_name().as_ref() == b"track" {
+                    if let Some(entry) = current.take().filter(|e| !e.location.is_empty()) {
+                        playlist.entries.push(entry);
+                    }
+                }
+            }
+            Ok(Event::Text(t)) => {
+                let text = t
+                    .unescape()
+                    .map_err(|e| AppError::BadRequest(format!("Invalid XSPF: {}", e)))?
+                    .trim()
+                    .to_string();
+                let element = path.last().map(String::as_str);
+
+                match (current.as_mut(), element) {
+                    (Some(entry), Some("location")) if entry.location.is_empty() => {
+                        entry.location = text
+                    }
+                    (Some(entry), Some("title")) => entry.title = Some(text),
+                    (Some(entry), Some("creator")) => entry.artist = Some(text),
+                    (Some(entry), Some("duration")) => {
+                        entry.duration = text.parse::<u64>().ok().map(|ms| (ms / 1000) as u32)
+                    }
+                    (None, Some("title")) if path.len() == 2 => playlist.title = Some(text),
+                    _ => {}
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(AppError::BadRequest(format!("Invalid XSPF: {}", e))),
+            _ => {}
+        }
+    }
+
+    Ok(playlist)
+}
+
+/// Normalize a string for fuzzy comparison (lowercase alphanumerics only).
+fn normalize(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Guess `(artist, title)` from a file stem such as `01 - Artist - Title`.
+fn guess_from_stem(stem: &str) -> (Option<String>, Option<String>) {
+    let stem = stem
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .trim_start_matches(['.', '-', '_', ' ']);
+    split_display_title(stem)
+}
+
+/// Convert an entry location into a filesystem path, if it is one.
+fn location_to_path(location: &str, base_dir: &Path) -> Option<PathBuf> {
+    let location = if let Some(rest) = location.strip_prefix("file://") {
+        percent_decode_str(rest).decode_utf8_lossy().into_owned()
+    } else if location.contains("://") {
+        return None;
+    } else {
+        location.replace('\\', "/")
+    };
+
+    let path = PathBuf::from(&location);
+    Some(if path.is_absolute() {
+        path
+    } else {
+        base_dir.join(path)
+    })
+}
+
+/// Resolve a playlist entry to a library song.
+///
+/// Tries, in order: a path (relative to the playlist file, or absolute) that
+/// points at a library file, a ferrum stream URL, a unique filename match,
+/// and finally a fuzzy artist/title match against the song tags.
+pub fn resolve_entry<'a>(
+    entry: &PlaylistEntry,
+    base_dir: &Path,
+    music_folder: &Path,
+    songs: &'a [SongMetadata],
+) -> Option<&'a SongMetadata> {
+    let path = location_to_path(&entry.location, base_dir);
+
+    // Exact path inside the music folder
+    if let Some(ref path) = path {
+        if let (Ok(canonical), Ok(music_canonical)) =
+            (path.canonicalize(), music_folder.canonicalize())
+        {
+            if canonical.parent() == Some(music_canonical.as_path()) {
+                let name = canonical.file_name()?.to_string_lossy();
+                if let Some(song) = songs.iter().find(|s| s.file == name) {
+                    return Some(song);
+                }
+            }
+        }
+    }
+
+    // Filename from a path or a previously exported stream URL
+    let filename = match path {
+        Some(ref path) => path.file_name().map(|n| n.to_string_lossy().into_owned()),
+        None => entry
+            .location
+            .split_once("/api/music/stream/")
+            .map(|(_, rest)| rest.split(['?', '#']).next().unwrap_or(rest))
+            .map(|f| percent_decode_str(f).decode_utf8_lossy().into_owned()),
+    };
+
+    if let Some(ref filename) = filename {
+        let mut matches = songs.iter().filter(|s| &s.file == filename);
+        if let (Some(song), None) = (matches.next(), matches.next()) {
+            return Some(song);
+        }
+    }
+
+    // Fuzzy tag match
+    let (artist, title) = match (&entry.artist, &entry.title) {
+        (_, Some(title)) => (entry.artist.clone(), Some(title.clone())),
+        (_, None) => filename
+            .as_deref()
+            .map(|f| {
+                Path::new(f)
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .map(|stem| guess_from_stem(&stem))
+            .unwrap_or((None, None)),
+    };
+
+    let title = normalize(&title?);
+    if title.is_empty() {
+        return None;
+    }
+    let artist = artist.map(|a| normalize(&a)).filter(|a| !a.is_empty());
+
+    let candidates: Vec<&SongMetadata> = songs
+        .iter()
+        .filter(|s| normalize(&s.title) == title)
+        .collect();
+
+    if let Some(ref artist) = artist {
+        if let Some(song) = candidates.iter().find(|s| &normalize(&s.artist) == artist) {
+            return Some(song);
+        }
+    }
+
+    match candidates.as_slice() {
+        [only] => Some(only),
+        _ => None,
+    }
+}
+
+/// A song with the location to write for it in an exported playlist.
+#[derive(Debug, Clone)]
+pub struct ExportTrack<'a> {
+    /// Song metadata.
+    pub song: &'a SongMetadata,
+    /// Stream URL or relative path.
+    pub location: String,
+}
+
+/// Replace line breaks, so a value cannot start a new M3U line.
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+/// Write an extended M3U (UTF-8) playlist.
+pub fn write_m3u8(name: &str, tracks: &[ExportTrack<'_>]) -> String {
+    let mut out = String::from("#EXTM3U\n");
+    out.push_str(&format!("#PLAYLIST:{}\n", single_line(name)));
+
+    for track in tracks {
+        out.push_str(&format!(
+            "#EXTINF:{},{} - {}\n{}\n",
+            track.song.duration.map(i64::from).unwrap_or(-1),
+            single_line(&track.song.artist),
+            single_line(&track.song.title),
+            single_line(&track.location)
+        ));
+    }
+
+    out
+}
+
+/// Write an XSPF playlist.
+pub fn write_xspf(name: &str, tracks: &[ExportTrack<'_>]) -> String {
+    use quick_xml::escape::escape;
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    out.push_str(&format!("  <title>{}</title>\n", escape(name)));
+    out.push_str("  <trackList>\n");
+
+    for track in tracks {
+        let song = track.song;
+        out.push_str("    <track>\n");
+        out.push_str(&format!(
+            "      <location>{}</location>\n",
+            escape(&track.location)
+        ));
+        out.push_str(&format!("      <title>{}</title>\n", escape(&song.title)));
+        out.push_str(&format!(
+            "      <creator>{}</creator>\n",
+            escape(&song.artist)
+        ));
+        out.push_str(&format!("      <album>{}</album>\n", escape(&song.album)));
+        if let Some(duration) = song.duration {
+            out.push_str(&format!(
+                "      <duration>{}</duration>\n",
+                u64::from(duration) * 1000
+            ));
+        }
+        if let Some(track_number) = song.track_number {
+            out.push_str(&format!("      <trackNum>{}</trackNum>\n", track_number));
+        }
+        out.push_str("    </track>\n");
+    }
+
+    out.push_str("  </trackList>\n</playlist>\n");
+    out
+}
+
+/// Percent-encode a filename for use in a URL path.
+pub fn encode_path_segment(segment: &str) -> String {
+    utf8_percent_encode(segment, PATH_SEGMENT).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(file: &str, artist: &str, title: &str) -> SongMetadata {
+        SongMetadata {
+            id: SongMetadata::generate_id(Path::new(file)),
+            title: title.to_string(),
+            artist: artist.to_string(),
+            artist_id: SongMetadata::generate_name_id("artist", artist),
+            album: "Album".to_string(),
+            album_id: SongMetadata::generate_name_id("album", "Album"),
+            duration: Some(200),
+            track_number: Some(1),
+            year: None,
+            genre: None,
+            format: "mp3".to_string(),
+            file: file.to_string(),
+            has_cover: false,
+            starred: None,
+            rating: None,
+            play_count: 0,
+            last_played: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_extended_m3u() {
+        let content = "#EXTM3U\n#PLAYLIST:Road Trip\n#EXTINF:215,Daft Punk - One More Time\nMusic/one.mp3\n\nother.flac\n";
+        let parsed = parse(PlaylistFormat::M3u, content).unwrap();
+
+        assert_eq!(parsed.title.as_deref(), Some("Road Trip"));
+        assert_eq!(parsed.entries.len(), 2);
+        assert_eq!(parsed.entries[0].artist.as_deref(), Some("Daft Punk"));
+        assert_eq!(parsed.entries[0].title.as_deref(), Some("One More Time"));
+        assert_eq!(parsed.entries[0].duration, Some(215));
+        assert_eq!(parsed.entries[1].location, "other.flac");
+        assert_eq!(parsed.entries[1].title, None);
+    }
+
+    #[test]
+    fn test_parse_pls() {
+        let content = "[playlist]\nFile2=b.mp3\nFile1=a.mp3\nTitle1=Artist - Song A\nLength1=-1\nNumberOfEntries=2\n";
+        let parsed = parse(PlaylistFormat::Pls, content).unwrap();
+
+        assert_eq!(parsed.entries.len(), 2);
+        assert_eq!(parsed.entries[0].location, "a.mp3");
+        assert_eq!(parsed.entries[0].title.as_deref(), Some("Song A"));
+        assert_eq!(parsed.entries[0].duration, None);
+        assert_eq!(parsed.entries[1].location, "b.mp3");
+    }
+
+    #[test]
+    fn test_parse_xspf() {
+        let content = r#"<?xml version="1.0"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <title>Mix &amp; Match</title>
+  <trackList>
+    <track><location>file:///music/My%20Song.mp3</location><title>My Song</title><creator>Me</creator><duration>120000</duration></track>
+  </trackList>
+</playlist>"#;
+        let parsed = parse(PlaylistFormat::Xspf, content).unwrap();
+
+        assert_eq!(parsed.title.as_deref(), Some("Mix & Match"));
+        assert_eq!(parsed.entries.len(), 1);
+        assert_eq!(parsed.entries[0].location, "file:///music/My%20Song.mp3");
+        assert_eq!(parsed.entries[0].artist.as_deref(), Some("Me"));
+        assert_eq!(parsed.entries[0].duration, Some(120));
+    }
+
+    #[test]
+    fn test_resolve_by_filename_and_fuzzy_tags() {
+        let songs = vec![
+            song("a.mp3", "Daft Punk", "One More Time"),
+            song("b.mp3", "Other", "Something"),
+        ];
+        let base = Path::new("/nonexistent");
+
+        let by_name = PlaylistEntry {
+            location: "C:\\Users\\me\\Music\\b.mp3".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            resolve_entry(&by_name, base, base, &songs).unwrap().file,
+            "b.mp3"
+        );
+
+        let by_tags = PlaylistEntry {
+            location: "/old/library/Daft Punk/Discovery/01 One More Time.flac".to_string(),
+            artist: Some("daft punk".to_string()),
+            title: Some("One more time!".to_string()),
+            duration: None,
+        };
+        assert_eq!(
+            resolve_entry(&by_tags, base, base, &songs).unwrap().file,
+            "a.mp3"
+        );
+
+        let by_stem = PlaylistEntry {
+            location: "elsewhere/03 - Other - Something.ogg".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            resolve_entry(&by_stem, base, base, &songs).unwrap().file,
+            "b.mp3"
+        );
+
+        let missing = PlaylistEntry {
+            location: "nope.mp3".to_string(),
+            ..Default::default()
+        };
+        assert!(resolve_entry(&missing, base, base, &songs).is_none());
+    }
+
+    #[test]
+    fn test_export_roundtrip() {
+        let songs = vec![song("My Song.mp3", "Me", "My Song")];
+        let tracks = vec![ExportTrack {
+            song: &songs[0],
+            location: format!(
+                "http://host/api/music/stream/{}",
+                encode_path_segment(&songs[0].file)
+            ),
+        }];
+
+        let m3u = write_m3u8("List", &tracks);
+        let parsed = parse(PlaylistFormat::M3u, &m3u).unwrap();
+        assert_eq!(
+            parsed.entries[0].location,
+            "http://host/api/music/stream/My%20Song.mp3"
+        );
+        let base = Path::new("/nonexistent");
+        assert_eq!(
+            resolve_entry(&parsed.entries[0], base, base, &songs)
+                .unwrap()
+                .id,
+            songs[0].id
+        );
+
+        // Line breaks in names and tags cannot inject entries
+        let evil = song("b.mp3", "A\nhttp://evil/x.mp3", "T\r\n#EXTINF:1,x");
+        let injected = vec![ExportTrack {
+            song: &evil,
+            location: "b.mp3".to_string(),
+        }];
+        let m3u = write_m3u8("L\nhttp://evil/y.mp3", &injected);
+        let parsed = parse(PlaylistFormat::M3u, &m3u).unwrap();
+        assert_eq!(parsed.entries.len(), 1);
+        assert_eq!(parsed.entries[0].location, "b.mp3");
+
+        let xspf = write_xspf("List <1>", &tracks);
+        let parsed = parse(PlaylistFormat::Xspf, &xspf).unwrap();
+        assert_eq!(parsed.title.as_deref(), Some("List <1>"));
+        assert_eq!(parsed.entries[0].title.as_deref(), Some("My Song"));
+    }
+}
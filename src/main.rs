@@ -1,22 +1,29 @@
-//! Ferrum - A lightweight, self-hosted music streaming server.
-//!
-//! Ferrum provides a REST API for streaming local music files,
-//! with JWT-based authentication and multi-user support.
-
-mod api;
-mod auth;
-mod config;
-mod error;
-mod models;
+//! Ferrum server binary.
 
 use actix_cors::Cors;
 use actix_web::{http::header, middleware::Logger, web, App, HttpServer};
 use std::sync::Arc;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-use crate::auth::JsonUserRepository;
-use crate::config::LogFormat;
-use crate::models::AppState;
+use ferrum::api;
+use ferrum::auth::{
+    JsonApiKeyRepository, JsonInviteRepository, JsonRefreshTokenRepository, JsonUserRepository,
+    LoginChallenges, LoginThrottle, SecretBox, SetupToken, UrlSigner, UserRepository,
+};
+use ferrum::config::{self, LogFormat};
+use ferrum::dlna::MediaServer;
+use ferrum::events::EventBus;
+use ferrum::library::Library;
+use ferrum::models::AppState;
+use ferrum::mpd::MpdServer;
+use ferrum::radio::Radio;
+use ferrum::scrobbling::queue::JsonScrobbleQueue;
+use ferrum::scrobbling::ScrobbleForwarder;
+use ferrum::sessions::SessionRegistry;
+use ferrum::userdata::{
+    JsonAnnotationRepository, JsonForwardingRepository, JsonHistoryRepository,
+    JsonPlayQueueRepository, JsonPlaylistRepository, JsonRoomRepository, JsonShareRepository,
+};
 
 /// Initialize the tracing/logging subsystem.
 fn init_tracing(config: &config::Config) {
@@ -47,6 +54,9 @@ fn configure_cors(config: &config::Config) -> Cors {
             header::AUTHORIZATION,
             header::ACCEPT,
             header::CONTENT_TYPE,
+            header::HeaderName::from_static("x-api-key"),
+            header::HeaderName::from_static("x-client-id"),
+            header::HeaderName::from_static("x-share-password"),
         ])
         .max_age(3600);
 
@@ -108,16 +118,199 @@ async fn main() -> std::io::Result<()> {
     let user_repo = Arc::new(
         JsonUserRepository::new(&config.users_file).map_err(|e| {
             tracing::error!(error = %e, "Failed to initialize user repository");
-            std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+            std::io::Error::other(e.to_string())
+        })?,
+    );
+
+    // Initialize refresh token repository
+    let refresh_token_repo = Arc::new(
+        JsonRefreshTokenRepository::new(
+            config.data_dir.join("refresh_tokens.json"),
+            chrono::Duration::days(config.jwt_expiry_days),
+            chrono::Duration::minutes(config.access_token_minutes),
+        )
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to initialize refresh token repository");
+            std::io::Error::other(e.to_string())
+        })?,
+    );
+
+    // Initialize API key repository
+    let api_key_repo = Arc::new(
+        JsonApiKeyRepository::new(config.data_dir.join("api_keys.json")).map_err(|e| {
+            tracing::error!(error = %e, "Failed to initialize API key repository");
+            std::io::Error::other(e.to_string())
+        })?,
+    );
+
+    // Initialize invite code repository
+    let invite_repo = Arc::new(
+        JsonInviteRepository::new(config.data_dir.join("invites.json")).map_err(|e| {
+            tracing::error!(error = %e, "Failed to initialize invite repository");
+            std::io::Error::other(e.to_string())
+        })?,
+    );
+
+    // Without users, the first admin must register with a one-time setup token
+    let setup_token = Arc::new(SetupToken::new());
+    if user_repo
+        .count()
+        .map_err(|e| std::io::Error::other(e.to_string()))?
+        == 0
+    {
+        let token = setup_token.generate();
+        tracing::warn!(
+            setup_token = %token,
+            "No users yet. Register the first admin with this setup token"
+        );
+    }
+
+    // Initialize playlist repository
+    let playlist_repo = Arc::new(
+        JsonPlaylistRepository::new(config.data_dir.join("playlists.json")).map_err(|e| {
+            tracing::error!(error = %e, "Failed to initialize playlist repository");
+            std::io::Error::other(e.to_string())
+        })?,
+    );
+
+    // Initialize annotation repository
+    let annotation_repo = Arc::new(
+        JsonAnnotationRepository::new(config.data_dir.join("annotations.json")).map_err(|e| {
+            tracing::error!(error = %e, "Failed to initialize annotation repository");
+            std::io::Error::other(e.to_string())
+        })?,
+    );
+
+    // Initialize play history repository
+    let history_repo = Arc::new(
+        JsonHistoryRepository::new(config.data_dir.join("history.jsonl")).map_err(|e| {
+            tracing::error!(error = %e, "Failed to initialize play history repository");
+            std::io::Error::other(e.to_string())
+        })?,
+    );
+
+    // Initialize play queue repository
+    let play_queue_repo = Arc::new(
+        JsonPlayQueueRepository::new(config.data_dir.join("play_queues.json")).map_err(|e| {
+            tracing::error!(error = %e, "Failed to initialize play queue repository");
+            std::io::Error::other(e.to_string())
+        })?,
+    );
+
+    // Initialize listening room repository
+    let room_repo = Arc::new(
+        JsonRoomRepository::new(config.data_dir.join("rooms.json")).map_err(|e| {
+            tracing::error!(error = %e, "Failed to initialize listening room repository");
+            std::io::Error::other(e.to_string())
+        })?,
+    );
+
+    // Initialize share link repository
+    let share_repo = Arc::new(
+        JsonShareRepository::new(config.data_dir.join("shares.json")).map_err(|e| {
+            tracing::error!(error = %e, "Failed to initialize share link repository");
+            std::io::Error::other(e.to_string())
+        })?,
+    );
+
+    // Load the key for secrets stored encrypted (Subsonic passwords, service tokens)
+    let secret_box = Arc::new(
+        SecretBox::load_or_create(&config.data_dir.join("secret.key")).map_err(|e| {
+            tracing::error!(error = %e, "Failed to load secret key");
+            std::io::Error::other(e.to_string())
+        })?,
+    );
+
+    // Initialize scrobble forwarding and its delivery queue
+    let forwarding_repo = Arc::new(
+        JsonForwardingRepository::new(config.data_dir.join("scrobble_forwarding.json")).map_err(
+            |e| {
+                tracing::error!(error = %e, "Failed to initialize scrobble forwarding settings");
+                std::io::Error::other(e.to_string())
+            },
+        )?,
+    );
+    let scrobble_queue = Arc::new(
+        JsonScrobbleQueue::new(config.data_dir.join("scrobble_queue.json")).map_err(|e| {
+            tracing::error!(error = %e, "Failed to initialize scrobble queue");
+            std::io::Error::other(e.to_string())
+        })?,
+    );
+    let scrobble_forwarder = Arc::new(ScrobbleForwarder::new(
+        forwarding_repo,
+        scrobble_queue,
+        secret_box.clone(),
+    ));
+    let sealed = scrobble_forwarder.seal_plaintext_tokens().map_err(|e| {
+        tracing::error!(error = %e, "Failed to seal scrobble forwarding tokens");
+        std::io::Error::other(e.to_string())
+    })?;
+    if sealed > 0 {
+        tracing::info!(
+            count = sealed,
+            "Sealed plaintext scrobble forwarding tokens"
+        );
+    }
+    scrobble_forwarder.clone().spawn();
+
+    // Load the key for signed stream URLs, kept apart from the JWT secret
+    let url_signer = Arc::new(
+        UrlSigner::load_or_create(&config.data_dir.join("stream.key")).map_err(|e| {
+            tracing::error!(error = %e, "Failed to load stream URL key");
+            std::io::Error::other(e.to_string())
         })?,
     );
 
     // Create application state
+    let events = Arc::new(EventBus::new());
     let app_state = AppState {
         music_folder: config.music_folder.clone(),
         user_repo: user_repo.clone(),
+        refresh_token_repo: refresh_token_repo.clone(),
+        api_key_repo: api_key_repo.clone(),
+        invite_repo,
+        setup_token,
+        login_throttle: Arc::new(LoginThrottle::new(
+            config.login_lockout_threshold,
+            chrono::Duration::minutes(config.login_lockout_minutes),
+        )),
+        login_challenges: Arc::new(LoginChallenges::new()),
+        library: Arc::new(Library::new(&config.music_folder).with_events(events.clone())),
+        playlist_repo,
+        annotation_repo,
+        history_repo,
+        play_queue_repo,
+        room_repo,
+        share_repo,
+        scrobble_forwarder,
+        secret_box,
+        url_signer,
+        radio: Arc::new(Radio::new(&config.radio_ffmpeg, config.radio_bitrate)),
+        events,
+        sessions: Arc::new(SessionRegistry::new()),
     };
 
+    // Start the UPnP/DLNA media server if enabled
+    let media_server = if config.dlna_enabled {
+        Some(MediaServer::start(config, app_state.clone()).map_err(|e| {
+            tracing::error!(error = %e, "Failed to start DLNA media server");
+            e
+        })?)
+    } else {
+        None
+    };
+
+    // Start the MPD protocol listener if enabled
+    if config.mpd_enabled {
+        MpdServer::bind(config, app_state.clone())
+            .await
+            .map_err(|e| {
+                tracing::error!(error = %e, "Failed to start MPD listener");
+                e
+            })?
+            .spawn();
+    }
+
     let bind_address = config.bind_address();
 
     tracing::info!(
@@ -135,12 +328,46 @@ async fn main() -> std::io::Result<()> {
             // Shared state
             .app_data(web::Data::new(app_state.clone()))
             .app_data(web::Data::from(user_repo.clone()))
+            .app_data(web::Data::from(refresh_token_repo.clone()))
+            .app_data(web::Data::from(api_key_repo.clone()))
             // Health endpoints (no auth required)
             .configure(api::health::configure)
             // Auth endpoints (no auth required for login/register)
             .configure(api::auth::configure)
+            // Admin user management (admin required)
+            .configure(api::users::configure)
+            // Invite codes (admin required)
+            .configure(api::invites::configure)
+            // API key management (auth required)
+            .configure(api::api_keys::configure)
             // Music endpoints (auth required)
             .configure(api::music::configure)
+            // Star and rating endpoints (auth required)
+            .configure(api::annotations::configure)
+            // Play history and scrobble endpoints (auth required)
+            .configure(api::history::configure)
+            // Scrobble forwarding settings (auth required)
+            .configure(api::scrobbling::configure)
+            // Playlist endpoints (auth required)
+            .configure(api::playlists::configure)
+            // Play queue sync endpoints (auth required)
+            .configure(api::queue::configure)
+            // Listening room endpoints (auth required)
+            .configure(api::rooms::configure)
+            // Share link management (auth required) and public share access
+            .configure(api::shares::configure)
+            // Listening statistics endpoints (auth required)
+            .configure(api::stats::configure)
+            // Subsonic-compatible API (per-request Subsonic authentication)
+            .configure(api::subsonic::configure)
+            // Real-time event channel (WebSocket, auth required)
+            .configure(api::events::configure)
+            // Remote control of playback sessions (auth required)
+            .configure(api::sessions::configure)
+            // Internet radio streams (Basic or bearer auth)
+            .configure(api::radio::configure)
+            // Read-only WebDAV view of the library (Basic or bearer auth)
+            .configure(api::webdav::configure)
     })
     .bind(&bind_address)?
     .shutdown_timeout(30)
@@ -152,6 +379,9 @@ async fn main() -> std::io::Result<()> {
             result
         }
         _ = shutdown_signal() => {
+            if let Some(media_server) = &media_server {
+                media_server.shutdown().await;
+            }
             tracing::info!("Shutdown complete");
             Ok(())
         }
@@ -1,9 +1,22 @@
 //! Data models for the application.
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
-use crate::auth::JsonUserRepository;
+use crate::auth::{
+    JsonApiKeyRepository, JsonInviteRepository, JsonRefreshTokenRepository, JsonUserRepository,
+    LoginChallenges, LoginThrottle, SecretBox, SetupToken, UrlSigner,
+};
+use crate::events::EventBus;
+use crate::library::Library;
+use crate::radio::Radio;
+use crate::scrobbling::ScrobbleForwarder;
+use crate::sessions::SessionRegistry;
+use crate::userdata::{
+    Annotation, JsonAnnotationRepository, JsonHistoryRepository, JsonPlayQueueRepository,
+    JsonPlaylistRepository, JsonRoomRepository, JsonShareRepository, SongPlayStats,
+};
 
 /// Shared application state.
 #[derive(Clone)]
@@ -12,10 +25,109 @@ pub struct AppState {
     pub music_folder: PathBuf,
     /// User repository.
     pub user_repo: std::sync::Arc<JsonUserRepository>,
+    /// Refresh token repository.
+    pub refresh_token_repo: std::sync::Arc<JsonRefreshTokenRepository>,
+    /// API key repository.
+    pub api_key_repo: std::sync::Arc<JsonApiKeyRepository>,
+    /// Invite code repository.
+    pub invite_repo: std::sync::Arc<JsonInviteRepository>,
+    /// Token for creating the first admin, while there are no users.
+    pub setup_token: std::sync::Arc<SetupToken>,
+    /// Brute-force protection for password logins.
+    pub login_throttle: std::sync::Arc<LoginThrottle>,
+    /// Two-factor logins waiting for a code.
+    pub login_challenges: std::sync::Arc<LoginChallenges>,
+    /// Cached library index.
+    pub library: std::sync::Arc<Library>,
+    /// User playlist repository.
+    pub playlist_repo: std::sync::Arc<JsonPlaylistRepository>,
+    /// Per-user annotation repository (stars and ratings).
+    pub annotation_repo: std::sync::Arc<JsonAnnotationRepository>,
+    /// Per-user play history repository.
+    pub history_repo: std::sync::Arc<JsonHistoryRepository>,
+    /// Per-user play queue repository.
+    pub play_queue_repo: std::sync::Arc<JsonPlayQueueRepository>,
+    /// Listening room repository.
+    pub room_repo: std::sync::Arc<JsonRoomRepository>,
+    /// Public share link repository.
+    pub share_repo: std::sync::Arc<JsonShareRepository>,
+    /// Scrobble forwarding to ListenBrainz-compatible services.
+    pub scrobble_forwarder: std::sync::Arc<ScrobbleForwarder>,
+    /// Encryption of secrets that must be stored recoverably.
+    pub secret_box: std::sync::Arc<SecretBox>,
+    /// Signing of expiring media URLs.
+    pub url_signer: std::sync::Arc<UrlSigner>,
+    /// Internet radio stations.
+    pub radio: std::sync::Arc<Radio>,
+    /// Events pushed to connected clients.
+    pub events: std::sync::Arc<EventBus>,
+    /// Connected players, for remote control.
+    pub sessions: std::sync::Arc<SessionRegistry>,
+}
+
+#[cfg(test)]
+impl AppState {
+    /// State with empty stores in `dir`, which is also the music folder.
+    /// Initializes the global configuration if needed.
+    pub(crate) fn for_tests(dir: &std::path::Path) -> Self {
+        use crate::scrobbling::queue::JsonScrobbleQueue;
+        use crate::userdata::JsonForwardingRepository;
+        use chrono::Duration;
+        use std::sync::Arc;
+
+        std::env::set_var("JWT_SECRET", "test-secret-key-for-testing-purposes-only");
+        std::env::set_var("MUSIC_FOLDER", ".");
+        let _ = crate::config::init();
+
+        let secret_box = Arc::new(SecretBox::load_or_create(&dir.join("secret.key")).unwrap());
+        let events = Arc::new(EventBus::new());
+        Self {
+            music_folder: dir.to_path_buf(),
+            user_repo: Arc::new(JsonUserRepository::new(dir.join("users.json")).unwrap()),
+            refresh_token_repo: Arc::new(
+                JsonRefreshTokenRepository::new(
+                    dir.join("refresh_tokens.json"),
+                    Duration::days(7),
+                    Duration::minutes(15),
+                )
+                .unwrap(),
+            ),
+            api_key_repo: Arc::new(JsonApiKeyRepository::new(dir.join("api_keys.json")).unwrap()),
+            invite_repo: Arc::new(JsonInviteRepository::new(dir.join("invites.json")).unwrap()),
+            setup_token: Arc::new(SetupToken::new()),
+            login_throttle: Arc::new(LoginThrottle::new(10, Duration::minutes(15))),
+            login_challenges: Arc::new(LoginChallenges::new()),
+            library: Arc::new(Library::new(dir).with_events(events.clone())),
+            playlist_repo: Arc::new(
+                JsonPlaylistRepository::new(dir.join("playlists.json")).unwrap(),
+            ),
+            annotation_repo: Arc::new(
+                JsonAnnotationRepository::new(dir.join("annotations.json")).unwrap(),
+            ),
+            history_repo: Arc::new(JsonHistoryRepository::new(dir.join("history.jsonl")).unwrap()),
+            play_queue_repo: Arc::new(
+                JsonPlayQueueRepository::new(dir.join("play_queues.json")).unwrap(),
+            ),
+            room_repo: Arc::new(JsonRoomRepository::new(dir.join("rooms.json")).unwrap()),
+            share_repo: Arc::new(JsonShareRepository::new(dir.join("shares.json")).unwrap()),
+            scrobble_forwarder: Arc::new(ScrobbleForwarder::new(
+                Arc::new(
+                    JsonForwardingRepository::new(dir.join("scrobble_forwarding.json")).unwrap(),
+                ),
+                Arc::new(JsonScrobbleQueue::new(dir.join("scrobble_queue.json")).unwrap()),
+                secret_box.clone(),
+            )),
+            secret_box,
+            url_signer: Arc::new(UrlSigner::load_or_create(&dir.join("stream.key")).unwrap()),
+            radio: Arc::new(Radio::new("ffmpeg", 128)),
+            events,
+            sessions: Arc::new(SessionRegistry::new()),
+        }
+    }
 }
 
 /// Song metadata extracted from audio files.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct SongMetadata {
     /// Unique identifier (hash of file path).
     pub id: String,
@@ -23,8 +135,12 @@ pub struct SongMetadata {
     pub title: String,
     /// Artist name.
     pub artist: String,
+    /// Artist identifier (hash of artist name).
+    pub artist_id: String,
     /// Album name.
     pub album: String,
+    /// Album identifier (hash of album name).
+    pub album_id: String,
     /// Track duration in seconds.
     pub duration: Option<u32>,
     /// Track number in album.
@@ -39,6 +155,17 @@ pub struct SongMetadata {
     pub file: String,
     /// Whether the track has embedded cover art.
     pub has_cover: bool,
+    /// When the requesting user starred the song.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub starred: Option<DateTime<Utc>>,
+    /// The requesting user's rating (1-5).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<u8>,
+    /// How often the requesting user played the song.
+    pub play_count: u32,
+    /// When the requesting user last played the song.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_played: Option<DateTime<Utc>>,
 }
 
 impl SongMetadata {
@@ -51,6 +178,32 @@ impl SongMetadata {
         path.hash(&mut hasher);
         format!("{:016x}", hasher.finish())
     }
+
+    /// Generate a stable ID for an artist or album name.
+    ///
+    /// Names are compared case-insensitively, and `kind` keeps artist and
+    /// album IDs apart when they share a name.
+    pub fn generate_name_id(kind: &str, name: &str) -> String {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = DefaultHasher::new();
+        kind.hash(&mut hasher);
+        name.to_lowercase().hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    /// Merge the requesting user's song annotation into the metadata.
+    pub fn apply_annotation(&mut self, annotation: Option<&Annotation>) {
+        self.starred = annotation.and_then(|a| a.starred_at);
+        self.rating = annotation.and_then(|a| a.rating);
+    }
+
+    /// Merge the requesting user's play statistics into the metadata.
+    pub fn apply_play_stats(&mut self, stats: Option<&SongPlayStats>) {
+        self.play_count = stats.map(|s| s.play_count).unwrap_or(0);
+        self.last_played = stats.and_then(|s| s.last_played);
+    }
 }
 
 /// Generic API response wrapper.
@@ -94,7 +247,7 @@ pub struct PaginatedResponse<T> {
 impl<T> PaginatedResponse<T> {
     /// Create a paginated response from a full collection.
     pub fn from_vec(items: Vec<T>, page: usize, per_page: usize, total: usize) -> Self {
-        let total_pages = (total + per_page - 1) / per_page;
+        let total_pages = total.div_ceil(per_page);
 
         Self {
             items,
@@ -119,6 +272,10 @@ pub struct ListSongsQuery {
     pub album: Option<String>,
     /// Filter by genre.
     pub genre: Option<String>,
+    /// Only starred (`true`) or unstarred (`false`) songs.
+    pub starred: Option<bool>,
+    /// Minimum rating (1-5).
+    pub min_rating: Option<u8>,
     /// Page number (1-indexed).
     #[serde(default = "default_page")]
     pub page: usize,
@@ -151,6 +308,12 @@ pub enum SortField {
     Album,
     Year,
     Duration,
+    Rating,
+    Starred,
+    #[serde(rename = "play_count")]
+    PlayCount,
+    #[serde(rename = "last_played")]
+    LastPlayed,
 }
 
 /// Sort order.
@@ -177,6 +340,18 @@ mod tests {
         assert!(!response.has_prev);
     }
 
+    #[test]
+    fn test_name_id_generation() {
+        assert_eq!(
+            SongMetadata::generate_name_id("artist", "Daft Punk"),
+            SongMetadata::generate_name_id("artist", "daft punk")
+        );
+        assert_ne!(
+            SongMetadata::generate_name_id("artist", "Discovery"),
+            SongMetadata::generate_name_id("album", "Discovery")
+        );
+    }
+
     #[test]
     fn test_song_id_generation() {
         let path1 = std::path::Path::new("/music/song.mp3");
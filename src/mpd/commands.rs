@@ -0,0 +1,697 @@
+//! MPD command handlers.
+//!
+//! Only browsing is supported: there is no queue or player, so `status`
+//! always reports a stopped player with an empty queue. Songs are
+//! identified by their ferrum stream URLs.
+
+use percent_encoding::percent_decode_str;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::filter::{Filter, Tag, TAG_TYPES};
+use super::protocol::{Ack, AckCode, Response};
+use crate::api::auth::{log_failed_login, verify_password};
+use crate::api::music::{read_cover, resolve_music_file};
+use crate::auth::{AuthenticatedUser, UserRepository};
+use crate::error::AppError;
+use crate::library::{playlist_file, LibraryIndex};
+use crate::models::{AppState, SongMetadata};
+use crate::userdata::{Playlist, PlaylistRepository};
+
+/// Default and maximum size of `albumart` chunks.
+const DEFAULT_BINARY_LIMIT: usize = 8192;
+const MAX_BINARY_LIMIT: usize = 1024 * 1024;
+
+/// Commands available without logging in.
+const PUBLIC_COMMANDS: &[&str] = &[
+    "password",
+    "ping",
+    "close",
+    "commands",
+    "notcommands",
+    "tagtypes",
+    "binarylimit",
+];
+
+/// Commands reported by `commands`.
+const COMMANDS: &[&str] = &[
+    "albumart",
+    "binarylimit",
+    "close",
+    "commands",
+    "count",
+    "currentsong",
+    "find",
+    "idle",
+    "list",
+    "listall",
+    "listallinfo",
+    "listplaylist",
+    "listplaylistinfo",
+    "listplaylists",
+    "lsinfo",
+    "noidle",
+    "notcommands",
+    "outputs",
+    "password",
+    "ping",
+    "playlistinfo",
+    "readpicture",
+    "search",
+    "stats",
+    "status",
+    "tagtypes",
+    "urlhandlers",
+];
+
+/// Listener-wide settings.
+#[derive(Debug)]
+pub struct Settings {
+    /// Base URL of stream URIs, without trailing slash.
+    pub base_url: String,
+    /// Whether commands are allowed before `password`.
+    pub anonymous: bool,
+    /// When the listener started, for `stats`.
+    pub started_at: Instant,
+}
+
+/// Stream URL of a song, used as its MPD URI.
+pub fn song_uri(base_url: &str, song: &SongMetadata) -> String {
+    format!(
+        "{}/api/music/stream/{}",
+        base_url,
+        playlist_file::encode_path_segment(&song.file)
+    )
+}
+
+/// Find a song by URI: its stream URL or its bare filename.
+fn find_song<'a>(index: &'a LibraryIndex, base_url: &str, uri: &str) -> Option<&'a SongMetadata> {
+    let file = uri
+        .strip_prefix(base_url)
+        .and_then(|rest| rest.strip_prefix("/api/music/stream/"))
+        .map(|encoded| percent_decode_str(encoded).decode_utf8_lossy().into_owned())
+        .unwrap_or_else(|| uri.to_string());
+    index.songs.iter().find(|s| s.file == file)
+}
+
+fn write_song(response: &mut Response, song: &SongMetadata, uri: &str) {
+    response.field("file", uri);
+    for tag in [
+        Tag::Artist,
+        Tag::AlbumArtist,
+        Tag::Title,
+        Tag::Album,
+        Tag::Genre,
+        Tag::Date,
+        Tag::Track,
+    ] {
+        for value in tag.values(song, uri) {
+            response.field(tag.name(), value);
+        }
+    }
+    if let Some(duration) = song.duration {
+        response.field("Time", duration);
+        response.field("duration", format!("{}.000", duration));
+    }
+}
+
+/// Options following a filter.
+#[derive(Debug, Default)]
+struct Options {
+    sort: Option<(Tag, bool)>,
+    window: Option<(usize, usize)>,
+    group: Vec<Tag>,
+}
+
+fn parse_options(mut args: &[String]) -> Result<Options, Ack> {
+    let mut options = Options::default();
+    while let [name, value, rest @ ..] = args {
+        match name.as_str() {
+            "sort" => {
+                let (descending, tag) = match value.strip_prefix('-') {
+                    Some(tag) => (true, tag),
+                    None => (false, value.as_str()),
+                };
+                let tag = Tag::parse(tag).ok_or_else(|| Ack::arg("Unknown sort tag"))?;
+                options.sort = Some((tag, descending));
+            }
+            "window" => {
+                let (start, end) = value
+                    .split_once(':')
+                    .ok_or_else(|| Ack::arg("Invalid window"))?;
+                let start = start.parse().map_err(|_| Ack::arg("Invalid window"))?;
+                let end = if end.is_empty() {
+                    usize::MAX
+                } else {
+                    end.parse().map_err(|_| Ack::arg("Invalid window"))?
+                };
+                options.window = Some((start, end));
+            }
+            "group" => {
+                options
+                    .group
+                    .push(Tag::parse(value).ok_or_else(|| Ack::arg("Unknown group tag"))?);
+            }
+            _ => return Err(Ack::arg(format!("Unexpected argument: {}", name))),
+        }
+        args = rest;
+    }
+    if !args.is_empty() {
+        return Err(Ack::arg("Too many arguments"));
+    }
+    Ok(options)
+}
+
+/// Read-only view of the library for one command.
+struct Catalog<'a> {
+    index: &'a LibraryIndex,
+    playlists: Vec<Playlist>,
+    base_url: &'a str,
+}
+
+impl Catalog<'_> {
+    fn uri(&self, song: &SongMetadata) -> String {
+        song_uri(self.base_url, song)
+    }
+
+    fn playlist(&self, name: &str) -> Result<&Playlist, Ack> {
+        self.playlists
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| Ack::no_exist("No such playlist"))
+    }
+
+    fn songs_by_file(&self) -> Vec<&SongMetadata> {
+        let mut songs: Vec<&SongMetadata> = self.index.songs.iter().collect();
+        songs.sort_by(|a, b| a.file.cmp(&b.file));
+        songs
+    }
+
+    fn write_playlists(&self, response: &mut Response) {
+        for playlist in &self.playlists {
+            response.field("playlist", &playlist.name);
+            response.field(
+                "Last-Modified",
+                playlist.updated_at.format("%Y-%m-%dT%H:%M:%SZ"),
+            );
+        }
+    }
+
+    /// `lsinfo [URI]`: the music folder is flat, so the root lists all
+    /// songs and the stored playlists.
+    fn lsinfo(&self, args: &[String]) -> Result<Response, Ack> {
+        let mut response = Response::new();
+        match args.first().map(String::as_str) {
+            None | Some("") | Some("/") => {
+                for song in self.songs_by_file() {
+                    write_song(&mut response, song, &self.uri(song));
+                }
+                self.write_playlists(&mut response);
+            }
+            Some(uri) => {
+                let song = find_song(self.index, self.base_url, uri)
+                    .ok_or_else(|| Ack::no_exist("No such directory"))?;
+                write_song(&mut response, song, &self.uri(song));
+            }
+        }
+        Ok(response)
+    }
+
+    /// `listall` / `listallinfo`.
+    fn listall(&self, info: bool) -> Response {
+        let mut response = Response::new();
+        for song in self.songs_by_file() {
+            let uri = self.uri(song);
+            if info {
+                write_song(&mut response, song, &uri);
+            } else {
+                response.field("file", uri);
+            }
+        }
+        response
+    }
+
+    /// Songs matching a filter, after `sort` and `window`.
+    fn filtered(&self, args: &[String], fold_case: bool) -> Result<Vec<&SongMetadata>, Ack> {
+        let (filter, rest) = Filter::parse(args, fold_case)?;
+        let options = parse_options(rest)?;
+
+        let mut songs: Vec<&SongMetadata> = self
+            .songs_by_file()
+            .into_iter()
+            .filter(|s| filter.matches(s, &self.uri(s)))
+            .collect();
+        if let Some((tag, descending)) = options.sort {
+            songs.sort_by_cached_key(|s| tag.values(s, &self.uri(s)).join(";").to_lowercase());
+            if descending {
+                songs.reverse();
+            }
+        }
+        if let Some((start, end)) = options.window {
+            songs = songs
+                .into_iter()
+                .skip(start)
+                .take(end.saturating_sub(start))
+                .collect();
+        }
+        Ok(songs)
+    }
+
+    /// `find` (exact) and `search` (case-insensitive substring).
+    fn find(&self, args: &[String], fold_case: bool) -> Result<Response, Ack> {
+        if args.is_empty() {
+            return Err(Ack::arg("Incorrect number of arguments"));
+        }
+        let mut response = Response::new();
+        for song in self.filtered(args, fold_case)? {
+            write_song(&mut response, song, &self.uri(song));
+        }
+        Ok(response)
+    }
+
+    /// `count FILTER`
+    fn count(&self, args: &[String]) -> Result<Response, Ack> {
+        let songs = self.filtered(args, false)?;
+        let mut response = Response::new();
+        response.field("songs", songs.len());
+        response.field(
+            "playtime",
+            songs
+                .iter()
+                .map(|s| u64::from(s.duration.unwrap_or(0)))
+                .sum::<u64>(),
+        );
+        Ok(response)
+    }
+
+    /// `list TYPE [FILTER] [group GROUPTYPE...]`
+    fn list(&self, args: &[String]) -> Result<Response, Ack> {
+        let (kind, filter_args) = args
+            .split_first()
+            .ok_or_else(|| Ack::arg("Incorrect number of arguments"))?;
+        let kind =
+            Tag::parse(kind).ok_or_else(|| Ack::arg(format!("Unknown tag type: {}", kind)))?;
+
+        // Legacy `list album ARTIST`
+        let (filter, rest) = match filter_args {
+            [artist] if kind == Tag::Album && !artist.starts_with('(') => {
+                let mut filter = Filter::default();
+                filter.require(Tag::Artist, artist.clone());
+                (filter, &filter_args[1..])
+            }
+            _ => Filter::parse(filter_args, false)?,
+        };
+        let options = parse_options(rest)?;
+
+        // Group values first, so output is ordered by group then value
+        let mut rows: BTreeSet<(Vec<String>, String)> = BTreeSet::new();
+        for song in &self.index.songs {
+            let uri = self.uri(song);
+            if !filter.matches(song, &uri) {
+                continue;
+            }
+            let groups: Vec<String> = options
+                .group
+                .iter()
+                .map(|tag| {
+                    tag.values(song, &uri)
+                        .into_iter()
+                        .next()
+                        .unwrap_or_default()
+                })
+                .collect();
+            for value in kind.values(song, &uri) {
+                rows.insert((groups.clone(), value));
+            }
+        }
+
+        let mut response = Response::new();
+        let mut previous: Option<Vec<String>> = None;
+        for (groups, value) in rows {
+            if previous.as_ref() != Some(&groups) {
+                for (tag, group) in options.group.iter().zip(&groups) {
+                    response.field(tag.name(), group);
+                }
+                previous = Some(groups);
+            }
+            response.field(kind.name(), value);
+        }
+        Ok(response)
+    }
+
+    /// `listplaylist NAME` / `listplaylistinfo NAME`
+    fn listplaylist(&self, args: &[String], info: bool) -> Result<Response, Ack> {
+        let name = args
+            .first()
+            .ok_or_else(|| Ack::arg("Incorrect number of arguments"))?;
+        let playlist = self.playlist(name)?;
+
+        let mut response = Response::new();
+        for song in playlist
+            .song_ids
+            .iter()
+            .filter_map(|id| self.index.song(id))
+        {
+            let uri = self.uri(song);
+            if info {
+                write_song(&mut response, song, &uri);
+            } else {
+                response.field("file", uri);
+            }
+        }
+        Ok(response)
+    }
+
+    /// `stats`
+    fn stats(&self, uptime: u64) -> Response {
+        let artists: BTreeSet<&str> = self
+            .index
+            .songs
+            .iter()
+            .map(|s| s.artist_id.as_str())
+            .collect();
+        let albums: BTreeSet<&str> = self
+            .index
+            .songs
+            .iter()
+            .map(|s| s.album_id.as_str())
+            .collect();
+
+        let mut response = Response::new();
+        response
+            .field("artists", artists.len())
+            .field("albums", albums.len())
+            .field("songs", self.index.songs.len())
+            .field("uptime", uptime)
+            .field("playtime", 0)
+            .field(
+                "db_playtime",
+                self.index
+                    .songs
+                    .iter()
+                    .map(|s| u64::from(s.duration.unwrap_or(0)))
+                    .sum::<u64>(),
+            );
+        response
+    }
+}
+
+/// State of one client connection.
+pub struct Session {
+    settings: Arc<Settings>,
+    /// Client address, for login throttling.
+    peer: IpAddr,
+    user: Option<AuthenticatedUser>,
+    binary_limit: usize,
+}
+
+impl Session {
+    pub fn new(settings: Arc<Settings>, peer: IpAddr) -> Self {
+        Self {
+            settings,
+            peer,
+            user: None,
+            binary_limit: DEFAULT_BINARY_LIMIT,
+        }
+    }
+
+    /// `password USERNAME:PASSWORD`
+    fn password(&mut self, state: &AppState, args: &[String]) -> Result<Response, Ack> {
+        let incorrect = || Ack::new(AckCode::Password, "incorrect password");
+        let (username, password) = args
+            .first()
+            .and_then(|credentials| credentials.split_once(':'))
+            .ok_or_else(incorrect)?;
+
+        let ip = self.peer.to_string();
+        state
+            .login_throttle
+            .check(&ip, username)
+            .map_err(|e| match e {
+                AppError::TooManyRequests { message, .. } => Ack::new(AckCode::Password, message),
+                _ => incorrect(),
+            })?;
+        let user = state
+            .user_repo
+            .find_by_username(username)
+            .map_err(|_| incorrect())?
+            .filter(|user| !user.disabled && !user.has_two_factor())
+            .filter(|user| verify_password(password, &user.password_hash).unwrap_or(false))
+            .ok_or_else(|| {
+                let failures = state.login_throttle.failed(&ip, username);
+                log_failed_login(&ip, username, failures);
+                incorrect()
+            })?;
+        state.login_throttle.passed(username);
+
+        self.user = Some(AuthenticatedUser::from_user(user));
+        Ok(Response::new())
+    }
+
+    /// `albumart URI OFFSET` / `readpicture URI OFFSET`
+    fn albumart(
+        &self,
+        state: &AppState,
+        index: &LibraryIndex,
+        args: &[String],
+    ) -> Result<Response, Ack> {
+        let [uri, offset] = args else {
+            return Err(Ack::arg("Incorrect number of arguments"));
+        };
+        let offset: usize = offset.parse().map_err(|_| Ack::arg("Invalid offset"))?;
+        let song = find_song(index, &self.settings.base_url, uri)
+            .filter(|s| s.has_cover)
+            .ok_or_else(|| Ack::no_exist("No file exists"))?;
+
+        let (mime, data) = resolve_music_file(&state.music_folder, &song.file)
+            .and_then(|path| read_cover(&path))
+            .map_err(|_| Ack::no_exist("No file exists"))?;
+        if offset > data.len() {
+            return Err(Ack::arg("Offset too large"));
+        }
+
+        let end = (offset + self.binary_limit).min(data.len());
+        let mut response = Response::new();
+        response.field("size", data.len()).field("type", mime);
+        response.binary(&data[offset..end]);
+        Ok(response)
+    }
+
+    /// Execute a command, returning its response body.
+    pub fn execute(&mut self, state: &AppState, args: &[String]) -> Result<Vec<u8>, Ack> {
+        let (command, args) = args
+            .split_first()
+            .ok_or_else(|| Ack::new(AckCode::Unknown, "No command given"))?;
+        let command = command.as_str();
+
+        if self.user.is_none() && !self.settings.anonymous && !PUBLIC_COMMANDS.contains(&command) {
+            return Err(Ack::new(
+                AckCode::Permission,
+                format!("you don't have permission for \"{}\"", command),
+            ));
+        }
+
+        let index = state
+            .library
+            .index()
+            .map_err(|e| Ack::new(AckCode::NoExist, e.to_string()))?;
+        let mut playlists = match &self.user {
+            Some(user) => state
+                .playlist_repo
+                .list_by_owner(user.id)
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
+        playlists.extend(index.playlists.iter().cloned());
+        let catalog = Catalog {
+            index: &index,
+            playlists,
+            base_url: &self.settings.base_url,
+        };
+
+        let response = match command {
+            "password" => self.password(state, args)?,
+            "ping" | "clearerror" => Response::new(),
+            "binarylimit" => {
+                let limit: usize = args
+                    .first()
+                    .and_then(|v| v.parse().ok())
+                    .filter(|v| *v >= 64)
+                    .ok_or_else(|| Ack::arg("Invalid binary limit"))?;
+                self.binary_limit = limit.min(MAX_BINARY_LIMIT);
+                Response::new()
+            }
+            "commands" => {
+                let mut response = Response::new();
+                for name in COMMANDS {
+                    response.field("command", name);
+                }
+                response
+            }
+            "notcommands" | "outputs" | "currentsong" | "playlistinfo" | "decoders" => {
+                Response::new()
+            }
+            "tagtypes" => {
+                let mut response = Response::new();
+                // `tagtypes clear/all/enable/disable` only affects what is sent
+                if args.is_empty() {
+                    for tag in TAG_TYPES {
+                        response.field("tagtype", tag.name());
+                    }
+                }
+                response
+            }
+            "urlhandlers" => {
+                let mut response = Response::new();
+                response
+                    .field("handler", "http://")
+                    .field("handler", "https://");
+                response
+            }
+            "status" => {
+                let mut response = Response::new();
+                response
+                    .field("volume", -1)
+                    .field("repeat", 0)
+                    .field("random", 0)
+                    .field("single", 0)
+                    .field("consume", 0)
+                    .field("playlist", 0)
+                    .field("playlistlength", 0)
+                    .field("state", "stop");
+                response
+            }
+            "stats" => catalog.stats(self.settings.started_at.elapsed().as_secs()),
+            "lsinfo" => catalog.lsinfo(args)?,
+            "listall" => catalog.listall(false),
+            "listallinfo" => catalog.listall(true),
+            "find" => catalog.find(args, false)?,
+            "search" => catalog.find(args, true)?,
+            "count" => catalog.count(args)?,
+            "list" => catalog.list(args)?,
+            "listplaylists" => {
+                let mut response = Response::new();
+                catalog.write_playlists(&mut response);
+                response
+            }
+            "listplaylist" => catalog.listplaylist(args, false)?,
+            "listplaylistinfo" => catalog.listplaylist(args, true)?,
+            "albumart" | "readpicture" => self.albumart(state, &index, args)?,
+            _ => {
+                return Err(Ack::new(
+                    AckCode::Unknown,
+                    format!("unknown command \"{}\"", command),
+                ))
+            }
+        };
+
+        Ok(response.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "http://music.local:8080";
+
+    fn song(file: &str, artist: &str, album: &str, title: &str) -> SongMetadata {
+        SongMetadata {
+            id: SongMetadata::generate_id(std::path::Path::new(file)),
+            title: title.to_string(),
+            artist: artist.to_string(),
+            artist_id: SongMetadata::generate_name_id("artist", artist),
+            album: album.to_string(),
+            album_id: SongMetadata::generate_name_id("album", album),
+            duration: Some(200),
+            track_number: None,
+            year: None,
+            genre: None,
+            format: "mp3".to_string(),
+            file: file.to_string(),
+            has_cover: false,
+            starred: None,
+            rating: None,
+            play_count: 0,
+            last_played: None,
+        }
+    }
+
+    fn index() -> LibraryIndex {
+        let mut index = LibraryIndex::default();
+        index.songs = vec![
+            song("b one.mp3", "Daft Punk", "Discovery", "One More Time"),
+            song("a aero.mp3", "Daft Punk", "Discovery", "Aerodynamic"),
+            song("c tear.mp3", "Massive Attack", "Mezzanine", "Teardrop"),
+        ];
+        index
+    }
+
+    fn run(catalog: &Catalog<'_>, line: &str) -> String {
+        let args = super::super::protocol::tokenize(line).unwrap();
+        let response = match args[0].as_str() {
+            "lsinfo" => catalog.lsinfo(&args[1..]),
+            "find" => catalog.find(&args[1..], false),
+            "search" => catalog.find(&args[1..], true),
+            "list" => catalog.list(&args[1..]),
+            _ => unreachable!(),
+        };
+        String::from_utf8(response.unwrap().into_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_uris_are_stream_urls() {
+        let index = index();
+        let uri = song_uri(BASE, &index.songs[0]);
+        assert_eq!(uri, "http://music.local:8080/api/music/stream/b%20one.mp3");
+        assert_eq!(
+            find_song(&index, BASE, &uri).unwrap().title,
+            "One More Time"
+        );
+        assert_eq!(
+            find_song(&index, BASE, "c tear.mp3").unwrap().title,
+            "Teardrop"
+        );
+
+        let catalog = Catalog {
+            index: &index,
+            playlists: Vec::new(),
+            base_url: BASE,
+        };
+        let root = run(&catalog, "lsinfo");
+        assert!(root.starts_with("file: http://music.local:8080/api/music/stream/a%20aero.mp3\n"));
+        assert!(run(&catalog, &format!("lsinfo \"{}\"", uri)).contains("Title: One More Time\n"));
+    }
+
+    #[test]
+    fn test_find_search_and_list() {
+        let index = index();
+        let catalog = Catalog {
+            index: &index,
+            playlists: Vec::new(),
+            base_url: BASE,
+        };
+
+        let found = run(&catalog, "find artist \"Daft Punk\" window 0:1");
+        assert_eq!(found.matches("file: ").count(), 1);
+        assert!(found.contains("Title: Aerodynamic\n"));
+        assert!(run(&catalog, "find artist \"daft punk\"").is_empty());
+        assert_eq!(
+            run(&catalog, "search title \"TEAR\"")
+                .matches("file: ")
+                .count(),
+            1
+        );
+
+        assert_eq!(
+            run(&catalog, "list album group artist"),
+            "Artist: Daft Punk\nAlbum: Discovery\nArtist: Massive Attack\nAlbum: Mezzanine\n"
+        );
+        assert_eq!(
+            run(&catalog, "list album \"Massive Attack\""),
+            "Album: Mezzanine\n"
+        );
+    }
+}
@@ -0,0 +1,376 @@
+//! Song filters for `find`, `search`, `list` and `count`.
+//!
+//! Both the legacy `TAG VALUE [TAG VALUE...]` form and filter expressions
+//! such as `((artist == 'Daft Punk') AND (album contains 'disc'))` are
+//! supported.
+
+use super::protocol::Ack;
+use crate::models::SongMetadata;
+
+/// A song tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tag {
+    Artist,
+    AlbumArtist,
+    Album,
+    Title,
+    Genre,
+    Date,
+    Track,
+    File,
+    Any,
+}
+
+/// Tags reported by `tagtypes`.
+pub const TAG_TYPES: &[Tag] = &[
+    Tag::Artist,
+    Tag::AlbumArtist,
+    Tag::Album,
+    Tag::Title,
+    Tag::Track,
+    Tag::Genre,
+    Tag::Date,
+];
+
+impl Tag {
+    /// Parse a tag name, case-insensitively.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "artist" => Some(Self::Artist),
+            "albumartist" => Some(Self::AlbumArtist),
+            "album" => Some(Self::Album),
+            "title" => Some(Self::Title),
+            "genre" => Some(Self::Genre),
+            "date" | "originaldate" => Some(Self::Date),
+            "track" => Some(Self::Track),
+            "file" => Some(Self::File),
+            "any" => Some(Self::Any),
+            _ => None,
+        }
+    }
+
+    /// Name used in responses.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Artist => "Artist",
+            Self::AlbumArtist => "AlbumArtist",
+            Self::Album => "Album",
+            Self::Title => "Title",
+            Self::Genre => "Genre",
+            Self::Date => "Date",
+            Self::Track => "Track",
+            Self::File => "file",
+            Self::Any => "any",
+        }
+    }
+
+    /// Values of this tag for a song whose URI is `uri`.
+    pub fn values(self, song: &SongMetadata, uri: &str) -> Vec<String> {
+        match self {
+            // Album artists are not tracked separately
+            Self::Artist | Self::AlbumArtist => vec![song.artist.clone()],
+            Self::Album => vec![song.album.clone()],
+            Self::Title => vec![song.title.clone()],
+            Self::Genre => song.genre.iter().cloned().collect(),
+            Self::Date => song.year.iter().map(|y| y.to_string()).collect(),
+            Self::Track => song.track_number.iter().map(|t| t.to_string()).collect(),
+            Self::File => vec![uri.to_string()],
+            Self::Any => [
+                Self::Artist,
+                Self::Album,
+                Self::Title,
+                Self::Genre,
+                Self::File,
+            ]
+            .iter()
+            .flat_map(|tag| tag.values(song, uri))
+            .collect(),
+        }
+    }
+}
+
+/// A comparison operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equals,
+    NotEquals,
+    Contains,
+    StartsWith,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Condition {
+    tag: Tag,
+    op: Op,
+    value: String,
+}
+
+/// A conjunction of conditions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    conditions: Vec<Condition>,
+    /// Compare case-insensitively (`search`) rather than exactly (`find`).
+    fold_case: bool,
+}
+
+impl Filter {
+    /// Parse filter arguments, returning the filter and the remaining
+    /// arguments (`sort`, `window`, `group` ...).
+    ///
+    /// In legacy pairs, `fold_case` also turns equality into substring
+    /// matching, as `search` does.
+    pub fn parse(args: &[String], fold_case: bool) -> Result<(Self, &[String]), Ack> {
+        let mut filter = Filter {
+            conditions: Vec::new(),
+            fold_case,
+        };
+        let mut rest = args;
+
+        while let Some(first) = rest.first() {
+            if first.starts_with('(') {
+                let mut parser = ExpressionParser {
+                    chars: first.chars().collect(),
+                    pos: 0,
+                };
+                parser.parse(&mut filter.conditions)?;
+                rest = &rest[1..];
+            } else if is_option(first) {
+                break;
+            } else {
+                let tag = Tag::parse(first)
+                    .ok_or_else(|| Ack::arg(format!("Unknown tag type: {}", first)))?;
+                let value = rest
+                    .get(1)
+                    .ok_or_else(|| Ack::arg("Incorrect number of filter arguments"))?;
+                filter.conditions.push(Condition {
+                    tag,
+                    op: if fold_case { Op::Contains } else { Op::Equals },
+                    value: value.clone(),
+                });
+                rest = &rest[2..];
+            }
+        }
+
+        Ok((filter, rest))
+    }
+
+    /// Add an exact match on a tag.
+    pub fn require(&mut self, tag: Tag, value: String) {
+        self.conditions.push(Condition {
+            tag,
+            op: Op::Equals,
+            value,
+        });
+    }
+
+    /// Whether a song matches all conditions.
+    pub fn matches(&self, song: &SongMetadata, uri: &str) -> bool {
+        let normalize = |s: &str| {
+            if self.fold_case {
+                s.to_lowercase()
+            } else {
+                s.to_string()
+            }
+        };
+
+        self.conditions.iter().all(|condition| {
+            let expected = normalize(&condition.value);
+            let values: Vec<String> = condition
+                .tag
+                .values(song, uri)
+                .iter()
+                .map(|v| normalize(v))
+                .collect();
+            let equals = if values.is_empty() {
+                expected.is_empty()
+            } else {
+                values.contains(&expected)
+            };
+            match condition.op {
+                Op::Equals => equals,
+                Op::NotEquals => !equals,
+                Op::Contains => values.iter().any(|v| v.contains(&expected)),
+                Op::StartsWith => values.iter().any(|v| v.starts_with(&expected)),
+            }
+        })
+    }
+}
+
+/// Whether an argument starts the options following a filter.
+pub fn is_option(arg: &str) -> bool {
+    matches!(arg, "sort" | "window" | "group" | "position")
+}
+
+struct ExpressionParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl ExpressionParser {
+    fn error(&self) -> Ack {
+        Ack::arg("Malformed filter expression")
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), Ack> {
+        self.skip_whitespace();
+        if self.chars.get(self.pos) == Some(&expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    fn word(&mut self) -> String {
+        self.skip_whitespace();
+        let start = self.pos;
+        while self.chars.get(self.pos).is_some_and(|c| {
+            !c.is_whitespace() && *c != '(' && *c != ')' && *c != '\'' && *c != '"'
+        }) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn quoted(&mut self) -> Result<String, Ack> {
+        self.skip_whitespace();
+        let quote = match self.chars.get(self.pos) {
+            Some(q @ ('\'' | '"')) => *q,
+            _ => return Err(self.error()),
+        };
+        self.pos += 1;
+
+        let mut value = String::new();
+        loop {
+            match self.chars.get(self.pos) {
+                Some(c) if *c == quote => {
+                    self.pos += 1;
+                    return Ok(value);
+                }
+                Some('\\') => {
+                    let escaped = self.chars.get(self.pos + 1).ok_or_else(|| self.error())?;
+                    value.push(*escaped);
+                    self.pos += 2;
+                }
+                Some(c) => {
+                    value.push(*c);
+                    self.pos += 1;
+                }
+                None => return Err(self.error()),
+            }
+        }
+    }
+
+    /// Parse one parenthesized expression: a comparison or an `AND` group.
+    fn parse(&mut self, conditions: &mut Vec<Condition>) -> Result<(), Ack> {
+        self.expect('(')?;
+        self.skip_whitespace();
+
+        if self.chars.get(self.pos) == Some(&'(') {
+            self.parse(conditions)?;
+            loop {
+                self.skip_whitespace();
+                if self.chars.get(self.pos) == Some(&')') {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                if self.word() != "AND" {
+                    return Err(self.error());
+                }
+                self.parse(conditions)?;
+            }
+        }
+
+        let tag_name = self.word();
+        let tag = Tag::parse(&tag_name)
+            .ok_or_else(|| Ack::arg(format!("Unknown tag type: {}", tag_name)))?;
+        let op = match self.word().as_str() {
+            "==" => Op::Equals,
+            "!=" => Op::NotEquals,
+            "contains" => Op::Contains,
+            "starts_with" => Op::StartsWith,
+            other => return Err(Ack::arg(format!("Unsupported filter operator: {}", other))),
+        };
+        let value = self.quoted()?;
+        self.expect(')')?;
+
+        conditions.push(Condition { tag, op, value });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song() -> SongMetadata {
+        SongMetadata {
+            id: "1".to_string(),
+            title: "One More Time".to_string(),
+            artist: "Daft Punk".to_string(),
+            artist_id: "a".to_string(),
+            album: "Discovery".to_string(),
+            album_id: "b".to_string(),
+            duration: Some(320),
+            track_number: Some(1),
+            year: Some(2001),
+            genre: None,
+            format: "mp3".to_string(),
+            file: "one.mp3".to_string(),
+            has_cover: false,
+            starred: None,
+            rating: None,
+            play_count: 0,
+            last_played: None,
+        }
+    }
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_legacy_filters() {
+        let song = song();
+        let find = args(&["artist", "Daft Punk", "date", "2001", "window", "0:10"]);
+        let (filter, rest) = Filter::parse(&find, false).unwrap();
+        assert!(filter.matches(&song, "u"));
+        assert_eq!(rest, &args(&["window", "0:10"])[..]);
+
+        let (filter, _) = Filter::parse(&args(&["artist", "daft"]), false).unwrap();
+        assert!(!filter.matches(&song, "u"));
+        let (filter, _) = Filter::parse(&args(&["any", "DISCO"]), true).unwrap();
+        assert!(filter.matches(&song, "u"));
+        let (filter, _) = Filter::parse(&args(&["genre", ""]), false).unwrap();
+        assert!(filter.matches(&song, "u"));
+
+        assert!(Filter::parse(&args(&["artist"]), false).is_err());
+        assert!(Filter::parse(&args(&["mood", "happy"]), false).is_err());
+    }
+
+    #[test]
+    fn test_filter_expressions() {
+        let song = song();
+        let matches = |expression: &str| {
+            let (filter, _) = Filter::parse(&args(&[expression]), false).unwrap();
+            filter.matches(&song, "u")
+        };
+
+        assert!(matches("(artist == 'Daft Punk')"));
+        assert!(matches(
+            "((artist == \"Daft Punk\") AND (album contains 'cover'))"
+        ));
+        assert!(!matches(
+            "((artist == 'Daft Punk') AND (title != 'One More Time'))"
+        ));
+        assert!(matches("(title starts_with 'One')"));
+        assert!(Filter::parse(&args(&["(artist == 'x'"]), false).is_err());
+        assert!(Filter::parse(&args(&["(artist =~ 'x')"]), false).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+//! Read-only MPD protocol frontend.
+//!
+//! Lets MPD clients browse and search the library over TCP. There is no
+//! player: song URIs are ferrum stream URLs, which clients such as
+//! ncmpcpp or MPDroid hand to a player (or an MPD instance) of their own.
+//! Clients log in with `password USERNAME:PASSWORD` unless anonymous
+//! access is enabled. Accounts with two-factor authentication cannot log
+//! in this way.
+
+mod commands;
+pub mod filter;
+pub mod protocol;
+
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::Config;
+use crate::models::AppState;
+use commands::{Session, Settings};
+use protocol::{Ack, PROTOCOL_VERSION};
+
+/// Longest accepted command line.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// An MPD listener.
+pub struct MpdServer {
+    listener: TcpListener,
+    settings: Arc<Settings>,
+    state: AppState,
+}
+
+impl MpdServer {
+    /// Bind the listener on `MPD_BIND`.
+    pub async fn bind(config: &Config, state: AppState) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(&config.mpd_bind).await?;
+        Ok(Self {
+            listener,
+            settings: Arc::new(Settings {
+                base_url: config.mpd_base_url.clone(),
+                anonymous: config.mpd_anonymous,
+                started_at: Instant::now(),
+            }),
+            state,
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accept connections in the background.
+    pub fn spawn(self) {
+        tracing::info!(address = ?self.listener.local_addr().ok(), "Started MPD listener");
+        tokio::spawn(async move {
+            loop {
+                match self.listener.accept().await {
+                    Ok((stream, peer)) => {
+                        let session = Session::new(self.settings.clone(), peer.ip());
+                        let state = self.state.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = serve(stream, session, state).await {
+                                tracing::debug!(peer = %peer, error = %e, "MPD connection closed");
+                            }
+                        });
+                    }
+                    Err(e) => tracing::warn!(error = %e, "Failed to accept MPD connection"),
+                }
+            }
+        });
+    }
+}
+
+/// Pending `command_list_begin` / `command_list_ok_begin`.
+struct CommandList {
+    commands: Vec<String>,
+    list_ok: bool,
+}
+
+async fn write_ack<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    ack: &Ack,
+    index: usize,
+    command: &str,
+) -> std::io::Result<()> {
+    writer
+        .write_all(ack.render(index, command).as_bytes())
+        .await
+}
+
+/// Serve one client until it disconnects or sends `close`.
+async fn serve(stream: TcpStream, mut session: Session, state: AppState) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer
+        .write_all(format!("OK MPD {}\n", PROTOCOL_VERSION).as_bytes())
+        .await?;
+
+    let mut list: Option<CommandList> = None;
+    while let Some(line) = lines.next_line().await? {
+        if line.len() > MAX_LINE_LENGTH {
+            return Ok(());
+        }
+
+        if let Some(pending) = &mut list {
+            if line.trim() != "command_list_end" {
+                pending.commands.push(line);
+                continue;
+            }
+            let CommandList { commands, list_ok } = list.take().unwrap_or_else(|| unreachable!());
+            let mut output = Vec::new();
+            let mut failed = None;
+            for (index, command) in commands.iter().enumerate() {
+                match run(&mut session, &state, command) {
+                    Ok(Some(body)) => {
+                        output.extend_from_slice(&body);
+                        if list_ok {
+                            output.extend_from_slice(b"list_OK\n");
+                        }
+                    }
+                    Ok(None) => return Ok(()),
+                    Err((ack, name)) => {
+                        failed = Some(ack.render(index, &name));
+                        break;
+                    }
+                }
+            }
+            writer.write_all(&output).await?;
+            match failed {
+                Some(ack) => writer.write_all(ack.as_bytes()).await?,
+                None => writer.write_all(b"OK\n").await?,
+            }
+            continue;
+        }
+
+        match line.trim() {
+            "command_list_begin" | "command_list_ok_begin" => {
+                list = Some(CommandList {
+                    commands: Vec::new(),
+                    list_ok: line.trim() == "command_list_ok_begin",
+                });
+            }
+            // The library never changes under a client's feet in a way
+            // idle could report, so idle just waits for noidle.
+            trimmed if trimmed == "idle" || trimmed.starts_with("idle ") => {
+                loop {
+                    match lines.next_line().await? {
+                        Some(next) if next.trim() == "noidle" => break,
+                        Some(_) => continue,
+                        None => return Ok(()),
+                    }
+                }
+                writer.write_all(b"OK\n").await?;
+            }
+            "noidle" => {}
+            _ => match run(&mut session, &state, &line) {
+                Ok(Some(body)) => {
+                    writer.write_all(&body).await?;
+                    writer.write_all(b"OK\n").await?;
+                }
+                Ok(None) => return Ok(()),
+                Err((ack, name)) => write_ack(&mut writer, &ack, 0, &name).await?,
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Run one command line. `Ok(None)` means the client asked to close.
+fn run(
+    session: &mut Session,
+    state: &AppState,
+    line: &str,
+) -> Result<Option<Vec<u8>>, (Ack, String)> {
+    let args = protocol::tokenize(line).map_err(|ack| (ack, String::new()))?;
+    let name = args.first().cloned().unwrap_or_default();
+    if name == "close" {
+        return Ok(None);
+    }
+    session
+        .execute(state, &args)
+        .map(Some)
+        .map_err(|ack| (ack, name))
+}
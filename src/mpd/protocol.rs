@@ -0,0 +1,140 @@
+//! MPD wire format: argument tokenizing, responses and errors.
+
+/// Protocol version announced in the greeting.
+pub const PROTOCOL_VERSION: &str = "0.23.5";
+
+/// MPD error codes used in `ACK` responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckCode {
+    Arg = 2,
+    Password = 3,
+    Permission = 4,
+    Unknown = 5,
+    NoExist = 50,
+}
+
+/// A failed command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ack {
+    pub code: AckCode,
+    pub message: String,
+}
+
+impl Ack {
+    pub fn new(code: AckCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Invalid or missing arguments.
+    pub fn arg(message: impl Into<String>) -> Self {
+        Self::new(AckCode::Arg, message)
+    }
+
+    /// The requested object does not exist.
+    pub fn no_exist(message: impl Into<String>) -> Self {
+        Self::new(AckCode::NoExist, message)
+    }
+
+    /// Render as an `ACK` line for the `index`-th command of a list.
+    pub fn render(&self, index: usize, command: &str) -> String {
+        format!(
+            "ACK [{}@{}] {{{}}} {}\n",
+            self.code as u32, index, command, self.message
+        )
+    }
+}
+
+/// Split a command line into arguments.
+///
+/// Arguments are separated by whitespace; double-quoted arguments may
+/// contain spaces and backslash-escaped quotes and backslashes.
+pub fn tokenize(line: &str) -> Result<Vec<String>, Ack> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Ok(args);
+        };
+
+        let mut arg = String::new();
+        if first == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c) => arg.push(c),
+                        None => return Err(Ack::arg("Unterminated string")),
+                    },
+                    Some(c) => arg.push(c),
+                    None => return Err(Ack::arg("Unterminated string")),
+                }
+            }
+            if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                return Err(Ack::arg("Space expected after closing '\"'"));
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        args.push(arg);
+    }
+}
+
+/// Response body of a successful command, without the final `OK`.
+#[derive(Debug, Default)]
+pub struct Response(Vec<u8>);
+
+impl Response {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a `name: value` line. Newlines in values would break framing
+    /// and are replaced.
+    pub fn field(&mut self, name: &str, value: impl std::fmt::Display) -> &mut Self {
+        let value = value.to_string().replace('\n', " ");
+        self.0
+            .extend_from_slice(format!("{}: {}\n", name, value).as_bytes());
+        self
+    }
+
+    /// Append a binary chunk (`binary: N`, the bytes, then a newline).
+    pub fn binary(&mut self, data: &[u8]) -> &mut Self {
+        self.field("binary", data.len());
+        self.0.extend_from_slice(data);
+        self.0.push(b'\n');
+        self
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_quoted_arguments() {
+        assert_eq!(
+            tokenize(r#"find artist "Daft \"Punk\"" album Discovery"#).unwrap(),
+            vec!["find", "artist", "Daft \"Punk\"", "album", "Discovery"]
+        );
+        assert_eq!(tokenize("  ping  ").unwrap(), vec!["ping"]);
+        assert_eq!(tokenize(r#"lsinfo """#).unwrap(), vec!["lsinfo", ""]);
+        assert!(tokenize(r#"find "artist"#).is_err());
+
+        assert_eq!(
+            Ack::no_exist("No such song").render(1, "lsinfo"),
+            "ACK [50@1] {lsinfo} No such song\n"
+        );
+    }
+}
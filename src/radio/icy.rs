@@ -0,0 +1,118 @@
+//! ICY (SHOUTcast/Icecast) in-band metadata.
+//!
+//! Clients that send `Icy-MetaData: 1` receive a metadata block after every
+//! `icy-metaint` bytes of audio: one length byte (in 16-byte units)
+//! followed by `StreamTitle='...';` padded with zeros.
+
+/// Audio bytes between metadata blocks.
+pub const METAINT: usize = 16000;
+
+/// Longest metadata block payload (the length byte counts 16-byte units).
+const MAX_METADATA_LEN: usize = 255 * 16;
+
+/// Encode a metadata block for a title.
+pub fn metadata_block(title: &str) -> Vec<u8> {
+    // Quotes and control characters would end the field early
+    let title: String = title
+        .chars()
+        .map(|c| if c == '\'' { '\u{2019}' } else { c })
+        .filter(|c| !c.is_control())
+        .collect();
+    let mut text = format!("StreamTitle='{}';", title);
+    while text.len() > MAX_METADATA_LEN {
+        let mut cut = MAX_METADATA_LEN - 2;
+        while !text.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        text.truncate(cut);
+        text.push_str("';");
+    }
+
+    let units = text.len().div_ceil(16);
+    let mut block = Vec::with_capacity(1 + units * 16);
+    block.push(units as u8);
+    block.extend_from_slice(text.as_bytes());
+    block.resize(1 + units * 16, 0);
+    block
+}
+
+/// Interleaves metadata blocks into one listener's audio stream.
+#[derive(Debug)]
+pub struct IcyEncoder {
+    /// Audio bytes left before the next metadata block.
+    remaining: usize,
+    /// Title sent in the last block; unchanged titles are sent as empty
+    /// blocks.
+    last_title: Option<String>,
+}
+
+impl Default for IcyEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IcyEncoder {
+    pub fn new() -> Self {
+        Self {
+            remaining: METAINT,
+            last_title: None,
+        }
+    }
+
+    /// Encode a chunk of audio, inserting metadata for `title` where due.
+    pub fn encode(&mut self, mut audio: &[u8], title: &str) -> Vec<u8> {
+        let mut out = Vec::with_capacity(audio.len() + 64);
+        while audio.len() >= self.remaining {
+            let (head, tail) = audio.split_at(self.remaining);
+            out.extend_from_slice(head);
+            if self.last_title.as_deref() == Some(title) {
+                out.push(0);
+            } else {
+                out.extend_from_slice(&metadata_block(title));
+                self.last_title = Some(title.to_string());
+            }
+            audio = tail;
+            self.remaining = METAINT;
+        }
+        out.extend_from_slice(audio);
+        self.remaining -= audio.len();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_block() {
+        let block = metadata_block("Daft Punk - One More Time");
+        assert_eq!(block.len(), 1 + usize::from(block[0]) * 16);
+        assert!(block[1..].starts_with(b"StreamTitle='Daft Punk - One More Time';"));
+        assert_eq!(*block.last().unwrap(), 0);
+
+        let long = metadata_block(&"x".repeat(10_000));
+        assert_eq!(long[0], 255);
+        assert!(std::str::from_utf8(&long[1..]).unwrap().ends_with("';"));
+        assert!(metadata_block("Don't Stop")[1..]
+            .starts_with("StreamTitle='Don\u{2019}t Stop';".as_bytes()));
+    }
+
+    #[test]
+    fn test_encoder_interleaves_metadata() {
+        let mut encoder = IcyEncoder::new();
+        let mut out = encoder.encode(&vec![1u8; METAINT - 10], "A");
+        assert_eq!(out.len(), METAINT - 10);
+
+        out.extend(encoder.encode(&[1u8; 20], "A"));
+        let block = metadata_block("A");
+        assert_eq!(&out[METAINT..METAINT + block.len()], &block[..]);
+        assert_eq!(out.len(), METAINT + block.len() + 10);
+
+        // Unchanged title: empty block
+        let out = encoder.encode(&vec![1u8; METAINT], "A");
+        assert_eq!(out.len(), METAINT + 1);
+        assert_eq!(out[METAINT - 10], 0);
+    }
+}
@@ -0,0 +1,472 @@
+//! Internet-radio style continuous streams.
+//!
+//! A station plays a playlist or a genre mix in a loop, transcoding each
+//! track with ffmpeg into one MP3 or Ogg/Vorbis stream. ffmpeg paces output
+//! in real time, and every listener of a station receives the same
+//! broadcast, so all listeners hear the same position. Stations start with
+//! their first listener and stop once the last one disconnects.
+//!
+//! MP3 frames are self-contained, so the outputs of consecutive tracks
+//! simply concatenate. Ogg streams chain one logical stream per track, like
+//! Icecast does; new listeners get the header pages of the current track
+//! and then whole pages, since a Vorbis decoder cannot start without them.
+
+pub mod icy;
+pub mod ogg;
+
+use actix_web::web::Bytes;
+use parking_lot::{Mutex, RwLock};
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio::sync::broadcast;
+
+use crate::api::music::resolve_music_file;
+use crate::error::{AppError, AppResult};
+use crate::models::{AppState, SongMetadata};
+use crate::userdata::PlaylistRepository;
+
+/// Chunks buffered per listener before it starts skipping.
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Recent audio sent to new listeners so players start without waiting.
+const BURST_BYTES: usize = 64 * 1024;
+
+/// Audio format of a station.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Mp3,
+    Ogg,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Mp3 => "audio/mpeg",
+            Self::Ogg => "audio/ogg",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Mp3 => "mp3",
+            Self::Ogg => "ogg",
+        }
+    }
+
+    /// ffmpeg encoder and muxer options.
+    fn encoder_args(self) -> &'static [&'static str] {
+        match self {
+            Self::Mp3 => &[
+                "-codec:a",
+                "libmp3lame",
+                "-f",
+                "mp3",
+                "-id3v2_version",
+                "0",
+                "-write_xing",
+                "0",
+            ],
+            Self::Ogg => &["-codec:a", "libvorbis", "-f", "ogg"],
+        }
+    }
+}
+
+/// What a station plays.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A user or library playlist, in order.
+    Playlist(String),
+    /// All songs whose genre contains the given text, shuffled on every
+    /// pass.
+    Genre(String),
+}
+
+impl Source {
+    fn key(&self) -> String {
+        match self {
+            Self::Playlist(id) => format!("playlist:{}", id),
+            Self::Genre(genre) => format!("genre:{}", genre.to_lowercase()),
+        }
+    }
+
+    /// Tracks of one pass through the source.
+    pub fn tracks(&self, state: &AppState) -> AppResult<Vec<SongMetadata>> {
+        let index = state.library.index()?;
+        match self {
+            Self::Playlist(id) => {
+                let playlist = match state.playlist_repo.find_by_id(id)? {
+                    Some(playlist) => playlist,
+                    None => index
+                        .playlist(id)
+                        .cloned()
+                        .ok_or_else(|| AppError::NotFound(format!("Playlist not found: {}", id)))?,
+                };
+                Ok(playlist
+                    .song_ids
+                    .iter()
+                    .filter_map(|id| index.song(id).cloned())
+                    .collect())
+            }
+            Self::Genre(genre) => {
+                let genre = genre.to_lowercase();
+                let mut songs: Vec<SongMetadata> = index
+                    .songs
+                    .iter()
+                    .filter(|s| {
+                        s.genre
+                            .as_ref()
+                            .is_some_and(|g| g.to_lowercase().contains(&genre))
+                    })
+                    .cloned()
+                    .collect();
+                songs.shuffle(&mut rand::thread_rng());
+                Ok(songs)
+            }
+        }
+    }
+}
+
+/// Broadcast side of a station.
+struct Feed {
+    /// `None` once the station has stopped.
+    sender: Option<broadcast::Sender<Bytes>>,
+    burst: VecDeque<Bytes>,
+    burst_len: usize,
+    /// Ogg header pages of the current track.
+    headers: Vec<Bytes>,
+}
+
+/// A running station.
+pub struct Station {
+    format: Format,
+    title: RwLock<String>,
+    feed: Mutex<Feed>,
+}
+
+impl Station {
+    fn new(format: Format) -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self {
+            format,
+            title: RwLock::new(String::new()),
+            feed: Mutex::new(Feed {
+                sender: Some(sender),
+                burst: VecDeque::new(),
+                burst_len: 0,
+                headers: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Title of the track currently playing, as `Artist - Title`.
+    pub fn title(&self) -> String {
+        self.title.read().clone()
+    }
+
+    /// Send a chunk to all listeners, returning how many there are.
+    fn broadcast(&self, chunk: Bytes) -> usize {
+        let mut feed = self.feed.lock();
+        feed.burst_len += chunk.len();
+        feed.burst.push_back(chunk.clone());
+        while feed.burst_len > BURST_BYTES {
+            if let Some(old) = feed.burst.pop_front() {
+                feed.burst_len -= old.len();
+            }
+        }
+        feed.sender
+            .as_ref()
+            .map(|sender| sender.send(chunk).unwrap_or(0))
+            .unwrap_or(0)
+    }
+
+    /// Send an Ogg page to all listeners, returning how many there are.
+    /// Header pages are kept for listeners joining during the track.
+    fn broadcast_page(&self, page: ogg::Page) -> usize {
+        let mut feed = self.feed.lock();
+        if page.begins_stream {
+            feed.headers.clear();
+        }
+        if page.is_header {
+            feed.headers.push(page.data.clone());
+        }
+        feed.sender
+            .as_ref()
+            .map(|sender| sender.send(page.data).unwrap_or(0))
+            .unwrap_or(0)
+    }
+
+    /// What a new listener receives before live chunks.
+    fn catch_up(feed: &Feed, format: Format) -> Vec<Bytes> {
+        match format {
+            Format::Mp3 => feed.burst.iter().cloned().collect(),
+            Format::Ogg => feed.headers.clone(),
+        }
+    }
+}
+
+/// A listener's view of a station.
+pub struct Listener {
+    pub station: Arc<Station>,
+    /// Recent audio, or the Ogg headers, to send before live chunks.
+    pub burst: Vec<Bytes>,
+    pub receiver: broadcast::Receiver<Bytes>,
+}
+
+/// All stations.
+pub struct Radio {
+    ffmpeg: String,
+    bitrate: u32,
+    stations: Mutex<HashMap<String, Arc<Station>>>,
+}
+
+impl Radio {
+    pub fn new(ffmpeg: impl Into<String>, bitrate: u32) -> Self {
+        Self {
+            ffmpeg: ffmpeg.into(),
+            bitrate,
+            stations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn bitrate(&self) -> u32 {
+        self.bitrate
+    }
+
+    /// Join the station for a source, starting it if needed.
+    pub fn tune_in(
+        self: &Arc<Self>,
+        state: &AppState,
+        source: Source,
+        format: Format,
+    ) -> AppResult<Listener> {
+        let key = format!("{}:{}", source.key(), format.name());
+        let mut stations = self.stations.lock();
+
+        if let Some(station) = stations.get(&key) {
+            let feed = station.feed.lock();
+            if let Some(sender) = &feed.sender {
+                return Ok(Listener {
+                    station: station.clone(),
+                    burst: Station::catch_up(&feed, format),
+                    receiver: sender.subscribe(),
+                });
+            }
+        }
+
+        if source.tracks(state)?.is_empty() {
+            return Err(AppError::NotFound("Nothing to play".to_string()));
+        }
+
+        let station = Arc::new(Station::new(format));
+        let receiver = station
+            .feed
+            .lock()
+            .sender
+            .as_ref()
+            .map(|sender| sender.subscribe())
+            .ok_or_else(|| AppError::Internal("Station closed".to_string()))?;
+        stations.insert(key.clone(), station.clone());
+        tracing::info!(station = %key, "Starting radio station");
+        tokio::spawn(
+            self.clone()
+                .run(key, station.clone(), source, state.clone()),
+        );
+
+        Ok(Listener {
+            station,
+            burst: Vec::new(),
+            receiver,
+        })
+    }
+
+    /// Stop a station, or with `only_if_idle`, only if nobody is listening.
+    /// Checked under the stations lock so that a concurrent `tune_in` either
+    /// joins before the check or starts a new station.
+    fn stop(&self, key: &str, station: &Arc<Station>, only_if_idle: bool) -> bool {
+        let mut stations = self.stations.lock();
+        let mut feed = station.feed.lock();
+        let listeners = feed.sender.as_ref().map_or(0, |s| s.receiver_count());
+        if listeners > 0 && only_if_idle {
+            return false;
+        }
+
+        feed.sender = None;
+        if stations.get(key).is_some_and(|s| Arc::ptr_eq(s, station)) {
+            stations.remove(key);
+        }
+        tracing::info!(station = %key, "Stopped radio station");
+        true
+    }
+
+    async fn run(
+        self: Arc<Self>,
+        key: String,
+        station: Arc<Station>,
+        source: Source,
+        state: AppState,
+    ) {
+        loop {
+            let tracks = match source.tracks(&state) {
+                Ok(tracks) => tracks,
+                Err(e) => {
+                    tracing::warn!(station = %key, error = %e, "Radio source unavailable");
+                    Vec::new()
+                }
+            };
+
+            let mut played = false;
+            for song in &tracks {
+                *station.title.write() = format!("{} - {}", song.artist, song.title);
+                match self.play(&key, &station, &state, song).await {
+                    Ok(Some(bytes)) => played |= bytes > 0,
+                    Ok(None) => return,
+                    Err(e) => {
+                        tracing::warn!(station = %key, file = %song.file, error = %e, "Failed to transcode track");
+                    }
+                }
+            }
+
+            // Nothing playable: stop rather than spin
+            if !played {
+                self.stop(&key, &station, false);
+                return;
+            }
+        }
+    }
+
+    /// Transcode and broadcast one track. Returns the bytes sent, or `None`
+    /// if the station stopped for lack of listeners.
+    async fn play(
+        &self,
+        key: &str,
+        station: &Arc<Station>,
+        state: &AppState,
+        song: &SongMetadata,
+    ) -> std::io::Result<Option<usize>> {
+        let path = resolve_music_file(&state.music_folder, &song.file)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::NotFound, e.to_string()))?;
+
+        // Same sample rate and channels for every track, and for MP3 no
+        // per-track headers, so the outputs concatenate into one valid
+        // stream. Ogg links carry the track title in their comments.
+        let format = station.format();
+        let mut command = Command::new(&self.ffmpeg);
+        command
+            .args(["-hide_banner", "-loglevel", "error", "-re", "-i"])
+            .arg(&path)
+            .args(["-vn", "-map_metadata", "-1"]);
+        if format == Format::Ogg {
+            command
+                .arg("-metadata")
+                .arg(format!("title={}", song.title))
+                .arg("-metadata")
+                .arg(format!("artist={}", song.artist));
+        }
+        let mut child = command
+            .args(format.encoder_args())
+            .args([
+                "-b:a",
+                &format!("{}k", self.bitrate),
+                "-ar",
+                "44100",
+                "-ac",
+                "2",
+                "-",
+            ])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let mut stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| std::io::Error::other("ffmpeg stdout unavailable"))?;
+
+        let mut sent = 0;
+        let mut buffer = vec![0u8; 8192];
+        let mut pages = ogg::PageReader::new();
+        loop {
+            let read = stdout.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            let listeners = match format {
+                Format::Mp3 => station.broadcast(Bytes::copy_from_slice(&buffer[..read])),
+                Format::Ogg => {
+                    let mut listeners = None;
+                    for page in pages.push(&buffer[..read]) {
+                        listeners = Some(station.broadcast_page(page));
+                    }
+                    // Nothing sent until a page is complete
+                    listeners.unwrap_or(1)
+                }
+            };
+            if listeners == 0 && self.stop(key, station, true) {
+                return Ok(None);
+            }
+            sent += read;
+        }
+        child.wait().await?;
+        Ok(Some(sent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_station_broadcast_and_burst() {
+        let station = Station::new(Format::Mp3);
+        let receiver = station.feed.lock().sender.as_ref().unwrap().subscribe();
+
+        for _ in 0..20 {
+            assert_eq!(station.broadcast(Bytes::from(vec![0u8; 8192])), 1);
+        }
+        let feed = station.feed.lock();
+        assert_eq!(feed.burst_len, BURST_BYTES);
+        assert_eq!(receiver.len(), 20);
+    }
+
+    #[test]
+    fn test_ogg_listeners_get_current_headers() {
+        let station = Station::new(Format::Ogg);
+        let _receiver = station.feed.lock().sender.as_ref().unwrap().subscribe();
+        let page = |data: &'static [u8], begins_stream, is_header| ogg::Page {
+            data: Bytes::from_static(data),
+            begins_stream,
+            is_header,
+        };
+
+        station.broadcast_page(page(b"ident 1", true, true));
+        station.broadcast_page(page(b"setup 1", false, true));
+        station.broadcast_page(page(b"audio 1", false, false));
+        station.broadcast_page(page(b"ident 2", true, true));
+        station.broadcast_page(page(b"setup 2", false, true));
+        station.broadcast_page(page(b"audio 2", false, false));
+
+        let feed = station.feed.lock();
+        assert_eq!(
+            Station::catch_up(&feed, Format::Ogg),
+            vec![Bytes::from("ident 2"), Bytes::from("setup 2")]
+        );
+    }
+
+    #[test]
+    fn test_source_keys() {
+        assert_eq!(Source::Genre("Jazz".to_string()).key(), "genre:jazz");
+        assert_eq!(Source::Playlist("p1".to_string()).key(), "playlist:p1");
+    }
+}
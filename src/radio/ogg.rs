@@ -0,0 +1,125 @@
+//! Splitting Ogg streams into pages.
+//!
+//! Ogg radio stations chain one logical stream per track. A listener can
+//! only start decoding at a page boundary and needs the header pages of
+//! the current track first, so the transcoder output is cut into whole
+//! pages before it is broadcast.
+
+use actix_web::web::Bytes;
+
+/// Page capture pattern.
+const CAPTURE: &[u8] = b"OggS";
+
+/// Length of the fixed part of a page header.
+const HEADER_LEN: usize = 27;
+
+/// Header type flag of the first page of a logical stream.
+const BEGIN_OF_STREAM: u8 = 0x02;
+
+/// A complete Ogg page.
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub data: Bytes,
+    /// Whether this page starts a new logical stream (a new track).
+    pub begins_stream: bool,
+    /// Whether this page carries codec headers rather than audio.
+    pub is_header: bool,
+}
+
+/// Collects bytes into complete pages.
+#[derive(Debug, Default)]
+pub struct PageReader {
+    buffer: Vec<u8>,
+}
+
+impl PageReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add bytes, returning the pages completed by them.
+    pub fn push(&mut self, data: &[u8]) -> Vec<Page> {
+        self.buffer.extend_from_slice(data);
+
+        let mut pages = Vec::new();
+        loop {
+            // Skip anything before the next capture pattern
+            match self
+                .buffer
+                .windows(CAPTURE.len())
+                .position(|w| w == CAPTURE)
+            {
+                Some(0) => {}
+                Some(start) => {
+                    self.buffer.drain(..start);
+                }
+                None => {
+                    let keep = self.buffer.len().min(CAPTURE.len() - 1);
+                    self.buffer.drain(..self.buffer.len() - keep);
+                    return pages;
+                }
+            }
+
+            let Some(len) = page_len(&self.buffer) else {
+                return pages;
+            };
+            let data: Vec<u8> = self.buffer.drain(..len).collect();
+            let granule = i64::from_le_bytes(data[6..14].try_into().expect("8 bytes"));
+            pages.push(Page {
+                begins_stream: data[5] & BEGIN_OF_STREAM != 0,
+                // Vorbis header pages have granule position 0
+                is_header: granule == 0,
+                data: Bytes::from(data),
+            });
+        }
+    }
+}
+
+/// Length of the page at the start of `buffer`, once all of it is there.
+fn page_len(buffer: &[u8]) -> Option<usize> {
+    if buffer.len() < HEADER_LEN {
+        return None;
+    }
+    let segments = usize::from(buffer[HEADER_LEN - 1]);
+    let table = buffer.get(HEADER_LEN..HEADER_LEN + segments)?;
+    let len = HEADER_LEN + segments + table.iter().map(|&s| usize::from(s)).sum::<usize>();
+    (buffer.len() >= len).then_some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(flags: u8, granule: i64, body: &[u8]) -> Vec<u8> {
+        let mut page = CAPTURE.to_vec();
+        page.push(0);
+        page.push(flags);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&[0; 12]);
+        page.push(1);
+        page.push(body.len() as u8);
+        page.extend_from_slice(body);
+        page
+    }
+
+    #[test]
+    fn test_pages_are_split_across_chunks() {
+        let mut stream = b"junk".to_vec();
+        stream.extend(page(BEGIN_OF_STREAM, 0, b"ident"));
+        stream.extend(page(0, 0, b"setup"));
+        stream.extend(page(0, 4096, b"audio"));
+
+        let mut reader = PageReader::new();
+        let mut pages = Vec::new();
+        for chunk in stream.chunks(7) {
+            pages.extend(reader.push(chunk));
+        }
+
+        assert_eq!(pages.len(), 3);
+        assert!(pages[0].begins_stream && pages[0].is_header);
+        assert!(!pages[1].begins_stream && pages[1].is_header);
+        assert!(!pages[2].is_header);
+        assert!(pages[2].data.ends_with(b"audio"));
+        assert!(pages.iter().all(|p| p.data.starts_with(CAPTURE)));
+    }
+}
@@ -0,0 +1,337 @@
+//! ListenBrainz submit-listens API client.
+//!
+//! Works with ListenBrainz itself and with compatible servers such as
+//! Maloja, which expose the same API under their own root URL.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::models::SongMetadata;
+
+/// Maximum listens sent in one request.
+pub const MAX_LISTENS_PER_REQUEST: usize = 100;
+
+/// Timeout for a submission request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Kind of submission.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenType {
+    /// One finished listen.
+    Single,
+    /// Several finished listens, e.g. a backlog.
+    Import,
+    /// The track currently playing.
+    PlayingNow,
+}
+
+/// Extra track information.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdditionalInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tracknumber: Option<u32>,
+    pub media_player: String,
+    pub submission_client: String,
+    pub submission_client_version: String,
+}
+
+/// Track metadata of a listen.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackMetadata {
+    pub artist_name: String,
+    pub track_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_name: Option<String>,
+    pub additional_info: AdditionalInfo,
+}
+
+/// A single listen.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Listen {
+    /// Unix timestamp of when playback started; absent for "playing now".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listened_at: Option<i64>,
+    pub track_metadata: TrackMetadata,
+}
+
+impl Listen {
+    /// Build a listen from library metadata.
+    ///
+    /// Metadata is captured at scrobble time so that queued listens are
+    /// still deliverable if the file is later renamed or removed.
+    pub fn from_song(song: &SongMetadata, listened_at: Option<DateTime<Utc>>) -> Self {
+        Self {
+            listened_at: listened_at.map(|t| t.timestamp()),
+            track_metadata: TrackMetadata {
+                artist_name: song.artist.clone(),
+                track_name: song.title.clone(),
+                release_name: Some(song.album.clone()).filter(|a| !a.is_empty()),
+                additional_info: AdditionalInfo {
+                    duration_ms: song.duration.map(|d| u64::from(d) * 1000),
+                    tracknumber: song.track_number,
+                    media_player: "Ferrum".to_string(),
+                    submission_client: "Ferrum".to_string(),
+                    submission_client_version: env!("CARGO_PKG_VERSION").to_string(),
+                },
+            },
+        }
+    }
+}
+
+/// Request body of `POST /1/submit-listens`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubmitListens {
+    pub listen_type: ListenType,
+    pub payload: Vec<Listen>,
+}
+
+/// Why a submission failed.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SubmitError {
+    /// The service rejected the token.
+    #[error("Token rejected by service: {0}")]
+    Unauthorized(String),
+
+    /// The service rejected the listens themselves; retrying will not help.
+    #[error("Listens rejected by service: {0}")]
+    Rejected(String),
+
+    /// The service could not be reached or is temporarily failing.
+    #[error("Service unavailable: {0}")]
+    Unavailable(String),
+}
+
+/// HTTP client for ListenBrainz-compatible services.
+#[derive(Debug, Clone)]
+pub struct ListenBrainzClient {
+    http: reqwest::Client,
+}
+
+impl Default for ListenBrainzClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ListenBrainzClient {
+    /// Create a new client.
+    pub fn new() -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .user_agent(concat!("Ferrum/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .unwrap_or_default();
+        Self { http }
+    }
+
+    /// Submit listens to the service rooted at `service_url`.
+    pub async fn submit(
+        &self,
+        service_url: &str,
+        token: &str,
+        listen_type: ListenType,
+        listens: &[Listen],
+    ) -> Result<(), SubmitError> {
+        let url = format!("{}/1/submit-listens", service_url.trim_end_matches('/'));
+        let body = SubmitListens {
+            listen_type,
+            payload: listens.to_vec(),
+        };
+
+        let response = self
+            .http
+            .post(&url)
+            .header(reqwest::header::AUTHORIZATION, format!("Token {}", token))
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| SubmitError::Unavailable(e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let message = response
+            .text()
+            .await
+            .ok()
+            .and_then(|text| error_message(&text))
+            .unwrap_or_else(|| status.to_string());
+
+        Err(match status.as_u16() {
+            401 | 403 => SubmitError::Unauthorized(message),
+            408 | 429 => SubmitError::Unavailable(message),
+            400..=499 => SubmitError::Rejected(message),
+            _ => SubmitError::Unavailable(message),
+        })
+    }
+}
+
+/// Extract the `error` field of a ListenBrainz error response.
+fn error_message(body: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct ErrorBody {
+        error: String,
+    }
+    serde_json::from_str::<ErrorBody>(body)
+        .ok()
+        .map(|b| b.error)
+}
+
+/// A minimal in-process ListenBrainz server for tests.
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::SubmitListens;
+    use actix_web::{dev::ServerHandle, post, web, App, HttpRequest, HttpResponse, HttpServer};
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    /// Token the mock accepts.
+    pub const TOKEN: &str = "mock-token";
+
+    #[derive(Default)]
+    pub struct MockState {
+        /// Accepted submissions, in order.
+        pub received: Mutex<Vec<SubmitListens>>,
+        /// Number of upcoming requests to answer with 503.
+        pub fail_next: Mutex<usize>,
+    }
+
+    pub struct MockServer {
+        pub url: String,
+        pub state: Arc<MockState>,
+        handle: ServerHandle,
+    }
+
+    impl MockServer {
+        pub async fn stop(self) {
+            self.handle.stop(false).await;
+        }
+    }
+
+    #[post("/1/submit-listens")]
+    async fn submit_listens(
+        req: HttpRequest,
+        state: web::Data<Arc<MockState>>,
+        body: web::Json<SubmitListens>,
+    ) -> HttpResponse {
+        {
+            let mut fail_next = state.fail_next.lock();
+            if *fail_next > 0 {
+                *fail_next -= 1;
+                return HttpResponse::ServiceUnavailable().finish();
+            }
+        }
+
+        let authorized = req
+            .headers()
+            .get("Authorization")
+            .and_then(|h| h.to_str().ok())
+            == Some(&format!("Token {}", TOKEN));
+        if !authorized {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({"code": 401, "error": "Invalid authorization token."}));
+        }
+
+        state.received.lock().push(body.into_inner());
+        HttpResponse::Ok().json(serde_json::json!({"status": "ok"}))
+    }
+
+    /// Start the mock on an ephemeral loopback port.
+    pub async fn start() -> MockServer {
+        let state = Arc::new(MockState::default());
+        let app_state = state.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(app_state.clone()))
+                .service(submit_listens)
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+
+        let url = format!("http://{}", server.addrs()[0]);
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        MockServer { url, state, handle }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listen() -> Listen {
+        Listen {
+            listened_at: Some(1_700_000_000),
+            track_metadata: TrackMetadata {
+                artist_name: "Daft Punk".to_string(),
+                track_name: "One More Time".to_string(),
+                release_name: None,
+                additional_info: AdditionalInfo {
+                    duration_ms: Some(320_000),
+                    tracknumber: None,
+                    media_player: "Ferrum".to_string(),
+                    submission_client: "Ferrum".to_string(),
+                    submission_client_version: "test".to_string(),
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn test_playing_now_omits_timestamp() {
+        let mut listen = listen();
+        listen.listened_at = None;
+        let body = SubmitListens {
+            listen_type: ListenType::PlayingNow,
+            payload: vec![listen],
+        };
+
+        let json = serde_json::to_value(&body).unwrap();
+        assert_eq!(json["listen_type"], "playing_now");
+        assert!(json["payload"][0].get("listened_at").is_none());
+        assert_eq!(
+            json["payload"][0]["track_metadata"]["artist_name"],
+            "Daft Punk"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_submit_against_mock_server() {
+        let server = mock::start().await;
+        let client = ListenBrainzClient::new();
+
+        client
+            .submit(&server.url, mock::TOKEN, ListenType::Single, &[listen()])
+            .await
+            .unwrap();
+        assert_eq!(server.state.received.lock()[0].payload[0], listen());
+
+        let result = client
+            .submit(&server.url, "wrong", ListenType::Single, &[listen()])
+            .await;
+        assert_eq!(
+            result,
+            Err(SubmitError::Unauthorized(
+                "Invalid authorization token.".to_string()
+            ))
+        );
+
+        *server.state.fail_next.lock() = 1;
+        let result = client
+            .submit(&server.url, mock::TOKEN, ListenType::Single, &[listen()])
+            .await;
+        assert!(matches!(result, Err(SubmitError::Unavailable(_))));
+
+        server.stop().await;
+    }
+}
@@ -0,0 +1,383 @@
+//! Forwarding of scrobbles to ListenBrainz-compatible services.
+//!
+//! Finished plays are written to a persistent queue and delivered by a
+//! background task, so plays made while the service is down (or the
+//! server is offline) are submitted once it is reachable again.
+
+pub mod listenbrainz;
+pub mod queue;
+
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::auth::SecretBox;
+use crate::error::AppResult;
+use crate::models::SongMetadata;
+use crate::userdata::{ForwardingConfig, ForwardingRepository, JsonForwardingRepository};
+use listenbrainz::{Listen, ListenBrainzClient, ListenType, SubmitError, MAX_LISTENS_PER_REQUEST};
+use queue::{JsonScrobbleQueue, QueuedListen, ScrobbleQueue};
+
+/// How often the queue is checked when nothing wakes the worker.
+const POLL_INTERVAL_SECS: u64 = 60;
+
+/// Delay after the first failed attempt; doubled on each further failure.
+const RETRY_BASE_SECS: i64 = 30;
+
+/// Upper bound of the retry delay.
+const RETRY_MAX_SECS: i64 = 6 * 60 * 60;
+
+/// Delay before the next attempt after `attempts` failures.
+fn retry_delay(attempts: u32) -> Duration {
+    let factor = 1i64 << attempts.min(20);
+    Duration::seconds((RETRY_BASE_SECS * factor).min(RETRY_MAX_SECS))
+}
+
+/// Queues scrobbles and delivers them to each user's configured service.
+pub struct ScrobbleForwarder {
+    configs: Arc<JsonForwardingRepository>,
+    queue: Arc<JsonScrobbleQueue>,
+    secrets: Arc<SecretBox>,
+    client: ListenBrainzClient,
+    wake: Notify,
+}
+
+impl ScrobbleForwarder {
+    /// Create a new forwarder.
+    pub fn new(
+        configs: Arc<JsonForwardingRepository>,
+        queue: Arc<JsonScrobbleQueue>,
+        secrets: Arc<SecretBox>,
+    ) -> Self {
+        Self {
+            configs,
+            queue,
+            secrets,
+            client: ListenBrainzClient::new(),
+            wake: Notify::new(),
+        }
+    }
+
+    /// Forwarding settings repository.
+    pub fn configs(&self) -> &JsonForwardingRepository {
+        &self.configs
+    }
+
+    /// Pending listen queue.
+    pub fn queue(&self) -> &JsonScrobbleQueue {
+        &self.queue
+    }
+
+    /// Key that service tokens are sealed with.
+    pub fn secrets(&self) -> &SecretBox {
+        &self.secrets
+    }
+
+    /// Seal tokens that earlier versions stored in plaintext, returning
+    /// how many were sealed.
+    pub fn seal_plaintext_tokens(&self) -> AppResult<usize> {
+        let mut sealed = 0;
+        for mut config in self.configs.list_all()? {
+            if self.secrets.open(&config.token).is_err() {
+                let token = std::mem::take(&mut config.token);
+                config.set_token(&token, &self.secrets)?;
+                self.configs.save(config)?;
+                sealed += 1;
+            }
+        }
+        Ok(sealed)
+    }
+
+    /// Queue a finished play if the user forwards scrobbles.
+    ///
+    /// Returns whether the play was queued.
+    pub fn enqueue(
+        &self,
+        user_id: Uuid,
+        song: &SongMetadata,
+        played_at: DateTime<Utc>,
+    ) -> AppResult<bool> {
+        match self.configs.find(user_id)? {
+            Some(config) if config.enabled => {}
+            _ => return Ok(false),
+        }
+
+        self.queue.push(QueuedListen::new(
+            user_id,
+            Listen::from_song(song, Some(played_at)),
+        ))?;
+        self.wake.notify_one();
+        Ok(true)
+    }
+
+    /// Make all of a user's pending listens due and wake the worker.
+    pub fn retry_now(&self, user_id: Uuid) -> AppResult<usize> {
+        let count = self.queue.retry_now(user_id)?;
+        self.wake.notify_one();
+        Ok(count)
+    }
+
+    /// Send a "playing now" notification in the background.
+    ///
+    /// These are not queued: a late "playing now" is meaningless.
+    pub fn send_now_playing(self: &Arc<Self>, user_id: Uuid, song: &SongMetadata) {
+        let config = match self.configs.find(user_id) {
+            Ok(Some(config)) if config.enabled => config,
+            _ => return,
+        };
+
+        let Ok(token) = self.secrets.open(&config.token) else {
+            return;
+        };
+
+        let listen = Listen::from_song(song, None);
+        let forwarder = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = forwarder
+                .client
+                .submit(
+                    &config.service_url,
+                    &token,
+                    ListenType::PlayingNow,
+                    &[listen],
+                )
+                .await
+            {
+                tracing::debug!(user_id = %user_id, error = %e, "Failed to forward now playing");
+            }
+        });
+    }
+
+    /// Start the background delivery task.
+    pub fn spawn(self: Arc<Self>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = self.deliver_due(Utc::now()).await {
+                    tracing::error!(error = %e, "Scrobble forwarding failed");
+                }
+
+                tokio::select! {
+                    _ = self.wake.notified() => {}
+                    _ = tokio::time::sleep(std::time::Duration::from_secs(POLL_INTERVAL_SECS)) => {}
+                }
+            }
+        })
+    }
+
+    /// Deliver every listen due at `now`, returning how many were delivered.
+    async fn deliver_due(&self, now: DateTime<Utc>) -> AppResult<usize> {
+        let mut by_user: HashMap<Uuid, Vec<QueuedListen>> = HashMap::new();
+        for entry in self.queue.due(now)? {
+            by_user.entry(entry.user_id).or_default().push(entry);
+        }
+
+        let mut delivered = 0;
+        for (user_id, entries) in by_user {
+            delivered += self.deliver_for_user(user_id, entries, now).await?;
+        }
+        Ok(delivered)
+    }
+
+    /// Deliver one user's due listens in batches.
+    async fn deliver_for_user(
+        &self,
+        user_id: Uuid,
+        entries: Vec<QueuedListen>,
+        now: DateTime<Utc>,
+    ) -> AppResult<usize> {
+        let config = match self.configs.find(user_id)? {
+            Some(config) if config.enabled => config,
+            // Held until forwarding is re-enabled (or dropped with the settings)
+            _ => return Ok(0),
+        };
+
+        let token = self.secrets.open(&config.token)?;
+        let (delivered, error) = self
+            .deliver_entries(&config, &token, &entries, MAX_LISTENS_PER_REQUEST, now)
+            .await?;
+        if let Some(e) = error {
+            tracing::warn!(user_id = %user_id, error = %e, "Scrobble delivery failed");
+        }
+        Ok(delivered)
+    }
+
+    /// Submit entries in batches of `batch_size`.
+    ///
+    /// Stops at the first temporary failure, postponing everything not yet
+    /// delivered so that listens keep their order, and returns that error.
+    async fn deliver_entries(
+        &self,
+        config: &ForwardingConfig,
+        token: &str,
+        entries: &[QueuedListen],
+        batch_size: usize,
+        now: DateTime<Utc>,
+    ) -> AppResult<(usize, Option<SubmitError>)> {
+        let mut delivered = 0;
+
+        for (n, batch) in entries.chunks(batch_size).enumerate() {
+            let listens: Vec<Listen> = batch.iter().map(|e| e.listen.clone()).collect();
+            let ids: Vec<Uuid> = batch.iter().map(|e| e.id).collect();
+            let listen_type = if listens.len() == 1 {
+                ListenType::Single
+            } else {
+                ListenType::Import
+            };
+
+            let result = self
+                .client
+                .submit(&config.service_url, token, listen_type, &listens)
+                .await;
+            let rest = &entries[((n + 1) * batch_size).min(entries.len())..];
+
+            match result {
+                Ok(()) => {
+                    self.queue.remove(&ids)?;
+                    self.configs.record_delivery(config.user_id, None)?;
+                    delivered += ids.len();
+                }
+                Err(SubmitError::Rejected(_)) if batch.len() > 1 => {
+                    // Find the offending listens by submitting one at a time
+                    let (count, error) =
+                        Box::pin(self.deliver_entries(config, token, batch, 1, now)).await?;
+                    delivered += count;
+                    if let Some(e) = error {
+                        self.postpone(config.user_id, rest, &e, now)?;
+                        return Ok((delivered, Some(e)));
+                    }
+                }
+                Err(SubmitError::Rejected(message)) => {
+                    tracing::warn!(
+                        user_id = %config.user_id,
+                        error = %message,
+                        "Dropping listen rejected by service"
+                    );
+                    self.queue.remove(&ids)?;
+                    self.configs
+                        .record_delivery(config.user_id, Some(message))?;
+                }
+                Err(e) => {
+                    let pending = &entries[n * batch_size..];
+                    self.postpone(config.user_id, pending, &e, now)?;
+                    return Ok((delivered, Some(e)));
+                }
+            }
+        }
+
+        Ok((delivered, None))
+    }
+
+    /// Postpone entries after a failed attempt and record the error.
+    fn postpone(
+        &self,
+        user_id: Uuid,
+        entries: &[QueuedListen],
+        error: &SubmitError,
+        now: DateTime<Utc>,
+    ) -> AppResult<()> {
+        let attempts = entries.iter().map(|e| e.attempts).max().unwrap_or(0);
+        let delay = match error {
+            // A bad token will not fix itself quickly
+            SubmitError::Unauthorized(_) => Duration::seconds(RETRY_MAX_SECS),
+            _ => retry_delay(attempts),
+        };
+
+        let ids: Vec<Uuid> = entries.iter().map(|e| e.id).collect();
+        self.queue.postpone(&ids, now + delay)?;
+        self.configs
+            .record_delivery(user_id, Some(error.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn song() -> SongMetadata {
+        SongMetadata {
+            id: SongMetadata::generate_id(Path::new("one.mp3")),
+            title: "One More Time".to_string(),
+            artist: "Daft Punk".to_string(),
+            artist_id: SongMetadata::generate_name_id("artist", "Daft Punk"),
+            album: "Discovery".to_string(),
+            album_id: SongMetadata::generate_name_id("album", "Discovery"),
+            duration: Some(320),
+            track_number: Some(1),
+            year: Some(2001),
+            genre: None,
+            format: "mp3".to_string(),
+            file: "one.mp3".to_string(),
+            has_cover: false,
+            starred: None,
+            rating: None,
+            play_count: 0,
+            last_played: None,
+        }
+    }
+
+    #[test]
+    fn test_retry_delay_is_capped() {
+        assert_eq!(retry_delay(0), Duration::seconds(30));
+        assert_eq!(retry_delay(2), Duration::seconds(120));
+        assert_eq!(retry_delay(30), Duration::seconds(RETRY_MAX_SECS));
+    }
+
+    #[actix_rt::test]
+    async fn test_listens_queued_during_outage_are_delivered_later() {
+        let server = listenbrainz::mock::start().await;
+        let dir = tempdir().unwrap();
+        let configs =
+            Arc::new(JsonForwardingRepository::new(dir.path().join("forwarding.json")).unwrap());
+        let queue = Arc::new(JsonScrobbleQueue::new(dir.path().join("queue.json")).unwrap());
+        let secrets = Arc::new(SecretBox::from_key(&[7; 32]));
+        let forwarder = ScrobbleForwarder::new(configs.clone(), queue.clone(), secrets.clone());
+
+        let user = Uuid::new_v4();
+        let stranger = Uuid::new_v4();
+        configs
+            .save(
+                ForwardingConfig::new(
+                    user,
+                    server.url.clone(),
+                    listenbrainz::mock::TOKEN,
+                    &secrets,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+
+        let played_at = Utc::now();
+        assert!(!forwarder.enqueue(stranger, &song(), played_at).unwrap());
+        assert!(forwarder.enqueue(user, &song(), played_at).unwrap());
+        assert!(forwarder.enqueue(user, &song(), played_at).unwrap());
+        let now = Utc::now();
+
+        // Outage: nothing is delivered and the listens are postponed
+        *server.state.fail_next.lock() = 1;
+        assert_eq!(forwarder.deliver_due(now).await.unwrap(), 0);
+        assert_eq!(queue.count_by_user(user), 2);
+        assert!(queue.due(now).unwrap().is_empty());
+        assert!(configs.find(user).unwrap().unwrap().last_error.is_some());
+
+        // Once the retry is due, the backlog goes out as one import
+        let later = now + retry_delay(0);
+        assert_eq!(forwarder.deliver_due(later).await.unwrap(), 2);
+        assert_eq!(queue.count_by_user(user), 0);
+        {
+            let received = server.state.received.lock();
+            assert_eq!(received.len(), 1);
+            assert_eq!(received[0].listen_type, ListenType::Import);
+            assert_eq!(
+                received[0].payload[0].listened_at,
+                Some(played_at.timestamp())
+            );
+        }
+        assert!(configs.find(user).unwrap().unwrap().last_error.is_none());
+
+        server.stop().await;
+    }
+}
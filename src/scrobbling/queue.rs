@@ -0,0 +1,243 @@
+//! Persistent queue of listens awaiting delivery.
+
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use super::listenbrainz::Listen;
+use crate::error::AppResult;
+use crate::storage;
+
+/// A listen waiting to be forwarded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedListen {
+    /// Queue entry ID.
+    pub id: Uuid,
+    /// Listening user.
+    pub user_id: Uuid,
+    /// The listen to submit.
+    pub listen: Listen,
+    /// When the listen was queued.
+    pub queued_at: DateTime<Utc>,
+    /// Failed delivery attempts so far.
+    pub attempts: u32,
+    /// Earliest time of the next attempt.
+    pub next_attempt_at: DateTime<Utc>,
+}
+
+impl QueuedListen {
+    /// Queue a listen for immediate delivery.
+    pub fn new(user_id: Uuid, listen: Listen) -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4(),
+            user_id,
+            listen,
+            queued_at: now,
+            attempts: 0,
+            next_attempt_at: now,
+        }
+    }
+}
+
+/// Queue storage format for JSON file.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct QueueStore {
+    listens: Vec<QueuedListen>,
+}
+
+/// Trait for scrobble queue operations.
+pub trait ScrobbleQueue: Send + Sync {
+    /// Append a listen to the queue.
+    fn push(&self, entry: QueuedListen) -> AppResult<()>;
+
+    /// Entries due at `now`, oldest first.
+    fn due(&self, now: DateTime<Utc>) -> AppResult<Vec<QueuedListen>>;
+
+    /// Remove delivered or dropped entries.
+    fn remove(&self, ids: &[Uuid]) -> AppResult<()>;
+
+    /// Count one more failed attempt for entries and postpone them.
+    fn postpone(&self, ids: &[Uuid], until: DateTime<Utc>) -> AppResult<()>;
+
+    /// Make all of a user's entries due now.
+    fn retry_now(&self, user_id: Uuid) -> AppResult<usize>;
+
+    /// Number of entries a user has waiting.
+    fn count_by_user(&self, user_id: Uuid) -> usize;
+
+    /// Drop all of a user's entries, returning how many were removed.
+    fn delete_by_user(&self, user_id: Uuid) -> AppResult<usize>;
+}
+
+/// JSON file-based scrobble queue.
+#[derive(Debug)]
+pub struct JsonScrobbleQueue {
+    file_path: PathBuf,
+    /// Entries in insertion order.
+    entries: RwLock<Vec<QueuedListen>>,
+}
+
+impl JsonScrobbleQueue {
+    /// Create a new JSON scrobble queue.
+    pub fn new(file_path: impl AsRef<Path>) -> AppResult<Self> {
+        let file_path = file_path.as_ref().to_path_buf();
+        let store: QueueStore = storage::load_json(&file_path)?;
+
+        tracing::info!(
+            count = store.listens.len(),
+            "Loaded scrobble queue from file"
+        );
+
+        Ok(Self {
+            file_path,
+            entries: RwLock::new(store.listens),
+        })
+    }
+
+    /// Write the queue to file.
+    fn persist(&self) -> AppResult<()> {
+        let store = QueueStore {
+            listens: self.entries.read().clone(),
+        };
+        storage::save_json(&self.file_path, &store)
+    }
+}
+
+impl ScrobbleQueue for JsonScrobbleQueue {
+    fn push(&self, entry: QueuedListen) -> AppResult<()> {
+        self.entries.write().push(entry);
+        self.persist()
+    }
+
+    fn due(&self, now: DateTime<Utc>) -> AppResult<Vec<QueuedListen>> {
+        Ok(self
+            .entries
+            .read()
+            .iter()
+            .filter(|e| e.next_attempt_at <= now)
+            .cloned()
+            .collect())
+    }
+
+    fn remove(&self, ids: &[Uuid]) -> AppResult<()> {
+        self.entries.write().retain(|e| !ids.contains(&e.id));
+        self.persist()
+    }
+
+    fn postpone(&self, ids: &[Uuid], until: DateTime<Utc>) -> AppResult<()> {
+        for entry in self.entries.write().iter_mut() {
+            if ids.contains(&entry.id) {
+                entry.attempts += 1;
+                entry.next_attempt_at = until;
+            }
+        }
+        self.persist()
+    }
+
+    fn retry_now(&self, user_id: Uuid) -> AppResult<usize> {
+        let now = Utc::now();
+        let mut count = 0;
+        for entry in self.entries.write().iter_mut() {
+            if entry.user_id == user_id {
+                entry.next_attempt_at = now;
+                count += 1;
+            }
+        }
+        if count > 0 {
+            self.persist()?;
+        }
+        Ok(count)
+    }
+
+    fn count_by_user(&self, user_id: Uuid) -> usize {
+        self.entries
+            .read()
+            .iter()
+            .filter(|e| e.user_id == user_id)
+            .count()
+    }
+
+    fn delete_by_user(&self, user_id: Uuid) -> AppResult<usize> {
+        let removed = {
+            let mut entries = self.entries.write();
+            let before = entries.len();
+            entries.retain(|e| e.user_id != user_id);
+            before - entries.len()
+        };
+        if removed > 0 {
+            self.persist()?;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrobbling::listenbrainz::{AdditionalInfo, TrackMetadata};
+    use chrono::Duration;
+    use tempfile::tempdir;
+
+    fn listen() -> Listen {
+        Listen {
+            listened_at: Some(1_700_000_000),
+            track_metadata: TrackMetadata {
+                artist_name: "Artist".to_string(),
+                track_name: "Track".to_string(),
+                release_name: None,
+                additional_info: AdditionalInfo {
+                    duration_ms: None,
+                    tracknumber: None,
+                    media_player: "Ferrum".to_string(),
+                    submission_client: "Ferrum".to_string(),
+                    submission_client_version: "test".to_string(),
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn test_postponed_entries_survive_reload() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("scrobble_queue.json");
+        let user = Uuid::new_v4();
+        let entry = QueuedListen::new(user, listen());
+        let later = Utc::now() + Duration::minutes(5);
+
+        {
+            let queue = JsonScrobbleQueue::new(&path).unwrap();
+            queue.push(entry.clone()).unwrap();
+            queue.postpone(&[entry.id], later).unwrap();
+        }
+
+        let queue = JsonScrobbleQueue::new(&path).unwrap();
+        assert!(queue.due(Utc::now()).unwrap().is_empty());
+        let due = queue.due(later).unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].attempts, 1);
+
+        assert_eq!(queue.retry_now(user).unwrap(), 1);
+        assert_eq!(queue.due(Utc::now()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_remove_and_delete_by_user() {
+        let dir = tempdir().unwrap();
+        let queue = JsonScrobbleQueue::new(dir.path().join("scrobble_queue.json")).unwrap();
+        let alice = Uuid::new_v4();
+        let bob = Uuid::new_v4();
+        let first = QueuedListen::new(alice, listen());
+
+        queue.push(first.clone()).unwrap();
+        queue.push(QueuedListen::new(alice, listen())).unwrap();
+        queue.push(QueuedListen::new(bob, listen())).unwrap();
+
+        queue.remove(&[first.id]).unwrap();
+        assert_eq!(queue.count_by_user(alice), 1);
+        assert_eq!(queue.delete_by_user(alice).unwrap(), 1);
+        assert_eq!(queue.count_by_user(bob), 1);
+    }
+}
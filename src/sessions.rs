@@ -0,0 +1,222 @@
+//! Playback sessions for remote control.
+//!
+//! A player that connects to the event channel with a device name becomes a
+//! session for as long as the connection stays open. It reports its
+//! playback state over the connection, and other clients of the same user
+//! can send it commands, which are relayed over the same connection.
+//! Commands are delivered live only; a player that is offline misses them
+//! rather than receiving stale ones on reconnect.
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::error::{AppError, AppResult};
+
+/// Highest volume.
+pub const MAX_VOLUME: u8 = 100;
+
+/// A remote control command.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Command {
+    Play,
+    Pause,
+    Seek { position_ms: u64 },
+    Next,
+    Volume { volume: u8 },
+}
+
+/// Playback state as last reported by a player.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PlaybackState {
+    /// Song being played or paused.
+    pub song_id: Option<String>,
+    /// Whether the player is playing (rather than paused or stopped).
+    #[serde(default)]
+    pub playing: bool,
+    /// Position within the song, in milliseconds.
+    #[serde(default)]
+    pub position_ms: u64,
+    /// Volume, 0-100.
+    pub volume: Option<u8>,
+    /// When the state was reported.
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// A connected player.
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    pub id: Uuid,
+    /// Device name chosen by the player.
+    pub name: String,
+    /// Client ID of the connection, if given.
+    pub client: Option<String>,
+    #[serde(skip)]
+    pub user_id: Uuid,
+    pub connected_at: DateTime<Utc>,
+    pub state: PlaybackState,
+}
+
+struct Entry {
+    session: Session,
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+/// Registry of connected players.
+#[derive(Default)]
+pub struct SessionRegistry {
+    sessions: Mutex<HashMap<Uuid, Entry>>,
+}
+
+impl std::fmt::Debug for SessionRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionRegistry")
+            .field("sessions", &self.sessions.lock().len())
+            .finish()
+    }
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a player, returning its session and the receiving end of
+    /// its command channel.
+    pub fn register(
+        &self,
+        user_id: Uuid,
+        name: String,
+        client: Option<String>,
+    ) -> (Session, mpsc::UnboundedReceiver<Command>) {
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let session = Session {
+            id: Uuid::new_v4(),
+            name,
+            client,
+            user_id,
+            connected_at: Utc::now(),
+            state: PlaybackState::default(),
+        };
+        self.sessions.lock().insert(
+            session.id,
+            Entry {
+                session: session.clone(),
+                commands,
+            },
+        );
+        (session, receiver)
+    }
+
+    /// Remove a session when its connection closes.
+    pub fn unregister(&self, id: Uuid) -> Option<Session> {
+        self.sessions.lock().remove(&id).map(|e| e.session)
+    }
+
+    /// A user's sessions, oldest first.
+    pub fn list(&self, user_id: Uuid) -> Vec<Session> {
+        let mut sessions: Vec<Session> = self
+            .sessions
+            .lock()
+            .values()
+            .filter(|e| e.session.user_id == user_id)
+            .map(|e| e.session.clone())
+            .collect();
+        sessions.sort_by_key(|s| s.connected_at);
+        sessions
+    }
+
+    /// Record a player's state.
+    pub fn update_state(&self, id: Uuid, mut state: PlaybackState) -> Option<Session> {
+        state.volume = state.volume.map(|v| v.min(MAX_VOLUME));
+        state.updated_at = Some(Utc::now());
+
+        let mut sessions = self.sessions.lock();
+        let entry = sessions.get_mut(&id)?;
+        entry.session.state = state;
+        Some(entry.session.clone())
+    }
+
+    /// Send a command to one of a user's sessions.
+    pub fn send(&self, user_id: Uuid, id: Uuid, command: Command) -> AppResult<Session> {
+        let sessions = self.sessions.lock();
+        let entry = sessions
+            .get(&id)
+            .filter(|e| e.session.user_id == user_id)
+            .ok_or_else(|| AppError::NotFound(format!("Session not found: {}", id)))?;
+        entry
+            .commands
+            .send(command)
+            .map_err(|_| AppError::NotFound(format!("Session not found: {}", id)))?;
+        Ok(entry.session.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sessions_are_per_user() {
+        let registry = SessionRegistry::new();
+        let alice = Uuid::new_v4();
+        let bob = Uuid::new_v4();
+        let (phone, _) = registry.register(alice, "Phone".to_string(), None);
+        let (speaker, _) = registry.register(alice, "Kitchen".to_string(), Some("k1".into()));
+        registry.register(bob, "Laptop".to_string(), None);
+
+        let names: Vec<_> = registry.list(alice).into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec!["Phone", "Kitchen"]);
+
+        let state = PlaybackState {
+            song_id: Some("abc".to_string()),
+            playing: true,
+            volume: Some(150),
+            ..Default::default()
+        };
+        let updated = registry.update_state(speaker.id, state).unwrap();
+        assert_eq!(updated.state.volume, Some(MAX_VOLUME));
+        assert!(updated.state.updated_at.is_some());
+
+        assert_eq!(registry.unregister(phone.id).unwrap().name, "Phone");
+        assert_eq!(registry.list(alice).len(), 1);
+    }
+
+    #[test]
+    fn test_commands_reach_only_the_owner_session() {
+        let registry = SessionRegistry::new();
+        let alice = Uuid::new_v4();
+        let (session, mut receiver) = registry.register(alice, "Phone".to_string(), None);
+
+        registry
+            .send(alice, session.id, Command::Seek { position_ms: 5000 })
+            .unwrap();
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            Command::Seek { position_ms: 5000 }
+        );
+
+        let result = registry.send(Uuid::new_v4(), session.id, Command::Pause);
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+
+        drop(receiver);
+        let result = registry.send(alice, session.id, Command::Pause);
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_command_format() {
+        let command: Command =
+            serde_json::from_str(r#"{"command": "volume", "volume": 40}"#).unwrap();
+        assert_eq!(command, Command::Volume { volume: 40 });
+        assert_eq!(
+            serde_json::to_value(Command::Next).unwrap(),
+            serde_json::json!({ "command": "next" })
+        );
+    }
+}
@@ -0,0 +1,425 @@
+//! Listening statistics computed from play history and the library index.
+
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::library::LibraryIndex;
+use crate::models::SongMetadata;
+use crate::userdata::PlayRecord;
+
+/// What a top list ranks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TopKind {
+    Artists,
+    Albums,
+    Tracks,
+    Genres,
+}
+
+/// A time window over the play history. Open ends are unbounded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Window {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl Window {
+    /// Whether a timestamp falls inside the window (`from` inclusive, `to` exclusive).
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        self.from.map(|from| at >= from).unwrap_or(true)
+            && self.to.map(|to| at < to).unwrap_or(true)
+    }
+
+    /// Keep only the plays inside the window.
+    pub fn filter<'a>(&self, plays: &'a [PlayRecord]) -> Vec<&'a PlayRecord> {
+        plays
+            .iter()
+            .filter(|p| self.contains(p.played_at))
+            .collect()
+    }
+}
+
+/// One entry of a top list.
+#[derive(Debug, Clone, Serialize)]
+pub struct TopEntry {
+    /// Song, album or artist ID, or the lowercased genre.
+    pub id: String,
+    /// Display name (title for tracks).
+    pub name: String,
+    /// Artist, for tracks and albums.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    /// Number of plays in the window.
+    pub plays: u32,
+    /// Listening time in seconds.
+    pub listening_seconds: u64,
+}
+
+/// Runs of consecutive listening days.
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub struct Streaks {
+    /// Length of the run ending today or yesterday, in days.
+    pub current: u32,
+    /// Length of the longest run, in days.
+    pub longest: u32,
+    /// First day of the longest run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longest_start: Option<NaiveDate>,
+    /// Last day of the longest run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longest_end: Option<NaiveDate>,
+}
+
+/// Aggregate listening statistics for a window.
+#[derive(Debug, Clone, Serialize)]
+pub struct Overview {
+    /// Number of plays.
+    pub plays: u32,
+    /// Total listening time in seconds.
+    pub listening_seconds: u64,
+    /// Distinct tracks played.
+    pub unique_tracks: usize,
+    /// Distinct artists played.
+    pub unique_artists: usize,
+    /// Plays per hour of day (0-23).
+    pub by_hour: [u32; 24],
+    /// Plays per weekday, Monday first.
+    pub by_weekday: [u32; 7],
+    /// Listening streaks.
+    pub streaks: Streaks,
+}
+
+/// Annual listening report.
+#[derive(Debug, Clone, Serialize)]
+pub struct YearInReview {
+    pub year: i32,
+    #[serde(flatten)]
+    pub overview: Overview,
+    /// Plays per month, January first.
+    pub by_month: [u32; 12],
+    /// Month (1-12) with the most plays.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_month: Option<u32>,
+    /// Day with the most plays.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_day: Option<NaiveDate>,
+    /// Artists first played during the year.
+    pub new_artists: usize,
+    pub top_artists: Vec<TopEntry>,
+    pub top_albums: Vec<TopEntry>,
+    pub top_tracks: Vec<TopEntry>,
+    pub top_genres: Vec<TopEntry>,
+}
+
+/// Listening time credited for one play of a song.
+fn play_seconds(song: Option<&SongMetadata>) -> u64 {
+    song.and_then(|s| s.duration).map(u64::from).unwrap_or(0)
+}
+
+/// Rank artists, albums, tracks or genres by play count.
+///
+/// Ties are broken by listening time, then by name. Plays of songs that
+/// are no longer in the library are skipped.
+pub fn top(
+    plays: &[&PlayRecord],
+    index: &LibraryIndex,
+    kind: TopKind,
+    limit: usize,
+) -> Vec<TopEntry> {
+    let mut entries: HashMap<String, TopEntry> = HashMap::new();
+
+    for play in plays {
+        let Some(song) = index.song(&play.song_id) else {
+            continue;
+        };
+
+        let (id, name, artist) = match kind {
+            TopKind::Artists => (song.artist_id.clone(), song.artist.clone(), None),
+            TopKind::Albums => (
+                song.album_id.clone(),
+                song.album.clone(),
+                Some(song.artist.clone()),
+            ),
+            TopKind::Tracks => (
+                song.id.clone(),
+                song.title.clone(),
+                Some(song.artist.clone()),
+            ),
+            TopKind::Genres => match song.genre.as_deref().map(str::trim) {
+                Some(genre) if !genre.is_empty() => (genre.to_lowercase(), genre.to_string(), None),
+                _ => continue,
+            },
+        };
+
+        let entry = entries.entry(id.clone()).or_insert_with(|| TopEntry {
+            id,
+            name,
+            artist,
+            plays: 0,
+            listening_seconds: 0,
+        });
+        entry.plays += 1;
+        entry.listening_seconds += play_seconds(Some(song));
+    }
+
+    let mut entries: Vec<TopEntry> = entries.into_values().collect();
+    entries.sort_by(|a, b| {
+        b.plays
+            .cmp(&a.plays)
+            .then(b.listening_seconds.cmp(&a.listening_seconds))
+            .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+    });
+    entries.truncate(limit);
+    entries
+}
+
+/// Compute streaks of consecutive days from a set of listening days.
+pub fn streaks(days: &BTreeSet<NaiveDate>, today: NaiveDate) -> Streaks {
+    let mut result = Streaks::default();
+    let mut run_start: Option<NaiveDate> = None;
+    let mut previous: Option<NaiveDate> = None;
+
+    for &day in days {
+        let continues = previous
+            .map(|p| p + Duration::days(1) == day)
+            .unwrap_or(false);
+        if !continues {
+            run_start = Some(day);
+        }
+        let start = run_start.unwrap_or(day);
+        let length = (day - start).num_days() as u32 + 1;
+
+        if length > result.longest {
+            result.longest = length;
+            result.longest_start = Some(start);
+            result.longest_end = Some(day);
+        }
+        previous = Some(day);
+    }
+
+    // The current streak is still alive if the last listening day is today or yesterday
+    if let (Some(last), Some(start)) = (previous, run_start) {
+        if last == today || last + Duration::days(1) == today {
+            result.current = (last - start).num_days() as u32 + 1;
+        }
+    }
+
+    result
+}
+
+/// Compute aggregate statistics for a set of plays.
+///
+/// `offset` is the listener's UTC offset, used to bucket plays into hours
+/// and days.
+pub fn overview(
+    plays: &[&PlayRecord],
+    index: &LibraryIndex,
+    offset: FixedOffset,
+    now: DateTime<Utc>,
+) -> Overview {
+    let mut by_hour = [0u32; 24];
+    let mut by_weekday = [0u32; 7];
+    let mut listening_seconds = 0u64;
+    let mut tracks = HashSet::new();
+    let mut artists = HashSet::new();
+    let mut days = BTreeSet::new();
+
+    for play in plays {
+        let local = play.played_at.with_timezone(&offset);
+        by_hour[local.hour() as usize] += 1;
+        by_weekday[local.weekday().num_days_from_monday() as usize] += 1;
+        days.insert(local.date_naive());
+        tracks.insert(play.song_id.as_str());
+
+        let song = index.song(&play.song_id);
+        listening_seconds += play_seconds(song);
+        if let Some(song) = song {
+            artists.insert(song.artist_id.as_str());
+        }
+    }
+
+    Overview {
+        plays: plays.len() as u32,
+        listening_seconds,
+        unique_tracks: tracks.len(),
+        unique_artists: artists.len(),
+        by_hour,
+        by_weekday,
+        streaks: streaks(&days, now.with_timezone(&offset).date_naive()),
+    }
+}
+
+/// Build the year-in-review report.
+///
+/// `all_plays` is the user's full history; it is needed to tell which
+/// artists were discovered during the year.
+pub fn year_in_review(
+    all_plays: &[PlayRecord],
+    index: &LibraryIndex,
+    year: i32,
+    offset: FixedOffset,
+    limit: usize,
+) -> YearInReview {
+    let in_year = |p: &PlayRecord| p.played_at.with_timezone(&offset).year() == year;
+    let plays: Vec<&PlayRecord> = all_plays.iter().filter(|p| in_year(p)).collect();
+
+    let mut by_month = [0u32; 12];
+    let mut by_day: HashMap<NaiveDate, u32> = HashMap::new();
+    for play in &plays {
+        let local = play.played_at.with_timezone(&offset);
+        by_month[local.month0() as usize] += 1;
+        *by_day.entry(local.date_naive()).or_default() += 1;
+    }
+
+    let top_month = by_month
+        .iter()
+        .enumerate()
+        .filter(|(_, &count)| count > 0)
+        .max_by_key(|(month, &count)| (count, std::cmp::Reverse(*month)))
+        .map(|(month, _)| month as u32 + 1);
+    let top_day = by_day
+        .iter()
+        .max_by_key(|(day, &count)| (count, std::cmp::Reverse(**day)))
+        .map(|(day, _)| *day);
+
+    let artist_of = |p: &PlayRecord| index.song(&p.song_id).map(|s| s.artist_id.clone());
+    let known_before: HashSet<String> = all_plays
+        .iter()
+        .filter(|p| p.played_at.with_timezone(&offset).year() < year)
+        .filter_map(artist_of)
+        .collect();
+    let new_artists = plays
+        .iter()
+        .filter_map(|p| artist_of(p))
+        .filter(|a| !known_before.contains(a))
+        .collect::<HashSet<_>>()
+        .len();
+
+    // Streaks within the year are measured as of its last day
+    let year_end = NaiveDate::from_ymd_opt(year, 12, 31)
+        .and_then(|d| d.and_hms_opt(23, 59, 59))
+        .map(|d| d.and_utc() - Duration::seconds(i64::from(offset.local_minus_utc())))
+        .unwrap_or_else(Utc::now)
+        .min(Utc::now());
+
+    YearInReview {
+        year,
+        overview: overview(&plays, index, offset, year_end),
+        by_month,
+        top_month,
+        top_day,
+        new_artists,
+        top_artists: top(&plays, index, TopKind::Artists, limit),
+        top_albums: top(&plays, index, TopKind::Albums, limit),
+        top_tracks: top(&plays, index, TopKind::Tracks, limit),
+        top_genres: top(&plays, index, TopKind::Genres, limit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::path::Path;
+    use uuid::Uuid;
+
+    fn song(file: &str, artist: &str, genre: Option<&str>, duration: u32) -> SongMetadata {
+        SongMetadata {
+            id: SongMetadata::generate_id(Path::new(file)),
+            title: file.to_string(),
+            artist: artist.to_string(),
+            artist_id: SongMetadata::generate_name_id("artist", artist),
+            album: "Album".to_string(),
+            album_id: SongMetadata::generate_name_id("album", "Album"),
+            duration: Some(duration),
+            track_number: None,
+            year: None,
+            genre: genre.map(str::to_string),
+            format: "mp3".to_string(),
+            file: file.to_string(),
+            has_cover: false,
+            starred: None,
+            rating: None,
+            play_count: 0,
+            last_played: None,
+        }
+    }
+
+    fn index() -> LibraryIndex {
+        let mut index = LibraryIndex::default();
+        index.songs = vec![
+            song("a.mp3", "Alpha", Some("Rock"), 100),
+            song("b.mp3", "Beta", Some("Jazz"), 200),
+        ];
+        index
+    }
+
+    fn play(file: &str, at: DateTime<Utc>) -> PlayRecord {
+        PlayRecord::new(Uuid::nil(), SongMetadata::generate_id(Path::new(file)), at)
+    }
+
+    #[test]
+    fn test_top_tracks_and_genres() {
+        let index = index();
+        let t = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let plays = [play("a.mp3", t), play("b.mp3", t), play("b.mp3", t)];
+        let refs: Vec<&PlayRecord> = plays.iter().collect();
+
+        let tracks = top(&refs, &index, TopKind::Tracks, 10);
+        assert_eq!(tracks[0].name, "b.mp3");
+        assert_eq!(tracks[0].plays, 2);
+        assert_eq!(tracks[0].listening_seconds, 400);
+
+        let genres = top(&refs, &index, TopKind::Genres, 1);
+        assert_eq!(genres.len(), 1);
+        assert_eq!(genres[0].id, "jazz");
+    }
+
+    #[test]
+    fn test_streaks() {
+        let d = |day| NaiveDate::from_ymd_opt(2024, 3, day).unwrap();
+        let days: BTreeSet<NaiveDate> = [1, 2, 3, 7, 8].into_iter().map(d).collect();
+
+        let result = streaks(&days, d(9));
+        assert_eq!(result.longest, 3);
+        assert_eq!(result.longest_start, Some(d(1)));
+        assert_eq!(result.current, 2);
+
+        assert_eq!(streaks(&days, d(20)).current, 0);
+    }
+
+    #[test]
+    fn test_overview_uses_offset_for_buckets() {
+        let index = index();
+        // 23:30 UTC on a Sunday is 01:30 Monday at UTC+2
+        let at = Utc.with_ymd_and_hms(2024, 3, 3, 23, 30, 0).unwrap();
+        let plays = [play("a.mp3", at)];
+        let refs: Vec<&PlayRecord> = plays.iter().collect();
+        let offset = FixedOffset::east_opt(2 * 3600).unwrap();
+
+        let result = overview(&refs, &index, offset, at);
+        assert_eq!(result.by_hour[1], 1);
+        assert_eq!(result.by_weekday[0], 1);
+        assert_eq!(result.listening_seconds, 100);
+        assert_eq!(result.streaks.current, 1);
+    }
+
+    #[test]
+    fn test_year_in_review_counts_new_artists() {
+        let index = index();
+        let plays = vec![
+            play("a.mp3", Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap()),
+            play("a.mp3", Utc.with_ymd_and_hms(2024, 2, 1, 12, 0, 0).unwrap()),
+            play("b.mp3", Utc.with_ymd_and_hms(2024, 2, 2, 12, 0, 0).unwrap()),
+        ];
+
+        let report = year_in_review(&plays, &index, 2024, FixedOffset::east_opt(0).unwrap(), 5);
+        assert_eq!(report.overview.plays, 2);
+        assert_eq!(report.new_artists, 1);
+        assert_eq!(report.top_month, Some(2));
+        assert_eq!(report.by_month[1], 2);
+        assert_eq!(report.overview.streaks.longest, 2);
+    }
+}
@@ -0,0 +1,94 @@
+//! JSON file persistence helpers.
+//!
+//! Shared by the file-backed repositories: each one keeps its data in an
+//! in-memory cache and writes the whole store back on every change.
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::Path;
+use uuid::Uuid;
+
+use crate::error::AppResult;
+
+/// Load a JSON store from disk, returning the default value if the file does not exist.
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> AppResult<T> {
+    if !path.exists() {
+        tracing::info!(path = %path.display(), "Store file not found, starting fresh");
+        return Ok(T::default());
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Write a JSON store to disk atomically (temp file + rename).
+///
+/// Each write uses its own temp file, so concurrent writes of the same
+/// store cannot clobber each other's partial output.
+pub fn save_json<T: Serialize>(path: &Path, value: &T) -> AppResult<()> {
+    let content = serde_json::to_string_pretty(value)?;
+
+    // Ensure parent directory exists
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let temp_path = path.with_extension(format!("json.{}.tmp", Uuid::new_v4().simple()));
+    let written =
+        std::fs::write(&temp_path, &content).and_then(|_| std::fs::rename(&temp_path, path));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+
+    tracing::debug!(path = %path.display(), "Saved store to file");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_missing_file_loads_default() {
+        let dir = tempdir().unwrap();
+        let store: HashMap<String, u32> = load_json(&dir.path().join("missing.json")).unwrap();
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("nested").join("store.json");
+
+        let mut store = HashMap::new();
+        store.insert("a".to_string(), 1u32);
+        save_json(&path, &store).unwrap();
+
+        let loaded: HashMap<String, u32> = load_json(&path).unwrap();
+        assert_eq!(loaded.get("a"), Some(&1));
+    }
+
+    #[test]
+    fn test_concurrent_saves_do_not_collide() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("store.json");
+
+        std::thread::scope(|scope| {
+            for i in 0..8u32 {
+                let path = &path;
+                scope.spawn(move || {
+                    let store: HashMap<String, u32> =
+                        (0..500).map(|j| (j.to_string(), i)).collect();
+                    save_json(path, &store).unwrap();
+                });
+            }
+        });
+
+        let loaded: HashMap<String, u32> = load_json(&path).unwrap();
+        assert_eq!(loaded.len(), 500);
+        // Only the store itself is left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,214 @@
+//! Per-user annotations (stars and ratings) and their repository.
+
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::error::AppResult;
+use crate::storage;
+
+/// Kind of library item an annotation refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemType {
+    Song,
+    Album,
+    Artist,
+}
+
+/// A user's annotation of a song, album or artist.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Annotation {
+    /// Annotating user.
+    pub user_id: Uuid,
+    /// Annotated item ID (song, album or artist ID).
+    pub item_id: String,
+    /// Kind of annotated item.
+    pub item_type: ItemType,
+    /// When the item was starred, if it is.
+    pub starred_at: Option<DateTime<Utc>>,
+    /// Rating from 1 to 5.
+    pub rating: Option<u8>,
+}
+
+impl Annotation {
+    /// Create an empty annotation.
+    pub fn new(user_id: Uuid, item_id: String, item_type: ItemType) -> Self {
+        Self {
+            user_id,
+            item_id,
+            item_type,
+            starred_at: None,
+            rating: None,
+        }
+    }
+
+    /// Whether the annotation carries no data and can be dropped.
+    pub fn is_empty(&self) -> bool {
+        self.starred_at.is_none() && self.rating.is_none()
+    }
+}
+
+/// Annotation storage format for JSON file.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct AnnotationStore {
+    annotations: Vec<Annotation>,
+}
+
+/// Trait for annotation repository operations.
+pub trait AnnotationRepository: Send + Sync {
+    /// Find a user's annotation of an item.
+    fn find(&self, user_id: Uuid, item_id: &str) -> AppResult<Option<Annotation>>;
+
+    /// List all annotations of a user.
+    fn list_by_user(&self, user_id: Uuid) -> AppResult<Vec<Annotation>>;
+
+    /// Insert or replace an annotation. Empty annotations are removed.
+    fn save(&self, annotation: Annotation) -> AppResult<Annotation>;
+
+    /// Delete all annotations of a user, returning how many were removed.
+    fn delete_by_user(&self, user_id: Uuid) -> AppResult<usize>;
+}
+
+/// JSON file-based annotation repository.
+#[derive(Debug)]
+pub struct JsonAnnotationRepository {
+    file_path: PathBuf,
+    /// In-memory cache keyed by user ID and item ID.
+    cache: RwLock<HashMap<(Uuid, String), Annotation>>,
+}
+
+impl JsonAnnotationRepository {
+    /// Create a new JSON annotation repository.
+    pub fn new(file_path: impl AsRef<Path>) -> AppResult<Self> {
+        let file_path = file_path.as_ref().to_path_buf();
+        let store: AnnotationStore = storage::load_json(&file_path)?;
+
+        let cache = store
+            .annotations
+            .into_iter()
+            .map(|a| ((a.user_id, a.item_id.clone()), a))
+            .collect::<HashMap<_, _>>();
+
+        tracing::info!(count = cache.len(), "Loaded annotations from file");
+
+        Ok(Self {
+            file_path,
+            cache: RwLock::new(cache),
+        })
+    }
+
+    /// Annotations of a user keyed by item ID, for merging into responses.
+    pub fn map_for_user(&self, user_id: Uuid) -> AppResult<HashMap<String, Annotation>> {
+        Ok(self
+            .list_by_user(user_id)?
+            .into_iter()
+            .map(|a| (a.item_id.clone(), a))
+            .collect())
+    }
+
+    /// Write annotations from cache to file.
+    fn persist(&self) -> AppResult<()> {
+        let cache = self.cache.read();
+        let store = AnnotationStore {
+            annotations: cache.values().cloned().collect(),
+        };
+        storage::save_json(&self.file_path, &store)
+    }
+}
+
+impl AnnotationRepository for JsonAnnotationRepository {
+    fn find(&self, user_id: Uuid, item_id: &str) -> AppResult<Option<Annotation>> {
+        Ok(self
+            .cache
+            .read()
+            .get(&(user_id, item_id.to_string()))
+            .cloned())
+    }
+
+    fn list_by_user(&self, user_id: Uuid) -> AppResult<Vec<Annotation>> {
+        Ok(self
+            .cache
+            .read()
+            .values()
+            .filter(|a| a.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    fn save(&self, annotation: Annotation) -> AppResult<Annotation> {
+        {
+            let mut cache = self.cache.write();
+            let key = (annotation.user_id, annotation.item_id.clone());
+            if annotation.is_empty() {
+                cache.remove(&key);
+            } else {
+                cache.insert(key, annotation.clone());
+            }
+        }
+
+        self.persist()?;
+        tracing::debug!(
+            user_id = %annotation.user_id,
+            item_id = %annotation.item_id,
+            "Saved annotation"
+        );
+        Ok(annotation)
+    }
+
+    fn delete_by_user(&self, user_id: Uuid) -> AppResult<usize> {
+        let removed = {
+            let mut cache = self.cache.write();
+            let before = cache.len();
+            cache.retain(|(owner, _), _| *owner != user_id);
+            before - cache.len()
+        };
+
+        if removed > 0 {
+            self.persist()?;
+            tracing::info!(user_id = %user_id, count = removed, "Deleted user annotations");
+        }
+
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_annotations_are_per_user() {
+        let dir = tempdir().unwrap();
+        let repo = JsonAnnotationRepository::new(dir.path().join("annotations.json")).unwrap();
+        let alice = Uuid::new_v4();
+        let bob = Uuid::new_v4();
+
+        let mut annotation = Annotation::new(alice, "song1".to_string(), ItemType::Song);
+        annotation.rating = Some(4);
+        repo.save(annotation).unwrap();
+
+        assert_eq!(repo.find(alice, "song1").unwrap().unwrap().rating, Some(4));
+        assert!(repo.find(bob, "song1").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_empty_annotation_is_removed() {
+        let dir = tempdir().unwrap();
+        let repo = JsonAnnotationRepository::new(dir.path().join("annotations.json")).unwrap();
+        let user = Uuid::new_v4();
+
+        let mut annotation = Annotation::new(user, "album1".to_string(), ItemType::Album);
+        annotation.starred_at = Some(Utc::now());
+        let mut annotation = repo.save(annotation).unwrap();
+
+        annotation.starred_at = None;
+        repo.save(annotation).unwrap();
+
+        assert!(repo.list_by_user(user).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+//! Per-user scrobble forwarding settings and their repository.
+
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::auth::SecretBox;
+use crate::error::AppResult;
+use crate::storage;
+
+/// Default ListenBrainz API root.
+pub const DEFAULT_SERVICE_URL: &str = "https://api.listenbrainz.org";
+
+/// A user's scrobble forwarding settings for a ListenBrainz-compatible service.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardingConfig {
+    /// Owning user.
+    pub user_id: Uuid,
+    /// API root of the service (e.g. `https://api.listenbrainz.org`).
+    pub service_url: String,
+    /// The user's token on that service, sealed with
+    /// [`crate::auth::SecretBox`].
+    pub token: String,
+    /// The token with all but its last four characters masked.
+    #[serde(default)]
+    pub token_hint: String,
+    /// Whether new plays are forwarded.
+    pub enabled: bool,
+    /// Error of the most recent failed delivery, cleared on success.
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// Time of the most recent successful delivery.
+    #[serde(default)]
+    pub last_success_at: Option<DateTime<Utc>>,
+    /// When the settings were created.
+    pub created_at: DateTime<Utc>,
+    /// When the settings were last changed.
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ForwardingConfig {
+    /// Create enabled forwarding settings with a plaintext token.
+    pub fn new(
+        user_id: Uuid,
+        service_url: String,
+        token: &str,
+        secrets: &SecretBox,
+    ) -> AppResult<Self> {
+        let now = Utc::now();
+        let mut config = Self {
+            user_id,
+            service_url,
+            token: String::new(),
+            token_hint: String::new(),
+            enabled: true,
+            last_error: None,
+            last_success_at: None,
+            created_at: now,
+            updated_at: now,
+        };
+        config.set_token(token, secrets)?;
+        Ok(config)
+    }
+
+    /// Seal and store a plaintext token.
+    pub fn set_token(&mut self, token: &str, secrets: &SecretBox) -> AppResult<()> {
+        self.token = secrets.seal(token)?;
+        self.token_hint = mask_token(token);
+        Ok(())
+    }
+}
+
+/// Mask all but the last four characters of a token.
+fn mask_token(token: &str) -> String {
+    let chars: Vec<char> = token.chars().collect();
+    let visible = chars.len().saturating_sub(4);
+    let tail: String = chars[visible..].iter().collect();
+    format!("{}{}", "*".repeat(visible.min(8)), tail)
+}
+
+/// Forwarding settings storage format for JSON file.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct ForwardingStore {
+    configs: Vec<ForwardingConfig>,
+}
+
+/// Trait for forwarding settings repository operations.
+pub trait ForwardingRepository: Send + Sync {
+    /// Find a user's forwarding settings.
+    fn find(&self, user_id: Uuid) -> AppResult<Option<ForwardingConfig>>;
+
+    /// List every user's forwarding settings.
+    fn list_all(&self) -> AppResult<Vec<ForwardingConfig>>;
+
+    /// Insert or replace a user's forwarding settings.
+    fn save(&self, config: ForwardingConfig) -> AppResult<ForwardingConfig>;
+
+    /// Delete a user's forwarding settings, returning whether they existed.
+    fn delete(&self, user_id: Uuid) -> AppResult<bool>;
+
+    /// Record the outcome of a delivery attempt.
+    fn record_delivery(&self, user_id: Uuid, error: Option<String>) -> AppResult<()>;
+}
+
+/// JSON file-based forwarding settings repository.
+#[derive(Debug)]
+pub struct JsonForwardingRepository {
+    file_path: PathBuf,
+    /// In-memory cache keyed by user ID.
+    cache: RwLock<HashMap<Uuid, ForwardingConfig>>,
+}
+
+impl JsonForwardingRepository {
+    /// Create a new JSON forwarding settings repository.
+    pub fn new(file_path: impl AsRef<Path>) -> AppResult<Self> {
+        let file_path = file_path.as_ref().to_path_buf();
+        let store: ForwardingStore = storage::load_json(&file_path)?;
+
+        let cache = store
+            .configs
+            .into_iter()
+            .map(|c| (c.user_id, c))
+            .collect::<HashMap<_, _>>();
+
+        tracing::info!(
+            count = cache.len(),
+            "Loaded scrobble forwarding settings from file"
+        );
+
+        Ok(Self {
+            file_path,
+            cache: RwLock::new(cache),
+        })
+    }
+
+    /// Write settings from cache to file.
+    fn persist(&self) -> AppResult<()> {
+        let cache = self.cache.read();
+        let store = ForwardingStore {
+            configs: cache.values().cloned().collect(),
+        };
+        storage::save_json(&self.file_path, &store)
+    }
+}
+
+impl ForwardingRepository for JsonForwardingRepository {
+    fn find(&self, user_id: Uuid) -> AppResult<Option<ForwardingConfig>> {
+        Ok(self.cache.read().get(&user_id).cloned())
+    }
+
+    fn list_all(&self) -> AppResult<Vec<ForwardingConfig>> {
+        Ok(self.cache.read().values().cloned().collect())
+    }
+
+    fn save(&self, config: ForwardingConfig) -> AppResult<ForwardingConfig> {
+        self.cache.write().insert(config.user_id, config.clone());
+        self.persist()?;
+        tracing::debug!(user_id = %config.user_id, "Saved scrobble forwarding settings");
+        Ok(config)
+    }
+
+    fn delete(&self, user_id: Uuid) -> AppResult<bool> {
+        let removed = self.cache.write().remove(&user_id).is_some();
+        if removed {
+            self.persist()?;
+            tracing::debug!(user_id = %user_id, "Deleted scrobble forwarding settings");
+        }
+        Ok(removed)
+    }
+
+    fn record_delivery(&self, user_id: Uuid, error: Option<String>) -> AppResult<()> {
+        {
+            let mut cache = self.cache.write();
+            let Some(config) = cache.get_mut(&user_id) else {
+                return Ok(());
+            };
+            if error.is_none() {
+                config.last_success_at = Some(Utc::now());
+            }
+            config.last_error = error;
+        }
+        self.persist()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_settings_persist_and_record_delivery() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("scrobble_forwarding.json");
+        let user = Uuid::new_v4();
+
+        let secrets = SecretBox::from_key(&[7; 32]);
+        let repo = JsonForwardingRepository::new(&path).unwrap();
+        repo.save(
+            ForwardingConfig::new(
+                user,
+                DEFAULT_SERVICE_URL.to_string(),
+                "secret-token",
+                &secrets,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        repo.record_delivery(user, Some("Service unavailable".to_string()))
+            .unwrap();
+
+        let repo = JsonForwardingRepository::new(&path).unwrap();
+        let config = repo.find(user).unwrap().unwrap();
+        // Only the sealed token is stored
+        assert!(!std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("secret-token"));
+        assert_eq!(secrets.open(&config.token).unwrap(), "secret-token");
+        assert_eq!(config.last_error.as_deref(), Some("Service unavailable"));
+
+        repo.record_delivery(user, None).unwrap();
+        let config = repo.find(user).unwrap().unwrap();
+        assert!(config.last_error.is_none());
+        assert!(config.last_success_at.is_some());
+    }
+
+    #[test]
+    fn test_token_hint_masks_token() {
+        assert_eq!(mask_token("abcdefgh1234"), "********1234");
+        assert_eq!(mask_token("abc"), "abc");
+    }
+}
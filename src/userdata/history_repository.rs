@@ -0,0 +1,294 @@
+//! Play history and its repository.
+//!
+//! History is append-only: each play is written as one JSON line, so a
+//! crash can at worst lose the line being written. Only deleting a user's
+//! plays rewrites the file.
+
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::error::AppResult;
+
+/// A single recorded play.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayRecord {
+    /// Listening user.
+    pub user_id: Uuid,
+    /// Played song ID.
+    pub song_id: String,
+    /// When playback started.
+    pub played_at: DateTime<Utc>,
+}
+
+impl PlayRecord {
+    /// Create a new play record.
+    pub fn new(user_id: Uuid, song_id: String, played_at: DateTime<Utc>) -> Self {
+        Self {
+            user_id,
+            song_id,
+            played_at,
+        }
+    }
+}
+
+/// A song a user is currently playing.
+#[derive(Debug, Clone, Serialize)]
+pub struct NowPlaying {
+    /// Song ID.
+    pub song_id: String,
+    /// When playback started.
+    pub started_at: DateTime<Utc>,
+}
+
+/// Aggregated play statistics of one song for one user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SongPlayStats {
+    /// Number of recorded plays.
+    pub play_count: u32,
+    /// Most recent play.
+    pub last_played: Option<DateTime<Utc>>,
+}
+
+/// Trait for play history repository operations.
+pub trait HistoryRepository: Send + Sync {
+    /// Append a play to the history.
+    fn record(&self, play: PlayRecord) -> AppResult<PlayRecord>;
+
+    /// List a user's plays in chronological order.
+    fn list_by_user(&self, user_id: Uuid) -> AppResult<Vec<PlayRecord>>;
+
+    /// Per-song play statistics for a user, keyed by song ID.
+    fn stats_for_user(&self, user_id: Uuid) -> AppResult<HashMap<String, SongPlayStats>>;
+
+    /// Set the song a user is currently playing.
+    fn set_now_playing(&self, user_id: Uuid, now_playing: NowPlaying);
+
+    /// Get the song a user is currently playing, if any.
+    fn now_playing(&self, user_id: Uuid) -> Option<NowPlaying>;
+
+    /// Drop all of a user's plays, returning how many were removed.
+    fn delete_by_user(&self, user_id: Uuid) -> AppResult<usize>;
+}
+
+/// JSON Lines file-based play history repository.
+#[derive(Debug)]
+pub struct JsonHistoryRepository {
+    file_path: PathBuf,
+    /// All plays, grouped by user in chronological order.
+    plays: RwLock<HashMap<Uuid, Vec<PlayRecord>>>,
+    /// Current "now playing" entries (not persisted).
+    now_playing: RwLock<HashMap<Uuid, NowPlaying>>,
+}
+
+impl JsonHistoryRepository {
+    /// Create a new play history repository.
+    pub fn new(file_path: impl AsRef<Path>) -> AppResult<Self> {
+        let file_path = file_path.as_ref().to_path_buf();
+        let mut plays: HashMap<Uuid, Vec<PlayRecord>> = HashMap::new();
+        let mut count = 0usize;
+
+        if file_path.exists() {
+            let content = std::fs::read_to_string(&file_path)?;
+            for (number, line) in content.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // Skip a torn trailing line rather than refusing to start
+                match serde_json::from_str::<PlayRecord>(line) {
+                    Ok(play) => {
+                        plays.entry(play.user_id).or_default().push(play);
+                        count += 1;
+                    }
+                    Err(e) => {
+                        tracing::warn!(line = number + 1, error = %e, "Skipping invalid history line")
+                    }
+                }
+            }
+
+            // Terminate a torn line so the next append starts on a fresh one
+            if !content.is_empty() && !content.ends_with('\n') {
+                std::fs::OpenOptions::new()
+                    .append(true)
+                    .open(&file_path)?
+                    .write_all(b"\n")?;
+            }
+        }
+
+        for user_plays in plays.values_mut() {
+            user_plays.sort_by_key(|p| p.played_at);
+        }
+
+        tracing::info!(count, "Loaded play history from file");
+
+        Ok(Self {
+            file_path,
+            plays: RwLock::new(plays),
+            now_playing: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Append a line to the history file.
+    fn append(&self, play: &PlayRecord) -> AppResult<()> {
+        if let Some(parent) = self.file_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut line = serde_json::to_string(play)?;
+        line.push('\n');
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file_path)?;
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Rewrite the history file from memory (temp file + rename).
+    fn rewrite(&self, plays: &HashMap<Uuid, Vec<PlayRecord>>) -> AppResult<()> {
+        let mut content = String::new();
+        for play in plays.values().flatten() {
+            content.push_str(&serde_json::to_string(play)?);
+            content.push('\n');
+        }
+
+        let temp_path = self.file_path.with_extension("jsonl.tmp");
+        std::fs::write(&temp_path, content)?;
+        std::fs::rename(&temp_path, &self.file_path)?;
+        Ok(())
+    }
+}
+
+impl HistoryRepository for JsonHistoryRepository {
+    fn record(&self, play: PlayRecord) -> AppResult<PlayRecord> {
+        // Hold the lock while appending so file order matches memory order
+        let mut plays = self.plays.write();
+        self.append(&play)?;
+
+        let user_plays = plays.entry(play.user_id).or_default();
+        let position = user_plays.partition_point(|p| p.played_at <= play.played_at);
+        user_plays.insert(position, play.clone());
+
+        tracing::debug!(user_id = %play.user_id, song_id = %play.song_id, "Recorded play");
+        Ok(play)
+    }
+
+    fn list_by_user(&self, user_id: Uuid) -> AppResult<Vec<PlayRecord>> {
+        Ok(self.plays.read().get(&user_id).cloned().unwrap_or_default())
+    }
+
+    fn stats_for_user(&self, user_id: Uuid) -> AppResult<HashMap<String, SongPlayStats>> {
+        let plays = self.plays.read();
+        let mut stats: HashMap<String, SongPlayStats> = HashMap::new();
+
+        for play in plays.get(&user_id).into_iter().flatten() {
+            let entry = stats.entry(play.song_id.clone()).or_default();
+            entry.play_count += 1;
+            entry.last_played = entry.last_played.max(Some(play.played_at));
+        }
+
+        Ok(stats)
+    }
+
+    fn set_now_playing(&self, user_id: Uuid, now_playing: NowPlaying) {
+        self.now_playing.write().insert(user_id, now_playing);
+    }
+
+    fn now_playing(&self, user_id: Uuid) -> Option<NowPlaying> {
+        self.now_playing.read().get(&user_id).cloned()
+    }
+
+    fn delete_by_user(&self, user_id: Uuid) -> AppResult<usize> {
+        self.now_playing.write().remove(&user_id);
+
+        let mut plays = self.plays.write();
+        let removed = plays.remove(&user_id).map_or(0, |p| p.len());
+        if removed > 0 {
+            self.rewrite(&plays)?;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_record_and_stats() {
+        let dir = tempdir().unwrap();
+        let repo = JsonHistoryRepository::new(dir.path().join("history.jsonl")).unwrap();
+        let user = Uuid::new_v4();
+        let now = Utc::now();
+
+        repo.record(PlayRecord::new(user, "a".into(), now)).unwrap();
+        repo.record(PlayRecord::new(user, "a".into(), now - Duration::hours(1)))
+            .unwrap();
+        repo.record(PlayRecord::new(Uuid::new_v4(), "a".into(), now))
+            .unwrap();
+
+        let stats = repo.stats_for_user(user).unwrap();
+        assert_eq!(stats["a"].play_count, 2);
+        assert_eq!(stats["a"].last_played, Some(now));
+
+        // Out-of-order submissions are kept chronological
+        let plays = repo.list_by_user(user).unwrap();
+        assert!(plays[0].played_at < plays[1].played_at);
+    }
+
+    #[test]
+    fn test_history_survives_reload_and_torn_lines() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let user = Uuid::new_v4();
+
+        {
+            let repo = JsonHistoryRepository::new(&path).unwrap();
+            repo.record(PlayRecord::new(user, "a".into(), Utc::now()))
+                .unwrap();
+        }
+
+        // Simulate a crash mid-write
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"{\"user_id\":").unwrap();
+
+        let repo = JsonHistoryRepository::new(&path).unwrap();
+        assert_eq!(repo.list_by_user(user).unwrap().len(), 1);
+
+        repo.record(PlayRecord::new(user, "b".into(), Utc::now()))
+            .unwrap();
+        let repo = JsonHistoryRepository::new(&path).unwrap();
+        assert_eq!(repo.list_by_user(user).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_delete_by_user() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let user = Uuid::new_v4();
+        let other = Uuid::new_v4();
+
+        let repo = JsonHistoryRepository::new(&path).unwrap();
+        repo.record(PlayRecord::new(user, "a".into(), Utc::now()))
+            .unwrap();
+        repo.record(PlayRecord::new(other, "b".into(), Utc::now()))
+            .unwrap();
+
+        assert_eq!(repo.delete_by_user(user).unwrap(), 1);
+        assert!(repo.list_by_user(user).unwrap().is_empty());
+
+        let repo = JsonHistoryRepository::new(&path).unwrap();
+        assert!(repo.list_by_user(user).unwrap().is_empty());
+        assert_eq!(repo.list_by_user(other).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,23 @@
+//! Per-user data stores.
+
+pub mod annotation_repository;
+pub mod forwarding_repository;
+pub mod history_repository;
+pub mod play_queue_repository;
+pub mod playlist_repository;
+pub mod room_repository;
+pub mod share_repository;
+
+pub use annotation_repository::{
+    Annotation, AnnotationRepository, ItemType, JsonAnnotationRepository,
+};
+pub use forwarding_repository::{
+    ForwardingConfig, ForwardingRepository, JsonForwardingRepository, DEFAULT_SERVICE_URL,
+};
+pub use history_repository::{
+    HistoryRepository, JsonHistoryRepository, NowPlaying, PlayRecord, SongPlayStats,
+};
+pub use play_queue_repository::{JsonPlayQueueRepository, PlayQueue, PlayQueueRepository};
+pub use playlist_repository::{JsonPlaylistRepository, Playlist, PlaylistRepository};
+pub use room_repository::{JsonRoomRepository, Proposal, Room, RoomPlayback, RoomRepository};
+pub use share_repository::{JsonShareRepository, Share, ShareKind, ShareRepository};
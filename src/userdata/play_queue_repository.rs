@@ -0,0 +1,196 @@
+//! Per-user play queues and their repository.
+//!
+//! A queue is saved whole by whichever device is playing. Every save bumps
+//! its version; a save that names an older version than the stored one is
+//! rejected so that two devices do not silently overwrite each other.
+
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::error::{AppError, AppResult};
+use crate::storage;
+
+/// A user's play queue.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PlayQueue {
+    /// Owning user.
+    pub user_id: Uuid,
+    /// Queued song IDs, in play order.
+    pub song_ids: Vec<String>,
+    /// Index of the current song in `song_ids`.
+    pub current: Option<usize>,
+    /// Playback position within the current song, in milliseconds.
+    pub position_ms: u64,
+    /// Device that last saved the queue.
+    pub changed_by: Option<String>,
+    /// Incremented on every save, starting at 1.
+    pub version: u64,
+    /// When the queue was last saved.
+    pub updated_at: DateTime<Utc>,
+}
+
+impl PlayQueue {
+    /// Create an unsaved queue (version 0).
+    pub fn new(user_id: Uuid) -> Self {
+        Self {
+            user_id,
+            song_ids: Vec::new(),
+            current: None,
+            position_ms: 0,
+            changed_by: None,
+            version: 0,
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+/// Play queue storage format for JSON file.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct PlayQueueStore {
+    queues: Vec<PlayQueue>,
+}
+
+/// Trait for play queue repository operations.
+pub trait PlayQueueRepository: Send + Sync {
+    /// Find a user's play queue.
+    fn find(&self, user_id: Uuid) -> AppResult<Option<PlayQueue>>;
+
+    /// Save a user's play queue, assigning the next version.
+    ///
+    /// With `expected_version`, the save fails with a conflict unless the
+    /// stored queue is at that version (0 for a user without a queue).
+    fn save(&self, queue: PlayQueue, expected_version: Option<u64>) -> AppResult<PlayQueue>;
+
+    /// Delete a user's play queue, returning whether it existed.
+    fn delete(&self, user_id: Uuid) -> AppResult<bool>;
+}
+
+/// JSON file-based play queue repository.
+#[derive(Debug)]
+pub struct JsonPlayQueueRepository {
+    file_path: PathBuf,
+    /// In-memory cache keyed by user ID.
+    cache: RwLock<HashMap<Uuid, PlayQueue>>,
+}
+
+impl JsonPlayQueueRepository {
+    /// Create a new JSON play queue repository.
+    pub fn new(file_path: impl AsRef<Path>) -> AppResult<Self> {
+        let file_path = file_path.as_ref().to_path_buf();
+        let store: PlayQueueStore = storage::load_json(&file_path)?;
+
+        let cache = store
+            .queues
+            .into_iter()
+            .map(|q| (q.user_id, q))
+            .collect::<HashMap<_, _>>();
+
+        tracing::info!(count = cache.len(), "Loaded play queues from file");
+
+        Ok(Self {
+            file_path,
+            cache: RwLock::new(cache),
+        })
+    }
+
+    /// Write queues from cache to file.
+    fn persist(&self) -> AppResult<()> {
+        let cache = self.cache.read();
+        let store = PlayQueueStore {
+            queues: cache.values().cloned().collect(),
+        };
+        storage::save_json(&self.file_path, &store)
+    }
+}
+
+impl PlayQueueRepository for JsonPlayQueueRepository {
+    fn find(&self, user_id: Uuid) -> AppResult<Option<PlayQueue>> {
+        Ok(self.cache.read().get(&user_id).cloned())
+    }
+
+    fn save(&self, mut queue: PlayQueue, expected_version: Option<u64>) -> AppResult<PlayQueue> {
+        {
+            let mut cache = self.cache.write();
+            let stored_version = cache.get(&queue.user_id).map_or(0, |q| q.version);
+            if let Some(expected) = expected_version {
+                if expected != stored_version {
+                    return Err(AppError::Conflict(format!(
+                        "Play queue was changed by another device (version {}, expected {})",
+                        stored_version, expected
+                    )));
+                }
+            }
+            queue.version = stored_version + 1;
+            queue.updated_at = Utc::now();
+            cache.insert(queue.user_id, queue.clone());
+        }
+        self.persist()?;
+
+        tracing::debug!(
+            user_id = %queue.user_id,
+            version = queue.version,
+            "Saved play queue"
+        );
+        Ok(queue)
+    }
+
+    fn delete(&self, user_id: Uuid) -> AppResult<bool> {
+        let removed = self.cache.write().remove(&user_id).is_some();
+        if removed {
+            self.persist()?;
+            tracing::debug!(user_id = %user_id, "Deleted play queue");
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_save_assigns_versions_and_persists() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("play_queues.json");
+        let user = Uuid::new_v4();
+
+        let repo = JsonPlayQueueRepository::new(&path).unwrap();
+        let mut queue = PlayQueue::new(user);
+        queue.song_ids = vec!["a".to_string(), "b".to_string()];
+        queue.current = Some(1);
+        queue.position_ms = 42_000;
+        queue.changed_by = Some("laptop".to_string());
+        let saved = repo.save(queue, Some(0)).unwrap();
+        assert_eq!(saved.version, 1);
+
+        let repo = JsonPlayQueueRepository::new(&path).unwrap();
+        let loaded = repo.find(user).unwrap().unwrap();
+        assert_eq!(loaded, saved);
+
+        assert!(repo.delete(user).unwrap());
+        assert!(repo.find(user).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_stale_version_is_rejected() {
+        let dir = tempdir().unwrap();
+        let repo = JsonPlayQueueRepository::new(dir.path().join("play_queues.json")).unwrap();
+        let user = Uuid::new_v4();
+
+        let laptop = repo.save(PlayQueue::new(user), Some(0)).unwrap();
+        let phone = repo.save(laptop.clone(), Some(laptop.version)).unwrap();
+        assert_eq!(phone.version, 2);
+
+        // The laptop still thinks it has version 1
+        let result = repo.save(laptop.clone(), Some(laptop.version));
+        assert!(matches!(result, Err(AppError::Conflict(_))));
+
+        // Saving without a version always wins
+        assert_eq!(repo.save(laptop, None).unwrap().version, 3);
+    }
+}
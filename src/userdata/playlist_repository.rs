@@ -0,0 +1,224 @@
+//! Playlist data model and repository.
+
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::error::{AppError, AppResult};
+use crate::storage;
+
+/// Playlist model.
+///
+/// User playlists are owned and editable; playlists imported from files in
+/// the music folder have no owner and are read-only.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Playlist {
+    /// Unique playlist ID.
+    pub id: String,
+    /// Playlist name.
+    pub name: String,
+    /// Owning user, `None` for imported playlists.
+    pub owner_id: Option<Uuid>,
+    /// Ordered song IDs.
+    pub song_ids: Vec<String>,
+    /// Whether the playlist can be modified through the API.
+    pub read_only: bool,
+    /// Source filename for imported playlists.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Number of entries in the source file that could not be matched to a song.
+    #[serde(default)]
+    pub unresolved: usize,
+    /// Creation timestamp.
+    pub created_at: DateTime<Utc>,
+    /// Last modification timestamp.
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Playlist {
+    /// Create a new user playlist.
+    pub fn new(owner_id: Uuid, name: String, song_ids: Vec<String>) -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            name,
+            owner_id: Some(owner_id),
+            song_ids,
+            read_only: false,
+            source: None,
+            unresolved: 0,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Check whether a user may see this playlist.
+    pub fn is_visible_to(&self, user_id: Uuid) -> bool {
+        self.owner_id.map(|owner| owner == user_id).unwrap_or(true)
+    }
+}
+
+/// Playlist storage format for JSON file.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct PlaylistStore {
+    playlists: Vec<Playlist>,
+}
+
+/// Trait for playlist repository operations.
+pub trait PlaylistRepository: Send + Sync {
+    /// Find a playlist by ID.
+    fn find_by_id(&self, id: &str) -> AppResult<Option<Playlist>>;
+
+    /// List playlists owned by a user.
+    fn list_by_owner(&self, owner_id: Uuid) -> AppResult<Vec<Playlist>>;
+
+    /// Create a new playlist.
+    fn create(&self, playlist: Playlist) -> AppResult<Playlist>;
+
+    /// Update a playlist.
+    fn update(&self, playlist: Playlist) -> AppResult<Playlist>;
+
+    /// Delete a playlist by ID.
+    fn delete(&self, id: &str) -> AppResult<bool>;
+}
+
+/// JSON file-based playlist repository.
+#[derive(Debug)]
+pub struct JsonPlaylistRepository {
+    file_path: PathBuf,
+    /// In-memory cache for fast reads.
+    cache: RwLock<HashMap<String, Playlist>>,
+}
+
+impl JsonPlaylistRepository {
+    /// Create a new JSON playlist repository.
+    pub fn new(file_path: impl AsRef<Path>) -> AppResult<Self> {
+        let file_path = file_path.as_ref().to_path_buf();
+        let store: PlaylistStore = storage::load_json(&file_path)?;
+
+        let cache = store
+            .playlists
+            .into_iter()
+            .map(|p| (p.id.clone(), p))
+            .collect::<HashMap<_, _>>();
+
+        tracing::info!(count = cache.len(), "Loaded playlists from file");
+
+        Ok(Self {
+            file_path,
+            cache: RwLock::new(cache),
+        })
+    }
+
+    /// Save playlists from cache to file.
+    fn save(&self) -> AppResult<()> {
+        let cache = self.cache.read();
+        let store = PlaylistStore {
+            playlists: cache.values().cloned().collect(),
+        };
+        storage::save_json(&self.file_path, &store)
+    }
+}
+
+impl PlaylistRepository for JsonPlaylistRepository {
+    fn find_by_id(&self, id: &str) -> AppResult<Option<Playlist>> {
+        Ok(self.cache.read().get(id).cloned())
+    }
+
+    fn list_by_owner(&self, owner_id: Uuid) -> AppResult<Vec<Playlist>> {
+        let cache = self.cache.read();
+        let mut playlists: Vec<Playlist> = cache
+            .values()
+            .filter(|p| p.owner_id == Some(owner_id))
+            .cloned()
+            .collect();
+        playlists.sort_by_key(|p| p.created_at);
+        Ok(playlists)
+    }
+
+    fn create(&self, playlist: Playlist) -> AppResult<Playlist> {
+        if playlist.owner_id.is_none() || playlist.read_only {
+            return Err(AppError::BadRequest(
+                "Only user playlists can be stored".to_string(),
+            ));
+        }
+
+        self.cache
+            .write()
+            .insert(playlist.id.clone(), playlist.clone());
+
+        self.save()?;
+        tracing::info!(playlist_id = %playlist.id, "Created playlist");
+        Ok(playlist)
+    }
+
+    fn update(&self, playlist: Playlist) -> AppResult<Playlist> {
+        {
+            let mut cache = self.cache.write();
+            if !cache.contains_key(&playlist.id) {
+                return Err(AppError::NotFound(format!(
+                    "Playlist {} not found",
+                    playlist.id
+                )));
+            }
+            cache.insert(playlist.id.clone(), playlist.clone());
+        }
+
+        self.save()?;
+        tracing::debug!(playlist_id = %playlist.id, "Updated playlist");
+        Ok(playlist)
+    }
+
+    fn delete(&self, id: &str) -> AppResult<bool> {
+        let removed = self.cache.write().remove(id).is_some();
+
+        if removed {
+            self.save()?;
+            tracing::info!(playlist_id = %id, "Deleted playlist");
+        }
+
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_create_and_list_by_owner() {
+        let dir = tempdir().unwrap();
+        let repo = JsonPlaylistRepository::new(dir.path().join("playlists.json")).unwrap();
+        let owner = Uuid::new_v4();
+
+        repo.create(Playlist::new(owner, "Mine".to_string(), vec!["a".into()]))
+            .unwrap();
+        repo.create(Playlist::new(Uuid::new_v4(), "Other".to_string(), vec![]))
+            .unwrap();
+
+        let mine = repo.list_by_owner(owner).unwrap();
+        assert_eq!(mine.len(), 1);
+        assert_eq!(mine[0].name, "Mine");
+    }
+
+    #[test]
+    fn test_persists_across_instances() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("playlists.json");
+        let owner = Uuid::new_v4();
+
+        let id = {
+            let repo = JsonPlaylistRepository::new(&path).unwrap();
+            repo.create(Playlist::new(owner, "Saved".to_string(), vec![]))
+                .unwrap()
+                .id
+        };
+
+        let repo = JsonPlaylistRepository::new(&path).unwrap();
+        assert!(repo.find_by_id(&id).unwrap().is_some());
+    }
+}
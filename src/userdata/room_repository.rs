@@ -0,0 +1,461 @@
+//! Listening room data model and repository.
+//!
+//! A room is created by a host, who invites other users. Joined members
+//! share one queue: anyone can propose a song, and it is queued once a
+//! majority of the members voted for it. The host drives playback and the
+//! position is broadcast to everyone. Rooms expire and are then treated as
+//! gone.
+
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::error::{AppError, AppResult};
+use crate::storage;
+
+/// Most songs in a room queue.
+pub const MAX_QUEUE_LEN: usize = 1000;
+
+/// A song proposed for the queue.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Proposal {
+    pub song_id: String,
+    pub proposed_by: Uuid,
+    /// Members who voted for the song, including the proposer.
+    pub votes: Vec<Uuid>,
+    pub proposed_at: DateTime<Utc>,
+}
+
+/// Shared playback position, set by the host.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RoomPlayback {
+    /// Index of the current song in the queue.
+    pub current: Option<usize>,
+    /// Position within the current song at `updated_at`, in milliseconds.
+    pub position_ms: u64,
+    pub playing: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for RoomPlayback {
+    fn default() -> Self {
+        Self {
+            current: None,
+            position_ms: 0,
+            playing: false,
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+/// Listening room model.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Room {
+    /// Unique room ID.
+    pub id: String,
+    pub name: String,
+    pub host_id: Uuid,
+    /// Joined members, including the host.
+    pub members: Vec<Uuid>,
+    /// Users invited but not yet joined.
+    pub invited: Vec<Uuid>,
+    /// Ordered song IDs.
+    pub queue: Vec<String>,
+    /// Songs waiting for enough votes.
+    pub proposals: Vec<Proposal>,
+    pub playback: RoomPlayback,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Room {
+    /// Create a room that expires after `lifetime`.
+    pub fn new(host_id: Uuid, name: String, lifetime: Duration) -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            name,
+            host_id,
+            members: vec![host_id],
+            invited: Vec::new(),
+            queue: Vec::new(),
+            proposals: Vec::new(),
+            playback: RoomPlayback::default(),
+            created_at: now,
+            expires_at: now + lifetime,
+        }
+    }
+
+    /// Check whether a user has joined the room.
+    pub fn is_member(&self, user_id: Uuid) -> bool {
+        self.members.contains(&user_id)
+    }
+
+    /// Check whether a user may see the room (members and invitees).
+    pub fn is_visible_to(&self, user_id: Uuid) -> bool {
+        self.is_member(user_id) || self.invited.contains(&user_id)
+    }
+
+    /// Check whether the room has expired.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+
+    /// Everyone who should hear about changes to the room.
+    pub fn audience(&self) -> Vec<Uuid> {
+        self.members.iter().chain(&self.invited).copied().collect()
+    }
+
+    /// Votes a proposal needs to be queued: a majority of the members.
+    pub fn votes_needed(&self) -> usize {
+        self.members.len() / 2 + 1
+    }
+
+    /// Vote for a song, proposing it if nobody has yet. Songs the host
+    /// votes for are queued right away. Returns whether the song was queued
+    /// as a result.
+    pub fn vote(&mut self, user_id: Uuid, song_id: &str) -> bool {
+        if user_id == self.host_id {
+            self.proposals.retain(|p| p.song_id != song_id);
+            self.queue.push(song_id.to_string());
+            return true;
+        }
+
+        match self.proposals.iter_mut().find(|p| p.song_id == song_id) {
+            Some(proposal) => {
+                if !proposal.votes.contains(&user_id) {
+                    proposal.votes.push(user_id);
+                }
+            }
+            None => self.proposals.push(Proposal {
+                song_id: song_id.to_string(),
+                proposed_by: user_id,
+                votes: vec![user_id],
+                proposed_at: Utc::now(),
+            }),
+        }
+        let queued = self.queue.len();
+        self.settle();
+        self.queue.len() > queued
+    }
+
+    /// Queue proposals that have enough votes, in proposal order.
+    fn settle(&mut self) {
+        let needed = self.votes_needed();
+        let (passed, pending): (Vec<_>, Vec<_>) = self
+            .proposals
+            .drain(..)
+            .partition(|p| p.votes.len() >= needed);
+        self.proposals = pending;
+        self.queue.extend(passed.into_iter().map(|p| p.song_id));
+    }
+
+    /// Remove a member or invitation, with their votes.
+    pub fn remove_member(&mut self, user_id: Uuid) -> bool {
+        let before = self.members.len() + self.invited.len();
+        self.members.retain(|id| *id != user_id);
+        self.invited.retain(|id| *id != user_id);
+        for proposal in &mut self.proposals {
+            proposal.votes.retain(|id| *id != user_id);
+        }
+        self.proposals.retain(|p| !p.votes.is_empty());
+        // Fewer members may mean fewer votes needed
+        self.settle();
+        self.members.len() + self.invited.len() < before
+    }
+
+    /// Remove a song from the queue, keeping the current song current.
+    pub fn remove_from_queue(&mut self, position: usize) -> Option<String> {
+        if position >= self.queue.len() {
+            return None;
+        }
+        let song_id = self.queue.remove(position);
+        self.playback.current = match self.playback.current {
+            Some(current) if position < current => Some(current - 1),
+            Some(current) if position == current => {
+                // The next song takes its place
+                self.playback.position_ms = 0;
+                self.playback.updated_at = Utc::now();
+                (current < self.queue.len()).then_some(current)
+            }
+            current => current,
+        };
+        Some(song_id)
+    }
+}
+
+/// Room storage format for JSON file.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct RoomStore {
+    rooms: Vec<Room>,
+}
+
+/// Trait for room repository operations.
+///
+/// Expired rooms are never returned.
+pub trait RoomRepository: Send + Sync {
+    /// Find a room by ID.
+    fn find_by_id(&self, id: &str) -> AppResult<Option<Room>>;
+
+    /// List all rooms.
+    fn list_all(&self) -> AppResult<Vec<Room>>;
+
+    /// List rooms a user has joined or been invited to.
+    fn list_for_user(&self, user_id: Uuid) -> AppResult<Vec<Room>>;
+
+    /// Create a new room.
+    fn create(&self, room: Room) -> AppResult<Room>;
+
+    /// Update a room.
+    fn update(&self, room: Room) -> AppResult<Room>;
+
+    /// Cast a member's vote for a song (see [`Room::vote`]). Done under the
+    /// write lock, so concurrent votes are not lost.
+    fn vote(&self, id: &str, user_id: Uuid, song_id: &str) -> AppResult<Room>;
+
+    /// Delete a room by ID.
+    fn delete(&self, id: &str) -> AppResult<bool>;
+
+    /// Delete expired rooms, returning how many were removed.
+    fn delete_expired(&self) -> AppResult<usize>;
+}
+
+/// JSON file-based room repository.
+#[derive(Debug)]
+pub struct JsonRoomRepository {
+    file_path: PathBuf,
+    /// In-memory cache keyed by room ID.
+    cache: RwLock<HashMap<String, Room>>,
+}
+
+impl JsonRoomRepository {
+    /// Create a new JSON room repository.
+    pub fn new(file_path: impl AsRef<Path>) -> AppResult<Self> {
+        let file_path = file_path.as_ref().to_path_buf();
+        let store: RoomStore = storage::load_json(&file_path)?;
+
+        let cache = store
+            .rooms
+            .into_iter()
+            .map(|r| (r.id.clone(), r))
+            .collect::<HashMap<_, _>>();
+
+        tracing::info!(count = cache.len(), "Loaded listening rooms from file");
+
+        Ok(Self {
+            file_path,
+            cache: RwLock::new(cache),
+        })
+    }
+
+    /// Write rooms from cache to file.
+    fn persist(&self) -> AppResult<()> {
+        let cache = self.cache.read();
+        let store = RoomStore {
+            rooms: cache.values().cloned().collect(),
+        };
+        storage::save_json(&self.file_path, &store)
+    }
+
+    fn list(&self, filter: impl Fn(&Room) -> bool) -> Vec<Room> {
+        let cache = self.cache.read();
+        let mut rooms: Vec<Room> = cache
+            .values()
+            .filter(|r| !r.is_expired() && filter(r))
+            .cloned()
+            .collect();
+        rooms.sort_by_key(|r| r.created_at);
+        rooms
+    }
+}
+
+impl RoomRepository for JsonRoomRepository {
+    fn find_by_id(&self, id: &str) -> AppResult<Option<Room>> {
+        Ok(self
+            .cache
+            .read()
+            .get(id)
+            .filter(|r| !r.is_expired())
+            .cloned())
+    }
+
+    fn list_all(&self) -> AppResult<Vec<Room>> {
+        Ok(self.list(|_| true))
+    }
+
+    fn list_for_user(&self, user_id: Uuid) -> AppResult<Vec<Room>> {
+        Ok(self.list(|r| r.is_visible_to(user_id)))
+    }
+
+    fn create(&self, room: Room) -> AppResult<Room> {
+        self.cache.write().insert(room.id.clone(), room.clone());
+        self.persist()?;
+        tracing::info!(room_id = %room.id, host_id = %room.host_id, "Created listening room");
+        Ok(room)
+    }
+
+    fn update(&self, room: Room) -> AppResult<Room> {
+        {
+            let mut cache = self.cache.write();
+            if cache.get(&room.id).is_none_or(|r| r.is_expired()) {
+                return Err(AppError::NotFound(format!("Room {} not found", room.id)));
+            }
+            cache.insert(room.id.clone(), room.clone());
+        }
+        self.persist()?;
+        tracing::debug!(room_id = %room.id, "Updated listening room");
+        Ok(room)
+    }
+
+    fn vote(&self, id: &str, user_id: Uuid, song_id: &str) -> AppResult<Room> {
+        let room = {
+            let mut cache = self.cache.write();
+            let room = cache
+                .get_mut(id)
+                .filter(|r| !r.is_expired())
+                .ok_or_else(|| AppError::NotFound(format!("Room {} not found", id)))?;
+            if !room.is_member(user_id) {
+                return Err(AppError::Forbidden("Join the room to vote".to_string()));
+            }
+            if room.queue.len() >= MAX_QUEUE_LEN {
+                return Err(AppError::Validation(format!(
+                    "Room queues are limited to {} songs",
+                    MAX_QUEUE_LEN
+                )));
+            }
+            room.vote(user_id, song_id);
+            room.clone()
+        };
+        self.persist()?;
+        tracing::debug!(room_id = %id, user_id = %user_id, song_id = %song_id, "Voted in listening room");
+        Ok(room)
+    }
+
+    fn delete(&self, id: &str) -> AppResult<bool> {
+        let removed = self.cache.write().remove(id).is_some();
+        if removed {
+            self.persist()?;
+            tracing::info!(room_id = %id, "Deleted listening room");
+        }
+        Ok(removed)
+    }
+
+    fn delete_expired(&self) -> AppResult<usize> {
+        let removed = {
+            let mut cache = self.cache.write();
+            let before = cache.len();
+            cache.retain(|_, r| !r.is_expired());
+            before - cache.len()
+        };
+        if removed > 0 {
+            self.persist()?;
+            tracing::info!(count = removed, "Deleted expired listening rooms");
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn room_with_members(count: usize) -> (Room, Vec<Uuid>) {
+        let members: Vec<Uuid> = (0..count).map(|_| Uuid::new_v4()).collect();
+        let mut room = Room::new(members[0], "Office".to_string(), Duration::hours(1));
+        room.members = members.clone();
+        (room, members)
+    }
+
+    #[test]
+    fn test_songs_are_queued_by_majority_vote() {
+        let (mut room, members) = room_with_members(4);
+        assert_eq!(room.votes_needed(), 3);
+
+        assert!(!room.vote(members[1], "a"));
+        assert!(!room.vote(members[1], "a"));
+        assert!(!room.vote(members[2], "a"));
+        assert!(room.vote(members[3], "a"));
+        assert_eq!(room.queue, vec!["a"]);
+        assert!(room.proposals.is_empty());
+
+        // Two votes pass once a member leaves (3 members need 2)
+        room.vote(members[1], "b");
+        room.vote(members[2], "b");
+        assert_eq!(room.queue.len(), 1);
+        assert!(room.remove_member(members[3]));
+        assert_eq!(room.queue, vec!["a", "b"]);
+
+        // The host queues directly
+        room.vote(members[2], "c");
+        assert!(room.vote(members[0], "c"));
+        assert_eq!(room.queue, vec!["a", "b", "c"]);
+        assert!(room.proposals.is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_votes_are_kept() {
+        let dir = tempdir().unwrap();
+        let repo = JsonRoomRepository::new(dir.path().join("rooms.json")).unwrap();
+        let (room, members) = room_with_members(9);
+        let room = repo.create(room).unwrap();
+
+        std::thread::scope(|scope| {
+            for (i, member) in members.iter().enumerate().skip(1) {
+                let (repo, id) = (&repo, &room.id);
+                scope.spawn(move || repo.vote(id, *member, &format!("song{}", i)).unwrap());
+            }
+        });
+        assert_eq!(
+            repo.find_by_id(&room.id).unwrap().unwrap().proposals.len(),
+            8
+        );
+
+        assert!(matches!(
+            repo.vote(&room.id, Uuid::new_v4(), "x"),
+            Err(AppError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn test_remove_from_queue_keeps_current_song() {
+        let (mut room, _) = room_with_members(1);
+        room.queue = vec!["a".into(), "b".into(), "c".into()];
+        room.playback.current = Some(1);
+
+        assert_eq!(room.remove_from_queue(0).as_deref(), Some("a"));
+        assert_eq!(room.playback.current, Some(0));
+        assert_eq!(room.remove_from_queue(0).as_deref(), Some("b"));
+        assert_eq!(room.playback.current, Some(0));
+        assert_eq!(room.remove_from_queue(0).as_deref(), Some("c"));
+        assert_eq!(room.playback.current, None);
+        assert!(room.remove_from_queue(0).is_none());
+    }
+
+    #[test]
+    fn test_expired_rooms_are_hidden_and_purged() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rooms.json");
+        let repo = JsonRoomRepository::new(&path).unwrap();
+        let host = Uuid::new_v4();
+
+        let live = repo
+            .create(Room::new(host, "Live".into(), Duration::hours(1)))
+            .unwrap();
+        let expired = repo
+            .create(Room::new(host, "Old".into(), Duration::seconds(-1)))
+            .unwrap();
+
+        assert!(repo.find_by_id(&expired.id).unwrap().is_none());
+        assert!(repo.update(expired).is_err());
+        assert_eq!(repo.list_for_user(host).unwrap(), vec![live.clone()]);
+        assert!(repo.list_for_user(Uuid::new_v4()).unwrap().is_empty());
+
+        assert_eq!(repo.delete_expired().unwrap(), 1);
+        let repo = JsonRoomRepository::new(&path).unwrap();
+        assert_eq!(repo.list_all().unwrap(), vec![live]);
+    }
+}
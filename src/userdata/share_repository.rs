@@ -0,0 +1,346 @@
+//! Public share link data model and repository.
+//!
+//! A share makes one song, album or playlist playable by anyone with its
+//! link, without an account. Shares may expire, require a password and
+//! limit how often they are played. Expired shares are treated as gone.
+
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::error::AppResult;
+use crate::storage;
+
+/// Kind of item a share refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShareKind {
+    Song,
+    Album,
+    Playlist,
+}
+
+/// Share link model.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Share {
+    /// Unique share ID, the secret part of the link.
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub kind: ShareKind,
+    /// Shared song, album or playlist ID.
+    pub item_id: String,
+    /// Argon2 hash of the share password, if one is required.
+    pub password_hash: Option<String>,
+    /// Most plays allowed, if limited.
+    pub max_plays: Option<u32>,
+    /// Plays so far.
+    pub plays: u32,
+    /// Whether songs may be downloaded as files.
+    pub allow_download: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Share {
+    /// Create a share of an item.
+    pub fn new(owner_id: Uuid, kind: ShareKind, item_id: String) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            owner_id,
+            kind,
+            item_id,
+            password_hash: None,
+            max_plays: None,
+            plays: 0,
+            allow_download: false,
+            created_at: Utc::now(),
+            expires_at: None,
+        }
+    }
+
+    /// Check whether the share has expired.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= Utc::now())
+    }
+
+    /// Check whether the play limit has been reached.
+    pub fn is_used_up(&self) -> bool {
+        self.max_plays.is_some_and(|max| self.plays >= max)
+    }
+}
+
+/// Share storage format for JSON file.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct ShareStore {
+    shares: Vec<Share>,
+}
+
+/// Trait for share repository operations.
+///
+/// Expired shares are never returned.
+pub trait ShareRepository: Send + Sync {
+    /// Find a share by ID.
+    fn find_by_id(&self, id: Uuid) -> AppResult<Option<Share>>;
+
+    /// List all shares, oldest first.
+    fn list_all(&self) -> AppResult<Vec<Share>>;
+
+    /// List a user's shares, oldest first.
+    fn list_by_owner(&self, owner_id: Uuid) -> AppResult<Vec<Share>>;
+
+    /// Store a new share.
+    fn create(&self, share: Share) -> AppResult<Share>;
+
+    /// Count a play, returning the updated share, or `None` if the share
+    /// is gone or its play limit has been reached.
+    ///
+    /// `visit` identifies the listener's copy of the link (a signed media
+    /// URL). Requests of a visit counted within the last `window` continue
+    /// that play: they are not counted again, and are allowed even once the
+    /// limit is reached.
+    fn record_play(&self, id: Uuid, visit: &str, window: Duration) -> AppResult<Option<Share>>;
+
+    /// Delete a share by ID.
+    fn delete(&self, id: Uuid) -> AppResult<bool>;
+
+    /// Delete all of a user's shares, returning how many were removed.
+    fn delete_by_owner(&self, owner_id: Uuid) -> AppResult<usize>;
+
+    /// Delete expired shares, returning how many were removed.
+    fn delete_expired(&self) -> AppResult<usize>;
+}
+
+/// JSON file-based share repository.
+#[derive(Debug)]
+pub struct JsonShareRepository {
+    file_path: PathBuf,
+    /// In-memory cache keyed by share ID.
+    cache: RwLock<HashMap<Uuid, Share>>,
+    /// Recently counted visits and until when they continue a play. Not
+    /// persisted.
+    visits: Mutex<HashMap<(Uuid, String), DateTime<Utc>>>,
+}
+
+impl JsonShareRepository {
+    /// Create a new JSON share repository.
+    pub fn new(file_path: impl AsRef<Path>) -> AppResult<Self> {
+        let file_path = file_path.as_ref().to_path_buf();
+        let store: ShareStore = storage::load_json(&file_path)?;
+
+        let cache = store
+            .shares
+            .into_iter()
+            .map(|s| (s.id, s))
+            .collect::<HashMap<_, _>>();
+
+        tracing::info!(count = cache.len(), "Loaded share links from file");
+
+        Ok(Self {
+            file_path,
+            cache: RwLock::new(cache),
+            visits: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Write shares from cache to file.
+    fn persist(&self) -> AppResult<()> {
+        let cache = self.cache.read();
+        let store = ShareStore {
+            shares: cache.values().cloned().collect(),
+        };
+        storage::save_json(&self.file_path, &store)
+    }
+
+    fn list(&self, filter: impl Fn(&Share) -> bool) -> Vec<Share> {
+        let cache = self.cache.read();
+        let mut shares: Vec<Share> = cache
+            .values()
+            .filter(|s| !s.is_expired() && filter(s))
+            .cloned()
+            .collect();
+        shares.sort_by_key(|s| s.created_at);
+        shares
+    }
+}
+
+impl ShareRepository for JsonShareRepository {
+    fn find_by_id(&self, id: Uuid) -> AppResult<Option<Share>> {
+        Ok(self
+            .cache
+            .read()
+            .get(&id)
+            .filter(|s| !s.is_expired())
+            .cloned())
+    }
+
+    fn list_all(&self) -> AppResult<Vec<Share>> {
+        Ok(self.list(|_| true))
+    }
+
+    fn list_by_owner(&self, owner_id: Uuid) -> AppResult<Vec<Share>> {
+        Ok(self.list(|s| s.owner_id == owner_id))
+    }
+
+    fn create(&self, share: Share) -> AppResult<Share> {
+        self.cache.write().insert(share.id, share.clone());
+        self.persist()?;
+        tracing::info!(share_id = %share.id, owner_id = %share.owner_id, "Created share link");
+        Ok(share)
+    }
+
+    fn record_play(&self, id: Uuid, visit: &str, window: Duration) -> AppResult<Option<Share>> {
+        let now = Utc::now();
+        let share = {
+            let mut cache = self.cache.write();
+            let Some(share) = cache.get_mut(&id).filter(|s| !s.is_expired()) else {
+                return Ok(None);
+            };
+
+            let mut visits = self.visits.lock();
+            visits.retain(|_, until| *until > now);
+            let key = (id, visit.to_string());
+            if visits.contains_key(&key) {
+                return Ok(Some(share.clone()));
+            }
+            if share.is_used_up() {
+                return Ok(None);
+            }
+            share.plays += 1;
+            visits.insert(key, now + window);
+            share.clone()
+        };
+        self.persist()?;
+        Ok(Some(share))
+    }
+
+    fn delete(&self, id: Uuid) -> AppResult<bool> {
+        let removed = self.cache.write().remove(&id).is_some();
+        if removed {
+            self.persist()?;
+            tracing::info!(share_id = %id, "Deleted share link");
+        }
+        Ok(removed)
+    }
+
+    fn delete_by_owner(&self, owner_id: Uuid) -> AppResult<usize> {
+        let removed = {
+            let mut cache = self.cache.write();
+            let before = cache.len();
+            cache.retain(|_, s| s.owner_id != owner_id);
+            before - cache.len()
+        };
+        if removed > 0 {
+            self.persist()?;
+        }
+        Ok(removed)
+    }
+
+    fn delete_expired(&self) -> AppResult<usize> {
+        let removed = {
+            let mut cache = self.cache.write();
+            let before = cache.len();
+            cache.retain(|_, s| !s.is_expired());
+            before - cache.len()
+        };
+        if removed > 0 {
+            self.persist()?;
+            tracing::info!(count = removed, "Deleted expired share links");
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_play_limit_and_password_survive_reload() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("shares.json");
+        let repo = JsonShareRepository::new(&path).unwrap();
+
+        let mut share = Share::new(Uuid::new_v4(), ShareKind::Song, "song".into());
+        share.max_plays = Some(2);
+        share.password_hash = Some("hash".into());
+        let share = repo.create(share).unwrap();
+
+        let window = Duration::minutes(5);
+        assert_eq!(
+            repo.record_play(share.id, "a", window)
+                .unwrap()
+                .unwrap()
+                .plays,
+            1
+        );
+        let repo = JsonShareRepository::new(&path).unwrap();
+        let reloaded = repo.find_by_id(share.id).unwrap().unwrap();
+        assert_eq!(reloaded.plays, 1);
+        assert_eq!(reloaded.password_hash.as_deref(), Some("hash"));
+
+        assert!(repo.record_play(share.id, "b", window).unwrap().is_some());
+        assert!(repo.record_play(share.id, "c", window).unwrap().is_none());
+        assert!(repo
+            .record_play(Uuid::new_v4(), "a", window)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_visits_count_once_per_window() {
+        let dir = tempdir().unwrap();
+        let repo = JsonShareRepository::new(dir.path().join("shares.json")).unwrap();
+        let mut share = Share::new(Uuid::new_v4(), ShareKind::Song, "song".into());
+        share.max_plays = Some(1);
+        let share = repo.create(share).unwrap();
+
+        // Seeking and resuming within the window continue the one play,
+        // even though it used up the limit
+        for _ in 0..3 {
+            let counted = repo.record_play(share.id, "a", Duration::minutes(5));
+            assert_eq!(counted.unwrap().unwrap().plays, 1);
+        }
+        assert!(repo
+            .record_play(share.id, "b", Duration::minutes(5))
+            .unwrap()
+            .is_none());
+
+        // A visit whose window has passed is a new play
+        let share = repo
+            .create(Share::new(share.owner_id, ShareKind::Song, "song".into()))
+            .unwrap();
+        repo.record_play(share.id, "a", Duration::zero()).unwrap();
+        let counted = repo.record_play(share.id, "a", Duration::zero());
+        assert_eq!(counted.unwrap().unwrap().plays, 2);
+    }
+
+    #[test]
+    fn test_expired_shares_are_hidden_and_purged() {
+        let dir = tempdir().unwrap();
+        let repo = JsonShareRepository::new(dir.path().join("shares.json")).unwrap();
+        let owner = Uuid::new_v4();
+
+        let live = repo
+            .create(Share::new(owner, ShareKind::Album, "album".into()))
+            .unwrap();
+        let mut expired = Share::new(owner, ShareKind::Playlist, "playlist".into());
+        expired.expires_at = Some(Utc::now() - Duration::minutes(1));
+        let expired = repo.create(expired).unwrap();
+
+        assert!(repo.find_by_id(expired.id).unwrap().is_none());
+        assert!(repo
+            .record_play(expired.id, "a", Duration::minutes(5))
+            .unwrap()
+            .is_none());
+        assert_eq!(repo.list_by_owner(owner).unwrap(), vec![live.clone()]);
+
+        assert_eq!(repo.delete_expired().unwrap(), 1);
+        assert_eq!(repo.delete_by_owner(owner).unwrap(), 1);
+        assert!(repo.list_all().unwrap().is_empty());
+    }
+}